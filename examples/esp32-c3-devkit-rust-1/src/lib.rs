@@ -1,8 +1,6 @@
 #![no_std]
-#![feature(variant_count)]
-
 use embassy_executor::Spawner;
-use post_haste::init_postmaster;
+use post_haste::{AddressSpace, init_postmaster};
 
 mod polite_agent;
 
@@ -12,7 +10,7 @@ enum Payloads {
     Hello,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, AddressSpace)]
 enum Addresses {
     A,
     B,
@@ -0,0 +1,127 @@
+//! This example measures how many messages per second a Postmaster can deliver from many concurrent senders.
+//! It is a rough benchmark for comparing changes to the Postmaster's send path, and should be run in release mode: `cargo run --release --example throughput`.
+//!
+//! The first scenario has every sender sending to a set of quick Agents.
+//! The second adds a slow Agent with a full queue, which senders should be able to wait on without holding up messages to the other Agents.
+//! The third has another task registering and deregistering addresses throughout, which shouldn't hold up the senders either.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use post_haste::AddressSpace;
+use post_haste::postmaster::{Message, Postmaster};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+#[derive(Debug, Clone, Copy, AddressSpace)]
+enum Address {
+    Sender,
+    #[dynamic]
+    Receiver(u64),
+}
+
+/// What else is happening while the messages are sent
+#[derive(Clone, Copy, PartialEq)]
+enum Scenario {
+    QuickAgents,
+    SlowAgent,
+    Registering,
+}
+
+const RECEIVERS: u64 = 16;
+const SENDERS: u64 = 64;
+const MESSAGES_PER_SENDER: u64 = 5_000;
+/// The send timeout, in microseconds
+const TIMEOUT_US: u32 = 100_000;
+
+#[tokio::main]
+async fn main() {
+    let throughput = run(Scenario::QuickAgents).await;
+    println!("{SENDERS} senders to {RECEIVERS} Agents: {throughput:.0} messages/s");
+    let throughput = run(Scenario::SlowAgent).await;
+    println!(
+        "{SENDERS} senders to {RECEIVERS} Agents, with a slow Agent: {throughput:.0} messages/s"
+    );
+    let throughput = run(Scenario::Registering).await;
+    println!(
+        "{SENDERS} senders to {RECEIVERS} Agents, while registering others: {throughput:.0} messages/s"
+    );
+}
+
+/// Send every message and return the number delivered per second, excluding those sent to the slow Agent.
+/// Messages which time out aren't counted.
+async fn run(scenario: Scenario) -> f64 {
+    let postmaster = Postmaster::<Address, u64>::with_timeout(TIMEOUT_US);
+    for id in 0..RECEIVERS {
+        let (sender, mut receiver) = mpsc::channel::<Message<Address, u64>>(1024);
+        postmaster
+            .register(Address::Receiver(id), sender)
+            .await
+            .unwrap();
+        tokio::spawn(async move { while receiver.recv().await.is_some() {} });
+    }
+    let stopping = Arc::new(AtomicBool::new(false));
+    let mut background = JoinSet::new();
+    if scenario == Scenario::SlowAgent {
+        let slow = Address::Receiver(RECEIVERS);
+        let (sender, mut receiver) = mpsc::channel::<Message<Address, u64>>(1);
+        postmaster.register(slow, sender).await.unwrap();
+        tokio::spawn(async move {
+            while receiver.recv().await.is_some() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+        for _ in 0..4 {
+            let postmaster = postmaster.clone();
+            let stopping = stopping.clone();
+            background.spawn(async move {
+                while !stopping.load(Ordering::Relaxed) {
+                    let _ = postmaster.send(slow, Address::Sender, 0).await;
+                }
+            });
+        }
+    }
+
+    if scenario == Scenario::Registering {
+        let postmaster = postmaster.clone();
+        let stopping = stopping.clone();
+        background.spawn(async move {
+            let mut id = RECEIVERS;
+            while !stopping.load(Ordering::Relaxed) {
+                let (sender, _receiver) = mpsc::channel(1);
+                postmaster
+                    .register(Address::Receiver(id), sender)
+                    .await
+                    .unwrap();
+                postmaster.deregister(Address::Receiver(id)).await.unwrap();
+                id += 1;
+                tokio::task::yield_now().await;
+            }
+        });
+    }
+
+    let started = Instant::now();
+    let mut senders = JoinSet::new();
+    for sender in 0..SENDERS {
+        let postmaster = postmaster.clone();
+        senders.spawn(async move {
+            let mut delivered = 0;
+            for message in 0..MESSAGES_PER_SENDER {
+                let destination = Address::Receiver((sender + message) % RECEIVERS);
+                if postmaster
+                    .send(destination, Address::Sender, message)
+                    .await
+                    .is_ok()
+                {
+                    delivered += 1;
+                }
+            }
+            delivered
+        });
+    }
+    let delivered: u64 = senders.join_all().await.into_iter().sum();
+    let elapsed = started.elapsed();
+    stopping.store(true, Ordering::Relaxed);
+    background.abort_all();
+    delivered as f64 / elapsed.as_secs_f64()
+}
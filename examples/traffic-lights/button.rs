@@ -1,15 +1,15 @@
 use tokio::io::{self, AsyncBufReadExt, BufReader};
 
-use crate::{Addresses, Payloads, lights::LightsMessage, postmaster, sequencer::SequencerMessage};
+use crate::{Addresses, lights::LightsMessage, postmaster, sequencer::SequencerMessage};
 
 pub async fn button_task() -> ! {
     let mut reader = BufReader::new(io::stdin()).lines();
     loop {
-        if let Some(_) = reader.next_line().await.unwrap() {
+        if reader.next_line().await.unwrap().is_some() {
             postmaster::send(
                 Addresses::SequencerAgent,
                 Addresses::ButtonTask,
-                Payloads::Sequencer(SequencerMessage::ButtonPress),
+                SequencerMessage::ButtonPress,
             )
             .await
             .unwrap();
@@ -18,9 +18,9 @@ pub async fn button_task() -> ! {
             postmaster::send(
                 Addresses::LightsAgent,
                 Addresses::ButtonTask,
-                Payloads::Lights(LightsMessage::DebugMessage(String::from(
+                LightsMessage::DebugMessage(String::from(
                     "Message sent from ButtonTask to LightsAgent",
-                ))),
+                )),
             )
             .await
             .unwrap();
@@ -192,12 +192,10 @@ impl Agent for LightsAgent {
 
     async fn run(mut self, mut inbox: Inbox<Self::Message>) -> ! {
         loop {
-            if let Some(message) = inbox.recv().await {
-                match message.payload {
-                    Payloads::Lights(lights_message) => self.message_handler(lights_message),
-                    _ => (),
-                }
-                // self.message_handler(message.payload);
+            if let Some(message) = inbox.recv().await
+                && let Payloads::Lights(lights_message) = message.payload
+            {
+                self.message_handler(lights_message)
             }
         }
     }
@@ -273,7 +271,7 @@ impl LightsAgent {
             .iter()
             .for_each(|message| println!("{}", message));
 
-        println!("");
+        println!();
         println!("----");
         println!("|{red_char}|   -------");
         println!("----   |{stop_chars}|");
@@ -1,7 +1,7 @@
-#![feature(variant_count)]
-
-use post_haste::init_postmaster;
+use post_haste::postmaster::Payloads;
+use post_haste::{AddressSpace, init_postmaster};
 use std::process::exit;
+use tokio::time::Duration;
 
 use crate::{
     button::button_task,
@@ -14,13 +14,13 @@ mod consts;
 mod lights;
 mod sequencer;
 
-#[derive(Debug)]
+#[derive(Debug, Payloads)]
 pub(crate) enum Payloads {
     Lights(LightsMessage),
     Sequencer(SequencerMessage),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, AddressSpace)]
 pub(crate) enum Addresses {
     LightsAgent,
     SequencerAgent,
@@ -33,21 +33,20 @@ init_postmaster!(Addresses, Payloads);
 async fn main() {
     println!("Press enter to press the crossing button");
 
-    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-
-    postmaster::register_agent!(LightsAgent, LightsAgent, ()).unwrap();
-    postmaster::register_agent!(SequencerAgent, SequencerAgent, ()).unwrap();
+    // The sequencer drives the lights as soon as it starts, so it waits for the lights to be ready
+    postmaster::startup()
+        .with_agent(Addresses::LightsAgent, [], async {
+            postmaster::register_agent!(LightsAgent, LightsAgent, ())
+        })
+        .with_agent(Addresses::SequencerAgent, [Addresses::LightsAgent], async {
+            postmaster::register_agent!(SequencerAgent, SequencerAgent, ())
+        })
+        .start()
+        .await
+        .unwrap();
     tokio::spawn(button_task());
 
-    postmaster::send(
-        Addresses::SequencerAgent,
-        Addresses::SequencerAgent,
-        Payloads::Sequencer(SequencerMessage::Begin),
-    )
-    .await
-    .unwrap();
-
-    let _ = tokio::signal::ctrl_c().await;
+    let report = postmaster::run_until_shutdown(Duration::from_secs(1)).await;
     println!();
-    exit(0);
+    exit(report.exit_code());
 }
@@ -1,14 +1,13 @@
 use post_haste::agent::Agent;
+use post_haste::agent::fsm::{self, Disposition, StateMachine, Transition};
+use tokio::time::Duration;
 
 use crate::lights::LightsMessage;
 use crate::{Addresses, Payloads, consts, postmaster};
 
 #[derive(Debug)]
 pub(crate) enum SequencerMessage {
-    Begin,
     ButtonPress,
-    #[allow(private_interfaces)]
-    InternalMessage,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -25,7 +24,6 @@ pub(crate) enum SequencerState {
 
 pub(crate) struct SequencerAgent {
     address: crate::Addresses,
-    state: SequencerState,
 }
 
 impl Agent for SequencerAgent {
@@ -34,172 +32,86 @@ impl Agent for SequencerAgent {
     type Config = ();
 
     async fn create(address: Self::Address, _config: Self::Config) -> Self {
-        Self {
-            address,
-            state: SequencerState::RedCrossEnding,
-        }
+        Self { address }
+    }
+
+    async fn run(self, inbox: post_haste::agent::Inbox<Self::Message>) -> ! {
+        fsm::run(self, inbox).await
+    }
+}
+
+impl StateMachine for SequencerAgent {
+    type State = SequencerState;
+    type Message = postmaster::Message;
+
+    fn initial_state(&self) -> SequencerState {
+        SequencerState::RedCrossEnding
     }
 
-    async fn run(mut self, mut inbox: post_haste::agent::Inbox<Self::Message>) -> ! {
-        loop {
-            let received_message = inbox.recv().await.unwrap();
-            match received_message.payload {
-                Payloads::Sequencer(message) => self.handle_message(message).await,
-                _ => println!(
-                    "SequencerAgent received unsupported message {:?}",
-                    received_message.payload
-                ),
+    fn accepts(&self, state: &SequencerState, message: &postmaster::Message) -> Disposition {
+        match (state, &message.payload) {
+            // The button only has an effect while the lights are (or are about to be) green
+            (
+                SequencerState::Green | SequencerState::RedToGreen,
+                Payloads::Sequencer(SequencerMessage::ButtonPress),
+            ) => Disposition::Handle,
+            (_, Payloads::Sequencer(SequencerMessage::ButtonPress)) => Disposition::Discard,
+            (_, payload) => {
+                println!("SequencerAgent received unsupported message {payload:?}");
+                Disposition::Discard
             }
         }
     }
-}
 
-impl SequencerAgent {
-    async fn handle_message(&mut self, message: SequencerMessage) {
-        match message {
-            SequencerMessage::Begin => self.begin().await,
-            SequencerMessage::InternalMessage => self.handle_internal_message().await,
-            SequencerMessage::ButtonPress => self.handle_button_press().await,
+    async fn on_message(
+        &mut self,
+        state: &SequencerState,
+        _message: postmaster::Message,
+    ) -> Transition<SequencerState> {
+        match state {
+            SequencerState::Green => Transition::To(SequencerState::GreenCrossPending),
+            SequencerState::RedToGreen => Transition::To(SequencerState::RedToGreenCrossPending),
+            _ => Transition::Stay,
         }
     }
 
-    async fn send_current_state_to_lights_agent(&mut self) {
+    async fn on_enter(&mut self, state: &SequencerState) {
         postmaster::send(
             Addresses::LightsAgent,
             self.address,
-            Payloads::Lights(LightsMessage::SetSequenceState {
-                sequence_state: self.state.clone(),
-            }),
+            LightsMessage::SetSequenceState {
+                sequence_state: state.clone(),
+            },
         )
         .await
         .unwrap();
     }
 
-    async fn handle_internal_message(&mut self) {
-        self.set_next_state().await;
-        self.send_current_state_to_lights_agent().await;
-    }
-
-    async fn handle_button_press(&mut self) {
-        match self.state {
-            SequencerState::Green => {
-                self.state = SequencerState::GreenCrossPending;
-                self.send_current_state_to_lights_agent().await;
-                self.schedule_next_state().await;
-            }
-            SequencerState::GreenCrossPending
-            | SequencerState::GreenToRed
-            | SequencerState::RedCrossPending
-            | SequencerState::RedCrossing
-            | SequencerState::RedCrossEnding
-            | SequencerState::RedToGreenCrossPending => (),
-            SequencerState::RedToGreen => {
-                self.state = SequencerState::RedToGreenCrossPending;
-                self.send_current_state_to_lights_agent().await;
-                // Do not schedule next state - in RedToGreen state, a delayed
-                // message will already have been sent!
-            }
-        }
-    }
-
-    async fn begin(&mut self) {
-        self.send_current_state_to_lights_agent().await;
-        self.schedule_next_state().await;
-    }
-
-    async fn set_next_state(&mut self) {
-        match self.state {
-            SequencerState::Green => unreachable!(),
-            SequencerState::GreenCrossPending => {
-                self.state = SequencerState::GreenToRed;
-                self.schedule_next_state().await;
-            }
-            SequencerState::GreenToRed => {
-                self.state = SequencerState::RedCrossPending;
-                self.schedule_next_state().await;
-            }
-            SequencerState::RedCrossPending => {
-                self.state = SequencerState::RedCrossing;
-                self.schedule_next_state().await;
-            }
-            SequencerState::RedCrossing => {
-                self.state = SequencerState::RedCrossEnding;
-                self.schedule_next_state().await;
-            }
-            SequencerState::RedCrossEnding => {
-                self.state = SequencerState::RedToGreen;
-                self.schedule_next_state().await;
-            }
-            SequencerState::RedToGreen => {
-                self.state = SequencerState::Green;
-            }
-            SequencerState::RedToGreenCrossPending => {
-                self.state = SequencerState::GreenCrossPending;
-                self.schedule_next_state().await;
+    fn timeout(&self, state: &SequencerState) -> Option<Duration> {
+        match state {
+            // The lights stay green until the button is pressed
+            SequencerState::Green => None,
+            SequencerState::GreenCrossPending => Some(consts::GREEN_TO_AMBER_DELAY),
+            SequencerState::GreenToRed => Some(consts::AMBER_TO_RED_DELAY),
+            SequencerState::RedCrossPending => Some(consts::CROSSING_START_DELAY),
+            SequencerState::RedCrossing => Some(consts::CROSSING_LENGTH),
+            SequencerState::RedCrossEnding => Some(consts::CROSSING_END_DELAY),
+            SequencerState::RedToGreen | SequencerState::RedToGreenCrossPending => {
+                Some(consts::AMBER_TO_GREEN_DELAY)
             }
         }
-        self.send_current_state_to_lights_agent().await;
     }
 
-    async fn schedule_next_state(&mut self) {
-        match self.state {
+    async fn on_timeout(&mut self, state: &SequencerState) -> Transition<SequencerState> {
+        Transition::To(match state {
             SequencerState::Green => unreachable!(),
-            SequencerState::GreenCrossPending => postmaster::message(
-                self.address,
-                self.address,
-                Payloads::Sequencer(SequencerMessage::InternalMessage),
-            )
-            .with_delay(consts::GREEN_TO_AMBER_DELAY)
-            .send()
-            .await
-            .unwrap(),
-            SequencerState::GreenToRed => postmaster::message(
-                self.address,
-                self.address,
-                Payloads::Sequencer(SequencerMessage::InternalMessage),
-            )
-            .with_delay(consts::AMBER_TO_RED_DELAY)
-            .send()
-            .await
-            .unwrap(),
-            SequencerState::RedCrossPending => postmaster::message(
-                self.address,
-                self.address,
-                Payloads::Sequencer(SequencerMessage::InternalMessage),
-            )
-            .with_delay(consts::CROSSING_START_DELAY)
-            .send()
-            .await
-            .unwrap(),
-            SequencerState::RedCrossing => postmaster::message(
-                self.address,
-                self.address,
-                Payloads::Sequencer(SequencerMessage::InternalMessage),
-            )
-            .with_delay(consts::CROSSING_LENGTH)
-            .send()
-            .await
-            .unwrap(),
-            SequencerState::RedCrossEnding => postmaster::message(
-                self.address,
-                self.address,
-                Payloads::Sequencer(SequencerMessage::InternalMessage),
-            )
-            .with_delay(consts::CROSSING_END_DELAY)
-            .send()
-            .await
-            .unwrap(),
-            SequencerState::RedToGreen | SequencerState::RedToGreenCrossPending => {
-                postmaster::message(
-                    self.address,
-                    self.address,
-                    Payloads::Sequencer(SequencerMessage::InternalMessage),
-                )
-                .with_delay(consts::AMBER_TO_GREEN_DELAY)
-                .send()
-                .await
-                .unwrap()
-            }
-        }
+            SequencerState::GreenCrossPending => SequencerState::GreenToRed,
+            SequencerState::GreenToRed => SequencerState::RedCrossPending,
+            SequencerState::RedCrossPending => SequencerState::RedCrossing,
+            SequencerState::RedCrossing => SequencerState::RedCrossEnding,
+            SequencerState::RedCrossEnding => SequencerState::RedToGreen,
+            SequencerState::RedToGreen => SequencerState::Green,
+            SequencerState::RedToGreenCrossPending => SequencerState::GreenCrossPending,
+        })
     }
 }
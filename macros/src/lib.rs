@@ -0,0 +1,531 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::{
+    Data, DeriveInput, Error, Fields, FnArg, Ident, ImplItem, ItemImpl, Pat, Path, Token,
+    parse_macro_input,
+};
+
+/// Derive `post_haste::AddressSpace` for an enum of addresses.
+/// Each unit variant becomes a static address, indexed in the order the variants are declared.
+/// A single variant may be marked `#[dynamic]` to hold dynamic addresses, in which case it must have exactly one unnamed `u64` field holding the address's identifier.
+/// A variant marked `#[nested]` holds another address space (whose addresses must all be static), taking a static address for each of the nested addresses, so that addresses can be arranged in a hierarchy.
+/// Nesting an address space with dynamic addresses (see `AddressSpace::HAS_DYNAMIC`) fails to compile.
+///
+/// # Example
+/// ```rust,ignore
+/// #[derive(Debug, Clone, Copy, AddressSpace)]
+/// enum Address {
+///     Listener,
+///     Logger,
+///     #[nested]
+///     Sensors(Sensor),
+///     #[dynamic]
+///     Client(u64),
+/// }
+/// ```
+#[proc_macro_derive(AddressSpace, attributes(dynamic, nested))]
+pub fn derive_address_space(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_address_space(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_address_space(input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new(
+            Span::call_site(),
+            "AddressSpace can only be derived for enums",
+        ));
+    };
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut index_arms = Vec::new();
+    let mut static_lookups = Vec::new();
+    let mut dynamic_variant = None;
+    let mut nested_checks = Vec::new();
+    // The index of the next static address, which is an expression once a nested address space has been counted
+    let mut static_count = quote! { 0usize };
+    for variant in &data.variants {
+        let variant_name = &variant.ident;
+        let has_attribute = |name| variant.attrs.iter().any(|attr| attr.path().is_ident(name));
+        if has_attribute("dynamic") {
+            if dynamic_variant.is_some() {
+                return Err(Error::new_spanned(
+                    variant,
+                    "only one variant can be marked #[dynamic]",
+                ));
+            }
+            match &variant.fields {
+                Fields::Unnamed(fields) if fields.unnamed.len() == 1 => (),
+                _ => {
+                    return Err(Error::new_spanned(
+                        variant,
+                        "a #[dynamic] variant must have exactly one unnamed u64 field, e.g. `Client(u64)`",
+                    ));
+                }
+            }
+            index_arms.push(quote! {
+                Self::#variant_name(id) => ::post_haste::address::AddressIndex::Dynamic(id),
+            });
+            dynamic_variant = Some(variant_name);
+        } else if has_attribute("nested") {
+            let nested = match &variant.fields {
+                Fields::Unnamed(fields) if fields.unnamed.len() == 1 => &fields.unnamed[0].ty,
+                _ => {
+                    return Err(Error::new_spanned(
+                        variant,
+                        "a #[nested] variant must have exactly one unnamed field holding the nested addresses, e.g. `Sensors(Sensor)`",
+                    ));
+                }
+            };
+            let variant_string = variant_name.to_string();
+            let message = format!(
+                "The addresses nested in {variant_string} must be static, so the address space nested in it can't have a #[dynamic] variant"
+            );
+            nested_checks.push(quote! {
+                ::core::assert!(!<#nested as ::post_haste::address::AddressSpace>::HAS_DYNAMIC, #message);
+            });
+            index_arms.push(quote! {
+                Self::#variant_name(nested) => match ::post_haste::address::AddressSpace::index(&nested) {
+                    ::post_haste::address::AddressIndex::Static(index) => ::post_haste::address::AddressIndex::Static(#static_count + index),
+                    ::post_haste::address::AddressIndex::Dynamic(_) => panic!("The addresses nested in {} must be static", #variant_string),
+                },
+            });
+            static_lookups.push(quote! {
+                if let ::core::option::Option::Some(nested) = index
+                    .checked_sub(#static_count)
+                    .filter(|&nested| nested < <#nested as ::post_haste::address::AddressSpace>::COUNT)
+                {
+                    return <#nested as ::post_haste::address::AddressSpace>::static_address(nested).map(Self::#variant_name);
+                }
+            });
+            static_count =
+                quote! { #static_count + <#nested as ::post_haste::address::AddressSpace>::COUNT };
+        } else {
+            if !matches!(variant.fields, Fields::Unit) {
+                return Err(Error::new_spanned(
+                    variant,
+                    "static addresses must be unit variants: mark a variant holding a u64 identifier with #[dynamic] to use it for dynamic addresses, or one holding another address space with #[nested]",
+                ));
+            }
+            index_arms.push(quote! {
+                Self::#variant_name => ::post_haste::address::AddressIndex::Static(#static_count),
+            });
+            static_lookups.push(quote! {
+                if index == #static_count {
+                    return ::core::option::Option::Some(Self::#variant_name);
+                }
+            });
+            static_count = quote! { #static_count + 1 };
+        }
+    }
+
+    let dynamic = dynamic_variant.map(|variant_name| {
+        quote! {
+            const HAS_DYNAMIC: bool = true;
+
+            fn dynamic(id: u64) -> ::core::option::Option<Self> {
+                ::core::option::Option::Some(Self::#variant_name(id))
+            }
+        }
+    });
+    // The nested address spaces are checked as the count is evaluated, which happens where the enum is defined unless it is generic
+    let evaluate_count = input.generics.params.is_empty().then(|| {
+        quote! {
+            const _: usize = <#name as ::post_haste::address::AddressSpace>::COUNT;
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::post_haste::address::AddressSpace for #name #type_generics #where_clause {
+            const COUNT: usize = {
+                #(#nested_checks)*
+                #static_count
+            };
+
+            fn index(&self) -> ::post_haste::address::AddressIndex {
+                match *self {
+                    #(#index_arms)*
+                }
+            }
+
+            fn static_address(index: usize) -> ::core::option::Option<Self> {
+                #(#static_lookups)*
+                ::core::option::Option::None
+            }
+
+            #dynamic
+        }
+
+        #evaluate_count
+    })
+}
+
+/// Derive `post_haste::postmaster::PayloadVariant` for an enum of payloads, naming each payload after its variant.
+///
+/// # Example
+/// ```rust,ignore
+/// #[derive(PayloadVariant)]
+/// enum Payloads {
+///     Hello,
+///     Lights(LightsMessage),
+///     Reading { celsius: f32 },
+/// }
+/// ```
+#[proc_macro_derive(PayloadVariant)]
+pub fn derive_payload_variant(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_payload_variant(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_payload_variant(input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new(
+            Span::call_site(),
+            "PayloadVariant can only be derived for enums",
+        ));
+    };
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    let variant_arms = data.variants.iter().map(|variant| {
+        let variant_name = &variant.ident;
+        let variant_string = variant_name.to_string();
+        quote! {
+            Self::#variant_name { .. } => #variant_string,
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::post_haste::postmaster::PayloadVariant for #name #type_generics #where_clause {
+            fn variant(&self) -> &'static str {
+                match *self {
+                    #(#variant_arms)*
+                }
+            }
+        }
+    })
+}
+
+/// Generate `run()` for an Agent whose messages are handled by a method for each payload variant, in place of the usual `loop { match message.payload { .. } }`.
+/// The attribute goes on the Agent's `impl Agent` block and names the enum of payloads, e.g. `#[message_handlers(Payloads)]`.
+/// Each method in the block whose name starts with `on_` handles the payload variant named after the rest of it, so `on_button_press()` handles `Payloads::ButtonPress`, and the methods are moved into an inherent `impl` block for the Agent.
+///
+/// A handler for a variant holding a single value takes that value as its parameter, and a handler for any other variant (e.g. a unit variant) takes no parameter, although either may also take a parameter named `source`, which receives the address the message was sent from.
+/// Messages with a payload which has no handler are discarded, unless a fallback is given with `fallback = method`, in which case that method is called with the whole message.
+///
+/// # Example
+/// ```rust,ignore
+/// #[message_handlers(Payloads, fallback = on_unexpected)]
+/// impl Agent for SequencerAgent {
+///     type Address = Addresses;
+///     type Message = postmaster::Message;
+///     type Config = ();
+///
+///     async fn create(address: Self::Address, _config: Self::Config) -> Self {
+///         Self { address }
+///     }
+///
+///     async fn on_sequencer(&mut self, message: SequencerMessage, source: Addresses) {
+///         // Handle the message...
+///     }
+///
+///     async fn on_unexpected(&mut self, message: postmaster::Message) {
+///         println!("{:?} received unsupported message {:?}", self.address, message.payload);
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn message_handlers(attribute: TokenStream, item: TokenStream) -> TokenStream {
+    let attribute = parse_macro_input!(attribute as HandlersAttribute);
+    let item = parse_macro_input!(item as ItemImpl);
+    expand_message_handlers(attribute, item)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// The arguments of `#[message_handlers(..)]`: the enum of payloads, and optionally the fallback method
+struct HandlersAttribute {
+    payloads: Path,
+    fallback: Option<Ident>,
+}
+
+impl Parse for HandlersAttribute {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let payloads = input.parse()?;
+        let mut fallback = None;
+        if input.parse::<Option<Token![,]>>()?.is_some() && !input.is_empty() {
+            let name: Ident = input.parse()?;
+            if name != "fallback" {
+                return Err(Error::new_spanned(name, "expected `fallback = method`"));
+            }
+            input.parse::<Token![=]>()?;
+            fallback = Some(input.parse()?);
+            input.parse::<Option<Token![,]>>()?;
+        }
+        Ok(Self { payloads, fallback })
+    }
+}
+
+fn expand_message_handlers(
+    attribute: HandlersAttribute,
+    mut item: ItemImpl,
+) -> Result<proc_macro2::TokenStream, Error> {
+    if item.trait_.is_none() {
+        return Err(Error::new(
+            Span::call_site(),
+            "#[message_handlers] goes on an Agent's `impl Agent for ..` block",
+        ));
+    }
+    let HandlersAttribute { payloads, fallback } = attribute;
+    let mut handlers = Vec::new();
+    let mut items = Vec::new();
+    for impl_item in core::mem::take(&mut item.items) {
+        match impl_item {
+            ImplItem::Fn(method) if method.sig.ident == "run" => {
+                return Err(Error::new_spanned(
+                    method.sig,
+                    "#[message_handlers] generates `run()`, so the Agent shouldn't implement it",
+                ));
+            }
+            ImplItem::Fn(method)
+                if method.sig.ident.to_string().starts_with("on_")
+                    || fallback
+                        .as_ref()
+                        .is_some_and(|fallback| method.sig.ident == *fallback) =>
+            {
+                handlers.push(method);
+            }
+            other => items.push(other),
+        }
+    }
+    item.items = items;
+
+    let mut arms = Vec::new();
+    let mut patterns = Vec::new();
+    let mut fallback_call = None;
+    for handler in &handlers {
+        let name = &handler.sig.ident;
+        let call_await = handler.sig.asyncness.map(|_| quote! { .await });
+        if fallback.as_ref() == Some(name) {
+            fallback_call = Some(quote! { self.#name(message) #call_await; });
+            continue;
+        }
+        let variant = Ident::new(
+            &name
+                .to_string()
+                .trim_start_matches("on_")
+                .split('_')
+                .map(|word| {
+                    let mut characters = word.chars();
+                    characters.next().map_or_else(String::new, |first| {
+                        first.to_uppercase().chain(characters).collect()
+                    })
+                })
+                .collect::<String>(),
+            name.span(),
+        );
+        let mut payload_parameters = 0;
+        let mut arguments = Vec::new();
+        for input in &handler.sig.inputs {
+            match input {
+                FnArg::Receiver(_) => (),
+                FnArg::Typed(parameter) => match &*parameter.pat {
+                    Pat::Ident(ident) if ident.ident == "source" => {
+                        arguments.push(quote! { message.source })
+                    }
+                    _ => {
+                        payload_parameters += 1;
+                        arguments.push(quote! { payload });
+                    }
+                },
+            }
+        }
+        let pattern = match payload_parameters {
+            0 => quote! { #payloads::#variant { .. } },
+            1 => quote! { #payloads::#variant(payload) },
+            _ => {
+                return Err(Error::new_spanned(
+                    &handler.sig,
+                    "a message handler takes at most one parameter for the payload, plus an optional `source` parameter",
+                ));
+            }
+        };
+        patterns.push(quote! { #payloads::#variant { .. } });
+        arms.push(quote! {
+            #pattern => self.#name(#(#arguments),*) #call_await,
+        });
+    }
+    if let (Some(fallback), None) = (&fallback, &fallback_call) {
+        return Err(Error::new_spanned(
+            fallback,
+            "the fallback method must be defined in the same block",
+        ));
+    }
+    // Messages without a handler are passed whole to the fallback, before their payload is taken apart
+    let fallback_check = fallback_call.map(|call| {
+        quote! {
+            if !matches!(message.payload, #(#patterns)|*) {
+                #call
+                continue;
+            }
+        }
+    });
+
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+    let self_type = &item.self_ty;
+    item.items.push(syn::parse_quote! {
+        #[allow(unused_mut)]
+        async fn run(mut self, mut inbox: ::post_haste::agent::Inbox<Self::Message>) -> ! {
+            loop {
+                #[cfg(target_os = "none")]
+                let message = inbox.receive().await;
+                #[cfg(not(target_os = "none"))]
+                let message = inbox.recv().await.expect("The Agent's inbox was closed");
+                #fallback_check
+                #[allow(unreachable_patterns)]
+                match message.payload {
+                    #(#arms)*
+                    _ => (),
+                }
+            }
+        }
+    });
+
+    Ok(quote! {
+        #item
+
+        impl #impl_generics #self_type #where_clause {
+            #(#handlers)*
+        }
+    })
+}
+
+/// Derive a `From` conversion into an enum of payloads for the type held by each of its variants which wrap a single value, so that e.g. a `LightsMessage` can be passed wherever a payload is expected, and is wrapped in `Payloads::Lights` automatically.
+/// A `TryFrom` conversion back out of the enum is also derived for each of these types, handing back the payload if it holds another variant, which is how a `TypedInbox` unwraps the messages it receives.
+/// A conversion can only be derived for one variant holding each type, so any other variants holding the same type must be marked `#[no_from]`.
+///
+/// # Example
+/// ```rust,ignore
+/// #[derive(Debug, Payloads)]
+/// enum Payloads {
+///     Lights(LightsMessage),
+///     Sequencer(SequencerMessage),
+///     Hello,
+/// }
+///
+/// postmaster::send(Addresses::LightsAgent, Addresses::SequencerAgent, LightsMessage::Display).await?;
+/// ```
+#[proc_macro_derive(Payloads, attributes(no_from))]
+pub fn derive_payloads(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_payloads(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_payloads(input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new(
+            Span::call_site(),
+            "Payloads can only be derived for enums",
+        ));
+    };
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut wrapped_types: Vec<&syn::Type> = Vec::new();
+    let mut conversions = Vec::new();
+    for variant in &data.variants {
+        if variant
+            .attrs
+            .iter()
+            .any(|attr| attr.path().is_ident("no_from"))
+        {
+            continue;
+        }
+        let Fields::Unnamed(fields) = &variant.fields else {
+            continue;
+        };
+        if fields.unnamed.len() != 1 {
+            continue;
+        }
+        let wrapped = &fields.unnamed[0].ty;
+        if wrapped_types.contains(&wrapped) {
+            return Err(Error::new_spanned(
+                variant,
+                "another variant already holds this type, so mark one of them with #[no_from]",
+            ));
+        }
+        wrapped_types.push(wrapped);
+        let variant_name = &variant.ident;
+        conversions.push(quote! {
+            impl #impl_generics ::core::convert::From<#wrapped> for #name #type_generics #where_clause {
+                fn from(payload: #wrapped) -> Self {
+                    Self::#variant_name(payload)
+                }
+            }
+
+            impl #impl_generics ::core::convert::TryFrom<#name #type_generics> for #wrapped #where_clause {
+                type Error = #name #type_generics;
+
+                fn try_from(payload: #name #type_generics) -> ::core::result::Result<Self, Self::Error> {
+                    match payload {
+                        #name::#variant_name(payload) => ::core::result::Result::Ok(payload),
+                        #[allow(unreachable_patterns)]
+                        other => ::core::result::Result::Err(other),
+                    }
+                }
+            }
+        });
+    }
+
+    Ok(quote! {
+        #(#conversions)*
+    })
+}
+
+/// Derive the conversions between a message type and `post_haste::postmaster::AnyPayload`, for a Postmaster whose payloads are of any type rather than one shared enum (tokio only).
+/// A `From` conversion into `AnyPayload` is derived, so that the message can be passed wherever a payload is expected, along with a `TryFrom` conversion back out of it, handing back the payload if it holds a message of another type, which is how a `TypedInbox` unwraps the messages it receives.
+///
+/// # Example
+/// ```rust,ignore
+/// #[derive(Debug, AnyPayload)]
+/// pub enum LightsMessage {
+///     Display,
+/// }
+///
+/// postmaster::send(Addresses::LightsAgent, Addresses::SequencerAgent, LightsMessage::Display).await?;
+/// ```
+#[proc_macro_derive(AnyPayload)]
+pub fn derive_any_payload(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_any_payload(input).into()
+}
+
+fn expand_any_payload(input: DeriveInput) -> proc_macro2::TokenStream {
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::core::convert::From<#name #type_generics> for ::post_haste::postmaster::AnyPayload #where_clause {
+            fn from(message: #name #type_generics) -> Self {
+                Self::new(message)
+            }
+        }
+
+        impl #impl_generics ::core::convert::TryFrom<::post_haste::postmaster::AnyPayload> for #name #type_generics #where_clause {
+            type Error = ::post_haste::postmaster::AnyPayload;
+
+            fn try_from(payload: ::post_haste::postmaster::AnyPayload) -> ::core::result::Result<Self, Self::Error> {
+                payload.downcast()
+            }
+        }
+    }
+}
@@ -0,0 +1,261 @@
+/// Describes how a project's address type maps onto the Postmaster's routing table.
+///
+/// Addresses are either static, meaning they are known at compile time and each has its own slot in the routing table, or dynamic, meaning they are created at runtime with `postmaster::allocate_address()`.
+/// For an enum of addresses this trait is implemented with `#[derive(AddressSpace)]`, which makes every unit variant a static address.
+/// To use dynamic addresses, add a variant holding a `u64` identifier to the address enum and mark it with `#[dynamic]`.
+/// Address enums can also be nested, by marking a variant holding another address enum with `#[nested]`.
+/// Every address in a nested address enum must be static, as each takes a static address of the enum it is nested in, so nesting an address enum with a `#[dynamic]` variant fails to compile.
+/// The trait can also be implemented by hand for address types the derive does not support.
+///
+/// # Example
+/// ```rust
+/// use post_haste::AddressSpace;
+///
+/// #[derive(Debug, Clone, Copy, AddressSpace)]
+/// enum Address {
+///     Listener,
+///     Logger,
+///     #[dynamic]
+///     Client(u64),
+/// }
+///
+/// assert_eq!(Address::COUNT, 2);
+/// assert!(matches!(Address::dynamic(7), Some(Address::Client(7))));
+/// ```
+///
+/// ```rust,compile_fail
+/// use post_haste::AddressSpace;
+///
+/// #[derive(Debug, Clone, Copy, AddressSpace)]
+/// enum Session {
+///     Control,
+///     #[dynamic]
+///     Client(u64),
+/// }
+///
+/// // Fails to compile, as the client addresses can't each be given a static address
+/// #[derive(Debug, Clone, Copy, AddressSpace)]
+/// enum Address {
+///     Listener,
+///     #[nested]
+///     Sessions(Session),
+/// }
+/// ```
+pub trait AddressSpace: Copy {
+    /// The number of static addresses.
+    /// The indexes of static addresses must all be less than this value.
+    const COUNT: usize;
+
+    /// Whether any of the addresses are dynamic, i.e. whether `index()` can return `AddressIndex::Dynamic`.
+    /// This is set by `#[derive(AddressSpace)]` for an enum with a `#[dynamic]` variant, and stops such an enum from being nested in another, or used as the nodes of a `NodeAddress`, where only static addresses are allowed.
+    /// A hand-written implementation with dynamic addresses should set it too, as it defaults to `false`.
+    const HAS_DYNAMIC: bool = false;
+
+    /// Locate the address within the routing table
+    fn index(&self) -> AddressIndex;
+
+    /// Create the dynamic address with the given identifier.
+    /// Returns `None` if the address space does not contain dynamic addresses, which is the default.
+    fn dynamic(_id: u64) -> Option<Self> {
+        None
+    }
+
+    /// The static address with the given index, or `None` if there is no such address.
+    /// This is the inverse of `index()` for static addresses, and is implemented by `#[derive(AddressSpace)]`; the default implementation never finds an address.
+    fn static_address(_index: usize) -> Option<Self> {
+        None
+    }
+}
+
+pub use post_haste_macros::AddressSpace;
+
+/// An address qualified by the node (e.g. the process or machine) which hosts it, for systems federated across several nodes with bridges.
+/// The node type is itself an address space, usually an enum of nodes with `#[derive(AddressSpace)]`, whose variants must all be static.
+/// Every node has the full set of the inner address type's static addresses, so a Postmaster using `NodeAddress` as its address type can route to any Agent on any node.
+/// Agents on the local node are registered at their local addresses as usual, while the addresses of other nodes are routed to the bridge to that node (see `Bridge::remote_node()` with the `remote` feature).
+///
+/// Dynamic addresses are only supported for the inner address type if the node is known, so `dynamic()` (and therefore `allocate_address()`) is not available.
+/// The node type mustn't have any dynamic addresses, which is checked when the address space is first used.
+///
+/// # Example
+/// ```rust
+/// use post_haste::address::{NodeAddress, OnNode};
+/// use post_haste::AddressSpace;
+///
+/// #[derive(Debug, Clone, Copy, PartialEq, AddressSpace)]
+/// enum NodeId {
+///     A,
+///     B,
+/// }
+///
+/// #[derive(Debug, Clone, Copy, PartialEq, AddressSpace)]
+/// enum Address {
+///     Sequencer,
+///     Lights,
+/// }
+///
+/// let lights = Address::Lights.on(NodeId::B);
+/// assert_eq!(lights, NodeAddress { node: NodeId::B, address: Address::Lights });
+/// assert_eq!(NodeAddress::<NodeId, Address>::COUNT, 4);
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeAddress<N, A> {
+    /// The node hosting the address
+    pub node: N,
+    /// The address on that node
+    pub address: A,
+}
+
+impl<N, A> NodeAddress<N, A> {
+    /// Qualify an address with the node hosting it
+    pub const fn new(node: N, address: A) -> Self {
+        Self { node, address }
+    }
+}
+
+impl<N: core::fmt::Debug, A: core::fmt::Debug> core::fmt::Debug for NodeAddress<N, A> {
+    fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(formatter, "{:?}/{:?}", self.node, self.address)
+    }
+}
+
+impl<N: AddressSpace, A: AddressSpace> AddressSpace for NodeAddress<N, A> {
+    const COUNT: usize = {
+        assert!(!N::HAS_DYNAMIC, "Nodes must be static addresses");
+        N::COUNT * A::COUNT
+    };
+    const HAS_DYNAMIC: bool = A::HAS_DYNAMIC;
+
+    fn index(&self) -> AddressIndex {
+        let AddressIndex::Static(node) = self.node.index() else {
+            panic!("Nodes must be static addresses");
+        };
+        match self.address.index() {
+            AddressIndex::Static(index) => AddressIndex::Static(node * A::COUNT + index),
+            AddressIndex::Dynamic(id) => {
+                AddressIndex::Dynamic(id.wrapping_mul(N::COUNT as u64).wrapping_add(node as u64))
+            }
+        }
+    }
+
+    fn static_address(index: usize) -> Option<Self> {
+        if A::COUNT == 0 {
+            return None;
+        }
+        Some(Self {
+            node: N::static_address(index / A::COUNT)?,
+            address: A::static_address(index % A::COUNT)?,
+        })
+    }
+}
+
+/// Qualify addresses with the node hosting them, e.g. `Address::Lights.on(NodeId::B)`
+pub trait OnNode: Sized {
+    /// This address on the given node
+    fn on<N>(self, node: N) -> NodeAddress<N, Self>;
+}
+
+impl<A: AddressSpace> OnNode for A {
+    fn on<N>(self, node: N) -> NodeAddress<N, Self> {
+        NodeAddress::new(node, self)
+    }
+}
+
+/// The location of an address within the Postmaster's routing table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AddressIndex {
+    /// A static address, with its index in the range `0..AddressSpace::COUNT`
+    Static(usize),
+    /// A dynamic address, with its identifier
+    Dynamic(u64),
+}
+
+/// Storage for a value per address, as used by the Postmaster for message queues and other per-Agent state.
+/// Static addresses are held in a table with one slot per address, while dynamic addresses are held in a map.
+#[doc(hidden)]
+#[cfg(not(target_os = "none"))]
+#[derive(Clone)]
+pub struct RoutingTable<T> {
+    static_routes: Vec<Option<T>>,
+    dynamic_routes: std::collections::BTreeMap<u64, T>,
+}
+
+#[cfg(not(target_os = "none"))]
+impl<T> RoutingTable<T> {
+    pub fn new(static_count: usize) -> Self {
+        Self {
+            static_routes: (0..static_count).map(|_| None).collect(),
+            dynamic_routes: std::collections::BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&self, index: AddressIndex) -> Option<&T> {
+        match index {
+            AddressIndex::Static(index) => self.static_routes.get(index)?.as_ref(),
+            AddressIndex::Dynamic(id) => self.dynamic_routes.get(&id),
+        }
+    }
+
+    pub fn get_mut(&mut self, index: AddressIndex) -> Option<&mut T> {
+        match index {
+            AddressIndex::Static(index) => self.static_routes.get_mut(index)?.as_mut(),
+            AddressIndex::Dynamic(id) => self.dynamic_routes.get_mut(&id),
+        }
+    }
+
+    pub fn contains(&self, index: AddressIndex) -> bool {
+        self.get(index).is_some()
+    }
+
+    /// Insert a value at a vacant address, handing the value back if the address is occupied or out of range
+    pub fn insert(&mut self, index: AddressIndex, value: T) -> Result<(), T> {
+        match index {
+            AddressIndex::Static(index) => match self.static_routes.get_mut(index) {
+                Some(route @ None) => {
+                    route.replace(value);
+                    Ok(())
+                }
+                _ => Err(value),
+            },
+            AddressIndex::Dynamic(id) => match self.dynamic_routes.entry(id) {
+                std::collections::btree_map::Entry::Vacant(entry) => {
+                    entry.insert(value);
+                    Ok(())
+                }
+                std::collections::btree_map::Entry::Occupied(_) => Err(value),
+            },
+        }
+    }
+
+    /// Get the value at an address, inserting one if the address is vacant.
+    /// Returns `None` if the address is out of range.
+    pub fn get_or_insert_with(
+        &mut self,
+        index: AddressIndex,
+        value: impl FnOnce() -> T,
+    ) -> Option<&mut T> {
+        match index {
+            AddressIndex::Static(index) => {
+                Some(self.static_routes.get_mut(index)?.get_or_insert_with(value))
+            }
+            AddressIndex::Dynamic(id) => Some(self.dynamic_routes.entry(id).or_insert_with(value)),
+        }
+    }
+
+    /// Iterate over the values at every occupied address, with static addresses first
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.static_routes
+            .iter()
+            .flatten()
+            .chain(self.dynamic_routes.values())
+    }
+
+    pub fn take(&mut self, index: AddressIndex) -> Option<T> {
+        match index {
+            AddressIndex::Static(index) => self.static_routes.get_mut(index)?.take(),
+            AddressIndex::Dynamic(id) => self.dynamic_routes.remove(&id),
+        }
+    }
+}
@@ -1,12 +1,544 @@
+pub mod fsm;
+#[cfg(not(target_os = "none"))]
+pub mod lazy;
+#[cfg(all(feature = "persistence", not(target_os = "none")))]
+pub mod persistent;
+#[cfg(not(target_os = "none"))]
+pub mod snapshot;
+pub mod typed;
+
+pub use post_haste_macros::message_handlers;
+
+#[cfg(not(target_os = "none"))]
+use core::any::Any;
+#[cfg(not(target_os = "none"))]
+use core::pin::Pin;
+#[cfg(not(target_os = "none"))]
+use core::sync::atomic::Ordering;
+#[cfg(not(target_os = "none"))]
+use core::task::{Context, Poll};
 #[cfg(target_os = "none")]
 use embassy_sync::channel::DynamicReceiver as Receiver;
+#[cfg(target_os = "none")]
+use embassy_time::{Duration, WithTimeout};
+#[cfg(not(target_os = "none"))]
+use futures_core::Stream;
+#[cfg(not(target_os = "none"))]
+use portable_atomic::AtomicU64;
+#[cfg(not(target_os = "none"))]
+use std::collections::VecDeque;
+#[cfg(not(target_os = "none"))]
+use std::sync::Arc;
 #[cfg(not(target_os = "none"))]
-use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+#[cfg(not(target_os = "none"))]
+use tokio::sync::{oneshot, watch};
+#[cfg(not(target_os = "none"))]
+use tokio::time::{Duration, Instant};
+
+#[cfg(not(target_os = "none"))]
+use crate::PostmasterError;
+#[cfg(not(target_os = "none"))]
+use snapshot::Snapshot;
 
 #[cfg(target_os = "none")]
 pub type Inbox<T> = Receiver<'static, T>;
+
+/// The inbox of an Agent, from which it receives the messages sent to it.
+/// Alongside the Agent's (bounded) message queue, the inbox has a control queue, which is unbounded and always received from first.
+/// The Postmaster uses the control queue for system messages, such as the notifications sent to watchers, and senders can use it for urgent commands (e.g. telling an Agent to stop) by sending with `as_control()`.
+/// This means an Agent can always be reached, however far behind it is with its regular messages.
+/// The inbox also answers the pings sent by `postmaster::ping()` whenever the Agent receives from it, so that an Agent which is still receiving messages is known to be alive.
+/// New configs sent by `postmaster::reconfigure()` skip the queues in the same way, and are applied by an Agent which receives with `recv_reconfiguring()` (see `Reconfigure`).
+///
+/// While the Agent is paused with `postmaster::pause()`, only control messages are received, and regular messages wait on the message queue until the Agent is resumed.
+///
+/// An inbox can also be made from a standalone tokio receiver with `Inbox::from()`, in which case it has no control queue and can't be paused.
+///
+/// The inbox is also a `Stream` of its messages, receiving them in the same way as `recv()`, so that it can be used with `StreamExt` combinators or merged with other streams (e.g. a socket's).
+#[cfg(not(target_os = "none"))]
+pub struct Inbox<T> {
+    control: UnboundedReceiver<T>,
+    messages: Receiver<T>,
+    paused: watch::Receiver<bool>,
+    dequeued: Arc<AtomicU64>,
+    pings: UnboundedReceiver<Ping>,
+    reconfigurations: UnboundedReceiver<Reconfiguration>,
+    /// Messages which have already been taken from the queues, and are the next to be received, in order (e.g. the message which woke a lazily spawned Agent, or those passed over by `recv_matching()`)
+    next: VecDeque<T>,
+    /// For an Agent spawned with `spawn_agent_lazy!()`, how long it may wait for a message before it is torn down, and where its inbox is handed back to when it is
+    idle: Option<(Duration, oneshot::Sender<Inbox<T>>)>,
+    /// Where the inbox's queues are kept when it is dropped, for an Agent whose restart policy preserves its mailbox
+    salvage: Option<Salvage<T>>,
+    /// The state captured by `recv_passivating()` when a lazily spawned Agent was last torn down, for its next instance to restore
+    snapshot: Option<Vec<u8>>,
+    /// While the inbox is polled as a `Stream`: the wait for the Agent to be paused or resumed, and for a lazily spawned Agent to be idle for too long
+    polling: Polling,
+}
+
+/// The waits which wake an inbox polled as a `Stream`, which are kept between polls so that they stay registered
+#[cfg(not(target_os = "none"))]
+#[derive(Default)]
+struct Polling {
+    paused_changed: Option<Pin<Box<dyn Future<Output = ()> + Send + Sync>>>,
+    idle: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+/// A ping sent to an Agent by `postmaster::ping()`, answered by its inbox
+#[cfg(not(target_os = "none"))]
+pub(crate) type Ping = oneshot::Sender<()>;
+
+/// A new config sent to an Agent by `postmaster::reconfigure()`, along with where to report whether the Agent took it
+#[cfg(not(target_os = "none"))]
+pub(crate) type Reconfiguration = (
+    Box<dyn Any + Send>,
+    oneshot::Sender<Result<(), PostmasterError>>,
+);
+
+/// What the inbox received: either a message, or a new config for the Agent, unless a lazily spawned Agent was left waiting for too long
 #[cfg(not(target_os = "none"))]
-pub type Inbox<T> = Receiver<T>;
+enum Received<T> {
+    Message(Option<T>),
+    Reconfiguration(Reconfiguration),
+    Idle,
+}
+
+#[cfg(not(target_os = "none"))]
+impl<T> Inbox<T> {
+    /// Receive the next message, preferring any waiting on the control queue, and waiting for one to arrive if none are waiting.
+    /// While the Agent is paused, only control messages are received.
+    /// Returns `None` once the message queue has been closed and no control messages are waiting.
+    /// Any new configs sent with `postmaster::reconfigure()` are refused, as the Agent has no way to take them (see `recv_reconfiguring()`).
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            match self.receive().await {
+                Received::Message(message) => return message,
+                Received::Reconfiguration((_, taken)) => {
+                    let _ = taken.send(Err(PostmasterError::NotReconfigurable));
+                }
+                Received::Idle => self.park().await,
+            }
+        }
+    }
+
+    /// Receive the next message as with `recv()`, for a lazily spawned Agent whose state should outlive it being torn down while idle.
+    /// If the Agent is torn down while waiting here, its snapshot is kept along with its message queue, and the next instance of the Agent (created when the next message arrives) restores it with `take_snapshot()`.
+    /// See `post_haste::agent::lazy` for details.
+    pub async fn recv_passivating(&mut self, agent: &impl Snapshot) -> Option<T> {
+        loop {
+            match self.receive().await {
+                Received::Message(message) => return message,
+                Received::Reconfiguration((_, taken)) => {
+                    let _ = taken.send(Err(PostmasterError::NotReconfigurable));
+                }
+                Received::Idle => {
+                    self.snapshot = Some(agent.snapshot());
+                    self.park().await
+                }
+            }
+        }
+    }
+
+    /// Take the snapshot captured by `recv_passivating()` when the previous instance of a lazily spawned Agent was torn down, if any, so that the new instance can restore its state before handling its first message
+    pub fn take_snapshot(&mut self) -> Option<Vec<u8>> {
+        self.snapshot.take()
+    }
+
+    /// Receive the next message as with `recv()`, applying any new configs sent with `postmaster::reconfigure()` to the Agent in the meantime.
+    /// New configs skip the queues, so they are applied before any waiting messages (even while the Agent is paused), and the sender is told once `on_reconfigure()` has returned.
+    /// A config of a different type to the Agent's is refused.
+    pub async fn recv_reconfiguring<G>(&mut self, agent: &mut G) -> Option<T>
+    where
+        G: Reconfigure,
+        G::Config: 'static,
+    {
+        loop {
+            match self.receive().await {
+                Received::Message(message) => return message,
+                Received::Reconfiguration((config, taken)) => match config.downcast() {
+                    Ok(config) => {
+                        agent.on_reconfigure(*config).await;
+                        let _ = taken.send(Ok(()));
+                    }
+                    Err(_) => {
+                        let _ = taken.send(Err(PostmasterError::NotReconfigurable));
+                    }
+                },
+                Received::Idle => self.park().await,
+            }
+        }
+    }
+
+    /// Receive the first message which matches the predicate, waiting for one to arrive if none are waiting, as with Erlang's selective receive.
+    /// The messages passed over on the way are kept, in order, and are received by the inbox's other methods (or a later call to `recv_matching()`) before anything else, so that e.g. a handler can wait for the reply to a request without losing the messages which arrive in the meantime.
+    /// Control messages are checked first, as with `recv()`, and while the Agent is paused only control messages (and those already passed over) are checked.
+    /// Returns `None` once the message queue has been closed and none of the waiting messages match.
+    ///
+    /// Every message passed over is held by the inbox until it is received, so waiting for a message which never comes (while others keep arriving) grows the inbox without limit; wrap the call in `tokio::time::timeout()` to give up after a while.
+    /// Giving up loses nothing, as the messages passed over so far are kept.
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// postmaster::message(Address::Database, address, Payloads::Query(query)).reply_to(address).send().await.unwrap();
+    /// let reply = inbox.recv_matching(|message| matches!(message.payload, Payloads::QueryResult(_))).await;
+    /// ```
+    pub async fn recv_matching(&mut self, mut predicate: impl FnMut(&T) -> bool) -> Option<T> {
+        if let Some(position) = self.next.iter().position(&mut predicate) {
+            return self.next.remove(position);
+        }
+        loop {
+            match self.receive_queued().await {
+                Received::Message(Some(message)) if predicate(&message) => return Some(message),
+                Received::Message(Some(message)) => self.next.push_back(message),
+                Received::Message(None) => return None,
+                Received::Reconfiguration((_, taken)) => {
+                    let _ = taken.send(Err(PostmasterError::NotReconfigurable));
+                }
+                // The Agent is part way through handling a message, so it can't be torn down here
+                Received::Idle => (),
+            }
+        }
+    }
+
+    /// Wait for the next message, or a new config for the Agent
+    async fn receive(&mut self) -> Received<T> {
+        match self.next.pop_front() {
+            Some(message) => Received::Message(Some(message)),
+            None => self.receive_queued().await,
+        }
+    }
+
+    /// Wait for the next message to arrive on the queues, or a new config for the Agent
+    async fn receive_queued(&mut self) -> Received<T> {
+        let idle_after = self.idle.as_ref().map(|(idle_after, _)| *idle_after);
+        let mut idle = core::pin::pin!(async move {
+            match idle_after {
+                Some(idle_after) => tokio::time::sleep(idle_after).await,
+                None => core::future::pending().await,
+            }
+        });
+        loop {
+            let paused = self.is_paused();
+            // Pausing or resuming wakes the inbox, so that it starts or stops receiving regular messages straight away
+            tokio::select! {
+                biased;
+                Some(ping) = self.pings.recv() => {
+                    let _ = ping.send(());
+                }
+                Some(reconfiguration) = self.reconfigurations.recv() => return Received::Reconfiguration(reconfiguration),
+                Some(message) = self.control.recv() => return Received::Message(Some(self.dequeued(message))),
+                Ok(()) = self.paused.changed() => (),
+                message = self.messages.recv(), if !paused => return Received::Message(message.map(|message| self.dequeued(message))),
+                () = &mut idle => return Received::Idle,
+            }
+        }
+    }
+
+    /// Hand the inbox back to the task running a lazily spawned Agent which has been idle for too long, then wait for the Agent to be torn down.
+    /// The Agent is waiting here rather than handling a message, so it can be dropped safely, and any messages which arrive in the meantime stay on the queues handed back.
+    async fn park(&mut self) -> ! {
+        if let Some((_, parked)) = self.idle.take() {
+            let _ = parked.send(self.take_queues());
+        }
+        core::future::pending().await
+    }
+
+    /// Move the queues (and the message kept for the Agent, if any) into a new inbox, leaving this one with closed queues
+    fn take_queues(&mut self) -> Inbox<T> {
+        let (_, control) = mpsc::unbounded_channel();
+        let (_, messages) = mpsc::channel(1);
+        let (_, paused) = watch::channel(false);
+        let (_, pings) = mpsc::unbounded_channel();
+        let (_, reconfigurations) = mpsc::unbounded_channel();
+        Inbox {
+            control: core::mem::replace(&mut self.control, control),
+            messages: core::mem::replace(&mut self.messages, messages),
+            paused: core::mem::replace(&mut self.paused, paused),
+            dequeued: self.dequeued.clone(),
+            pings: core::mem::replace(&mut self.pings, pings),
+            reconfigurations: core::mem::replace(&mut self.reconfigurations, reconfigurations),
+            next: core::mem::take(&mut self.next),
+            idle: None,
+            salvage: self.salvage.take(),
+            snapshot: self.snapshot.take(),
+            polling: Polling::default(),
+        }
+    }
+
+    /// Receive a message if one is waiting (preferring the control queue), without waiting for one to arrive.
+    /// While the Agent is paused, only control messages are received.
+    pub fn try_recv(&mut self) -> Result<T, mpsc::error::TryRecvError> {
+        if let Some(message) = self.next.pop_front() {
+            return Ok(message);
+        }
+        while let Ok(ping) = self.pings.try_recv() {
+            let _ = ping.send(());
+        }
+        // Only `recv_reconfiguring()` can apply a new config
+        while let Ok((_, taken)) = self.reconfigurations.try_recv() {
+            let _ = taken.send(Err(PostmasterError::NotReconfigurable));
+        }
+        match self.control.try_recv() {
+            Ok(message) => Ok(message),
+            Err(_) if self.is_paused() => Err(mpsc::error::TryRecvError::Empty),
+            Err(_) => self.messages.try_recv(),
+        }
+        .map(|message| self.dequeued(message))
+    }
+
+    /// Count a message as received, so that the Postmaster's watchdog can tell that the Agent is making progress
+    fn dequeued(&self, message: T) -> T {
+        self.dequeued.fetch_add(1, Ordering::Relaxed);
+        message
+    }
+
+    /// Whether regular messages are being held back, because the Agent has been paused with `postmaster::pause()`.
+    /// An Agent which is no longer registered with the Postmaster can't be resumed, so isn't treated as paused.
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow() && self.paused.has_changed().is_ok()
+    }
+
+    /// The number of messages waiting to be received, on both the control queue and the message queue.
+    /// This includes regular messages held back while the Agent is paused, so an Agent can tell how far behind it is (e.g. to switch to a cheaper way of handling messages under load).
+    pub fn len(&self) -> usize {
+        self.next.len() + self.control.len() + self.messages.len()
+    }
+
+    /// Whether no messages are waiting to be received
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Look at the next message to be received, if one is waiting, without receiving it or waiting for one to arrive.
+    /// The message is taken from its queue (preferring the control queue, and only taking control messages while the Agent is paused), and kept as the next message to be received by any of the inbox's methods.
+    pub fn peek(&mut self) -> Option<&T> {
+        if self.next.is_empty() {
+            let message = match self.control.try_recv() {
+                Ok(message) => message,
+                Err(_) if self.is_paused() => return None,
+                Err(_) => self.messages.try_recv().ok()?,
+            };
+            let message = self.dequeued(message);
+            self.next.push_back(message);
+        }
+        self.next.front()
+    }
+
+    /// Wait for at least one message, then receive up to `limit` messages in total, adding them to the buffer and returning the number received.
+    /// Control messages are received before any regular messages.
+    /// Returns 0 if `limit` is 0, or if the inbox has been closed.
+    pub async fn recv_many(&mut self, buffer: &mut Vec<T>, limit: usize) -> usize {
+        InboxExt::recv_many(self, buffer, limit).await
+    }
+
+    /// Close the inbox, so that no further messages can be sent to it, while still allowing the messages already waiting to be received
+    pub fn close(&mut self) {
+        self.control.close();
+        self.messages.close();
+    }
+
+    /// Turn the inbox into a `Stream` of its messages, e.g. to hand it to code which takes any stream.
+    /// The inbox is a stream itself, so this only hides its other methods.
+    pub fn into_stream(self) -> impl Stream<Item = T> + Unpin
+    where
+        T: 'static,
+    {
+        self
+    }
+
+    /// Poll for the next message, in the same way as `recv()` waits for it
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        if let Some(message) = self.next.pop_front() {
+            return Poll::Ready(Some(message));
+        }
+        while let Poll::Ready(Some(ping)) = self.pings.poll_recv(cx) {
+            let _ = ping.send(());
+        }
+        // Only `recv_reconfiguring()` can apply a new config
+        while let Poll::Ready(Some((_, taken))) = self.reconfigurations.poll_recv(cx) {
+            let _ = taken.send(Err(PostmasterError::NotReconfigurable));
+        }
+        if let Poll::Ready(Some(message)) = self.control.poll_recv(cx) {
+            return self.polled(message);
+        }
+        // Pausing or resuming wakes the stream, so that it starts or stops receiving regular messages straight away
+        let paused_changed = self.polling.paused_changed.get_or_insert_with(|| {
+            let mut paused = self.paused.clone();
+            Box::pin(async move {
+                loop {
+                    // Whether the Agent is paused is read afresh below, so only later changes need to wake the stream
+                    paused.mark_unchanged();
+                    if paused.changed().await.is_err() {
+                        core::future::pending::<()>().await
+                    }
+                }
+            })
+        });
+        let _ = paused_changed.as_mut().poll(cx);
+        if !self.is_paused()
+            && let Poll::Ready(message) = self.messages.poll_recv(cx)
+        {
+            return match message {
+                Some(message) => self.polled(message),
+                None => Poll::Ready(None),
+            };
+        }
+        if let Some((idle_after, _)) = &self.idle {
+            let idle_after = *idle_after;
+            let idle = self
+                .polling
+                .idle
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep(idle_after)));
+            if idle.as_mut().poll(cx).is_ready()
+                && let Some((_, parked)) = self.idle.take()
+            {
+                // As with `park()`, the Agent is waiting for a message rather than handling one, so the queues can be handed back and the Agent left waiting until it is torn down
+                let _ = parked.send(self.take_queues());
+            }
+        }
+        Poll::Pending
+    }
+
+    /// Receive a message polled from one of the queues, restarting the wait for a lazily spawned Agent to become idle
+    fn polled(&mut self, message: T) -> Poll<Option<T>> {
+        self.polling.idle = None;
+        Poll::Ready(Some(self.dequeued(message)))
+    }
+}
+
+// The inbox never pins the messages it holds, so it can be moved while it is being polled whatever the message type
+#[cfg(not(target_os = "none"))]
+impl<T> Unpin for Inbox<T> {}
+
+#[cfg(not(target_os = "none"))]
+impl<T> Stream for Inbox<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.get_mut().poll_recv(cx)
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl<T> Drop for Inbox<T> {
+    fn drop(&mut self) {
+        if let Some(salvage) = self.salvage.take() {
+            let queues = self.take_queues();
+            // The inbox may be dropped while its Agent is panicking, so a poisoned lock is taken over rather than panicking again
+            *salvage
+                .0
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(queues);
+        }
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl<T> From<Receiver<T>> for Inbox<T> {
+    fn from(messages: Receiver<T>) -> Self {
+        // Without senders, the control queue is closed from the outset and is never received from, and the inbox is never paused
+        let (_, control) = mpsc::unbounded_channel();
+        let (_, paused) = watch::channel(false);
+        let (_, pings) = mpsc::unbounded_channel();
+        let (_, reconfigurations) = mpsc::unbounded_channel();
+        Self {
+            control,
+            messages,
+            paused,
+            dequeued: Arc::default(),
+            pings,
+            reconfigurations,
+            next: VecDeque::new(),
+            idle: None,
+            salvage: None,
+            snapshot: None,
+            polling: Polling::default(),
+        }
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl<T> core::fmt::Debug for Inbox<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Inbox").finish_non_exhaustive()
+    }
+}
+
+/// The sending side of an Agent's inbox, as registered with the Postmaster by `spawn_agent!()`
+#[doc(hidden)]
+#[cfg(not(target_os = "none"))]
+pub struct Mailbox<T> {
+    pub(crate) messages: Sender<T>,
+    pub(crate) control: Option<UnboundedSender<T>>,
+    pub(crate) paused: Option<watch::Sender<bool>>,
+    /// The number of messages the Agent has received from its inbox
+    pub(crate) dequeued: Option<Arc<AtomicU64>>,
+    pub(crate) pings: Option<UnboundedSender<Ping>>,
+    pub(crate) reconfigurations: Option<UnboundedSender<Reconfiguration>>,
+}
+
+#[cfg(not(target_os = "none"))]
+impl<T> Clone for Mailbox<T> {
+    fn clone(&self) -> Self {
+        Self {
+            messages: self.messages.clone(),
+            control: self.control.clone(),
+            paused: self.paused.clone(),
+            dequeued: self.dequeued.clone(),
+            pings: self.pings.clone(),
+            reconfigurations: self.reconfigurations.clone(),
+        }
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl<T> From<Sender<T>> for Mailbox<T> {
+    fn from(messages: Sender<T>) -> Self {
+        Self {
+            messages,
+            control: None,
+            paused: None,
+            dequeued: None,
+            pings: None,
+            reconfigurations: None,
+        }
+    }
+}
+
+/// Create an Agent's inbox, with a message queue of the given size, along with the mailbox to register with the Postmaster
+#[doc(hidden)]
+#[cfg(not(target_os = "none"))]
+pub fn inbox<T>(queue_size: usize) -> (Mailbox<T>, Inbox<T>) {
+    let (messages, message_receiver) = mpsc::channel(queue_size);
+    let (control, control_receiver) = mpsc::unbounded_channel();
+    let (paused, paused_receiver) = watch::channel(false);
+    let dequeued = Arc::new(AtomicU64::new(0));
+    let (pings, ping_receiver) = mpsc::unbounded_channel();
+    let (reconfigurations, reconfiguration_receiver) = mpsc::unbounded_channel();
+    (
+        Mailbox {
+            messages,
+            control: Some(control),
+            paused: Some(paused),
+            dequeued: Some(dequeued.clone()),
+            pings: Some(pings),
+            reconfigurations: Some(reconfigurations),
+        },
+        Inbox {
+            control: control_receiver,
+            messages: message_receiver,
+            paused: paused_receiver,
+            dequeued,
+            pings: ping_receiver,
+            reconfigurations: reconfiguration_receiver,
+            next: VecDeque::new(),
+            idle: None,
+            salvage: None,
+            snapshot: None,
+            polling: Polling::default(),
+        },
+    )
+}
 
 #[allow(async_fn_in_trait)]
 pub trait Agent {
@@ -18,3 +550,747 @@ pub trait Agent {
 
     async fn run(self, inbox: Inbox<Self::Message>) -> !;
 }
+
+/// An Agent which can take a new config while it is running, e.g. to change a threshold without being restarted.
+/// New configs are sent with `postmaster::reconfigure()`, and applied by the Agent's inbox when the Agent receives with `Inbox::recv_reconfiguring()`.
+/// An Agent restarted after a panic is created from the config it was registered with, rather than the last config it was sent.
+///
+/// # Example
+/// ```rust,ignore
+/// impl Reconfigure for SensorAgent {
+///     async fn on_reconfigure(&mut self, config: SensorConfig) {
+///         self.threshold = config.threshold;
+///     }
+/// }
+///
+/// // In the Agent's main loop
+/// while let Some(message) = inbox.recv_reconfiguring(&mut self).await {
+///     // Handle the message...
+/// }
+///
+/// // Elsewhere
+/// postmaster::reconfigure(Address::Sensor, SensorConfig { threshold: 40 }).await?;
+/// ```
+#[cfg(not(target_os = "none"))]
+#[allow(async_fn_in_trait)]
+pub trait Reconfigure: Agent {
+    /// Apply a new config in place of the one the Agent was created with
+    async fn on_reconfigure(&mut self, config: Self::Config);
+}
+
+/// Additional ways for an Agent to receive from its inbox, for Agents which interleave checking their inbox with other work (e.g. polling external I/O).
+/// Import the trait to use its methods, e.g. `use post_haste::agent::InboxExt;`.
+///
+/// # Example
+/// ```rust,ignore
+/// async fn run(self, mut inbox: post_haste::agent::Inbox<Self::Message>) -> ! {
+///     loop {
+///         if let Some(message) = inbox.recv_timeout(Duration::from_millis(10)).await {
+///             // Handle the message...
+///         }
+///         // Poll the hardware...
+///     }
+/// }
+/// ```
+#[allow(async_fn_in_trait)]
+pub trait InboxExt<T> {
+    /// The error returned by `try_recv()` when no message could be received
+    type TryRecvError;
+
+    /// Receive a message if one is waiting, without waiting for one to arrive.
+    /// With tokio this is the inbox's own `try_recv()`, so the same code (e.g. `if let Ok(message) = inbox.try_recv()`) works on both targets.
+    fn try_recv(&mut self) -> Result<T, Self::TryRecvError>;
+
+    /// Wait for a message for a limited time, returning `None` if no message arrives before the timeout expires.
+    /// With tokio, `None` is also returned if the inbox has been closed.
+    async fn recv_timeout(&mut self, timeout: Duration) -> Option<T>;
+
+    /// Wait for at least one message, then receive up to `limit` messages in total, adding them to the buffer and returning the number received.
+    /// Draining a burst of messages in one go avoids waking the Agent for every message, which matters for Agents handling many messages per second.
+    /// Returns 0 if `limit` is 0, or (with tokio) if the inbox has been closed.
+    ///
+    /// This mirrors tokio's own `Receiver::recv_many()`, but accepts any buffer implementing `Extend`, e.g. a `heapless::Vec` on bare metal targets.
+    /// With tokio, `inbox.recv_many()` calls the inbox's own method, which takes a `Vec`; other buffers can be used by calling `InboxExt::recv_many(&mut inbox, ..)`.
+    async fn recv_many(&mut self, buffer: &mut impl Extend<T>, limit: usize) -> usize;
+}
+
+#[cfg(target_os = "none")]
+impl<T> InboxExt<T> for Inbox<T> {
+    type TryRecvError = embassy_sync::channel::TryReceiveError;
+
+    fn try_recv(&mut self) -> Result<T, Self::TryRecvError> {
+        self.try_receive()
+    }
+
+    async fn recv_timeout(&mut self, timeout: Duration) -> Option<T> {
+        self.receive().with_timeout(timeout).await.ok()
+    }
+
+    async fn recv_many(&mut self, buffer: &mut impl Extend<T>, limit: usize) -> usize {
+        if limit == 0 {
+            return 0;
+        }
+        buffer.extend([self.receive().await]);
+        let mut received = 1;
+        while received < limit {
+            match self.try_receive() {
+                Ok(message) => buffer.extend([message]),
+                Err(_) => break,
+            }
+            received += 1;
+        }
+        received
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl<T> InboxExt<T> for Inbox<T> {
+    type TryRecvError = tokio::sync::mpsc::error::TryRecvError;
+
+    fn try_recv(&mut self) -> Result<T, Self::TryRecvError> {
+        Inbox::try_recv(self)
+    }
+
+    async fn recv_timeout(&mut self, timeout: Duration) -> Option<T> {
+        tokio::time::timeout(timeout, self.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn recv_many(&mut self, buffer: &mut impl Extend<T>, limit: usize) -> usize {
+        if limit == 0 {
+            return 0;
+        }
+        let Some(message) = self.recv().await else {
+            return 0;
+        };
+        buffer.extend([message]);
+        let mut received = 1;
+        while received < limit {
+            match Inbox::try_recv(self) {
+                Ok(message) => buffer.extend([message]),
+                Err(_) => break,
+            }
+            received += 1;
+        }
+        received
+    }
+}
+
+/// A standalone tokio receiver (e.g. one registered with `postmaster::register()`) can be received from in the same ways as an Agent's inbox
+#[cfg(not(target_os = "none"))]
+impl<T> InboxExt<T> for Receiver<T> {
+    type TryRecvError = mpsc::error::TryRecvError;
+
+    fn try_recv(&mut self) -> Result<T, Self::TryRecvError> {
+        Receiver::try_recv(self)
+    }
+
+    async fn recv_timeout(&mut self, timeout: Duration) -> Option<T> {
+        tokio::time::timeout(timeout, self.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn recv_many(&mut self, buffer: &mut impl Extend<T>, limit: usize) -> usize {
+        if limit == 0 {
+            return 0;
+        }
+        let Some(message) = self.recv().await else {
+            return 0;
+        };
+        buffer.extend([message]);
+        let mut received = 1;
+        while received < limit {
+            match Receiver::try_recv(self) {
+                Ok(message) => buffer.extend([message]),
+                Err(_) => break,
+            }
+            received += 1;
+        }
+        received
+    }
+}
+
+/// An inbox which lets an Agent set aside messages it isn't ready to handle yet, and handle them later in the order they arrived.
+/// For example, an Agent which is still initialising can stash the commands it receives, then unstash them once it is ready.
+/// Stashed messages are held in memory, so this is only available with tokio.
+///
+/// # Example
+/// ```rust,ignore
+/// async fn run(self, inbox: post_haste::agent::Inbox<Self::Message>) -> ! {
+///     let mut inbox = StashingInbox::new(inbox);
+///     loop {
+///         let message = inbox.recv().await.unwrap();
+///         match message.payload {
+///             Payloads::Ready => {
+///                 self.ready = true;
+///                 inbox.unstash_all();
+///             }
+///             _ if !self.ready => inbox.stash(message),
+///             _ => self.handle(message).await,
+///         }
+///     }
+/// }
+/// ```
+#[cfg(not(target_os = "none"))]
+pub struct StashingInbox<T> {
+    inbox: Inbox<T>,
+    stash: std::collections::VecDeque<T>,
+    unstashed: std::collections::VecDeque<T>,
+}
+
+#[cfg(not(target_os = "none"))]
+impl<T> StashingInbox<T> {
+    /// Wrap an Agent's inbox (or a standalone receiver)
+    pub fn new(inbox: impl Into<Inbox<T>>) -> Self {
+        Self {
+            inbox: inbox.into(),
+            stash: std::collections::VecDeque::new(),
+            unstashed: std::collections::VecDeque::new(),
+        }
+    }
+
+    /// Set a message aside, to be received again after `unstash_all()` is called
+    pub fn stash(&mut self, message: T) {
+        self.stash.push_back(message);
+    }
+
+    /// Return all of the stashed messages to the inbox.
+    /// They are received (in the order they were stashed) before any other messages, including any which were unstashed earlier and have not yet been received.
+    pub fn unstash_all(&mut self) {
+        while let Some(message) = self.stash.pop_back() {
+            self.unstashed.push_front(message);
+        }
+    }
+
+    /// The number of messages currently stashed
+    pub fn stashed(&self) -> usize {
+        self.stash.len()
+    }
+
+    /// Receive the next message, waiting for one to arrive if none have been unstashed.
+    /// Returns `None` if the inbox has been closed and there are no unstashed messages left.
+    pub async fn recv(&mut self) -> Option<T> {
+        match self.unstashed.pop_front() {
+            Some(message) => Some(message),
+            None => self.inbox.recv().await,
+        }
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl<T> InboxExt<T> for StashingInbox<T> {
+    type TryRecvError = tokio::sync::mpsc::error::TryRecvError;
+
+    fn try_recv(&mut self) -> Result<T, Self::TryRecvError> {
+        match self.unstashed.pop_front() {
+            Some(message) => Ok(message),
+            None => self.inbox.try_recv(),
+        }
+    }
+
+    async fn recv_timeout(&mut self, timeout: Duration) -> Option<T> {
+        match self.unstashed.pop_front() {
+            Some(message) => Some(message),
+            None => self.inbox.recv_timeout(timeout).await,
+        }
+    }
+
+    async fn recv_many(&mut self, buffer: &mut impl Extend<T>, limit: usize) -> usize {
+        let unstashed = limit.min(self.unstashed.len());
+        buffer.extend(self.unstashed.drain(..unstashed));
+        if unstashed > 0 || limit == 0 {
+            return unstashed;
+        }
+        InboxExt::recv_many(&mut self.inbox, buffer, limit).await
+    }
+}
+
+/// How a `CoalescingInbox` holds back the messages matched by one of its rules
+#[cfg(not(target_os = "none"))]
+#[derive(Debug, Clone, Copy)]
+enum Coalescing {
+    /// Deliver the latest message once no more have arrived for the given period
+    Debounce(Duration),
+    /// Deliver at most one message per interval, keeping the latest
+    Throttle(Duration),
+}
+
+/// A kind of message which a `CoalescingInbox` holds back, along with the latest message of that kind
+#[cfg(not(target_os = "none"))]
+struct CoalescingRule<T> {
+    matches: Box<dyn Fn(&T) -> bool + Send>,
+    coalescing: Coalescing,
+    /// The latest matching message which is being held back, and when it is due to be delivered
+    pending: Option<(Instant, T)>,
+    /// When a message matched by a throttling rule was last delivered
+    last_delivered: Option<Instant>,
+}
+
+/// An inbox which coalesces bursts of messages of the same kind, so that an Agent only handles the latest of them rather than every one.
+/// Each kind of message is picked out by a predicate, and is either debounced (only the latest message is delivered, once no more have arrived for a quiet period) or throttled (at most one message is delivered per interval, and any which arrive in between are replaced by the latest).
+/// For example, a display Agent which is sent readings hundreds of times a second can throttle them to the rate at which it redraws.
+///
+/// Messages which match none of the predicates are received as usual, and a message is coalesced by the first rule it matches.
+/// Because messages are held back, a coalesced message may be received after messages which were sent later.
+/// Messages are held in memory, so this is only available with tokio.
+///
+/// # Example
+/// ```rust,ignore
+/// async fn run(self, inbox: post_haste::agent::Inbox<Self::Message>) -> ! {
+///     let mut inbox = CoalescingInbox::new(inbox)
+///         // Redraw at most 20 times a second, showing the latest reading
+///         .throttle(|message: &Message| matches!(message.payload, Payloads::Reading(_)), Duration::from_millis(50))
+///         // Only apply a new brightness once the user has stopped turning the knob
+///         .debounce(|message: &Message| matches!(message.payload, Payloads::Brightness(_)), Duration::from_millis(200));
+///     loop {
+///         let message = inbox.recv().await.unwrap();
+///         // Handle the message...
+///     }
+/// }
+/// ```
+#[cfg(not(target_os = "none"))]
+pub struct CoalescingInbox<T> {
+    inbox: Inbox<T>,
+    rules: Vec<CoalescingRule<T>>,
+}
+
+#[cfg(not(target_os = "none"))]
+impl<T> CoalescingInbox<T> {
+    /// Wrap an Agent's inbox (or a standalone receiver), initially without coalescing any messages
+    pub fn new(inbox: impl Into<Inbox<T>>) -> Self {
+        Self {
+            inbox: inbox.into(),
+            rules: Vec::new(),
+        }
+    }
+
+    /// Debounce the messages matching the predicate: each one is held back until no more have arrived for `quiet`, and only the latest is delivered.
+    /// The quiet period is measured from when each message is taken off the queue, which is as soon as it arrives while the Agent is waiting in `recv()`.
+    pub fn debounce(self, matches: impl Fn(&T) -> bool + Send + 'static, quiet: Duration) -> Self {
+        self.with_rule(matches, Coalescing::Debounce(quiet))
+    }
+
+    /// Throttle the messages matching the predicate: at most one is delivered per `interval`, and those which arrive sooner are held back, with only the latest delivered once the interval has passed
+    pub fn throttle(
+        self,
+        matches: impl Fn(&T) -> bool + Send + 'static,
+        interval: Duration,
+    ) -> Self {
+        self.with_rule(matches, Coalescing::Throttle(interval))
+    }
+
+    /// The number of messages currently being held back
+    pub fn pending(&self) -> usize {
+        self.rules
+            .iter()
+            .filter(|rule| rule.pending.is_some())
+            .count()
+    }
+
+    /// Receive the next message, either from the inbox or (once it is due) one which was held back.
+    /// Returns `None` once the inbox has been closed and no messages are being held back; messages still held back when the inbox is closed are delivered straight away rather than lost.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            let now = Instant::now();
+            if let Some(message) = self.take_pending(Some(now)) {
+                return Some(message);
+            }
+            let received = match self.next_due() {
+                Some(due) => tokio::select! {
+                    received = self.inbox.recv() => received,
+                    () = tokio::time::sleep_until(due) => continue,
+                },
+                None => self.inbox.recv().await,
+            };
+            match received {
+                Some(message) => {
+                    if let Some(message) = self.coalesce(message, Instant::now()) {
+                        return Some(message);
+                    }
+                }
+                None => return self.take_pending(None),
+            }
+        }
+    }
+
+    fn with_rule(
+        mut self,
+        matches: impl Fn(&T) -> bool + Send + 'static,
+        coalescing: Coalescing,
+    ) -> Self {
+        self.rules.push(CoalescingRule {
+            matches: Box::new(matches),
+            coalescing,
+            pending: None,
+            last_delivered: None,
+        });
+        self
+    }
+
+    /// Hold back a message if it matches a rule (replacing the message already held back by that rule), or hand it back if it should be delivered now
+    fn coalesce(&mut self, message: T, now: Instant) -> Option<T> {
+        let Some(rule) = self.rules.iter_mut().find(|rule| (rule.matches)(&message)) else {
+            return Some(message);
+        };
+        let due = match rule.coalescing {
+            Coalescing::Debounce(quiet) => now + quiet,
+            Coalescing::Throttle(interval) => {
+                let due = rule.last_delivered.map_or(now, |last| last + interval);
+                if due <= now && rule.pending.is_none() {
+                    rule.last_delivered = Some(now);
+                    return Some(message);
+                }
+                due
+            }
+        };
+        rule.pending = Some((due, message));
+        None
+    }
+
+    /// When the next message being held back is due, if any are
+    fn next_due(&self) -> Option<Instant> {
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.pending.as_ref().map(|(due, _)| *due))
+            .min()
+    }
+
+    /// Take the message held back which is due first, provided it is due by the given time (if any)
+    fn take_pending(&mut self, now: Option<Instant>) -> Option<T> {
+        let rule = self
+            .rules
+            .iter_mut()
+            .filter(|rule| {
+                rule.pending
+                    .as_ref()
+                    .is_some_and(|(due, _)| now.is_none_or(|now| *due <= now))
+            })
+            .min_by_key(|rule| rule.pending.as_ref().map(|(due, _)| *due))?;
+        let (_, message) = rule.pending.take()?;
+        if let Coalescing::Throttle(_) = rule.coalescing {
+            rule.last_delivered = Some(now.unwrap_or_else(Instant::now));
+        }
+        Some(message)
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl<T> InboxExt<T> for CoalescingInbox<T> {
+    type TryRecvError = tokio::sync::mpsc::error::TryRecvError;
+
+    fn try_recv(&mut self) -> Result<T, Self::TryRecvError> {
+        loop {
+            let now = Instant::now();
+            if let Some(message) = self.take_pending(Some(now)) {
+                return Ok(message);
+            }
+            match self.inbox.try_recv() {
+                Ok(message) => {
+                    if let Some(message) = self.coalesce(message, now) {
+                        return Ok(message);
+                    }
+                }
+                Err(mpsc::error::TryRecvError::Disconnected) => {
+                    return self
+                        .take_pending(None)
+                        .ok_or(mpsc::error::TryRecvError::Disconnected);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    async fn recv_timeout(&mut self, timeout: Duration) -> Option<T> {
+        tokio::time::timeout(timeout, self.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn recv_many(&mut self, buffer: &mut impl Extend<T>, limit: usize) -> usize {
+        if limit == 0 {
+            return 0;
+        }
+        let Some(message) = self.recv().await else {
+            return 0;
+        };
+        buffer.extend([message]);
+        let mut received = 1;
+        while received < limit {
+            match InboxExt::try_recv(self) {
+                Ok(message) => buffer.extend([message]),
+                Err(_) => break,
+            }
+            received += 1;
+        }
+        received
+    }
+}
+
+/// A boxed Agent main loop, as spawned by `register_agent!()`
+#[doc(hidden)]
+#[cfg(not(target_os = "none"))]
+pub type AgentTask = core::pin::Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A boxed main loop of an Agent which isn't `Send`, as spawned by `register_agent_local!()`
+#[doc(hidden)]
+#[cfg(not(target_os = "none"))]
+pub type LocalAgentTask = core::pin::Pin<Box<dyn Future<Output = ()>>>;
+
+/// Creates the main loop of an Agent on its own thread, as spawned by `register_agent_threaded!()`
+#[doc(hidden)]
+#[cfg(not(target_os = "none"))]
+pub type ThreadedAgentTask = Box<dyn FnOnce() -> LocalAgentTask + Send>;
+
+/// Run the registration of an Agent on the runtime behind the given handle, as done by `register_agent_on!()`.
+/// Everything spawned by the registration (the Agent's main loop, its supervisor and any restarted instances) lands on that runtime.
+#[doc(hidden)]
+#[cfg(not(target_os = "none"))]
+pub async fn register_on<T: Send + 'static>(
+    runtime: &tokio::runtime::Handle,
+    registration: impl Future<Output = T> + Send + 'static,
+) -> T {
+    match runtime.spawn(registration).await {
+        Ok(result) => result,
+        Err(error) if error.is_panic() => std::panic::resume_unwind(error.into_panic()),
+        Err(_) => panic!("The runtime was shut down while registering an Agent"),
+    }
+}
+
+/// Determines how the Postmaster responds to an Agent panicking.
+/// By default, an Agent which panics is not restarted: its address is freed and any subsequent messages sent to it will fail with `PostmasterError::NoRecipient`.
+/// If the Agent is registered with a restart policy (see `register_agent!()`), a new instance of the Agent is created from a clone of its original config, and registered at the same address with a fresh message queue.
+/// With `MailboxPolicy::Preserve`, the new instance instead takes over the message queue of the instance which panicked, along with any messages waiting on it.
+/// Restarting is only available with tokio: on bare metal targets a panic halts the program.
+///
+/// # Example
+/// ```rust
+/// use post_haste::agent::{MailboxPolicy, RestartPolicy};
+///
+/// // Restart up to 3 times, carrying on with the messages which were waiting when the Agent panicked
+/// let policy = RestartPolicy::limited(3).with_mailbox_policy(MailboxPolicy::Preserve);
+/// ```
+#[cfg(not(target_os = "none"))]
+#[derive(Debug, Clone, Copy)]
+pub struct RestartPolicy {
+    max_restarts: Option<u32>,
+    mailbox_policy: MailboxPolicy,
+}
+
+/// What happens to the messages waiting for an Agent when it is restarted after a panic
+#[cfg(not(target_os = "none"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailboxPolicy {
+    /// The new instance is registered with a fresh, empty message queue, and the messages which were waiting are dropped (the default).
+    /// Messages sent while the Agent is being restarted fail with `PostmasterError::NoRecipient`.
+    Clear,
+    /// The new instance takes over the message queue of the instance which panicked, so that no queued messages are lost to a transient failure.
+    /// The address stays registered throughout, so messages sent while the Agent is being restarted wait on the queue, and an Agent which was paused stays paused.
+    /// The message being handled when the Agent panicked had already been received, so it isn't handled again.
+    Preserve,
+}
+
+#[cfg(not(target_os = "none"))]
+impl RestartPolicy {
+    /// Never restart the Agent
+    pub const fn never() -> Self {
+        Self {
+            max_restarts: Some(0),
+            mailbox_policy: MailboxPolicy::Clear,
+        }
+    }
+
+    /// Restart the Agent every time it panics
+    pub const fn always() -> Self {
+        Self {
+            max_restarts: None,
+            mailbox_policy: MailboxPolicy::Clear,
+        }
+    }
+
+    /// Restart the Agent until it has been restarted `max_restarts` times, after which it is left stopped
+    pub const fn limited(max_restarts: u32) -> Self {
+        Self {
+            max_restarts: Some(max_restarts),
+            mailbox_policy: MailboxPolicy::Clear,
+        }
+    }
+
+    /// Set what happens to the messages waiting for the Agent when it is restarted
+    pub const fn with_mailbox_policy(mut self, mailbox_policy: MailboxPolicy) -> Self {
+        self.mailbox_policy = mailbox_policy;
+        self
+    }
+
+    /// What happens to the messages waiting for the Agent when it is restarted
+    pub fn mailbox_policy(&self) -> MailboxPolicy {
+        self.mailbox_policy
+    }
+
+    /// Whether the policy permits another restart, given the number of restarts made so far
+    pub fn permits(&self, restarts: u32) -> bool {
+        self.max_restarts.is_none_or(|max| restarts < max)
+    }
+}
+
+/// Keeps hold of the queues of an Agent's inbox when the Agent stops, so that an instance restarted with `MailboxPolicy::Preserve` can take them over.
+/// This is used by `spawn_agent!()` and should not need to be used directly.
+#[doc(hidden)]
+#[cfg(not(target_os = "none"))]
+pub struct Salvage<T>(Arc<std::sync::Mutex<Option<Inbox<T>>>>, MailboxPolicy);
+
+#[cfg(not(target_os = "none"))]
+impl<T> Salvage<T> {
+    pub fn new(restart_policy: &RestartPolicy) -> Self {
+        Self(Arc::default(), restart_policy.mailbox_policy)
+    }
+
+    /// Whether the restart policy preserves the Agent's mailbox
+    pub fn preserves(&self) -> bool {
+        self.1 == MailboxPolicy::Preserve
+    }
+
+    /// Arrange for the inbox's queues to be kept when it is dropped, if the restart policy preserves them
+    pub fn keep(&self, mut inbox: Inbox<T>) -> Inbox<T> {
+        if self.preserves() {
+            inbox.salvage = Some(self.clone());
+        }
+        inbox
+    }
+
+    /// Take the queues kept from the instance which stopped, if there are any, to give to the next instance
+    pub fn recover(&self) -> Option<Inbox<T>> {
+        let inbox = self
+            .0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .take()?;
+        Some(self.keep(inbox))
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl<T> Clone for Salvage<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone(), self.1)
+    }
+}
+
+/// Details of a panic caught by the Postmaster, passed to the hook set with `postmaster::set_panic_hook()`
+#[cfg(not(target_os = "none"))]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AgentPanic<A> {
+    /// The address of the Agent which panicked
+    pub address: A,
+    /// The message the Agent panicked with, if it was a string
+    pub message: String,
+    /// Whether the Agent is being restarted according to its `RestartPolicy`
+    pub restarting: bool,
+}
+
+/// Extract the message from a caught panic payload
+#[doc(hidden)]
+#[cfg(not(target_os = "none"))]
+pub fn panic_message(payload: Box<dyn core::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => String::from("<non-string panic payload>"),
+        },
+    }
+}
+
+/// Notification that a watched Agent has terminated, delivered to watchers registered with `postmaster::watch()`
+#[cfg(not(target_os = "none"))]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AgentTerminated<A> {
+    /// The address of the Agent which terminated
+    pub address: A,
+    /// The reason the Agent terminated
+    pub reason: TerminationReason,
+}
+
+/// The reason an Agent terminated
+#[cfg(not(target_os = "none"))]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TerminationReason {
+    /// The Agent panicked with the given message, and was not restarted
+    Panicked(String),
+    /// The Agent's address was deregistered with `postmaster::deregister()`
+    Deregistered,
+    /// The Agent panicked, and the instance started to replace it couldn't be registered, e.g. because its address had been taken in the meantime
+    RestartFailed(crate::PostmasterError),
+}
+
+/// A handle to an Agent spawned with `register_agent!()` or `spawn_agent!()`.
+/// The handle can be used to await the Agent's termination, or to tear the Agent down individually.
+/// Dropping the handle does not stop the Agent.
+#[cfg(not(target_os = "none"))]
+pub struct AgentHandle<A, P> {
+    postmaster: crate::postmaster::Postmaster<A, P>,
+    address: A,
+    join_handle: tokio::task::JoinHandle<()>,
+}
+
+#[cfg(not(target_os = "none"))]
+impl<A, P> AgentHandle<A, P>
+where
+    A: crate::AddressSpace + core::fmt::Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    #[doc(hidden)]
+    pub fn new(
+        postmaster: crate::postmaster::Postmaster<A, P>,
+        address: A,
+        join_handle: tokio::task::JoinHandle<()>,
+    ) -> Self {
+        Self {
+            postmaster,
+            address,
+            join_handle,
+        }
+    }
+
+    /// The address the Agent is registered at
+    pub fn address(&self) -> A {
+        self.address
+    }
+
+    /// The handle of the task supervising the Agent.
+    /// The task finishes once the Agent has terminated for good: either it has been deregistered, or it has panicked and is not being restarted.
+    pub fn join_handle(&mut self) -> &mut tokio::task::JoinHandle<()> {
+        &mut self.join_handle
+    }
+
+    /// Consume the handle, returning the handle of the task supervising the Agent
+    pub fn into_join_handle(self) -> tokio::task::JoinHandle<()> {
+        self.join_handle
+    }
+
+    /// Whether the Agent has terminated for good
+    pub fn is_finished(&self) -> bool {
+        self.join_handle.is_finished()
+    }
+
+    /// Stop the Agent and deregister its address, notifying any watchers.
+    /// The Agent is stopped asynchronously, so this must be called from within the tokio runtime.
+    /// Has no effect if the Agent has already terminated.
+    pub fn abort(&self) {
+        if self.is_finished() {
+            return;
+        }
+        let postmaster = self.postmaster.clone();
+        let address = self.address;
+        tokio::task::spawn(async move {
+            let _ = postmaster.deregister(address).await;
+        });
+    }
+}
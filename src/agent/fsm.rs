@@ -0,0 +1,176 @@
+//! A helper for writing Agents as finite state machines.
+//!
+//! Rather than matching on its state and its messages by hand in `run()`, an Agent implements `StateMachine`, declaring which messages each state accepts, how each accepted message is handled, what happens on entering a state and how long the Agent may stay in a state before timing out.
+//! The Agent's `run()` then hands its inbox over to `fsm::run()`, which provides the main loop.
+//!
+//! State timeouts replace sending delayed messages to the Agent itself: the timeout is restarted every time a state is entered, so a timer from a state the Agent has already left can never fire.
+
+#[cfg(target_os = "none")]
+use embassy_time::{Duration, Instant, WithTimeout};
+#[cfg(not(target_os = "none"))]
+use tokio::time::{self, Duration, Instant};
+
+use super::Inbox;
+#[cfg(not(target_os = "none"))]
+use super::StashingInbox;
+
+/// How a state machine handles a message it has received, as decided by `StateMachine::accepts()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    /// Pass the message to `StateMachine::on_message()`
+    Handle,
+    /// Drop the message, as it has no effect in the current state
+    Discard,
+    /// Set the message aside until the state machine next changes state, then receive it again (in the order it arrived).
+    /// Stashed messages are held in memory, so this is only available with tokio.
+    #[cfg(not(target_os = "none"))]
+    Stash,
+}
+
+/// What a state machine does after handling a message or a timeout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transition<S> {
+    /// Remain in the current state, without restarting its timeout
+    Stay,
+    /// Enter the given state, calling `StateMachine::on_enter()` and restarting the timeout (even if the state is the same as the current state)
+    To(S),
+}
+
+/// An Agent written as a finite state machine, which is run by `fsm::run()`.
+///
+/// # Example
+/// ```rust,ignore
+/// impl StateMachine for DoorAgent {
+///     type State = DoorState;
+///     type Message = postmaster::Message;
+///
+///     fn initial_state(&self) -> DoorState {
+///         DoorState::Closed
+///     }
+///
+///     fn accepts(&self, state: &DoorState, message: &postmaster::Message) -> Disposition {
+///         match (state, &message.payload) {
+///             (DoorState::Closed, Payloads::Open) => Disposition::Handle,
+///             _ => Disposition::Discard,
+///         }
+///     }
+///
+///     async fn on_message(&mut self, _state: &DoorState, _message: postmaster::Message) -> Transition<DoorState> {
+///         Transition::To(DoorState::Open)
+///     }
+///
+///     fn timeout(&self, state: &DoorState) -> Option<Duration> {
+///         (*state == DoorState::Open).then_some(Duration::from_secs(10))
+///     }
+///
+///     async fn on_timeout(&mut self, _state: &DoorState) -> Transition<DoorState> {
+///         Transition::To(DoorState::Closed)
+///     }
+/// }
+///
+/// impl Agent for DoorAgent {
+///     // ...
+///     async fn run(self, inbox: post_haste::agent::Inbox<Self::Message>) -> ! {
+///         post_haste::agent::fsm::run(self, inbox).await
+///     }
+/// }
+/// ```
+#[allow(async_fn_in_trait)]
+pub trait StateMachine {
+    /// The states the machine can be in
+    type State;
+    /// The type of message the machine receives, usually `postmaster::Message`
+    type Message;
+
+    /// The state the machine starts in, which is entered (calling `on_enter()`) when the machine starts running
+    fn initial_state(&self) -> Self::State;
+
+    /// Decide how a message received in the given state should be handled
+    fn accepts(&self, state: &Self::State, message: &Self::Message) -> Disposition;
+
+    /// Handle a message accepted in the given state, returning the transition to make
+    async fn on_message(
+        &mut self,
+        state: &Self::State,
+        message: Self::Message,
+    ) -> Transition<Self::State>;
+
+    /// Called whenever a state is entered, e.g. to report the new state to other Agents
+    async fn on_enter(&mut self, state: &Self::State) {
+        let _ = state;
+    }
+
+    /// How long the machine may stay in the given state without a transition before `on_timeout()` is called, or `None` if the state has no timeout
+    fn timeout(&self, state: &Self::State) -> Option<Duration> {
+        let _ = state;
+        None
+    }
+
+    /// Called when the machine has stayed in the given state for longer than its timeout, returning the transition to make
+    async fn on_timeout(&mut self, state: &Self::State) -> Transition<Self::State> {
+        let _ = state;
+        Transition::Stay
+    }
+}
+
+/// Run a state machine as an Agent's main loop, receiving from the Agent's inbox.
+/// This is intended to be called from the `run()` function of the Agent trait, and never returns.
+///
+/// # Panics
+/// With tokio, panics if the Agent's inbox is closed.
+pub async fn run<M: StateMachine>(mut machine: M, inbox: Inbox<M::Message>) -> ! {
+    #[cfg(target_os = "none")]
+    let mut inbox = inbox;
+    #[cfg(not(target_os = "none"))]
+    let mut inbox = StashingInbox::new(inbox);
+    let mut state = machine.initial_state();
+    machine.on_enter(&state).await;
+    let mut deadline = machine
+        .timeout(&state)
+        .map(|timeout| Instant::now() + timeout);
+    loop {
+        let transition = match receive(&mut inbox, deadline).await {
+            None => {
+                deadline = None;
+                machine.on_timeout(&state).await
+            }
+            Some(message) => match machine.accepts(&state, &message) {
+                Disposition::Handle => machine.on_message(&state, message).await,
+                Disposition::Discard => Transition::Stay,
+                #[cfg(not(target_os = "none"))]
+                Disposition::Stash => {
+                    inbox.stash(message);
+                    Transition::Stay
+                }
+            },
+        };
+        if let Transition::To(next) = transition {
+            state = next;
+            #[cfg(not(target_os = "none"))]
+            inbox.unstash_all();
+            machine.on_enter(&state).await;
+            deadline = machine
+                .timeout(&state)
+                .map(|timeout| Instant::now() + timeout);
+        }
+    }
+}
+
+/// Receive the next message, or `None` if the deadline passes first
+#[cfg(target_os = "none")]
+async fn receive<T>(inbox: &mut Inbox<T>, deadline: Option<Instant>) -> Option<T> {
+    match deadline {
+        Some(deadline) => inbox.receive().with_deadline(deadline).await.ok(),
+        None => Some(inbox.receive().await),
+    }
+}
+
+/// Receive the next message, or `None` if the deadline passes first
+#[cfg(not(target_os = "none"))]
+async fn receive<T>(inbox: &mut StashingInbox<T>, deadline: Option<Instant>) -> Option<T> {
+    let message = match deadline {
+        Some(deadline) => time::timeout_at(deadline, inbox.recv()).await.ok()?,
+        None => inbox.recv().await,
+    };
+    Some(message.expect("The Agent's inbox was closed"))
+}
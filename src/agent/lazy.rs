@@ -0,0 +1,71 @@
+//! Agents which are only created once they are needed.
+//!
+//! An Agent registered with `register_agent_lazy!()` (or `post_haste::spawn_agent_lazy!()`) has its message queue registered at its address straight away, so it can be sent messages, watched and paused like any other Agent, but the Agent itself isn't created until the first message arrives.
+//! A system with many rarely used Agents (e.g. one for each device which might be attached) then only pays for the queues of the Agents which haven't been needed yet, and doesn't wait for them all to be created at startup.
+//!
+//! An idle timeout may also be given, in which case an Agent which has waited that long in `Inbox::recv()` without receiving a message is torn down, and created afresh from a clone of its config when the next message arrives.
+//! The Agent is only torn down while it is waiting for a message, never while it is handling one, and its message queue stays registered throughout, so no messages are lost.
+//! Tearing the Agent down drops it along with the future running it, leaving only its message queue and a small task waiting for the next message, so a system with thousands of mostly idle Agents (e.g. one for each user, at dynamic addresses) only holds the memory of the ones in use.
+//!
+//! Any state the Agent holds is lost along with it, unless the Agent is passivated: an Agent implementing `Snapshot` which receives with `Inbox::recv_passivating()` has its snapshot taken as it is torn down, and kept with its message queue.
+//! The next instance of the Agent then restores the snapshot from `Inbox::take_snapshot()` before handling its first message, so that it carries on where the previous instance left off.
+//! Otherwise, an Agent whose state must outlive it should keep that state elsewhere (e.g. with `post_haste::agent::persistent`).
+//!
+//! # Example
+//! ```rust,ignore
+//! // Created when the first message is sent to Address::Printer, and torn down after 5 minutes without one
+//! postmaster::register_agent_lazy!(Printer, PrinterAgent, config, 4, Duration::from_secs(300)).unwrap();
+//!
+//! // A session for each user, passivated after a minute without messages
+//! impl Agent for SessionAgent {
+//!     // ...
+//!     async fn run(mut self, mut inbox: Inbox<Self::Message>) -> ! {
+//!         if let Some(snapshot) = inbox.take_snapshot() {
+//!             self.restore(&snapshot);
+//!         }
+//!         loop {
+//!             let message = inbox.recv_passivating(&self).await.unwrap();
+//!             // ...
+//!         }
+//!     }
+//! }
+//!
+//! let address = postmaster::allocate_address().unwrap();
+//! postmaster::register_agent_lazy!(address = address, SessionAgent, config, 4, Duration::from_secs(60)).unwrap();
+//! ```
+
+use tokio::sync::oneshot;
+use tokio::time::Duration;
+
+use super::{Agent, Inbox};
+
+/// Run a lazily created Agent: wait for a message to arrive, then create the Agent from a clone of its config and run it until it has been idle for `idle_after` (if given), before waiting for the next message.
+/// This is the main loop spawned by `spawn_agent_lazy!()` and should not need to be called directly.
+/// Returns once the Agent's message queue has been closed while the Agent isn't running.
+#[doc(hidden)]
+pub async fn run_lazily<G>(
+    address: G::Address,
+    config: G::Config,
+    mut inbox: Inbox<G::Message>,
+    idle_after: Option<Duration>,
+) where
+    G: Agent,
+    G::Address: Copy,
+    G::Config: Clone,
+{
+    loop {
+        // The message is kept on the inbox, so that it is the first one the Agent receives
+        match inbox.recv().await {
+            Some(message) => inbox.next.push_front(message),
+            None => return,
+        }
+        let agent = G::create(address, config.clone()).await;
+        let (parked, unparked) = oneshot::channel();
+        inbox.idle = idle_after.map(|idle_after| (idle_after, parked));
+        // Without an idle timeout the inbox is never handed back, and the Agent runs for good
+        tokio::select! {
+            never = agent.run(inbox) => match never {},
+            Ok(parked) = unparked => inbox = parked,
+        }
+    }
+}
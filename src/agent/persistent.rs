@@ -0,0 +1,309 @@
+//! A helper for writing event-sourced Agents, whose state survives restarts.
+//! Enabled with the `persistence` feature.
+//!
+//! Rather than changing its state directly when handling a message, a `PersistentAgent` decides which events the message gives rise to.
+//! Each event is appended to the Agent's `EventJournal` before it is applied to the Agent's state, so when the Agent is created again (e.g. after the process restarts) its state is rebuilt by replaying the journal in `create()`.
+//! The Agent's `run()` then hands its inbox over to `persistent::run()`, which provides the main loop.
+//!
+//! So that the journal doesn't grow forever, an Agent can compact it by returning a snapshot of its state from `PersistentAgent::compact()`, which replaces all of the events before it.
+//! When rebuilding such an Agent, its state is first restored from the journal's `snapshot()`, and then the events since the snapshot are replayed.
+//!
+//! For Agents with large events, the journal can compress its events and snapshots with any `Compression` by opening it `with_compression()`.
+
+use std::borrow::Cow;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::Inbox;
+use crate::compression::{self, Compression};
+
+/// Set in the length of a record to mark the record as a snapshot rather than an event
+const SNAPSHOT_FLAG: u32 = 1 << 31;
+
+/// An Agent whose state is built up from a journal of events, run by `persistent::run()`.
+///
+/// # Example
+/// ```rust,ignore
+/// impl PersistentAgent for OrderAgent {
+///     type Message = postmaster::Message;
+///     type Event = OrderEvent;
+///
+///     async fn on_message(&mut self, message: postmaster::Message) -> Vec<OrderEvent> {
+///         match message.payload {
+///             Payloads::PlaceOrder(order) if !self.orders.contains_key(&order.id) => vec![OrderEvent::Placed(order)],
+///             Payloads::CancelOrder(id) if self.orders.contains_key(&id) => vec![OrderEvent::Cancelled(id)],
+///             _ => Vec::new(),
+///         }
+///     }
+///
+///     fn apply(&mut self, event: &OrderEvent) {
+///         match event {
+///             OrderEvent::Placed(order) => self.orders.insert(order.id, order.clone()),
+///             OrderEvent::Cancelled(id) => self.orders.remove(id),
+///         };
+///     }
+///
+///     fn encode_event(event: &OrderEvent) -> Vec<u8> { /* ... */ }
+///     fn decode_event(bytes: &[u8]) -> Option<OrderEvent> { /* ... */ }
+/// }
+///
+/// impl Agent for OrderAgent {
+///     // ...
+///     async fn create(address: Self::Address, config: Self::Config) -> Self {
+///         let mut agent = OrderAgent { address, orders: HashMap::new(), journal: None };
+///         let mut journal = EventJournal::open(&config.journal_path).await.expect("Failed to open the journal");
+///         if let Some(snapshot) = journal.snapshot().await.expect("Failed to read the journal") {
+///             agent.restore(&snapshot);
+///         }
+///         journal.replay(&mut agent).await.expect("Failed to replay the journal");
+///         agent.journal = Some(journal);
+///         agent
+///     }
+///
+///     async fn run(mut self, inbox: post_haste::agent::Inbox<Self::Message>) -> ! {
+///         let journal = self.journal.take().unwrap();
+///         post_haste::agent::persistent::run(self, journal, inbox).await
+///     }
+/// }
+/// ```
+#[allow(async_fn_in_trait)]
+pub trait PersistentAgent {
+    /// The type of message the Agent receives, usually `postmaster::Message`
+    type Message;
+    /// The events which make up the Agent's journal
+    type Event;
+
+    /// Handle a message, returning the events it gives rise to (if any), which are persisted and then applied in order.
+    /// Handling a message should not change the Agent's state directly, as only the events are replayed when the Agent is rebuilt.
+    async fn on_message(&mut self, message: Self::Message) -> Vec<Self::Event>;
+
+    /// Apply an event to the Agent's state, both as it happens and when replaying the journal.
+    /// As replaying must rebuild the same state, applying an event should have no other effects (such as sending messages).
+    fn apply(&mut self, event: &Self::Event);
+
+    /// Encode an event to be stored in the journal
+    fn encode_event(event: &Self::Event) -> Vec<u8>;
+
+    /// Decode an event stored in the journal, or return `None` if it is invalid
+    fn decode_event(bytes: &[u8]) -> Option<Self::Event>;
+
+    /// Called after each event is applied, with the number of events in the journal since it was last compacted.
+    /// Returning a snapshot of the Agent's state (e.g. from `Snapshot::snapshot()`, every thousand events) replaces the journal with the snapshot; by default the journal is never compacted.
+    fn compact(&self, events: usize) -> Option<Vec<u8>> {
+        let _ = events;
+        None
+    }
+}
+
+/// The journal of a `PersistentAgent`'s events, stored in a file.
+/// Each event is stored as its length followed by its encoding, and is flushed to disk before it is applied.
+/// A compacted journal starts with a snapshot of the Agent's state, which is stored in the same way.
+pub struct EventJournal {
+    file: File,
+    path: PathBuf,
+    length: u64,
+    events: usize,
+    /// The compression for the events and snapshots, and the size from which they are compressed
+    compression: Option<(Box<dyn Compression>, usize)>,
+}
+
+impl EventJournal {
+    /// Open the journal at the given path, creating it if it doesn't exist
+    pub async fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)
+            .await?;
+        let length = file.metadata().await?.len();
+        Ok(Self {
+            file,
+            path,
+            length,
+            events: 0,
+            compression: None,
+        })
+    }
+
+    /// Compress the events and snapshots which are at least `threshold` bytes long, as a `Compressed` codec does for messages.
+    /// Every record is then prefixed with whether it was compressed, so a journal written without compression can't be read with it (or the other way around), and must be emptied before compression is turned on or off.
+    pub fn with_compression(mut self, compression: impl Compression, threshold: usize) -> Self {
+        self.compression = Some((Box::new(compression), threshold));
+        self
+    }
+
+    /// The path of the journal's file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The snapshot at the start of the journal, if it has been compacted.
+    /// The Agent's state should be restored from the snapshot before the events after it are replayed.
+    pub async fn snapshot(&self) -> io::Result<Option<Vec<u8>>> {
+        let mut journal = Vec::new();
+        File::open(&self.path)
+            .await?
+            .read_to_end(&mut journal)
+            .await?;
+        read_record(&journal)
+            .filter(|(snapshot, _, _)| *snapshot)
+            .map(|(_, bytes, _)| {
+                self.unpack(bytes).map(Cow::into_owned).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Invalid snapshot in the journal",
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// Rebuild an Agent's state by applying every event in the journal to it (after the snapshot, if the journal has been compacted), in the order they were appended, returning the number of events applied.
+    /// An incomplete event at the end of the journal, left by the process stopping part-way through appending it, is discarded.
+    /// Fails with `io::ErrorKind::InvalidData` if an event can't be decoded.
+    pub async fn replay<M: PersistentAgent>(&mut self, agent: &mut M) -> io::Result<usize> {
+        let mut journal = Vec::new();
+        let mut file = File::open(&self.path).await?;
+        file.read_to_end(&mut journal).await?;
+        let mut remaining = journal.as_slice();
+        let mut applied = 0;
+        while let Some((snapshot, bytes, rest)) = read_record(remaining) {
+            remaining = rest;
+            if snapshot {
+                continue;
+            }
+            let event = self.unpack(bytes).and_then(|bytes| M::decode_event(&bytes));
+            let event = event.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Invalid event in the journal")
+            })?;
+            agent.apply(&event);
+            applied += 1;
+        }
+        self.events = applied;
+        // Drop the incomplete event, so that further events are appended after the last complete one
+        let complete = (journal.len() - remaining.len()) as u64;
+        if complete < self.length {
+            self.file.set_len(complete).await?;
+            self.length = complete;
+        }
+        Ok(applied)
+    }
+
+    /// Append an encoded event to the journal, waiting until it has been written to disk
+    pub async fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let record = record(false, &self.pack(bytes))?;
+        self.file.write_all(&record).await?;
+        self.file.flush().await?;
+        self.file.sync_data().await?;
+        self.length += record.len() as u64;
+        self.events += 1;
+        Ok(())
+    }
+
+    /// The number of events in the journal since the snapshot (or since it was created), as known from replaying and appending to it
+    pub fn events(&self) -> usize {
+        self.events
+    }
+
+    /// Replace the whole journal with a snapshot of the Agent's state.
+    /// The compacted journal is written alongside the old one and then moved into its place, so if the process stops part-way through, the old journal is left intact.
+    pub async fn compact(&mut self, snapshot: &[u8]) -> io::Result<()> {
+        let record = record(true, &self.pack(snapshot))?;
+        let compacted_path = self.path.with_extension("compacting");
+        let mut compacted = File::create(&compacted_path).await?;
+        compacted.write_all(&record).await?;
+        compacted.sync_all().await?;
+        drop(compacted);
+        fs::rename(&compacted_path, &self.path).await?;
+        self.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        self.length = record.len() as u64;
+        self.events = 0;
+        Ok(())
+    }
+
+    /// Compress a record's contents, if the journal is compressed
+    fn pack<'a>(&self, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+        match &self.compression {
+            Some((compression, threshold)) => {
+                Cow::Owned(compression::pack(&**compression, *threshold, bytes))
+            }
+            None => Cow::Borrowed(bytes),
+        }
+    }
+
+    /// Decompress a record's contents, if the journal is compressed, or return `None` if they are not valid
+    fn unpack<'a>(&self, bytes: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        match &self.compression {
+            Some((compression, _)) => compression::unpack(&**compression, bytes),
+            None => Some(Cow::Borrowed(bytes)),
+        }
+    }
+}
+
+/// Encode a record of the journal: its length (marked if it is a snapshot), followed by its contents
+fn record(snapshot: bool, bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let length = u32::try_from(bytes.len())
+        .ok()
+        .filter(|length| length & SNAPSHOT_FLAG == 0)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Record too large"))?;
+    let flag = if snapshot { SNAPSHOT_FLAG } else { 0 };
+    let mut record = Vec::with_capacity(bytes.len() + 4);
+    record.extend_from_slice(&(length | flag).to_be_bytes());
+    record.extend_from_slice(bytes);
+    Ok(record)
+}
+
+/// Read the first record of the journal, returning whether it is a snapshot, its contents and the rest of the journal, or `None` if the record is incomplete
+fn read_record(journal: &[u8]) -> Option<(bool, &[u8], &[u8])> {
+    let (length, rest) = journal.split_first_chunk::<4>()?;
+    let length = u32::from_be_bytes(*length);
+    let bytes = usize::try_from(length & !SNAPSHOT_FLAG).ok()?;
+    (rest.len() >= bytes).then(|| {
+        let (bytes, rest) = rest.split_at(bytes);
+        (length & SNAPSHOT_FLAG != 0, bytes, rest)
+    })
+}
+
+/// Run a persistent Agent's main loop, receiving from the Agent's inbox and persisting the events from each message to the journal before applying them.
+/// This is intended to be called from the `run()` function of the Agent trait, once the Agent's state has been rebuilt with `EventJournal::replay()`, and never returns.
+///
+/// # Panics
+/// Panics if the Agent's inbox is closed, or if an event can't be written to the journal (as the Agent's state would otherwise run ahead of its journal).
+pub async fn run<M: PersistentAgent>(
+    mut agent: M,
+    mut journal: EventJournal,
+    mut inbox: Inbox<M::Message>,
+) -> ! {
+    loop {
+        let message = inbox.recv().await.expect("The Agent's inbox was closed");
+        for event in agent.on_message(message).await {
+            journal
+                .append(&M::encode_event(&event))
+                .await
+                .unwrap_or_else(|error| {
+                    panic!(
+                        "Failed to write to the journal {}: {error}",
+                        journal.path().display()
+                    )
+                });
+            agent.apply(&event);
+            if let Some(snapshot) = agent.compact(journal.events()) {
+                journal.compact(&snapshot).await.unwrap_or_else(|error| {
+                    panic!(
+                        "Failed to compact the journal {}: {error}",
+                        journal.path().display()
+                    )
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Snapshots of Agents' state, for persisting and restoring the state of a whole system, or freezing it to inspect it.
+//!
+//! An Agent which can be snapshotted implements `Snapshot`, and handles a payload wrapping a `SnapshotRequest` by responding with its snapshot.
+//! `postmaster::snapshot_all()` sends the request to every registered address and collects the responses into a `SystemSnapshot`, which `postmaster::restore_all()` can later send back to the Agents to restore their state.
+
+use tokio::sync::oneshot;
+
+use crate::address::AddressSpace;
+
+/// An Agent whose state can be captured as bytes and later restored
+pub trait Snapshot {
+    /// Capture the Agent's state
+    fn snapshot(&self) -> Vec<u8>;
+
+    /// Replace the Agent's state with one captured by `snapshot()`
+    fn restore(&mut self, snapshot: &[u8]);
+}
+
+/// A request for an Agent's snapshot, sent by `postmaster::snapshot_all()`.
+/// As the Postmaster has no knowledge of the project's payloads, the request is wrapped in a payload by the function passed to `snapshot_all()`, which is most conveniently a variant of the payload enum, e.g. `Payloads::Snapshot(SnapshotRequest)`.
+#[derive(Debug)]
+pub struct SnapshotRequest {
+    reply: oneshot::Sender<Vec<u8>>,
+}
+
+impl SnapshotRequest {
+    pub(crate) fn new() -> (Self, oneshot::Receiver<Vec<u8>>) {
+        let (reply, receiver) = oneshot::channel();
+        (Self { reply }, receiver)
+    }
+
+    /// Respond to the request with the given snapshot
+    pub fn respond(self, snapshot: Vec<u8>) {
+        let _ = self.reply.send(snapshot);
+    }
+
+    /// Respond to the request with the Agent's snapshot
+    pub fn respond_with(self, agent: &impl Snapshot) {
+        self.respond(agent.snapshot());
+    }
+}
+
+/// The snapshots of every Agent which responded to `postmaster::snapshot_all()`.
+/// The snapshots (like the addresses) are public, so they can be written out in the project's own format, and read back in to restore the system later.
+#[derive(Debug, Clone, Default)]
+pub struct SystemSnapshot<A> {
+    /// Each Agent's address, along with its snapshot
+    pub agents: Vec<(A, Vec<u8>)>,
+}
+
+impl<A: AddressSpace> SystemSnapshot<A> {
+    /// The snapshot of the Agent at the given address, if it responded
+    pub fn get(&self, address: A) -> Option<&[u8]> {
+        self.agents
+            .iter()
+            .find(|(agent, _)| agent.index() == address.index())
+            .map(|(_, snapshot)| snapshot.as_slice())
+    }
+}
@@ -0,0 +1,125 @@
+//! Agents which only accept one of the message types wrapped by the payload enum, with the type of every message sent to them checked at compile time.
+//!
+//! An Agent usually receives the whole payload enum, so it has to match on every payload it receives and handle those meant for other Agents at runtime (e.g. by logging "received unsupported message").
+//! A `TypedAgent` instead declares the message type it accepts, such as `type Accepts = LightsMessage`, and its address is declared once as a `Recipient` of that type.
+//! Registering the Agent at the recipient with `register_agent!(recipient = ..)` checks that the Agent accepts the recipient's type, and sending through the recipient with `postmaster::send_to()` checks that every message carries that type, so a message of the wrong type fails to compile instead of being discarded at runtime.
+//! The Agent wraps its inbox in a `TypedInbox`, which unwraps each payload into the accepted type.
+//!
+//! The conversions between the payload enum and the accepted type are the ones derived by `#[derive(Payloads)]`.
+//! With tokio, the payload type can instead be `AnyPayload`, with each Agent's message type deriving `#[derive(AnyPayload)]`, so that there is no shared enum at all.
+//!
+//! # Example
+//! ```rust,ignore
+//! pub const LIGHTS: Recipient<Addresses, LightsMessage> = Recipient::new(Addresses::LightsAgent);
+//!
+//! impl TypedAgent for LightsAgent {
+//!     type Accepts = LightsMessage;
+//! }
+//!
+//! impl Agent for LightsAgent {
+//!     // ...
+//!     async fn run(self, inbox: Inbox<Self::Message>) -> ! {
+//!         let mut inbox = TypedInbox::<_, _, LightsMessage>::new(inbox);
+//!         loop {
+//!             let message = inbox.recv().await.unwrap();
+//!             match message.payload {
+//!                 LightsMessage::Display => { /* ... */ }
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! postmaster::register_agent!(recipient = LIGHTS, LightsAgent, ()).unwrap();
+//! postmaster::send_to(LIGHTS, Addresses::ButtonTask, LightsMessage::Display).await.unwrap();
+//! ```
+
+use core::fmt::{self, Debug};
+use core::marker::PhantomData;
+
+use super::{Agent, Inbox};
+use crate::postmaster::Message;
+
+/// An Agent which only accepts one of the message types wrapped by the payload enum
+pub trait TypedAgent: Agent {
+    /// The type of the messages the Agent accepts
+    type Accepts;
+}
+
+/// The address of an Agent which accepts messages of type `M`.
+/// A recipient is usually declared once as a constant, and used both to register the Agent and to send to it, so that the type of every message sent to the address is checked at compile time.
+pub struct Recipient<A, M> {
+    address: A,
+    accepts: PhantomData<fn(M)>,
+}
+
+impl<A: Copy, M> Recipient<A, M> {
+    /// Declare that the Agent at the given address accepts messages of type `M`
+    pub const fn new(address: A) -> Self {
+        Self {
+            address,
+            accepts: PhantomData,
+        }
+    }
+
+    /// The address of the recipient
+    pub fn address(&self) -> A {
+        self.address
+    }
+
+    /// The address of the recipient, for registering an Agent of type `T` there, which fails to compile unless `T` accepts the recipient's message type
+    pub fn address_for<T: TypedAgent<Accepts = M>>(&self) -> A {
+        self.address
+    }
+}
+
+impl<A: Copy, M> Clone for Recipient<A, M> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<A: Copy, M> Copy for Recipient<A, M> {}
+
+impl<A: Debug, M> Debug for Recipient<A, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Recipient").field(&self.address).finish()
+    }
+}
+
+/// An Agent's inbox, receiving only the messages whose payload holds the accepted type `M`, with the payload unwrapped.
+/// Messages sent through the Agent's `Recipient` always hold the accepted type, so any others can only have been sent to the Agent's address directly (e.g. with `postmaster::send()`), and are discarded.
+pub struct TypedInbox<A: 'static, P: 'static, M> {
+    inbox: Inbox<Message<A, P>>,
+    accepts: PhantomData<fn() -> M>,
+}
+
+impl<A: 'static, P: 'static, M: TryFrom<P, Error = P>> TypedInbox<A, P, M> {
+    /// Wrap the inbox given to the Agent's `run()`
+    pub fn new(inbox: Inbox<Message<A, P>>) -> Self {
+        Self {
+            inbox,
+            accepts: PhantomData,
+        }
+    }
+
+    /// Receive the next message of the accepted type, waiting for one to arrive if none are waiting.
+    /// Returns `None` once the inbox has been closed.
+    #[cfg(not(target_os = "none"))]
+    pub async fn recv(&mut self) -> Option<Message<A, M>> {
+        loop {
+            if let Ok(message) = self.inbox.recv().await?.try_map_payload(M::try_from) {
+                return Some(message);
+            }
+        }
+    }
+
+    /// Receive the next message of the accepted type, waiting for one to arrive if none are waiting
+    #[cfg(target_os = "none")]
+    pub async fn recv(&mut self) -> Message<A, M> {
+        loop {
+            if let Ok(message) = self.inbox.receive().await.try_map_payload(M::try_from) {
+                return message;
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! Zero-copy bulk payloads, for passing large binary data (such as video frames or file contents) between Agents.
+//! Enabled with the `bytes` feature.
+//!
+//! A payload is moved onto the recipient's queue rather than copied, but a payload holding a `Vec<u8>` still has to be copied whenever the same data goes to more than one place, e.g. when it is sent to a group or with `send_matching()`, or when one stage of a pipeline keeps a frame as well as passing it on.
+//! Holding the data in a payload variant as a `Bytes` avoids this: a `Bytes` is a reference-counted view of a shared buffer, so cloning it (or slicing it) only adjusts the count, however large the buffer is.
+//!
+//! A buffer is built up with a `BytesMut` and then frozen into a `Bytes` once it is complete, after which it can no longer be changed.
+//! Large payloads are kept alive for as long as they are waiting on queues, so the memory held by a pipeline is bounded by the capacity of its queues times the size of its frames; Agents handling large frames should be registered with small queues.
+//!
+//! # Example
+//! ```rust
+//! use post_haste::bulk::{Bytes, BytesMut, BufMut};
+//!
+//! enum Payloads {
+//!     Frame(Bytes),
+//! }
+//!
+//! let mut buffer = BytesMut::with_capacity(4 * 1024 * 1024);
+//! buffer.put_bytes(0x80, 4 * 1024 * 1024);
+//! let frame = buffer.freeze();
+//! // Both payloads share the same buffer
+//! let preview = Payloads::Frame(frame.slice(..1024));
+//! let full = Payloads::Frame(frame.clone());
+//! ```
+
+pub use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+/// Split a buffer into consecutive slices of at most `chunk_size` bytes, without copying it, e.g. to send a large frame as a batch of smaller messages with `send_all()`.
+///
+/// # Panics
+/// Panics if `chunk_size` is zero.
+///
+/// # Example
+/// ```rust
+/// use post_haste::bulk::{Bytes, chunks};
+///
+/// let frame = Bytes::from(vec![0u8; 10]);
+/// let lengths: Vec<usize> = chunks(&frame, 4).map(|chunk| chunk.len()).collect();
+/// assert_eq!(lengths, [4, 4, 2]);
+/// ```
+pub fn chunks(buffer: &Bytes, chunk_size: usize) -> impl Iterator<Item = Bytes> + use<> {
+    assert!(chunk_size > 0, "Chunks must hold at least one byte");
+    let buffer = buffer.clone();
+    (0..buffer.len())
+        .step_by(chunk_size)
+        .map(move |start| buffer.slice(start..(start + chunk_size).min(buffer.len())))
+}
@@ -0,0 +1,275 @@
+//! Fault injection for testing a system of Agents, by randomly dropping, delaying, duplicating and reordering the messages sent through a Postmaster.
+//! Enabled with the `chaos` feature.
+//!
+//! Agents which work when every message arrives promptly, once and in order can still fail in a deployed system, where a bridge loses messages, an outbox redelivers them or a slow Agent lets later messages overtake earlier ones.
+//! Running the tests with a `Chaos` layer on the Postmaster shakes out these assumptions in CI, rather than in the field.
+//! The faults are chosen using a random number generator seeded with the chaos' seed, so a run which fails can be investigated with the same seed (although, unlike a `Simulation`, timing still depends on how the tasks are scheduled).
+//!
+//! Faults are only injected into regular messages: control messages (such as the notifications sent to watchers) are always delivered as they would have been.
+
+use core::fmt::Debug;
+use std::collections::BTreeMap;
+
+use tokio::time::Duration;
+
+use crate::address::{AddressIndex, AddressSpace};
+use crate::postmaster::{Message, Postmaster};
+use crate::random::{chance, next_random};
+
+/// A pair of source and destination addresses, between which faults can be configured separately
+type Route = (AddressIndex, AddressIndex);
+/// The probability of duplicating a message, along with how to copy its payload
+type Duplicate<P> = (f64, fn(&P) -> P);
+
+/// The faults to inject into messages, each happening to a message with its own probability (from 0, never, to 1, always).
+/// A message which is dropped suffers no other faults, and a message is either delayed or held back to be reordered, but not both.
+pub struct Faults<P> {
+    drop: f64,
+    delay: (f64, Duration),
+    reorder: (f64, Duration),
+    duplicate: Option<Duplicate<P>>,
+}
+
+impl<P> Faults<P> {
+    /// No faults, to which faults are added with the other methods
+    pub fn new() -> Self {
+        Self {
+            drop: 0.0,
+            delay: (0.0, Duration::ZERO),
+            reorder: (0.0, Duration::ZERO),
+            duplicate: None,
+        }
+    }
+
+    /// Silently discard messages with the given probability, as if they were lost in transit.
+    /// The sender is still told that the message was sent.
+    pub fn with_drop(mut self, probability: f64) -> Self {
+        self.drop = probability;
+        self
+    }
+
+    /// Deliver messages late with the given probability, after a random delay of up to `max_delay`.
+    /// Messages sent after a delayed message may overtake it.
+    pub fn with_delay(mut self, probability: f64, max_delay: Duration) -> Self {
+        self.delay = (probability, max_delay);
+        self
+    }
+
+    /// Hold messages back with the given probability, delivering each one straight after the next message sent between the same pair of addresses, so that the two arrive the wrong way round.
+    /// A message is held for at most `max_hold`, after which it is delivered anyway, so that a message with no message following it is still delivered.
+    pub fn with_reorder(mut self, probability: f64, max_hold: Duration) -> Self {
+        self.reorder = (probability, max_hold);
+        self
+    }
+}
+
+impl<P: Clone> Faults<P> {
+    /// Deliver messages twice with the given probability, as an at-least-once transport might.
+    /// The copy has the same `MessageId` as the original, so it is discarded by a destination with a dedup window.
+    pub fn with_duplicates(mut self, probability: f64) -> Self {
+        self.duplicate = Some((probability, P::clone));
+        self
+    }
+}
+
+impl<P> Default for Faults<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P> Clone for Faults<P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<P> Copy for Faults<P> {}
+
+/// The number of each kind of fault injected by a `Chaos` so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChaosStats {
+    /// The number of messages discarded
+    pub dropped: usize,
+    /// The number of messages delivered late
+    pub delayed: usize,
+    /// The number of messages delivered twice
+    pub duplicated: usize,
+    /// The number of messages held back to be delivered after a later message
+    pub reordered: usize,
+}
+
+/// Injects faults into the messages sent through a Postmaster, for as long as it is kept.
+/// The same faults apply to every message, unless different faults are configured for a particular pair of addresses with `with_route()`.
+/// Dropping the `Chaos` returns the Postmaster to delivering messages normally, and delivers any messages still being held back to be reordered.
+///
+/// # Example
+/// ```rust,ignore
+/// #[tokio::test]
+/// async fn crossing_survives_a_flaky_network() {
+///     let seed = 42;
+///     let faults = Faults::new()
+///         .with_drop(0.01)
+///         .with_delay(0.1, Duration::from_millis(20))
+///         .with_duplicates(0.05);
+///     let chaos = Chaos::new(postmaster::instance(), seed, faults)
+///         .with_route(Addresses::SequencerAgent, Addresses::LightsAgent, Faults::new().with_reorder(0.5, Duration::from_millis(50)));
+///     // Run the system and assert on its final state, reporting the seed on failure...
+///     println!("{:?}", chaos.stats());
+/// }
+/// ```
+pub struct Chaos<A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    postmaster: Postmaster<A, P>,
+    seed: u64,
+}
+
+impl<A, P> Chaos<A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    /// Start injecting faults into the messages sent through the given Postmaster, chosen using the seed.
+    /// Any `Chaos` already running on the Postmaster is replaced.
+    pub fn new(postmaster: &Postmaster<A, P>, seed: u64, faults: Faults<P>) -> Self {
+        postmaster.set_chaos(Some(ChaosState {
+            random: seed,
+            faults,
+            routes: BTreeMap::new(),
+            held: BTreeMap::new(),
+            next_hold: 0,
+            stats: ChaosStats::default(),
+        }));
+        Self {
+            postmaster: postmaster.clone(),
+            seed,
+        }
+    }
+
+    /// Inject different faults into the messages sent from `source` to `destination`, e.g. `Faults::new()` to leave a route untouched
+    pub fn with_route(self, source: A, destination: A, faults: Faults<P>) -> Self {
+        self.postmaster.with_chaos(|chaos| {
+            chaos
+                .routes
+                .insert((source.index(), destination.index()), faults)
+        });
+        self
+    }
+
+    /// The seed the faults are chosen with
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The number of each kind of fault injected so far
+    pub fn stats(&self) -> ChaosStats {
+        self.postmaster
+            .with_chaos(|chaos| chaos.stats)
+            .unwrap_or_default()
+    }
+}
+
+impl<A, P> Drop for Chaos<A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    fn drop(&mut self) {
+        self.postmaster.set_chaos(None);
+    }
+}
+
+/// A message to be delivered in the background, after a delay
+pub(crate) type Delivery<A, P> = (Duration, A, Message<A, P>);
+
+/// The state of a running `Chaos`, kept by the Postmaster
+pub(crate) struct ChaosState<A, P> {
+    random: u64,
+    faults: Faults<P>,
+    routes: BTreeMap<Route, Faults<P>>,
+    /// The message held back on each route to be reordered, numbered so that the wait to release it can tell whether it has already been delivered
+    held: BTreeMap<Route, (u64, A, Message<A, P>)>,
+    next_hold: u64,
+    stats: ChaosStats,
+}
+
+/// What happens to a message which has had faults injected into it
+pub(crate) struct Injected<A, P> {
+    /// The message to deliver as usual, unless it has been dropped, delayed or held back
+    pub(crate) now: Option<Message<A, P>>,
+    /// The messages to deliver in the background instead, in order of their delays
+    pub(crate) later: Vec<Delivery<A, P>>,
+    /// The message held back, and how long to wait before delivering it anyway
+    pub(crate) held: Option<(Route, u64, Duration)>,
+}
+
+impl<A: AddressSpace, P> ChaosState<A, P> {
+    /// Choose the faults to inject into a message which is about to be delivered
+    pub(crate) fn inject(&mut self, destination: A, message: Message<A, P>) -> Injected<A, P> {
+        let route = (message.source.index(), destination.index());
+        let mut injected = Injected {
+            now: None,
+            later: Vec::new(),
+            held: None,
+        };
+        // A message held back on the route is delivered straight after this one
+        if let Some((_, held_destination, held)) = self.held.remove(&route) {
+            injected.later.push((Duration::ZERO, destination, message));
+            injected
+                .later
+                .push((Duration::ZERO, held_destination, held));
+            return injected;
+        }
+        let faults = *self.routes.get(&route).unwrap_or(&self.faults);
+        if chance(&mut self.random, faults.drop) {
+            self.stats.dropped += 1;
+            return injected;
+        }
+        if let Some((probability, copy)) = faults.duplicate
+            && chance(&mut self.random, probability)
+        {
+            self.stats.duplicated += 1;
+            let copy = message.duplicate_with(copy);
+            injected.later.push((Duration::ZERO, destination, copy));
+        }
+        let ((delay, max_delay), (reorder, max_hold)) = (faults.delay, faults.reorder);
+        if chance(&mut self.random, delay) {
+            self.stats.delayed += 1;
+            let nanos = u64::try_from(max_delay.as_nanos()).unwrap_or(u64::MAX);
+            let delay =
+                Duration::from_nanos(next_random(&mut self.random) % nanos.saturating_add(1));
+            injected.later.push((delay, destination, message));
+        } else if chance(&mut self.random, reorder) {
+            self.stats.reordered += 1;
+            let hold = self.next_hold;
+            self.next_hold += 1;
+            self.held.insert(route, (hold, destination, message));
+            injected.held = Some((route, hold, max_hold));
+        } else {
+            injected.now = Some(message);
+        }
+        injected
+    }
+
+    /// Take a message held back to be reordered, if it hasn't already been delivered after a later message
+    pub(crate) fn release(&mut self, route: Route, hold: u64) -> Option<(A, Message<A, P>)> {
+        match self.held.get(&route) {
+            Some((held, ..)) if *held == hold => self
+                .held
+                .remove(&route)
+                .map(|(_, destination, message)| (destination, message)),
+            _ => None,
+        }
+    }
+
+    /// Take every message still held back, as the chaos is stopping
+    pub(crate) fn release_all(self) -> Vec<Delivery<A, P>> {
+        self.held
+            .into_values()
+            .map(|(_, destination, message)| (Duration::ZERO, destination, message))
+            .collect()
+    }
+}
@@ -0,0 +1,265 @@
+//! Compression for the messages which are encoded to be sent across a bridge or stored in a journal, e.g. for large, repetitive payloads sent over a slow link.
+//! Enabled along with any of the `remote`, `outbox` and `persistence` features.
+//!
+//! A `Compressed` codec wraps the project's own codec, compressing each encoded message which is at least as large as its threshold, and decompressing it again before it is decoded.
+//! Small messages are left as they are, as are messages which compression doesn't make any smaller, so that short control messages don't pay for compression they don't benefit from.
+//! Each message is prefixed with a single byte noting whether it was compressed.
+//! The events of a `PersistentAgent` are compressed in the same way, by opening its `EventJournal` `with_compression()`.
+//!
+//! The compression itself is provided as a `Compression`.
+//! The `lz4` feature provides `Lz4`, for where speed matters most, and the `zstd` feature provides `Zstd`, for where bandwidth does, but any other algorithm (and crate) can be used by implementing `Compression`.
+//! Both ends of a bridge (or the writer and reader of a journal) must use the same compression, and an existing journal can't be switched over to compression without being emptied first.
+//!
+//! Messages are compressed one at a time, so a bridge's stream of messages isn't compressed as a whole, and the repetition between one message and the next isn't taken advantage of.
+//! For payloads which are only repetitive across messages, batching them into larger payloads before they are sent gives compression more to work with.
+//!
+//! # Example
+//! ```rust,ignore
+//! // Telemetry readings are large JSON blobs, so anything over 512 bytes is compressed before crossing the field link
+//! let codec = Compressed::new(JsonCodec, Zstd::new()).with_threshold(512);
+//! let bridge = Bridge::new(postmaster::instance(), codec).remote(Address::Telemetry);
+//! ```
+
+use std::borrow::Cow;
+
+#[cfg(feature = "outbox")]
+use crate::outbox::{OutboxCodec, OutboxMessage};
+#[cfg(feature = "remote")]
+use crate::remote::{Codec, RemoteMessage};
+#[cfg(feature = "persistence")]
+use crate::schedule::{ScheduleCodec, ScheduledMessage};
+
+/// The size (in bytes) from which encoded messages are compressed, unless changed with `Compressed::with_threshold()`
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 256;
+
+/// The length to which `Lz4` and `Zstd` decompress a message at most, unless changed with their `with_max_length()`
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+pub const DEFAULT_MAX_DECOMPRESSED_LENGTH: usize = 16 * 1024 * 1024;
+
+/// The prefix of a message which was stored as encoded by the wrapped codec
+const UNCOMPRESSED: u8 = 0;
+/// The prefix of a message which was compressed
+const COMPRESSED: u8 = 1;
+
+/// A compression algorithm, used by a `Compressed` codec to compress and decompress encoded messages
+pub trait Compression: Send + Sync + 'static {
+    /// Compress an encoded message
+    fn compress(&self, bytes: &[u8]) -> Vec<u8>;
+
+    /// Decompress a message compressed by `compress()`, or return `None` if it is not valid (in which case the message is treated as invalid)
+    fn decompress(&self, bytes: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// A codec which compresses the messages encoded by another codec, once they reach a size threshold.
+/// It wraps any bridge (`Codec`), outbox (`OutboxCodec`) or schedule (`ScheduleCodec`) codec, and can be used wherever that codec could.
+/// It can also be combined with a `Versioned` codec, in either order.
+#[derive(Debug, Clone)]
+pub struct Compressed<C, Z> {
+    codec: C,
+    compression: Z,
+    threshold: usize,
+}
+
+impl<C, Z: Compression> Compressed<C, Z> {
+    /// Wrap a codec, compressing the messages it encodes which are at least `DEFAULT_COMPRESSION_THRESHOLD` bytes long
+    pub fn new(codec: C, compression: Z) -> Self {
+        Self {
+            codec,
+            compression,
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        }
+    }
+
+    /// Set the size (in bytes) from which encoded messages are compressed.
+    /// A threshold of zero compresses every message.
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// The size (in bytes) from which encoded messages are compressed
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    fn pack(&self, bytes: Vec<u8>) -> Vec<u8> {
+        pack(&self.compression, self.threshold, &bytes)
+    }
+
+    fn unpack<'a>(&self, bytes: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        unpack(&self.compression, bytes)
+    }
+}
+
+/// Compress an encoded message if it is at least `threshold` bytes long (and compression makes it smaller), prefixing it with whether it was compressed
+pub(crate) fn pack<Z: Compression + ?Sized>(
+    compression: &Z,
+    threshold: usize,
+    bytes: &[u8],
+) -> Vec<u8> {
+    if bytes.len() >= threshold {
+        let compressed = compression.compress(bytes);
+        if compressed.len() < bytes.len() {
+            return [&[COMPRESSED], compressed.as_slice()].concat();
+        }
+    }
+    [&[UNCOMPRESSED], bytes].concat()
+}
+
+/// Remove the prefix from a message, decompressing it if it was compressed, or return `None` if it is not valid
+pub(crate) fn unpack<'a, Z: Compression + ?Sized>(
+    compression: &Z,
+    bytes: &'a [u8],
+) -> Option<Cow<'a, [u8]>> {
+    match bytes.split_first()? {
+        (&UNCOMPRESSED, bytes) => Some(Cow::Borrowed(bytes)),
+        (&COMPRESSED, bytes) => compression.decompress(bytes).map(Cow::Owned),
+        _ => None,
+    }
+}
+
+/// LZ4 compression, with the `lz4` feature: fast, but compressing less than `Zstd`.
+/// Each compressed message is prefixed with its decompressed length, and a message which claims to decompress to more than the maximum length is treated as invalid, so that a corrupt (or hostile) message can't exhaust memory.
+#[cfg(feature = "lz4")]
+#[derive(Debug, Clone, Copy)]
+pub struct Lz4 {
+    max_length: usize,
+}
+
+#[cfg(feature = "lz4")]
+impl Lz4 {
+    /// LZ4 compression, decompressing messages of up to `DEFAULT_MAX_DECOMPRESSED_LENGTH` bytes
+    pub const fn new() -> Self {
+        Self {
+            max_length: DEFAULT_MAX_DECOMPRESSED_LENGTH,
+        }
+    }
+
+    /// Set the length (in bytes) to which a message is decompressed at most
+    pub const fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+}
+
+#[cfg(feature = "lz4")]
+impl Default for Lz4 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "lz4")]
+impl Compression for Lz4 {
+    fn compress(&self, bytes: &[u8]) -> Vec<u8> {
+        lz4_flex::compress_prepend_size(bytes)
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> Option<Vec<u8>> {
+        // The length is checked before decompressing, as the buffer for the decompressed message is allocated up front
+        let (length, compressed) = bytes.split_first_chunk::<4>()?;
+        let length = usize::try_from(u32::from_le_bytes(*length)).ok()?;
+        if length > self.max_length {
+            return None;
+        }
+        lz4_flex::decompress(compressed, length)
+            .ok()
+            .filter(|decompressed| decompressed.len() == length)
+    }
+}
+
+/// Zstandard compression, with the `zstd` feature: slower than `Lz4`, but compressing more.
+/// A message which decompresses to more than the maximum length is treated as invalid, so that a corrupt (or hostile) message can't exhaust memory.
+#[cfg(feature = "zstd")]
+#[derive(Debug, Clone, Copy)]
+pub struct Zstd {
+    level: i32,
+    max_length: usize,
+}
+
+#[cfg(feature = "zstd")]
+impl Zstd {
+    /// Zstandard compression at its default level, decompressing messages of up to `DEFAULT_MAX_DECOMPRESSED_LENGTH` bytes
+    pub const fn new() -> Self {
+        Self {
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            max_length: DEFAULT_MAX_DECOMPRESSED_LENGTH,
+        }
+    }
+
+    /// Set the compression level, from 1 (the fastest) to 22 (the smallest)
+    pub const fn with_level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Set the length (in bytes) to which a message is decompressed at most
+    pub const fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Default for Zstd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Compression for Zstd {
+    fn compress(&self, bytes: &[u8]) -> Vec<u8> {
+        // A message which can't be compressed (e.g. with an invalid level) is returned as it is, which is then stored uncompressed as it is no smaller
+        zstd::bulk::compress(bytes, self.level).unwrap_or_else(|_| bytes.to_vec())
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> Option<Vec<u8>> {
+        zstd::bulk::decompress(bytes, self.max_length).ok()
+    }
+}
+
+#[cfg(feature = "remote")]
+impl<A, P, C, Z> Codec<A, P> for Compressed<C, Z>
+where
+    C: Codec<A, P>,
+    Z: Compression,
+{
+    fn encode(&self, message: &RemoteMessage<A, P>) -> Vec<u8> {
+        self.pack(self.codec.encode(message))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<RemoteMessage<A, P>> {
+        self.codec.decode(&self.unpack(bytes)?)
+    }
+}
+
+#[cfg(feature = "outbox")]
+impl<A, P, C, Z> OutboxCodec<A, P> for Compressed<C, Z>
+where
+    C: OutboxCodec<A, P>,
+    Z: Compression,
+{
+    fn encode(&self, message: &OutboxMessage<A, P>) -> Vec<u8> {
+        self.pack(self.codec.encode(message))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<OutboxMessage<A, P>> {
+        self.codec.decode(&self.unpack(bytes)?)
+    }
+}
+
+#[cfg(feature = "persistence")]
+impl<A, P, C, Z> ScheduleCodec<A, P> for Compressed<C, Z>
+where
+    C: ScheduleCodec<A, P>,
+    Z: Compression,
+{
+    fn encode(&self, message: &ScheduledMessage<A, P>) -> Vec<u8> {
+        self.pack(self.codec.encode(message))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<ScheduledMessage<A, P>> {
+        self.codec.decode(&self.unpack(bytes)?)
+    }
+}
@@ -0,0 +1,255 @@
+//! An HTTP endpoint serving the Postmaster's introspection data as JSON, for observing an Agent system (e.g. in staging) with nothing more than `curl`.
+//! Enabled with the `debug-server` feature.
+//!
+//! A `DebugServer` is an Agent which listens on a local port and answers these requests:
+//! - `GET /` returns everything below in one object, along with the Postmaster's diagnostics.
+//! - `GET /addresses` returns the status of each address known to the Postmaster (see `postmaster::status()`): whether it is registered, whether its Agent is running, the depth of its queue, and the delayed messages and timers pending for it.
+//! - `GET /dead-letters` returns the most recent messages which couldn't be delivered (see `postmaster::dead_letters()`).
+//! - `POST /inject?destination=<address>` sends a test message, whose payload is decoded from the body of the request, to the given address from the server's own address.
+//!
+//! Addresses are rendered with their `Debug` format, and the project provides a `DebugCodec` to parse the addresses and payloads of injected messages.
+//! Replies to injected messages are sent to the server, which discards them.
+//! The server has no authentication and speaks plain HTTP, so it should only be bound to a local or otherwise trusted interface.
+
+mod http;
+
+use core::fmt::{Debug, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task;
+
+use crate::address::AddressSpace;
+use crate::agent::{Agent, Inbox};
+use crate::postmaster::{AddressStatus, DeadLetter, Message, Postmaster, TaskState};
+use http::Request;
+
+/// Parses the destinations and payloads of the test messages injected through a `DebugServer`
+pub trait DebugCodec<A, P>: Send + Sync + 'static {
+    /// Parse the destination given in an injection request, or return `None` if it isn't a valid address
+    fn address(&self, address: &str) -> Option<A>;
+
+    /// Decode the body of an injection request, or return `None` if it isn't a valid payload
+    fn decode(&self, body: &[u8]) -> Option<P>;
+}
+
+/// The configuration of a `DebugServer`
+pub struct DebugServerConfig<A, P, C> {
+    postmaster: Postmaster<A, P>,
+    bind: SocketAddr,
+    codec: Arc<C>,
+}
+
+impl<A, P, C> DebugServerConfig<A, P, C> {
+    /// Serve the introspection data of the given Postmaster on the given socket address, e.g. `127.0.0.1:9090`
+    pub fn new(postmaster: &Postmaster<A, P>, bind: SocketAddr, codec: C) -> Self {
+        Self {
+            postmaster: postmaster.clone(),
+            bind,
+            codec: Arc::new(codec),
+        }
+    }
+}
+
+impl<A, P, C> Clone for DebugServerConfig<A, P, C> {
+    fn clone(&self) -> Self {
+        Self {
+            postmaster: self.postmaster.clone(),
+            bind: self.bind,
+            codec: self.codec.clone(),
+        }
+    }
+}
+
+/// An Agent serving the Postmaster's introspection data as JSON over HTTP.
+/// Panics if the server can't listen on its socket address, so it should be registered with a `RestartPolicy` if it should try again.
+///
+/// # Example
+/// ```rust,ignore
+/// let config = DebugServerConfig::new(postmaster::instance(), "127.0.0.1:9090".parse()?, DebugPayloads);
+/// postmaster::register_agent!(Debug, DebugServer<Address, Payloads, DebugPayloads>, config, 16).unwrap();
+/// // Then, e.g.: curl http://127.0.0.1:9090/addresses
+/// ```
+pub struct DebugServer<A, P, C> {
+    address: A,
+    config: DebugServerConfig<A, P, C>,
+}
+
+impl<A, P, C> Agent for DebugServer<A, P, C>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+    C: DebugCodec<A, P>,
+{
+    type Address = A;
+    type Message = Message<A, P>;
+    type Config = DebugServerConfig<A, P, C>;
+
+    async fn create(address: Self::Address, config: Self::Config) -> Self {
+        Self { address, config }
+    }
+
+    async fn run(self, mut inbox: Inbox<Self::Message>) -> ! {
+        let listener = TcpListener::bind(self.config.bind)
+            .await
+            .expect("Failed to bind the debug server");
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let Ok((stream, _)) = accepted else {
+                        continue;
+                    };
+                    task::spawn(serve(stream, self.config.clone(), self.address));
+                }
+                message = inbox.recv() => {
+                    // Replies to injected messages have nowhere to go
+                    message.expect("The Agent's inbox was closed");
+                }
+            }
+        }
+    }
+}
+
+/// Answer a single request on a connection
+async fn serve<A, P, C>(mut stream: TcpStream, config: DebugServerConfig<A, P, C>, source: A)
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+    C: DebugCodec<A, P>,
+{
+    let Ok(request) = http::read(&mut stream).await else {
+        let _ = http::respond(&mut stream, "400 Bad Request", &error("Malformed request")).await;
+        return;
+    };
+    let (status, body) = handle(&request, &config, source).await;
+    let _ = http::respond(&mut stream, status, &body).await;
+}
+
+/// Produce the status and body of the response to a request
+async fn handle<A, P, C>(
+    request: &Request,
+    config: &DebugServerConfig<A, P, C>,
+    source: A,
+) -> (&'static str, String)
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+    C: DebugCodec<A, P>,
+{
+    let postmaster = &config.postmaster;
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => {
+            let diagnostics = postmaster.get_diagnostics();
+            let body = format!(
+                r#"{{"addresses":{},"dead_letters":{},"diagnostics":{{"messages_sent":{},"send_failures":{},"agent_panics":{},"pending_delayed":{}}}}}"#,
+                addresses(postmaster).await,
+                dead_letters(postmaster),
+                diagnostics.messages_sent,
+                diagnostics.send_failures,
+                diagnostics.agent_panics,
+                diagnostics.pending_delayed,
+            );
+            ("200 OK", body)
+        }
+        ("GET", "/addresses") => ("200 OK", addresses(postmaster).await),
+        ("GET", "/dead-letters") => ("200 OK", dead_letters(postmaster)),
+        ("POST", "/inject") => {
+            let Some(destination) = request
+                .parameter("destination")
+                .and_then(|destination| config.codec.address(destination))
+            else {
+                return ("400 Bad Request", error("Missing or invalid destination"));
+            };
+            let Some(payload) = config.codec.decode(&request.body) else {
+                return ("400 Bad Request", error("Invalid payload"));
+            };
+            match postmaster.send(destination, source, payload).await {
+                Ok(()) => ("202 Accepted", r#"{"sent":true}"#.to_owned()),
+                Err(failure) => ("503 Service Unavailable", error(&format!("{failure:?}"))),
+            }
+        }
+        (_, "/" | "/addresses" | "/dead-letters" | "/inject") => {
+            ("405 Method Not Allowed", error("Method not allowed"))
+        }
+        _ => ("404 Not Found", error("Not found")),
+    }
+}
+
+/// The status of every address as a JSON array
+async fn addresses<A, P>(postmaster: &Postmaster<A, P>) -> String
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    let status = postmaster.status().await;
+    array(status.addresses.iter().map(address_status))
+}
+
+fn address_status<A: Debug>(status: &AddressStatus<A>) -> String {
+    let task = match status.task {
+        None => "null",
+        Some(TaskState::Running) => r#""running""#,
+        Some(TaskState::Terminated) => r#""terminated""#,
+    };
+    let processed = status
+        .messages_processed
+        .map_or("null".to_owned(), |processed| processed.to_string());
+    format!(
+        r#"{{"address":{},"registered":{},"task":{task},"queue_depth":{},"queue_capacity":{},"messages_processed":{processed},"delayed_messages":{},"timers":{}}}"#,
+        string(&format!("{:?}", status.address)),
+        status.registered,
+        status.queue_depth,
+        status.queue_capacity,
+        status.delayed_messages,
+        status.timers,
+    )
+}
+
+/// The recent dead letters as a JSON array, oldest first
+fn dead_letters<A, P>(postmaster: &Postmaster<A, P>) -> String
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    array(postmaster.dead_letters().iter().map(dead_letter))
+}
+
+fn dead_letter<A: Debug>(dead_letter: &DeadLetter<A>) -> String {
+    format!(
+        r#"{{"source":{},"destination":{},"error":{},"age_ms":{}}}"#,
+        string(&format!("{:?}", dead_letter.source)),
+        string(&format!("{:?}", dead_letter.destination)),
+        string(&format!("{:?}", dead_letter.error)),
+        dead_letter.failed_at.elapsed().as_millis(),
+    )
+}
+
+fn error(message: &str) -> String {
+    format!(r#"{{"error":{}}}"#, string(message))
+}
+
+fn array(items: impl Iterator<Item = String>) -> String {
+    format!("[{}]", items.collect::<Vec<_>>().join(","))
+}
+
+/// A JSON string literal
+fn string(value: &str) -> String {
+    let mut literal = String::with_capacity(value.len() + 2);
+    literal.push('"');
+    for character in value.chars() {
+        match character {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            control if control.is_control() => {
+                let _ = write!(literal, "\\u{:04x}", control as u32);
+            }
+            other => literal.push(other),
+        }
+    }
+    literal.push('"');
+    literal
+}
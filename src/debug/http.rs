@@ -0,0 +1,92 @@
+//! Just enough of HTTP/1.1 for the debug server: reading a request and writing a response, one request per connection.
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The longest request head which will be read, to bound the memory used by a misbehaving client
+const MAX_HEAD_LENGTH: usize = 8 * 1024;
+
+/// The largest request body which will be read
+const MAX_BODY_LENGTH: usize = 64 * 1024;
+
+/// A request received from a client
+pub(super) struct Request {
+    pub(super) method: String,
+    pub(super) path: String,
+    pub(super) query: Option<String>,
+    pub(super) body: Vec<u8>,
+}
+
+impl Request {
+    /// Find the value of a query parameter, which is not percent-decoded
+    pub(super) fn parameter(&self, name: &str) -> Option<&str> {
+        self.query
+            .as_deref()?
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Read a request, including its body if it has a `Content-Length`
+pub(super) async fn read<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Request> {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD_LENGTH {
+            return Err(invalid("HTTP request head too long"));
+        }
+        head.push(stream.read_u8().await?);
+    }
+    let head = String::from_utf8(head).map_err(|_| invalid("HTTP request head is not UTF-8"))?;
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines
+        .next()
+        .ok_or_else(|| invalid("Missing request line"))?
+        .split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Err(invalid("Malformed request line"));
+    };
+    let length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(header, _)| header.trim().eq_ignore_ascii_case("content-length"))
+        .map(|(_, value)| value.trim().parse::<usize>())
+        .transpose()
+        .map_err(|_| invalid("Malformed Content-Length"))?
+        .unwrap_or(0);
+    if length > MAX_BODY_LENGTH {
+        return Err(invalid("HTTP request body too long"));
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await?;
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_owned())),
+        None => (target, None),
+    };
+    Ok(Request {
+        method: method.to_owned(),
+        path: path.to_owned(),
+        query,
+        body,
+    })
+}
+
+/// Write a response with a JSON body, then close the connection
+pub(super) async fn respond<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    status: &str,
+    body: &str,
+) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await
+}
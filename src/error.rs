@@ -12,7 +12,8 @@ pub mod imports {
 use imports::*;
 
 /// Enumeration of potential errors which the Postmaster may encounter
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PostmasterError {
     /// The address specified has already been assigned
     AddressAlreadyTaken,
@@ -26,6 +27,48 @@ pub enum PostmasterError {
     /// The Receiver for the specified address has closed (gone out of scope).
     #[cfg(not(target_os = "none"))]
     ReceiverClosed, // Tokio Specific
+    /// A dynamic address was requested, but the address type does not contain dynamic addresses.
+    #[cfg(not(target_os = "none"))]
+    DynamicAddressesUnsupported,
+    /// An interceptor refused to deliver the message.
+    #[cfg(not(target_os = "none"))]
+    Rejected,
+    /// The sender has exceeded its rate limit.
+    #[cfg(not(target_os = "none"))]
+    RateLimited,
+    /// A batch of messages sent with `send_all()` is larger than the recipient's message queue can hold.
+    #[cfg(not(target_os = "none"))]
+    BatchTooLarge,
+    /// A message sent `with_ack()` was dropped without being handled by its recipient.
+    #[cfg(not(target_os = "none"))]
+    NotAcknowledged,
+    /// An `Outbox` was unable to write to its journal.
+    #[cfg(not(target_os = "none"))]
+    OutboxFailed,
+    /// A `Schedule` was unable to write to its journal.
+    #[cfg(not(target_os = "none"))]
+    ScheduleFailed,
+    /// A `Spillover` was unable to write to its spool.
+    #[cfg(not(target_os = "none"))]
+    SpillFailed,
+    /// The group has not been created with `create_group()`.
+    #[cfg(not(target_os = "none"))]
+    NoSuchGroup,
+    /// A group with the same identifier has already been created.
+    #[cfg(not(target_os = "none"))]
+    GroupAlreadyExists,
+    /// The address belongs to a standalone message queue rather than an Agent, so it can't be paused, resumed or pinged.
+    #[cfg(not(target_os = "none"))]
+    NotAnAgent,
+    /// The Agent doesn't take new configs of the type sent with `postmaster::reconfigure()`, either because it doesn't receive with `Inbox::recv_reconfiguring()`, or because its config is of a different type.
+    #[cfg(not(target_os = "none"))]
+    NotReconfigurable,
+    /// The send policy set with `postmaster::set_send_policy()` doesn't allow the source to send the message's payload to the destination.
+    #[cfg(not(target_os = "none"))]
+    Forbidden,
+    /// The Postmaster is draining (see `postmaster::drain()`), so it only delivers messages sent by Agents, and control messages.
+    #[cfg(not(target_os = "none"))]
+    Draining,
     /// Calling `try_send()` on the recipient's message queue failed.
     /// This is most likely due to teh recipient's message queue being full.
     TrySendFailed,
@@ -1,10 +1,60 @@
 #![cfg_attr(target_os = "none", no_std)]
 
+// tokio's timer and task spawning have no browser backend, so every send would panic in the browser.
+#[cfg(all(target_family = "wasm", target_os = "unknown"))]
+compile_error!(
+    "post-haste does not support wasm32-unknown-unknown; WASI targets such as wasm32-wasip1 are supported"
+);
+
+pub mod address;
 pub mod agent;
+#[cfg(all(feature = "bytes", not(target_os = "none")))]
+pub mod bulk;
+#[cfg(all(feature = "chaos", not(target_os = "none")))]
+pub mod chaos;
+#[cfg(all(
+    any(feature = "outbox", feature = "persistence", feature = "remote"),
+    not(target_os = "none")
+))]
+pub mod compression;
+#[cfg(all(feature = "debug-server", not(target_os = "none")))]
+pub mod debug;
 pub mod error;
+#[cfg(all(feature = "mqtt", not(target_os = "none")))]
+pub mod mqtt;
+#[cfg(all(feature = "outbox", not(target_os = "none")))]
+pub mod outbox;
+pub mod postmaster;
+#[cfg(all(
+    any(feature = "chaos", feature = "simulation"),
+    not(target_os = "none")
+))]
+mod random;
+#[cfg(all(feature = "recording", not(target_os = "none")))]
+pub mod recording;
+#[cfg(all(feature = "remote", not(target_os = "none")))]
+pub mod remote;
+#[cfg(all(feature = "persistence", not(target_os = "none")))]
+pub mod schedule;
+#[cfg(all(feature = "simulation", not(target_os = "none")))]
+pub mod simulation;
+#[cfg(all(feature = "spillover", not(target_os = "none")))]
+pub mod spillover;
+#[cfg(all(feature = "testkit", not(target_os = "none")))]
+pub mod testkit;
+#[cfg(all(feature = "tui", not(target_os = "none")))]
+pub mod tui;
+#[cfg(all(
+    any(feature = "outbox", feature = "persistence", feature = "remote"),
+    not(target_os = "none")
+))]
+pub mod versioning;
+#[cfg(all(feature = "websocket", not(target_os = "none")))]
+pub mod websocket;
 
 #[cfg(not(target_os = "none"))]
 pub mod async_runtime_dependencies {
+    pub use futures_core::Stream;
     pub use once_cell::sync::Lazy;
     pub use tokio::sync::Mutex;
     pub use tokio::sync::mpsc::{Receiver, Sender, channel};
@@ -25,28 +75,482 @@ pub mod async_runtime_dependencies {
 pub mod dependencies {
     pub use crate::async_runtime_dependencies::*;
     pub use const_env::env_item;
-    pub use portable_atomic::{AtomicU32, AtomicUsize};
+    pub use portable_atomic::{AtomicU32, AtomicU64, AtomicUsize};
 }
+pub use address::AddressSpace;
 pub use error::PostmasterError;
 
+/// Create an Agent and register it with a Postmaster instance.
+/// This is the instance-based equivalent of `postmaster::register_agent!()`: it instantiates the Agent, creates its message queue, registers the queue with the given Postmaster at the given address and kicks off the Agent's main loop.
+/// The first argument is a reference to a `post_haste::postmaster::Postmaster`, followed by the address, the Agent type and an instance of the Agent's associated Config type.
+/// An optional queue size (defaulting to the Postmaster's `default_queue_size()`) and `RestartPolicy` may follow, and behave exactly as they do for `register_agent!()`.
+/// On success, an `AgentHandle` is returned which can be used to await the Agent's termination or to stop it.
+///
+/// # Example
+/// ```rust,ignore
+/// let postmaster = Postmaster::<Address, Payloads>::new();
+/// post_haste::spawn_agent!(&postmaster, Address::Logger, LoggerAgent, LoggerConfig::default(), 8).unwrap();
+/// ```
+#[cfg(not(target_os = "none"))]
+#[macro_export]
+macro_rules! spawn_agent {
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr, $queue_size:expr, $restart_policy:expr) => {{
+        use $crate::agent::{Agent, AgentTask, Salvage};
+
+        let postmaster = $crate::postmaster::Postmaster::clone($postmaster);
+        let address = $address;
+        let config: <$agent as Agent>::Config = $config;
+        let restart_policy = $restart_policy;
+        let salvage = Salvage::new(&restart_policy);
+        let (mailbox, inbox) = $crate::agent::inbox($queue_size);
+        let inbox = salvage.keep(inbox);
+
+        let agent = <$agent>::create(address, config.clone()).await;
+        postmaster.register_inbox(address, mailbox).await.map(|_| {
+            let restart_postmaster = postmaster.clone();
+            let restart = move || -> AgentTask {
+                let postmaster = restart_postmaster.clone();
+                let config = config.clone();
+                let salvage = salvage.clone();
+                Box::pin(async move {
+                    let agent = <$agent>::create(address, config).await;
+                    match postmaster
+                        .restart_inbox(address, $queue_size, &salvage, false)
+                        .await
+                    {
+                        Ok(inbox) => agent.run(inbox).await,
+                        Err(error) => postmaster.restart_failed(address, error).await,
+                    }
+                })
+            };
+            let join_handle = postmaster.supervise(
+                address,
+                Box::pin(async move {
+                    agent.run(inbox).await;
+                }),
+                restart_policy,
+                Some(restart),
+            );
+            $crate::agent::AgentHandle::new(postmaster, address, join_handle)
+        })
+    }};
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr, $queue_size:expr) => {{
+        use $crate::agent::{Agent, AgentTask, RestartPolicy};
+
+        let postmaster = $crate::postmaster::Postmaster::clone($postmaster);
+        let address = $address;
+        let (mailbox, inbox) = $crate::agent::inbox($queue_size);
+
+        let agent = <$agent>::create(address, $config).await;
+        postmaster.register_inbox(address, mailbox).await.map(|_| {
+            let join_handle = postmaster.supervise(
+                address,
+                Box::pin(async move {
+                    agent.run(inbox).await;
+                }),
+                RestartPolicy::never(),
+                None::<fn() -> AgentTask>,
+            );
+            $crate::agent::AgentHandle::new(postmaster, address, join_handle)
+        })
+    }};
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr) => {{
+        let queue_size = $crate::postmaster::Postmaster::default_queue_size($postmaster);
+        $crate::spawn_agent!($postmaster, $address, $agent, $config, queue_size)
+    }};
+}
+
+/// Create an Agent which isn't `Send` (e.g. because it holds `Rc` or `RefCell` based handles) and register it with a Postmaster instance.
+/// This works in the same way as `spawn_agent!()`, except that the Agent's main loop is spawned onto the current `tokio::task::LocalSet`, so it must be called from a task running on a `LocalSet`.
+/// The Agent is addressed through the Postmaster in exactly the same way as any other Agent.
+/// As the Agent is created on the `LocalSet`, its Config type does not need to be `Send` either.
+///
+/// # Example
+/// ```rust,ignore
+/// let local = tokio::task::LocalSet::new();
+/// local
+///     .run_until(async {
+///         post_haste::spawn_agent_local!(&postmaster, Address::Display, DisplayAgent, ()).unwrap();
+///         // ...
+///     })
+///     .await;
+/// ```
+#[cfg(not(target_os = "none"))]
+#[macro_export]
+macro_rules! spawn_agent_local {
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr, $queue_size:expr, $restart_policy:expr) => {{
+        use $crate::agent::{Agent, LocalAgentTask, Salvage};
+
+        let postmaster = $crate::postmaster::Postmaster::clone($postmaster);
+        let address = $address;
+        let config: <$agent as Agent>::Config = $config;
+        let restart_policy = $restart_policy;
+        let salvage = Salvage::new(&restart_policy);
+        let (mailbox, inbox) = $crate::agent::inbox($queue_size);
+        let inbox = salvage.keep(inbox);
+
+        let agent = <$agent>::create(address, config.clone()).await;
+        postmaster.register_inbox(address, mailbox).await.map(|_| {
+            let restart_postmaster = postmaster.clone();
+            let restart = move || -> LocalAgentTask {
+                let postmaster = restart_postmaster.clone();
+                let config = config.clone();
+                let salvage = salvage.clone();
+                Box::pin(async move {
+                    let agent = <$agent>::create(address, config).await;
+                    match postmaster
+                        .restart_inbox(address, $queue_size, &salvage, false)
+                        .await
+                    {
+                        Ok(inbox) => agent.run(inbox).await,
+                        Err(error) => postmaster.restart_failed(address, error).await,
+                    }
+                })
+            };
+            let join_handle = postmaster.supervise_local(
+                address,
+                Box::pin(async move {
+                    agent.run(inbox).await;
+                }),
+                restart_policy,
+                Some(restart),
+            );
+            $crate::agent::AgentHandle::new(postmaster, address, join_handle)
+        })
+    }};
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr, $queue_size:expr) => {{
+        use $crate::agent::{Agent, LocalAgentTask, RestartPolicy};
+
+        let postmaster = $crate::postmaster::Postmaster::clone($postmaster);
+        let address = $address;
+        let (mailbox, inbox) = $crate::agent::inbox($queue_size);
+
+        let agent = <$agent>::create(address, $config).await;
+        postmaster.register_inbox(address, mailbox).await.map(|_| {
+            let join_handle = postmaster.supervise_local(
+                address,
+                Box::pin(async move {
+                    agent.run(inbox).await;
+                }),
+                RestartPolicy::never(),
+                None::<fn() -> LocalAgentTask>,
+            );
+            $crate::agent::AgentHandle::new(postmaster, address, join_handle)
+        })
+    }};
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr) => {{
+        let queue_size = $crate::postmaster::Postmaster::default_queue_size($postmaster);
+        $crate::spawn_agent_local!($postmaster, $address, $agent, $config, queue_size)
+    }};
+}
+
+/// Create an Agent whose main loop runs on a dedicated thread, and register it with a Postmaster instance.
+/// This is intended for Agents which do CPU-heavy work or blocking I/O, which would otherwise stall the other Agents sharing the async runtime.
+/// The arguments are exactly as for `spawn_agent!()`.
+/// The Agent is created on the calling task, then moved onto a new OS thread which runs it on its own single-threaded tokio runtime, so the Agent may block without affecting anything else.
+/// Its inbox is an ordinary message queue, so it is addressed through the Postmaster in exactly the same way as any other Agent.
+/// Tasks spawned by the Agent (e.g. delayed messages that it sends) run on its thread, so only make progress while the Agent is waiting for a message.
+/// As the Agent's main loop is driven on its own thread, `run()` does not need to return a `Send` future.
+///
+/// # Example
+/// ```rust,ignore
+/// post_haste::spawn_agent_threaded!(&postmaster, Address::ImageProcessor, ImageProcessorAgent, ()).unwrap();
+/// ```
+///
+/// Not available on WebAssembly, which has no threads.
+#[cfg(not(any(target_os = "none", target_family = "wasm")))]
+#[macro_export]
+macro_rules! spawn_agent_threaded {
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr, $queue_size:expr, $restart_policy:expr) => {{
+        use $crate::agent::{Agent, Salvage, ThreadedAgentTask};
+
+        let postmaster = $crate::postmaster::Postmaster::clone($postmaster);
+        let address = $address;
+        let config: <$agent as Agent>::Config = $config;
+        let restart_policy = $restart_policy;
+        let salvage = Salvage::new(&restart_policy);
+        let (mailbox, inbox) = $crate::agent::inbox($queue_size);
+        let inbox = salvage.keep(inbox);
+
+        let agent = <$agent>::create(address, config.clone()).await;
+        postmaster.register_inbox(address, mailbox).await.map(|_| {
+            let restart_postmaster = postmaster.clone();
+            let restart = move || -> ThreadedAgentTask {
+                let postmaster = restart_postmaster.clone();
+                let config = config.clone();
+                let salvage = salvage.clone();
+                Box::new(move || {
+                    Box::pin(async move {
+                        let agent = <$agent>::create(address, config).await;
+                        match postmaster
+                            .restart_inbox(address, $queue_size, &salvage, false)
+                            .await
+                        {
+                            Ok(inbox) => agent.run(inbox).await,
+                            Err(error) => postmaster.restart_failed(address, error).await,
+                        }
+                    })
+                })
+            };
+            let join_handle = postmaster.supervise_threaded(
+                address,
+                Box::new(move || {
+                    Box::pin(async move {
+                        agent.run(inbox).await;
+                    })
+                }),
+                restart_policy,
+                Some(restart),
+            );
+            $crate::agent::AgentHandle::new(postmaster, address, join_handle)
+        })
+    }};
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr, $queue_size:expr) => {{
+        use $crate::agent::{Agent, RestartPolicy, ThreadedAgentTask};
+
+        let postmaster = $crate::postmaster::Postmaster::clone($postmaster);
+        let address = $address;
+        let (mailbox, inbox) = $crate::agent::inbox($queue_size);
+
+        let agent = <$agent>::create(address, $config).await;
+        postmaster.register_inbox(address, mailbox).await.map(|_| {
+            let join_handle = postmaster.supervise_threaded(
+                address,
+                Box::new(move || {
+                    Box::pin(async move {
+                        agent.run(inbox).await;
+                    })
+                }),
+                RestartPolicy::never(),
+                None::<fn() -> ThreadedAgentTask>,
+            );
+            $crate::agent::AgentHandle::new(postmaster, address, join_handle)
+        })
+    }};
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr) => {{
+        let queue_size = $crate::postmaster::Postmaster::default_queue_size($postmaster);
+        $crate::spawn_agent_threaded!($postmaster, $address, $agent, $config, queue_size)
+    }};
+}
+
+/// Register a message queue for an Agent with a Postmaster instance, but only create the Agent once the first message arrives.
+/// The arguments are as for `spawn_agent!()`, except that the optional argument following the queue size is a `Duration` after which an Agent which is waiting for a message is torn down, to be created again when the next message arrives.
+/// Each instance of the Agent is created from a clone of the config, so the Config type must implement Clone.
+/// If the Agent panics it is not restarted, and its address is deregistered as for any other Agent.
+/// See `post_haste::agent::lazy` for details.
+///
+/// # Example
+/// ```rust,ignore
+/// post_haste::spawn_agent_lazy!(&postmaster, Address::Printer, PrinterAgent, config, 4, Duration::from_secs(300)).unwrap();
+/// ```
+#[cfg(not(target_os = "none"))]
+#[macro_export]
+macro_rules! spawn_agent_lazy {
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr, $queue_size:expr, $idle_after:expr) => {
+        $crate::spawn_agent_lazy!(@spawn $postmaster, $address, $agent, $config, $queue_size, Some($idle_after))
+    };
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr, $queue_size:expr) => {
+        $crate::spawn_agent_lazy!(@spawn $postmaster, $address, $agent, $config, $queue_size, None)
+    };
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr) => {{
+        let queue_size = $crate::postmaster::Postmaster::default_queue_size($postmaster);
+        $crate::spawn_agent_lazy!($postmaster, $address, $agent, $config, queue_size)
+    }};
+    (@spawn $postmaster:expr, $address:expr, $agent:ty, $config:expr, $queue_size:expr, $idle_after:expr) => {{
+        use $crate::agent::{Agent, AgentTask, RestartPolicy};
+
+        let postmaster = $crate::postmaster::Postmaster::clone($postmaster);
+        let address = $address;
+        let config: <$agent as Agent>::Config = $config;
+        let (mailbox, inbox) = $crate::agent::inbox($queue_size);
+
+        postmaster.register_inbox(address, mailbox).await.map(|_| {
+            let join_handle = postmaster.supervise(
+                address,
+                Box::pin($crate::agent::lazy::run_lazily::<$agent>(
+                    address,
+                    config,
+                    inbox,
+                    $idle_after,
+                )),
+                RestartPolicy::never(),
+                None::<fn() -> AgentTask>,
+            );
+            $crate::agent::AgentHandle::new(postmaster, address, join_handle)
+        })
+    }};
+}
+
+/// Create an Agent and register it with a Postmaster instance, running the Agent on the tokio runtime behind the given `tokio::runtime::Handle`.
+/// This allows latency-critical Agents to be isolated on a runtime with its own worker threads, while other Agents share another.
+/// The first argument is a reference to the runtime's handle, followed by exactly the same arguments as `spawn_agent!()`.
+/// The Agent is created, registered and supervised from a task spawned on the given runtime, so its main loop, its supervisor and any restarted instances all run there, as do any tasks the Agent spawns itself.
+/// The Agent remains addressable through the Postmaster from any runtime.
+///
+/// # Panics
+/// Panics if the given runtime is shut down before the Agent has been registered.
+///
+/// # Example
+/// ```rust,ignore
+/// let realtime = tokio::runtime::Builder::new_multi_thread().worker_threads(2).enable_all().build().unwrap();
+/// post_haste::spawn_agent_on!(realtime.handle(), &postmaster, Address::Motor, MotorAgent, config, 8).unwrap();
+/// ```
+#[cfg(not(target_os = "none"))]
+#[macro_export]
+macro_rules! spawn_agent_on {
+    ($runtime:expr, $postmaster:expr, $($arguments:tt)*) => {{
+        let postmaster = $crate::postmaster::Postmaster::clone($postmaster);
+        $crate::agent::register_on($runtime, async move {
+            $crate::spawn_agent!(&postmaster, $($arguments)*)
+        })
+        .await
+    }};
+}
+
+/// Create a child Agent at a newly allocated dynamic address, register it with a Postmaster instance and link it to its parent with `Postmaster::link_child()`.
+/// The first argument is a reference to a `post_haste::postmaster::Postmaster`, followed by the parent's address and the function converting the child's `AgentTerminated` notification into a payload for the parent.
+/// The remaining arguments are exactly as for `spawn_agent!()`, without the address.
+/// If the child can't be linked (e.g. because the parent has already terminated) it is stopped again, so that it isn't left running without a parent.
+/// On success, an `AgentHandle` is returned, from which the child's address can be obtained.
+///
+/// # Example
+/// ```rust,ignore
+/// let connection = post_haste::spawn_child!(&postmaster, self.address, Payloads::ConnectionClosed, ConnectionAgent, socket, 8)?;
+/// self.connections.insert(connection.address(), peer);
+/// ```
+#[cfg(not(target_os = "none"))]
+#[macro_export]
+macro_rules! spawn_child {
+    ($postmaster:expr, $parent:expr, $notification:expr, $agent:ty, $($arguments:tt)*) => {{
+        let postmaster = $crate::postmaster::Postmaster::clone($postmaster);
+        match postmaster.allocate_address() {
+            Ok(child) => match $crate::spawn_agent!(&postmaster, child, $agent, $($arguments)*) {
+                Ok(handle) => match postmaster.link_child($parent, child, $notification).await {
+                    Ok(()) => Ok(handle),
+                    Err(error) => {
+                        let _ = postmaster.deregister(child).await;
+                        Err(error)
+                    }
+                },
+                Err(error) => Err(error),
+            },
+            Err(error) => Err(error),
+        }
+    }};
+}
+
+/// Create a pool of identical Agents sharing a single address, and register it with a Postmaster instance.
+/// This is the instance-based equivalent of `postmaster::register_agent_pool!()`.
+/// The arguments are as for `spawn_agent!()`, with the number of Agents in the pool given after the config.
+/// Each Agent is created from a clone of the config, so the Config type must implement Clone.
+/// An optional queue size (for each Agent in the pool), `RestartPolicy` and `PoolRouting` may follow.
+/// By default, messages sent to the address are dispatched across the Agents in the pool in turn.
+/// On success, an `AgentHandle` is returned for each Agent in the pool.
+#[cfg(not(target_os = "none"))]
+#[macro_export]
+macro_rules! spawn_agent_pool {
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr, $size:expr, $queue_size:expr, $restart_policy:expr, $routing:expr) => {{
+        use $crate::agent::{Agent, AgentTask, Salvage};
+
+        let postmaster = $crate::postmaster::Postmaster::clone($postmaster);
+        let address = $address;
+        let config: <$agent as Agent>::Config = $config;
+        let restart_policy = $restart_policy;
+
+        let mut mailboxes = Vec::new();
+        let mut members = Vec::new();
+        for _ in 0..$size {
+            let (mailbox, inbox) = $crate::agent::inbox($queue_size);
+            mailboxes.push(mailbox);
+            members.push((<$agent>::create(address, config.clone()).await, inbox));
+        }
+        postmaster
+            .register_pool(address, mailboxes, $routing)
+            .await
+            .map(|_| {
+                members
+                    .into_iter()
+                    .map(|(agent, inbox)| {
+                        // Each member keeps its own message queue
+                        let salvage = Salvage::new(&restart_policy);
+                        let inbox = salvage.keep(inbox);
+                        let restart_postmaster = postmaster.clone();
+                        let config = config.clone();
+                        let restart = move || -> AgentTask {
+                            let postmaster = restart_postmaster.clone();
+                            let config = config.clone();
+                            let salvage = salvage.clone();
+                            Box::pin(async move {
+                                let agent = <$agent>::create(address, config).await;
+                                match postmaster
+                                    .restart_inbox(address, $queue_size, &salvage, true)
+                                    .await
+                                {
+                                    Ok(inbox) => agent.run(inbox).await,
+                                    Err(error) => postmaster.restart_failed(address, error).await,
+                                }
+                            })
+                        };
+                        let join_handle = postmaster.supervise(
+                            address,
+                            Box::pin(async move {
+                                agent.run(inbox).await;
+                            }),
+                            restart_policy,
+                            Some(restart),
+                        );
+                        $crate::agent::AgentHandle::new(postmaster.clone(), address, join_handle)
+                    })
+                    .collect::<Vec<_>>()
+            })
+    }};
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr, $size:expr, $queue_size:expr, $restart_policy:expr) => {
+        $crate::spawn_agent_pool!(
+            $postmaster,
+            $address,
+            $agent,
+            $config,
+            $size,
+            $queue_size,
+            $restart_policy,
+            $crate::postmaster::PoolRouting::round_robin()
+        )
+    };
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr, $size:expr, $queue_size:expr) => {
+        $crate::spawn_agent_pool!(
+            $postmaster,
+            $address,
+            $agent,
+            $config,
+            $size,
+            $queue_size,
+            $crate::agent::RestartPolicy::never()
+        )
+    };
+    ($postmaster:expr, $address:expr, $agent:ty, $config:expr, $size:expr) => {{
+        let queue_size = $crate::postmaster::Postmaster::default_queue_size($postmaster);
+        $crate::spawn_agent_pool!($postmaster, $address, $agent, $config, $size, queue_size)
+    }};
+}
+
 /// Initialise the Postmaster for use in your project.
 /// As the code for the Postmaster is no_std, it requires information about the project.
 /// Therefore, the code must be generated by a macro within the host crate.
 /// This macro requires two arguments: an enum type defining the Agent addresses, and an enum type defining the message payloads.
 /// An optional third argument allows the setting of the default timeout (in microseconds) used when attempting to send a message.
 /// If this third argument is omitted, a timeout of 1 ms (1000 us) will be used.
+/// With tokio, the third argument may instead be a `post_haste::postmaster::PostmasterConfig` given with the `config =` prefix, which sets the default timeout along with the Postmaster's other options (see the second example).
 /// The output of the macro is the `postmaster` module, which contains the API for the Postmaster.
 ///
-/// # Notes
-/// The logic generated by this macro relies on the (currently) unstable feature `variant_count`.
-/// Therefore the project must be built with the nightly compiler and you will need to include this feature at the top of the file (see example).
+/// # Addresses
+/// The address type must implement `post_haste::AddressSpace`, which is most easily done with `#[derive(AddressSpace)]`.
+/// Every unit variant of the enum is a static address, and a variant holding a `u64` identifier can be marked with `#[dynamic]` to support dynamic addresses created with `postmaster::allocate_address()`.
 ///
 /// # Examples
 /// ```rust
-/// #![feature(variant_count)]
-///
-/// use post_haste::init_postmaster;
+/// use post_haste::{AddressSpace, init_postmaster};
 ///
+/// #[derive(Debug, Clone, Copy, AddressSpace)]
 /// enum Address {
 ///   AgentOne,
 ///   AgentTwo,
@@ -60,12 +564,56 @@ pub use error::PostmasterError;
 /// }
 ///
 /// init_postmaster!(Address, Payloads);
+/// # fn main() {}
+/// ```
+///
+/// ```rust
+/// use post_haste::{AddressSpace, init_postmaster};
+/// use post_haste::postmaster::{OverflowPolicy, PostmasterConfig};
+///
+/// #[derive(Debug, Clone, Copy, AddressSpace)]
+/// enum Address {
+///   Sensor,
+///   Logger,
+/// }
+///
+/// enum Payloads {
+///   Reading(f32),
+/// }
+///
+/// init_postmaster!(
+///   Address,
+///   Payloads,
+///   config = PostmasterConfig::new()
+///     .with_name("sensors")
+///     .with_queue_size(8)
+///     .with_overflow_policy(OverflowPolicy::DropNewest)
+/// );
+/// # fn main() {}
 /// ```
 #[macro_export]
 #[allow(clippy::crate_in_macro_def)]
 macro_rules! init_postmaster {
 
+    ($address_enum:ty, $payload_enum:ty, config = $postmaster_config:expr) => {
+        #[cfg(target_os = "none")]
+        compile_error!("init_postmaster!() only accepts a PostmasterConfig with tokio");
+        $crate::init_postmaster!(@init $address_enum, $payload_enum, $postmaster_config);
+    };
     ($address_enum:ty, $payload_enum:ty, $timeout_us: expr) => {
+        $crate::init_postmaster!(
+            @init $address_enum,
+            $payload_enum,
+            post_haste::postmaster::PostmasterConfig::new().with_timeout_us($timeout_us)
+        );
+    };
+    (@init $address_enum:ty, $payload_enum:ty, $postmaster_config:expr) => {
+        /// The configuration of the global Postmaster, evaluated alongside `init_postmaster!()` so that it can refer to anything in scope there
+        #[cfg(not(target_os = "none"))]
+        fn postmaster_config() -> post_haste::postmaster::PostmasterConfig {
+            $postmaster_config
+        }
+
         /// API module for the Postmaster
         /// This module contains all of the functions required to pass messages between Agents, facilitated by the Postmaster.
         ///
@@ -76,38 +624,73 @@ macro_rules! init_postmaster {
             use super::{$address_enum, $payload_enum};
             use post_haste::PostmasterError;
             use post_haste::dependencies::*;
+            #[cfg(target_os = "none")]
+            use postmaster_internal::POSTMASTER;
+
+            #[cfg(target_os = "none")]
+            const ADDRESS_COUNT: usize = <$address_enum as post_haste::address::AddressSpace>::COUNT;
+
+            #[cfg(not(target_os = "none"))]
+            static POSTMASTER: Lazy<post_haste::postmaster::Postmaster<$address_enum, $payload_enum>> =
+                Lazy::new(|| post_haste::postmaster::Postmaster::with_config(super::postmaster_config()));
 
-            const ADDRESS_COUNT: usize = core::mem::variant_count::<$address_enum>();
+            /// Access the global Postmaster instance used by the functions in this module.
+            /// This allows the global Postmaster to be passed to code written against `post_haste::postmaster::Postmaster`, e.g. `post_haste::spawn_agent!()`.
+            #[cfg(not(target_os = "none"))]
+            pub fn instance() -> &'static post_haste::postmaster::Postmaster<$address_enum, $payload_enum> {
+                &POSTMASTER
+            }
 
             /// Initialises an Agent and its message queue
             /// This macro both instantiates an Actor and kicks off its main loop.
             /// It also creates the message queue for the Agent at the provided address, so that messages sent to that address will be delivered specifically to that Agent instance.
             /// As well as the address and Agent type this macro also requires an instance of the Agent's associated Config type which is used during the instantiation of the Agent, and an optional queue size parameter which dictates the number of messages the Agent's message queue can hold.
-            /// If no queue size parameter is given this defaults to 1, meaning that if there is already a message waiting in an Agent's queue then any attempt to send a message to the Agent will have to wait until either the queued message is received, or the send timeout is reached (in which case message sending is considered a failure).
+            /// If no queue size parameter is given this defaults to the Postmaster's default queue size, which is 1 unless set with `PostmasterConfig::with_queue_size()`. A queue size of 1 means that if there is already a message waiting in an Agent's queue then any attempt to send a message to the Agent will have to wait until either the queued message is received, or the send timeout is reached (in which case message sending is considered a failure).
             /// If try_send() is used to send to a full message queue, it will immediately return with failure.
+            ///
+            /// If the Agent panics, the panic is caught and passed to the hook set with `postmaster::set_panic_hook()` (or logged along with the Agent's address if no hook is set), and the Agent's address is deregistered (notifying any watchers).
+            /// A `RestartPolicy` may be given as a fifth argument, in which case the Agent is instead recreated (using a clone of the config, so the Config type must implement Clone) and registered again with a fresh message queue.
+            ///
+            /// The address is usually given as the name of a variant of the address enum.
+            /// Alternatively, any expression evaluating to an address (such as a dynamic address from `postmaster::allocate_address()`) can be given with the `address =` prefix, e.g. `register_agent!(address = client_address, ClientAgent, config)`.
+            /// A `TypedAgent` can be registered at its `post_haste::agent::typed::Recipient` with the `recipient =` prefix, which fails to compile unless the Agent accepts the recipient's message type.
+            ///
+            /// The Agent type may be generic, e.g. `register_agent!(Store, StoreAgent<DiskBackend>, config)`, so one Agent implementation can be registered at several addresses with different type arguments.
+            /// This also works from within a generic function, e.g. `register_agent!(address = address, StoreAgent<B>, config)` inside `fn start<B: Backend>()`.
+            ///
+            /// On success, an `AgentHandle` is returned, giving access to the Agent's address and the task supervising it.
+            /// It can be used to await the Agent's termination, or to stop the Agent with `abort()`.
+            ///
+            /// This is equivalent to calling `post_haste::spawn_agent!()` with `postmaster::instance()`.
             #[macro_export]
             #[cfg(not(target_os = "none"))]
             macro_rules! _register_agent {
-                ($agent_address:ident, $agent:ty, $config:expr, $queue_size: expr) => {{
-                    use crate::postmaster::Message;
-                    use post_haste::agent::Agent;
-                    use post_haste::dependencies::*;
-                    struct Mailbox {
-                        pub sender: Sender<Message>,
-                        pub receiver: Receiver<Message>,
-                    }
-                    let (sender, receiver) = channel::<Message>($queue_size);
-
-                    let agent = <$agent>::create(<$address_enum>::$agent_address, $config).await;
-                    postmaster::register(<$address_enum>::$agent_address, sender).await.inspect(|_|{
-
-                        tokio::task::spawn(async move {
-                            agent.run(receiver).await;
-                        });
-                    })
-                }};
+                (address = $agent_address:expr, $agent:ty, $config:expr, $queue_size: expr, $restart_policy: expr) => {
+                    post_haste::spawn_agent!(crate::postmaster::instance(), $agent_address, $agent, $config, $queue_size, $restart_policy)
+                };
+                (address = $agent_address:expr, $agent:ty, $config:expr, $queue_size: expr) => {
+                    post_haste::spawn_agent!(crate::postmaster::instance(), $agent_address, $agent, $config, $queue_size)
+                };
+                (address = $agent_address:expr, $agent:ty, $config:expr) => {
+                    post_haste::spawn_agent!(crate::postmaster::instance(), $agent_address, $agent, $config)
+                };
+                (recipient = $recipient:expr, $agent:ty, $config:expr, $queue_size: expr, $restart_policy: expr) => {
+                    crate::postmaster::register_agent!(address = $recipient.address_for::<$agent>(), $agent, $config, $queue_size, $restart_policy)
+                };
+                (recipient = $recipient:expr, $agent:ty, $config:expr, $queue_size: expr) => {
+                    crate::postmaster::register_agent!(address = $recipient.address_for::<$agent>(), $agent, $config, $queue_size)
+                };
+                (recipient = $recipient:expr, $agent:ty, $config:expr) => {
+                    crate::postmaster::register_agent!(address = $recipient.address_for::<$agent>(), $agent, $config)
+                };
+                ($agent_address:ident, $agent:ty, $config:expr, $queue_size: expr, $restart_policy: expr) => {
+                    crate::postmaster::register_agent!(address = <$address_enum>::$agent_address, $agent, $config, $queue_size, $restart_policy)
+                };
+                ($agent_address:ident, $agent:ty, $config:expr, $queue_size: expr) => {
+                    crate::postmaster::register_agent!(address = <$address_enum>::$agent_address, $agent, $config, $queue_size)
+                };
                 ($agent_address:ident, $agent:ty, $config:expr) => {
-                    crate::postmaster::register_agent!($agent_address, $agent, $config, 1)
+                    crate::postmaster::register_agent!(address = <$address_enum>::$agent_address, $agent, $config)
                 };
             }
 
@@ -117,6 +700,9 @@ macro_rules! init_postmaster {
             /// As well as the address and Agent type this macro also requires an instance of the Agent's associated Config type which is used during the instantiation of the Agent, and an optional queue size parameter which dictates the number of messages the Agent's message queue can hold.
             /// If no queue size parameter is given this defaults to 1, meaning that if there is already a message waiting in an Agent's queue then any attempt to send a message to the Agent will have to wait until either the queued message is received, or the send timeout is reached (in which case message sending is considered a failure).
             /// If try_send() is used to send to a full message queue, it will immediately return with failure.
+            ///
+            /// The Agent type may be generic, e.g. `register_agent!(spawner, Store, StoreAgent<FlashBackend>, config)`.
+            /// Each registration creates its own static task and message queue, so the type arguments must be concrete, and the macro can't be used within a generic function.
             #[macro_export]
             #[cfg(target_os = "none")]
             macro_rules! _register_agent {
@@ -150,6 +736,223 @@ macro_rules! init_postmaster {
             #[doc(hidden)]
             pub use _register_agent as register_agent;
 
+            /// Initialises an Agent which isn't `Send` (e.g. because it holds `Rc` or `RefCell` based handles) and its message queue.
+            /// The arguments are exactly as for `register_agent!()`, but the Agent's main loop is spawned onto the current `tokio::task::LocalSet`, so this must be called from a task running on a `LocalSet`.
+            /// The Agent is addressed through the Postmaster in the same way as any other Agent.
+            ///
+            /// This is equivalent to calling `post_haste::spawn_agent_local!()` with `postmaster::instance()`.
+            #[macro_export]
+            #[cfg(not(target_os = "none"))]
+            macro_rules! _register_agent_local {
+                (address = $agent_address:expr, $agent:ty, $config:expr, $queue_size: expr, $restart_policy: expr) => {
+                    post_haste::spawn_agent_local!(crate::postmaster::instance(), $agent_address, $agent, $config, $queue_size, $restart_policy)
+                };
+                (address = $agent_address:expr, $agent:ty, $config:expr, $queue_size: expr) => {
+                    post_haste::spawn_agent_local!(crate::postmaster::instance(), $agent_address, $agent, $config, $queue_size)
+                };
+                (address = $agent_address:expr, $agent:ty, $config:expr) => {
+                    post_haste::spawn_agent_local!(crate::postmaster::instance(), $agent_address, $agent, $config)
+                };
+                ($agent_address:ident, $agent:ty, $config:expr, $queue_size: expr, $restart_policy: expr) => {
+                    crate::postmaster::register_agent_local!(address = <$address_enum>::$agent_address, $agent, $config, $queue_size, $restart_policy)
+                };
+                ($agent_address:ident, $agent:ty, $config:expr, $queue_size: expr) => {
+                    crate::postmaster::register_agent_local!(address = <$address_enum>::$agent_address, $agent, $config, $queue_size)
+                };
+                ($agent_address:ident, $agent:ty, $config:expr) => {
+                    crate::postmaster::register_agent_local!(address = <$address_enum>::$agent_address, $agent, $config)
+                };
+            }
+
+            #[doc(hidden)]
+            #[cfg(not(target_os = "none"))]
+            pub use _register_agent_local as register_agent_local;
+
+            /// Initialises an Agent whose main loop runs on a dedicated thread, for Agents which do CPU-heavy work or blocking I/O.
+            /// The arguments are exactly as for `register_agent!()`, but the Agent is moved onto a new OS thread with its own single-threaded tokio runtime, so it may block without stalling the other Agents.
+            /// The Agent is addressed through the Postmaster in the same way as any other Agent.
+            ///
+            /// This is equivalent to calling `post_haste::spawn_agent_threaded!()` with `postmaster::instance()`.
+            #[macro_export]
+            #[cfg(not(any(target_os = "none", target_family = "wasm")))]
+            macro_rules! _register_agent_threaded {
+                (address = $agent_address:expr, $agent:ty, $config:expr, $queue_size: expr, $restart_policy: expr) => {
+                    post_haste::spawn_agent_threaded!(crate::postmaster::instance(), $agent_address, $agent, $config, $queue_size, $restart_policy)
+                };
+                (address = $agent_address:expr, $agent:ty, $config:expr, $queue_size: expr) => {
+                    post_haste::spawn_agent_threaded!(crate::postmaster::instance(), $agent_address, $agent, $config, $queue_size)
+                };
+                (address = $agent_address:expr, $agent:ty, $config:expr) => {
+                    post_haste::spawn_agent_threaded!(crate::postmaster::instance(), $agent_address, $agent, $config)
+                };
+                ($agent_address:ident, $agent:ty, $config:expr, $queue_size: expr, $restart_policy: expr) => {
+                    crate::postmaster::register_agent_threaded!(address = <$address_enum>::$agent_address, $agent, $config, $queue_size, $restart_policy)
+                };
+                ($agent_address:ident, $agent:ty, $config:expr, $queue_size: expr) => {
+                    crate::postmaster::register_agent_threaded!(address = <$address_enum>::$agent_address, $agent, $config, $queue_size)
+                };
+                ($agent_address:ident, $agent:ty, $config:expr) => {
+                    crate::postmaster::register_agent_threaded!(address = <$address_enum>::$agent_address, $agent, $config)
+                };
+            }
+
+            #[doc(hidden)]
+            #[cfg(not(any(target_os = "none", target_family = "wasm")))]
+            pub use _register_agent_threaded as register_agent_threaded;
+
+            /// Registers the message queue for an Agent, but only creates the Agent once the first message arrives.
+            /// The arguments are as for `register_agent!()`, except that the optional argument following the queue size is a `Duration` after which an Agent which is waiting for a message is torn down, to be created again when the next message arrives, e.g. `register_agent_lazy!(Printer, PrinterAgent, config, 4, Duration::from_secs(300))`.
+            /// Each instance of the Agent is created from a clone of the config, so the Config type must implement Clone.
+            /// See `post_haste::agent::lazy` for details.
+            ///
+            /// This is equivalent to calling `post_haste::spawn_agent_lazy!()` with `postmaster::instance()`.
+            #[macro_export]
+            #[cfg(not(target_os = "none"))]
+            macro_rules! _register_agent_lazy {
+                (address = $agent_address:expr, $agent:ty, $config:expr, $queue_size: expr, $idle_after: expr) => {
+                    post_haste::spawn_agent_lazy!(crate::postmaster::instance(), $agent_address, $agent, $config, $queue_size, $idle_after)
+                };
+                (address = $agent_address:expr, $agent:ty, $config:expr, $queue_size: expr) => {
+                    post_haste::spawn_agent_lazy!(crate::postmaster::instance(), $agent_address, $agent, $config, $queue_size)
+                };
+                (address = $agent_address:expr, $agent:ty, $config:expr) => {
+                    post_haste::spawn_agent_lazy!(crate::postmaster::instance(), $agent_address, $agent, $config)
+                };
+                ($agent_address:ident, $agent:ty, $config:expr, $queue_size: expr, $idle_after: expr) => {
+                    crate::postmaster::register_agent_lazy!(address = <$address_enum>::$agent_address, $agent, $config, $queue_size, $idle_after)
+                };
+                ($agent_address:ident, $agent:ty, $config:expr, $queue_size: expr) => {
+                    crate::postmaster::register_agent_lazy!(address = <$address_enum>::$agent_address, $agent, $config, $queue_size)
+                };
+                ($agent_address:ident, $agent:ty, $config:expr) => {
+                    crate::postmaster::register_agent_lazy!(address = <$address_enum>::$agent_address, $agent, $config)
+                };
+            }
+
+            #[doc(hidden)]
+            #[cfg(not(target_os = "none"))]
+            pub use _register_agent_lazy as register_agent_lazy;
+
+            /// Initialises a child Agent at a newly allocated dynamic address, linked to the given parent with `postmaster::link_child()`.
+            /// The parent is sent the notification built by the second argument when the child terminates, and the child is stopped when the parent terminates or is restarted.
+            /// The remaining arguments are the Agent type, its config and the optional queue size and `RestartPolicy`, as for `register_agent!()`, e.g. `register_child!(self.address, Payloads::ConnectionClosed, ConnectionAgent, socket, 8)`.
+            /// On success, an `AgentHandle` is returned, from which the child's address can be obtained.
+            ///
+            /// This is equivalent to calling `post_haste::spawn_child!()` with `postmaster::instance()`.
+            #[macro_export]
+            #[cfg(not(target_os = "none"))]
+            macro_rules! _register_child {
+                ($parent:expr, $notification:expr, $agent:ty, $config:expr, $queue_size: expr, $restart_policy: expr) => {
+                    post_haste::spawn_child!(crate::postmaster::instance(), $parent, $notification, $agent, $config, $queue_size, $restart_policy)
+                };
+                ($parent:expr, $notification:expr, $agent:ty, $config:expr, $queue_size: expr) => {
+                    post_haste::spawn_child!(crate::postmaster::instance(), $parent, $notification, $agent, $config, $queue_size)
+                };
+                ($parent:expr, $notification:expr, $agent:ty, $config:expr) => {
+                    post_haste::spawn_child!(crate::postmaster::instance(), $parent, $notification, $agent, $config)
+                };
+            }
+
+            #[doc(hidden)]
+            #[cfg(not(target_os = "none"))]
+            pub use _register_child as register_child;
+
+            /// Initialises an Agent and its message queue on the tokio runtime behind the given `tokio::runtime::Handle`.
+            /// This allows latency-critical Agents to be isolated on a runtime with its own worker threads, while other Agents share another.
+            /// The first argument is a reference to the runtime's handle, followed by exactly the same arguments as `register_agent!()`, e.g. `register_agent_on!(realtime.handle(), Motor, MotorAgent, config, 8)`.
+            /// The Agent's main loop, its supervisor and any restarted instances all run on the given runtime, and the Agent remains addressable from any runtime.
+            ///
+            /// This is equivalent to calling `post_haste::spawn_agent_on!()` with `postmaster::instance()`.
+            #[macro_export]
+            #[cfg(not(target_os = "none"))]
+            macro_rules! _register_agent_on {
+                ($runtime:expr, address = $agent_address:expr, $agent:ty, $config:expr, $queue_size: expr, $restart_policy: expr) => {
+                    post_haste::agent::register_on($runtime, async move {
+                        crate::postmaster::register_agent!(address = $agent_address, $agent, $config, $queue_size, $restart_policy)
+                    })
+                    .await
+                };
+                ($runtime:expr, address = $agent_address:expr, $agent:ty, $config:expr, $queue_size: expr) => {
+                    post_haste::agent::register_on($runtime, async move {
+                        crate::postmaster::register_agent!(address = $agent_address, $agent, $config, $queue_size)
+                    })
+                    .await
+                };
+                ($runtime:expr, address = $agent_address:expr, $agent:ty, $config:expr) => {
+                    post_haste::agent::register_on($runtime, async move {
+                        crate::postmaster::register_agent!(address = $agent_address, $agent, $config)
+                    })
+                    .await
+                };
+                ($runtime:expr, $agent_address:ident, $agent:ty, $config:expr, $queue_size: expr, $restart_policy: expr) => {
+                    post_haste::agent::register_on($runtime, async move {
+                        crate::postmaster::register_agent!($agent_address, $agent, $config, $queue_size, $restart_policy)
+                    })
+                    .await
+                };
+                ($runtime:expr, $agent_address:ident, $agent:ty, $config:expr, $queue_size: expr) => {
+                    post_haste::agent::register_on($runtime, async move {
+                        crate::postmaster::register_agent!($agent_address, $agent, $config, $queue_size)
+                    })
+                    .await
+                };
+                ($runtime:expr, $agent_address:ident, $agent:ty, $config:expr) => {
+                    post_haste::agent::register_on($runtime, async move {
+                        crate::postmaster::register_agent!($agent_address, $agent, $config)
+                    })
+                    .await
+                };
+            }
+
+            #[doc(hidden)]
+            #[cfg(not(target_os = "none"))]
+            pub use _register_agent_on as register_agent_on;
+
+            /// Initialises a pool of identical Agents sharing a single address.
+            /// Messages sent to the address are dispatched across the Agents in the pool in turn, allowing messages to be handled in parallel.
+            /// The arguments are as for `register_agent!()`, with the number of Agents in the pool given after the config, e.g. `register_agent_pool!(Workers, WorkerAgent, config, 4)`.
+            /// Each Agent is created from a clone of the config, so the Config type must implement Clone.
+            /// An optional queue size (for each Agent in the pool), `RestartPolicy` and `PoolRouting` may follow.
+            /// With `PoolRouting::by_key()`, each message is instead delivered to the member chosen by hashing its payload's `RoutingKey`, so that all messages with the same key are handled by the same Agent.
+            /// If a member of the pool panics and is not restarted, the pool continues with its remaining members, and watchers are only notified once every member has stopped.
+            ///
+            /// On success, an `AgentHandle` is returned for each Agent in the pool.
+            /// Please note that aborting any one of these handles deregisters the pool's address, stopping the entire pool.
+            ///
+            /// This is equivalent to calling `post_haste::spawn_agent_pool!()` with `postmaster::instance()`.
+            #[macro_export]
+            #[cfg(not(target_os = "none"))]
+            macro_rules! _register_agent_pool {
+                (address = $pool_address:expr, $agent:ty, $config:expr, $size:expr, $queue_size: expr, $restart_policy: expr, $routing: expr) => {
+                    post_haste::spawn_agent_pool!(crate::postmaster::instance(), $pool_address, $agent, $config, $size, $queue_size, $restart_policy, $routing)
+                };
+                (address = $pool_address:expr, $agent:ty, $config:expr, $size:expr, $queue_size: expr, $restart_policy: expr) => {
+                    post_haste::spawn_agent_pool!(crate::postmaster::instance(), $pool_address, $agent, $config, $size, $queue_size, $restart_policy)
+                };
+                (address = $pool_address:expr, $agent:ty, $config:expr, $size:expr, $queue_size: expr) => {
+                    post_haste::spawn_agent_pool!(crate::postmaster::instance(), $pool_address, $agent, $config, $size, $queue_size)
+                };
+                (address = $pool_address:expr, $agent:ty, $config:expr, $size:expr) => {
+                    post_haste::spawn_agent_pool!(crate::postmaster::instance(), $pool_address, $agent, $config, $size)
+                };
+                ($pool_address:ident, $agent:ty, $config:expr, $size:expr, $queue_size: expr, $restart_policy: expr, $routing: expr) => {
+                    crate::postmaster::register_agent_pool!(address = <$address_enum>::$pool_address, $agent, $config, $size, $queue_size, $restart_policy, $routing)
+                };
+                ($pool_address:ident, $agent:ty, $config:expr, $size:expr, $queue_size: expr, $restart_policy: expr) => {
+                    crate::postmaster::register_agent_pool!(address = <$address_enum>::$pool_address, $agent, $config, $size, $queue_size, $restart_policy)
+                };
+                ($pool_address:ident, $agent:ty, $config:expr, $size:expr, $queue_size: expr) => {
+                    crate::postmaster::register_agent_pool!(address = <$address_enum>::$pool_address, $agent, $config, $size, $queue_size)
+                };
+                ($pool_address:ident, $agent:ty, $config:expr, $size:expr) => {
+                    crate::postmaster::register_agent_pool!(address = <$address_enum>::$pool_address, $agent, $config, $size)
+                };
+            }
+
+            #[doc(hidden)]
+            #[cfg(not(target_os = "none"))]
+            pub use _register_agent_pool as register_agent_pool;
+
             /// This function can be used to register a standalone address with the Postmaster.
             /// When registering an Agent (using the register_agent!() macro), the Agent's message queue is generated and assigned to the given address automatically.
             /// However, there may be some scenarios where you may want to register a message queue without tying it to an Agent.
@@ -179,7 +982,7 @@ macro_rules! init_postmaster {
                 address: $address_enum,
                 mailbox: DynamicSender<'static, Message>,
             ) -> Result<(), PostmasterError> {
-                postmaster_internal::register(address, mailbox).await
+                POSTMASTER.register(address, mailbox).await
             }
 
             /// This function can be used to register a standalone address with the Postmaster.
@@ -209,77 +1012,897 @@ macro_rules! init_postmaster {
                 address: $address_enum,
                 mailbox: Sender<Message>,
             ) -> Result<(), PostmasterError> {
-                postmaster_internal::register(address, mailbox).await
+                POSTMASTER.register(address, mailbox).await
             }
 
 
-            /// Send a message using the Postmaster's default timeout
-            /// The Postmaster will attempt to push the message onto the destination Agent's queue.
-            /// The future returned by this function will resolve when either:
-            /// - The message was added to the queue successfully
-            /// - The message could not be added to the queue before the timeout expired.
-            /// Reasons for failure include:
-            /// - The message queue being consistently full for longer than the timeout
-            /// - The Postmaster being unable to acquire a lock on the senders before the timeout expires
-            /// - There being no recipient registered at the destination address
-            pub async fn send(
-                destination: $address_enum,
-                source: $address_enum,
-                payload: $payload_enum,
+            /// Allocate a new dynamic address.
+            /// Each call returns a unique address, which can then be used to register an Agent or a standalone message queue, for example one Agent per incoming network connection.
+            /// The address type must support dynamic addresses (see `post_haste::address::AddressSpace`), otherwise this fails with `PostmasterError::DynamicAddressesUnsupported`.
+            #[cfg(not(target_os = "none"))]
+            pub fn allocate_address() -> Result<$address_enum, PostmasterError> {
+                POSTMASTER.allocate_address()
+            }
+
+            /// Remove the message queue registered at the given address.
+            /// Any subsequent messages sent to the address will fail with `PostmasterError::NoRecipient`, until a new message queue is registered there.
+            /// If the address belongs to an Agent registered with `register_agent!()`, the Agent's task is stopped.
+            /// Any Agents watching the address (see `watch()`) are notified that it has terminated.
+            pub async fn deregister(address: $address_enum) -> Result<(), PostmasterError> {
+                POSTMASTER.deregister(address).await
+            }
+
+            /// Watch an address for termination.
+            /// When the watched Agent terminates, either by panicking (and not being restarted) or by its address being deregistered, the watcher is sent a message from the watched address.
+            /// As the Postmaster has no knowledge of the project's payloads, the notification is converted into a payload using the provided function, which is most conveniently a variant of the payload enum wrapping `AgentTerminated`.
+            /// Each watch is triggered only once: after notifying the watcher it is removed.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above, with a `Payloads::Terminated(AgentTerminated<Address>)` variant...
+            ///
+            /// postmaster::watch(Address::Backup, Address::Primary, Payloads::Terminated).await.unwrap();
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub async fn watch(
+                watcher: $address_enum,
+                watched: $address_enum,
+                notification: fn(post_haste::agent::AgentTerminated<$address_enum>) -> $payload_enum,
             ) -> Result<(), PostmasterError> {
-                postmaster_internal::send_internal(destination, Message { source, payload }, None)
-                    .await
+                POSTMASTER.watch(watcher, watched, notification).await
             }
 
-            /// Attempt to send a message without waiting
-            /// This function works very similarly to `postmaster::send()`, however if this is not immediately possible it will return with an error rather than attempting to wait for a timeout period.
-            /// Reasons for failure include:
-            /// - The recipient's message queue being full
-            /// - The lock on the senders not being available
-            /// - There being no recipient registered at the destination address
-            pub fn try_send(
-                destination: $address_enum,
-                source: $address_enum,
-                payload: $payload_enum,
+            /// Stop watching an address, removing any watches previously added with `watch()` for the given watcher and watched addresses.
+            #[cfg(not(target_os = "none"))]
+            pub fn unwatch(watcher: $address_enum, watched: $address_enum) {
+                POSTMASTER.unwatch(watcher, watched)
+            }
+
+            /// Link a child Agent to its parent, so that the parent is notified (as with `watch()`) when the child terminates, and the child is stopped when the parent terminates or is restarted.
+            /// Children are usually registered at a dynamic address and linked in one go with `postmaster::register_child!()`.
+            #[cfg(not(target_os = "none"))]
+            pub async fn link_child(
+                parent: $address_enum,
+                child: $address_enum,
+                notification: fn(post_haste::agent::AgentTerminated<$address_enum>) -> $payload_enum,
             ) -> Result<(), PostmasterError> {
-                postmaster_internal::try_send_internal(destination, Message { source, payload })
+                POSTMASTER.link_child(parent, child, notification).await
             }
 
-            /// Begin building a message with custom settings
-            /// The function takes a source and destination address and a payload, but instead of immediately attempting to send the message, it instead returns a MessageBuilder type.
+            /// The children currently linked to the given parent
+            #[cfg(not(target_os = "none"))]
+            pub fn children(parent: $address_enum) -> Vec<$address_enum> {
+                POSTMASTER.children(parent)
+            }
+
+            /// Stop handing regular messages to the Agent (or pool of Agents) at the given address, e.g. to quiesce it while its configuration or an upstream dependency is swapped out.
+            /// Messages sent to the Agent wait on its queue until it is resumed with `postmaster::resume()`, so senders are held up (and eventually time out) once the queue is full.
+            /// Control messages are still received while the Agent is paused.
+            /// Fails with `PostmasterError::NotAnAgent` if a standalone message queue is registered at the address.
+            #[cfg(not(target_os = "none"))]
+            pub fn pause(address: $address_enum) -> Result<(), PostmasterError> {
+                POSTMASTER.pause(address)
+            }
+
+            /// Resume handing messages to an Agent paused with `postmaster::pause()`, starting with the messages which have waited on its queue
+            #[cfg(not(target_os = "none"))]
+            pub fn resume(address: $address_enum) -> Result<(), PostmasterError> {
+                POSTMASTER.resume(address)
+            }
+
+            /// Check that the Agent at the given address is alive, returning the time it took to answer a ping.
+            /// Pings are answered by the Agent's inbox the next time the Agent receives from it, so no payload variant is needed for them, but an Agent which is stuck doesn't answer.
+            /// Fails with `PostmasterError::Timeout` if the Agent doesn't answer in time, or with `PostmasterError::NotAnAgent` if a standalone message queue is registered at the address.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above...
+            ///
+            /// if postmaster::ping(Address::Display, Duration::from_millis(100)).await.is_err() {
+            ///     eprintln!("The display Agent isn't responding");
+            /// }
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub async fn ping(address: $address_enum, timeout: Duration) -> Result<Duration, PostmasterError> {
+                POSTMASTER.ping(address, timeout).await
+            }
+
+            /// Ping every registered Agent at once, as with `postmaster::ping()`, returning the result for each of them, e.g. for a health check endpoint
+            #[cfg(not(target_os = "none"))]
+            pub async fn ping_all(timeout: Duration) -> Vec<($address_enum, Result<Duration, PostmasterError>)> {
+                POSTMASTER.ping_all(timeout).await
+            }
+
+            /// Push a new config to the running Agent at the given address (or every Agent in its pool), e.g. to change a threshold without restarting the Agent.
+            /// The Agent must implement `post_haste::agent::Reconfigure` and receive with `Inbox::recv_reconfiguring()`, which applies the config before any waiting messages, and this waits until it has been applied.
+            /// Fails with `PostmasterError::NotReconfigurable` if the Agent doesn't take configs of this type, or with `PostmasterError::NotAnAgent` if a standalone message queue is registered at the address.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above...
+            ///
+            /// postmaster::reconfigure(Address::Sensor, SensorConfig { threshold: 40 }).await?;
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub async fn reconfigure<C>(address: $address_enum, config: C) -> Result<(), PostmasterError>
+            where
+                C: Clone + Send + 'static,
+            {
+                POSTMASTER.reconfigure(address, config).await
+            }
+
+            /// Create an empty group of addresses, e.g. for all of the display Agents.
+            /// Addresses join and leave the group with `join_group()` and `leave_group()`, and `send_to_group()` sends a message to every member, so senders don't need to track the membership themselves.
+            /// Fails with `PostmasterError::GroupAlreadyExists` if the group has already been created.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above...
+            ///
+            /// postmaster::create_group("displays").unwrap();
+            /// postmaster::join_group("displays", Address::FrontPanel).unwrap();
+            /// postmaster::join_group("displays", Address::Dashboard).unwrap();
+            /// let delivered = postmaster::send_to_group("displays", Address::Clock, Payloads::Time(now)).await.unwrap();
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub fn create_group(group: &'static str) -> Result<(), PostmasterError> {
+                POSTMASTER.create_group(group)
+            }
+
+            /// Remove a group, along with its membership.
+            /// Fails with `PostmasterError::NoSuchGroup` if the group hasn't been created.
+            #[cfg(not(target_os = "none"))]
+            pub fn delete_group(group: &'static str) -> Result<(), PostmasterError> {
+                POSTMASTER.delete_group(group)
+            }
+
+            /// Add an address to a group, which has no effect if it is already a member.
+            /// An address leaves all of its groups when it is deregistered, but remains a member while its Agent is restarted.
+            /// Fails with `PostmasterError::NoSuchGroup` if the group hasn't been created.
+            #[cfg(not(target_os = "none"))]
+            pub fn join_group(group: &'static str, address: $address_enum) -> Result<(), PostmasterError> {
+                POSTMASTER.join_group(group, address)
+            }
+
+            /// Remove an address from a group, which has no effect if it is not a member.
+            /// Fails with `PostmasterError::NoSuchGroup` if the group hasn't been created.
+            #[cfg(not(target_os = "none"))]
+            pub fn leave_group(group: &'static str, address: $address_enum) -> Result<(), PostmasterError> {
+                POSTMASTER.leave_group(group, address)
+            }
+
+            /// Send a copy of a message to every member of a group, using the Postmaster's default timeout for each copy.
+            /// A copy which can't be delivered (e.g. because the member's queue stays full) doesn't stop the others from being sent.
+            /// Returns the number of members the message was delivered to, or fails with `PostmasterError::NoSuchGroup` if the group hasn't been created.
+            /// The payload is cloned for each member, so a large payload can be wrapped in a `post_haste::postmaster::SharedPayload` to share it between the members instead.
+            #[cfg(not(target_os = "none"))]
+            pub async fn send_to_group(
+                group: &'static str,
+                source: $address_enum,
+                payload: impl Into<$payload_enum> + Clone,
+            ) -> Result<usize, PostmasterError> {
+                POSTMASTER.send_to_group(group, source, payload).await
+            }
+
+            /// Send a message using the Postmaster's default timeout
+            /// The Postmaster will attempt to push the message onto the destination Agent's queue.
+            /// The future returned by this function will resolve when either:
+            /// - The message was added to the queue successfully
+            /// - The message could not be added to the queue before the timeout expired.
+            /// Reasons for failure include:
+            /// - The message queue being consistently full for longer than the timeout
+            /// - The Postmaster being unable to acquire a lock on the senders before the timeout expires (on bare metal targets)
+            /// - There being no recipient registered at the destination address
+            pub async fn send(
+                destination: $address_enum,
+                source: $address_enum,
+                payload: impl Into<$payload_enum>,
+            ) -> Result<(), PostmasterError> {
+                POSTMASTER.send(destination, source, payload.into()).await
+            }
+
+            /// Send a message using the Postmaster's default timeout, handing the payload back if it can't be sent.
+            /// This works in the same way as `postmaster::send()`, except that a failure results in a `SendError` holding the payload where possible, with a variant for each of the common causes, so the sender can retry, persist or reroute the message without cloning it beforehand.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above...
+            ///
+            /// match postmaster::send_returning(Address::Logger, Address::Sensor, reading).await {
+            ///     Err(SendError::NoRecipient(reading)) => pending.push(reading),
+            ///     Err(SendError::Timeout(reading)) => journal.write(reading),
+            ///     _ => (),
+            /// }
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub async fn send_returning(
+                destination: $address_enum,
+                source: $address_enum,
+                payload: impl Into<$payload_enum>,
+            ) -> Result<(), SendError> {
+                POSTMASTER.send_returning(destination, source, payload.into()).await
+            }
+
+            /// Send a message, giving up once the timeout has elapsed and handing the payload back as `SendError::Timeout(payload)` if it does.
+            /// Unlike the timeout of `postmaster::send()`, which only limits how long the message waits for space on the recipient's queue, this bounds the whole send, including any wait for the sender's rate limit, so a latency-sensitive sender is never held up for longer than it can afford by a misbehaving recipient.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above...
+            ///
+            /// // The control loop must never block for more than 10 ms
+            /// if let Err(SendError::Timeout(command)) = postmaster::send_with_timeout(Address::Motor, Address::Controller, command, Duration::from_millis(10)).await {
+            ///     skipped.push(command);
+            /// }
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub async fn send_with_timeout(
+                destination: $address_enum,
+                source: $address_enum,
+                payload: impl Into<$payload_enum>,
+                timeout: Duration,
+            ) -> Result<(), SendError> {
+                POSTMASTER.send_with_timeout(destination, source, payload.into(), timeout).await
+            }
+
+            /// Send a message to a `TypedAgent` through its `post_haste::agent::typed::Recipient`, using the Postmaster's default timeout.
+            /// The message must be of the type the recipient accepts, so sending the wrong type of message to the Agent fails to compile.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above, with `LIGHTS` declared as a `Recipient<Addresses, LightsMessage>`...
+            ///
+            /// postmaster::send_to(LIGHTS, Addresses::ButtonTask, LightsMessage::Display).await.unwrap();
+            /// ```
+            pub async fn send_to<M: Into<$payload_enum>>(
+                recipient: post_haste::agent::typed::Recipient<$address_enum, M>,
+                source: $address_enum,
+                payload: M,
+            ) -> Result<(), PostmasterError> {
+                POSTMASTER.send(recipient.address(), source, payload.into()).await
+            }
+
+            /// Send a message, waiting for as long as it takes for space on the destination Agent's queue rather than giving up after the Postmaster's default timeout.
+            /// A sender which produces messages faster than the recipient handles them is slowed down to the recipient's pace (backpressure), rather than having its messages fail.
+            /// Reasons for failure include:
+            /// - There being no recipient registered at the destination address
+            /// - The recipient stopping while the message is waiting
+            #[cfg(not(target_os = "none"))]
+            pub async fn send_with_backpressure(
+                destination: $address_enum,
+                source: $address_enum,
+                payload: impl Into<$payload_enum>,
+            ) -> Result<(), PostmasterError> {
+                POSTMASTER.send_with_backpressure(destination, source, payload.into()).await
+            }
+
+            /// Send a batch of messages from the same source to the same destination, using the Postmaster's default timeout for the batch as a whole.
+            /// No other messages are interleaved with the batch, and the destination is only looked up once.
+            /// With tokio, space for the whole batch is reserved on the recipient's queue before any message is added to it, so either all of the messages are delivered or none are.
+            /// Every message is checked against the send policy, the rate limit and the interceptors before any is delivered, so one which is refused or rejected fails the whole batch.
+            /// On bare metal targets the Postmaster's lock on the senders is held while the messages are added one at a time, so if the timeout expires part-way through, the messages before it will already have been delivered.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above...
+            ///
+            /// postmaster::send_all(
+            ///     Address::Logger,
+            ///     Address::Clock,
+            ///     (0..100).map(|tick| Payloads::Tick(tick)),
+            /// ).await.unwrap();
+            /// ```
+            pub async fn send_all(
+                destination: $address_enum,
+                source: $address_enum,
+                payloads: impl IntoIterator<Item = impl Into<$payload_enum>>,
+            ) -> Result<(), PostmasterError> {
+                POSTMASTER.send_all(destination, source, payloads.into_iter().map(Into::into)).await
+            }
+
+            /// Send a copy of a message to every registered address which matches a pattern, so that one send can reach a whole subtree of nested addresses.
+            /// Only static addresses are matched, and each copy is sent with the Postmaster's default timeout; a copy which can't be delivered doesn't stop the others from being sent.
+            /// Returns the number of Agents the message was delivered to, or fails with `PostmasterError::NoRecipient` if no registered address matches the pattern.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above, with a `#[nested] Sensors(Sensor)` address variant...
+            ///
+            /// postmaster::send_matching(
+            ///     |address| matches!(address, Address::Sensors(Sensor::Temperature(_))),
+            ///     Address::Controller,
+            ///     Payloads::Calibrate,
+            /// ).await.unwrap();
+            /// ```
+            pub async fn send_matching(
+                pattern: impl Fn(&$address_enum) -> bool,
+                source: $address_enum,
+                payload: impl Into<$payload_enum> + Clone,
+            ) -> Result<usize, PostmasterError> {
+                POSTMASTER.send_matching(pattern, source, payload).await
+            }
+
+            /// Send a message from synchronous code running outside of any tokio runtime, such as a C callback or a dedicated OS thread, blocking the thread until the message is sent.
+            /// This works in the same way as `postmaster::send()`, including the default timeout, but doesn't need a handle to the runtime which the Agents run on.
+            /// It panics if called from within an asynchronous context, where `postmaster::send()` should be awaited instead.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above...
+            ///
+            /// extern "C" fn on_sample(value: u32) {
+            ///     let _ = postmaster::blocking_send(Address::Sampler, Address::Driver, Payloads::Sample(value));
+            /// }
+            /// ```
+            #[cfg(not(any(target_os = "none", target_family = "wasm")))]
+            pub fn blocking_send(
+                destination: $address_enum,
+                source: $address_enum,
+                payload: impl Into<$payload_enum>,
+            ) -> Result<(), PostmasterError> {
+                POSTMASTER.blocking_send(destination, source, payload.into())
+            }
+
+            /// Attempt to send a message without waiting
+            /// This function works very similarly to `postmaster::send()`, however if this is not immediately possible it will return with an error rather than attempting to wait for a timeout period.
+            /// Reasons for failure include:
+            /// - The recipient's message queue being full
+            /// - The lock on the senders not being available (on bare metal targets)
+            /// - There being no recipient registered at the destination address
+            pub fn try_send(
+                destination: $address_enum,
+                source: $address_enum,
+                payload: impl Into<$payload_enum>,
+            ) -> Result<(), PostmasterError> {
+                POSTMASTER.try_send(destination, source, payload.into())
+            }
+
+            /// Attempt to send a message without waiting, handing the payload back if it can't be sent.
+            /// This works in the same way as `postmaster::try_send()`, except that a full message queue results in `TrySendError::Full(payload)`, and other failures also return the payload where possible, so the sender can decide what to do with a message which the recipient has no room for.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above...
+            ///
+            /// if let Err(TrySendError::Full(reading)) = postmaster::try_send_returning(Address::Logger, Address::Sensor, reading) {
+            ///     backlog.push(reading);
+            /// }
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub fn try_send_returning(
+                destination: $address_enum,
+                source: $address_enum,
+                payload: impl Into<$payload_enum>,
+            ) -> Result<(), TrySendError> {
+                POSTMASTER.try_send_returning(destination, source, payload.into())
+            }
+
+            /// Begin building a message with custom settings
+            /// The function takes a source and destination address and a payload, but instead of immediately attempting to send the message, it instead returns a MessageBuilder type.
             /// The MessageBuilder provides methods to further configure the message before it is sent.
             pub fn message(
                 destination: $address_enum,
                 source: $address_enum,
-                payload: $payload_enum,
+                payload: impl Into<$payload_enum>,
+            ) -> MessageBuilder {
+                POSTMASTER.message(destination, source, payload.into())
+            }
+
+            /// Begin building a reply to a received message.
+            /// The reply is addressed to the message's `reply_address()` (the `reply_to` address set by its sender, or otherwise its source), and carries the message's correlation ID, so a reply can be sent without the replying Agent needing to know who is waiting for it.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Within an Agent's run() function...
+            ///
+            /// let message = self.inbox.recv().await.unwrap();
+            /// postmaster::reply(&message, self.address, Payloads::Ack)
+            ///     .send()
+            ///     .await
+            ///     .unwrap();
+            /// ```
+            pub fn reply(
+                request: &Message,
+                source: $address_enum,
+                payload: impl Into<$payload_enum>,
             ) -> MessageBuilder {
-                MessageBuilder {
-                    destination,
-                    message: Message { source, payload },
-                    timeout: None,
-                    delay: None,
+                let builder = message(request.reply_address(), source, payload.into());
+                match request.correlation_id {
+                    Some(correlation_id) => builder.with_correlation_id(correlation_id),
+                    None => builder,
                 }
             }
 
+            /// Replying to a received message without naming the replying Agent's address, as a method of the message.
+            /// The reply is built as with `postmaster::reply()`, sent from the address the message was sent to.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Within an Agent's run() function...
+            /// use postmaster::Reply;
+            ///
+            /// let message = self.inbox.recv().await.unwrap();
+            /// message.reply(Payloads::Ack).send().await.unwrap();
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub trait Reply {
+                /// Begin building a reply to the message, addressed to its `reply_address()` and carrying its correlation ID
+                fn reply(&self, payload: impl Into<$payload_enum>) -> MessageBuilder;
+            }
+
+            #[cfg(not(target_os = "none"))]
+            impl Reply for Message {
+                fn reply(&self, payload: impl Into<$payload_enum>) -> MessageBuilder {
+                    let source = self
+                        .destination()
+                        .expect("Only a message which has been sent can be replied to");
+                    reply(self, source, payload)
+                }
+            }
+
+            /// Send a received message on to another address, keeping its original source, correlation ID and reply-to address, so that the new recipient replies to the original sender rather than to the Agent passing it on.
+            /// This is meant for proxies and load balancers, which would otherwise have to send a new message from their own address.
+            /// An acknowledgement requested by the original sender travels with the message.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Within a load balancer's run() function...
+            ///
+            /// let message = self.inbox.recv().await.unwrap();
+            /// postmaster::forward(self.next_worker(), message).await.unwrap();
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub async fn forward(destination: $address_enum, message: Message) -> Result<(), PostmasterError> {
+                POSTMASTER.forward(destination, message).await
+            }
+
+            /// Send a request to an Agent and wait for its reply.
+            /// A temporary message queue is registered at `source`, which must be vacant (e.g. a dynamic address from `postmaster::allocate_address()`), and the payload is sent to the destination from that address.
+            /// The Agent replies as normal, by sending a message back to the source of the request, and the first message to arrive there is returned.
+            /// Fails with `PostmasterError::Timeout` if no reply arrives before the timeout expires.
+            ///
+            /// If many callers may make the same idempotent request at once, `postmaster::instance().request_coalesced()` (which requires the payload type to implement Clone) sends only one of them, and shares its reply with the others.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above, with a dynamic address variant...
+            ///
+            /// let reply = postmaster::request(
+            ///     Address::Config,
+            ///     postmaster::allocate_address().unwrap(),
+            ///     Payloads::Lookup { key },
+            ///     Duration::from_millis(100),
+            /// ).await?;
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub async fn request(
+                destination: $address_enum,
+                source: $address_enum,
+                payload: impl Into<$payload_enum>,
+                timeout: Duration,
+            ) -> Result<Message, PostmasterError> {
+                POSTMASTER.request(destination, source, payload, timeout).await
+            }
+
+            /// Send a request to several Agents and gather their replies, e.g. to implement a quorum read.
+            /// A temporary message queue is registered at `source`, which must be vacant (e.g. a dynamic address from `postmaster::allocate_address()`), and the payload is sent to each of the destinations from that address.
+            /// Each Agent replies as normal, by sending a message back to the source of the request.
+            /// The replies are collected until every Agent which was successfully sent the request has replied, or until the timeout expires.
+            /// In the event of a timeout, only the replies received so far are returned, so the caller can decide whether enough Agents have replied.
+            /// Once finished, the temporary message queue is removed again.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above, with a dynamic address variant...
+            ///
+            /// let replies = postmaster::scatter_gather(
+            ///     [Address::ReplicaA, Address::ReplicaB, Address::ReplicaC],
+            ///     postmaster::allocate_address().unwrap(),
+            ///     Payloads::Read { key },
+            ///     Duration::from_millis(100),
+            /// ).await.unwrap();
+            /// let quorum = replies.len() >= 2;
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub async fn scatter_gather(
+                destinations: impl IntoIterator<Item = $address_enum>,
+                source: $address_enum,
+                payload: impl Into<$payload_enum> + Clone,
+                timeout: Duration,
+            ) -> Result<Vec<Message>, PostmasterError> {
+                POSTMASTER.scatter_gather(destinations, source, payload, timeout).await
+            }
+
+            /// Ask every registered Agent for a snapshot of its state, e.g. to persist the whole system's state or to freeze it for inspection.
+            /// Each Agent is sent a `SnapshotRequest` from `source`, wrapped in a payload by `request` (most conveniently a variant of the payload enum), and responds with `request.respond_with(&self)` if it implements `Snapshot`.
+            /// Agents which don't respond before the timeout are left out of the returned `SystemSnapshot`.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above, with a `Payloads::Snapshot(SnapshotRequest)` variant...
+            ///
+            /// let snapshot = postmaster::snapshot_all(Address::Admin, Payloads::Snapshot, Duration::from_millis(100)).await;
+            /// // Later, with a `Payloads::Restore(Vec<u8>)` variant handled by calling `Snapshot::restore()`...
+            /// postmaster::restore_all(&snapshot, Address::Admin, Payloads::Restore).await;
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub async fn snapshot_all(
+                source: $address_enum,
+                request: fn(post_haste::agent::snapshot::SnapshotRequest) -> $payload_enum,
+                timeout: Duration,
+            ) -> post_haste::agent::snapshot::SystemSnapshot<$address_enum> {
+                POSTMASTER.snapshot_all(source, request, timeout).await
+            }
+
+            /// Send each Agent in a snapshot taken with `snapshot_all()` its own snapshot from `source`, wrapped in a payload by `restore`, so that it can restore its state.
+            /// Returns the number of Agents the snapshots were delivered to.
+            #[cfg(not(target_os = "none"))]
+            pub async fn restore_all(
+                snapshot: &post_haste::agent::snapshot::SystemSnapshot<$address_enum>,
+                source: $address_enum,
+                restore: fn(Vec<u8>) -> $payload_enum,
+            ) -> usize {
+                POSTMASTER.restore_all(snapshot, source, restore).await
+            }
+
             /// Retrieve diagnostic information for the Postmaster
             /// The diagnostics contain information about how many messages have been sent in total since boot, and how many (if any) sending failures have occurred.
             /// This provides a simple high-level overview of the health of the system.
             pub fn get_diagnostics() -> Diagnostics {
-                postmaster_internal::get_diagnostics()
+                POSTMASTER.get_diagnostics()
+            }
+
+            /// Take a snapshot of the Postmaster's metrics for each Agent.
+            /// For every address, the metrics contain the number of messages it has sent, received and had dropped, how full its message queue currently is, and a histogram of how long senders waited for their messages to be placed on its queue.
+            /// An Agent which is the bottleneck in a system has a full queue and a rising delivery latency.
+            #[cfg(not(target_os = "none"))]
+            pub async fn metrics() -> Metrics {
+                POSTMASTER.metrics().await
+            }
+
+            /// Take a snapshot of the state of each address known to the Postmaster, e.g. for an operations console.
+            /// For each address it gives whether a queue is registered, whether its Agent's task is still running, the depth of its queue, the number of messages its Agent has processed, and the number of delayed messages and timers pending for it.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above...
+            ///
+            /// for address in postmaster::status().await.addresses {
+            ///     println!("{:?}: {} of {} queued, {:?} processed", address.address, address.queue_depth, address.queue_capacity, address.messages_processed);
+            /// }
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub async fn status() -> Status {
+                POSTMASTER.status().await
+            }
+
+            /// The most recent messages which couldn't be delivered, oldest first, giving the source and destination of each and why it failed
+            #[cfg(not(target_os = "none"))]
+            pub fn dead_letters() -> Vec<post_haste::postmaster::DeadLetter<$address_enum>> {
+                POSTMASTER.dead_letters()
+            }
+
+            /// Drain the Postmaster as part of an orderly shutdown, waiting (for up to the timeout) for the Agents to work through the messages already on their queues.
+            /// From now on, only messages sent by Agents and control messages are delivered, and other sends fail with `PostmasterError::Draining`.
+            /// The report gives the number of messages left on each Agent's queue, so an Agent which stopped with messages unprocessed can be told apart from one which finished its work.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above...
+            ///
+            /// let report = postmaster::drain(Duration::from_secs(5)).await;
+            /// for agent in report.agents.iter().filter(|agent| agent.remaining > 0) {
+            ///     eprintln!("{:?} stopped with {} messages unprocessed", agent.address, agent.remaining);
+            /// }
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub async fn drain(timeout: Duration) -> DrainReport {
+                POSTMASTER.drain(timeout).await
+            }
+
+            /// Drain the Postmaster as with `postmaster::drain()`, then stop the Agents started with `postmaster::startup()` in the reverse of the order they were started, each phase of Agents once they have worked through their queues.
+            /// Agents which weren't started with `postmaster::startup()` are drained first, and left registered.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Agents have been started with postmaster::startup() above...
+            ///
+            /// let report = postmaster::shutdown(Duration::from_secs(5)).await;
+            /// assert!(report.is_complete());
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub async fn shutdown(timeout: Duration) -> DrainReport {
+                POSTMASTER.shutdown(timeout).await
+            }
+
+            /// Start a system of Agents in the order of their dependencies, so that each Agent is only registered once the Agents it depends on are ready.
+            /// The order is recorded, so that `postmaster::shutdown()` and `postmaster::run_until_shutdown()` stop the Agents in reverse.
+            /// See `post_haste::postmaster::Startup` for details.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above...
+            ///
+            /// postmaster::startup()
+            ///     .with_agent(Address::Lights, [], async { postmaster::register_agent!(Lights, LightsAgent, ()) })
+            ///     .with_agent(Address::Sequencer, [Address::Lights], async { postmaster::register_agent!(Sequencer, SequencerAgent, ()) })
+            ///     .on_ready(|address| println!("{address:?} is ready"))
+            ///     .start()
+            ///     .await
+            ///     .unwrap();
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub fn startup() -> post_haste::postmaster::Startup<$address_enum, $payload_enum> {
+                post_haste::postmaster::Startup::new(&POSTMASTER)
+            }
+
+            /// Keep the program running until it is asked to stop with ctrl-c (or SIGTERM on Unix), then shut down gracefully with `postmaster::shutdown()`, draining the Postmaster for up to the timeout.
+            /// A second signal while draining cuts the drain short.
+            /// The report gives the signal, how the drain went and the exit code for the program to exit with.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised and Agents registered above...
+            ///
+            /// let report = postmaster::run_until_shutdown(Duration::from_secs(5)).await;
+            /// if !report.is_clean() {
+            ///     eprintln!("Shut down with {} messages unprocessed", report.drain.remaining());
+            /// }
+            /// std::process::exit(report.exit_code());
+            /// ```
+            #[cfg(not(any(target_os = "none", target_family = "wasm")))]
+            pub async fn run_until_shutdown(drain_timeout: Duration) -> ShutdownReport {
+                POSTMASTER.run_until_shutdown(drain_timeout).await
             }
 
             /// Change the Postmaster's default timeout for sending messages
             pub fn set_timeout(timeout_us: u32) {
-                postmaster_internal::set_timeout(timeout_us)
+                POSTMASTER.set_timeout(timeout_us)
             }
 
             /// Pass a reference to the spawner to the Postmaster for use in delayed messages.
             /// Please note that you should not need to call this function, as the Postmaster automatically acquires a reference to the spawner when an Agent is registered with `register_agent!()`.
             #[cfg(target_os = "none")]
             pub fn set_spawner(spawner: Spawner) {
-                postmaster_internal::set_spawner(spawner)
+                POSTMASTER.set_spawner(spawner)
+            }
+
+            /// Start a watchdog which calls `sink` whenever an Agent with messages waiting on its queue goes for `stall_after` without receiving any of them, e.g. because it has deadlocked.
+            /// The sink receives the address of the Agent, the number of messages waiting for it and when it last made progress, and each stall is only reported once.
+            /// Starting a watchdog replaces any watchdog already running.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above...
+            ///
+            /// postmaster::start_watchdog(Duration::from_secs(5), |stall| {
+            ///     eprintln!("{:?} has been stuck for {:?} with {} messages waiting", stall.address, stall.stalled_for, stall.queue_depth);
+            /// });
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub fn start_watchdog(stall_after: Duration, sink: impl Fn(&post_haste::postmaster::AgentStall<$address_enum>) + Send + Sync + 'static) {
+                POSTMASTER.start_watchdog(stall_after, sink)
+            }
+
+            /// Stop the watchdog started with `start_watchdog()`, if one is running
+            #[cfg(not(target_os = "none"))]
+            pub fn stop_watchdog() {
+                POSTMASTER.stop_watchdog()
+            }
+
+            /// Set a hook to be called whenever an Agent panics.
+            /// The hook receives the address of the Agent, the message it panicked with and whether it is being restarted.
+            /// Only one hook can be set at a time: setting a new hook replaces the previous one.
+            /// Once a hook is set, panics are no longer logged by the Postmaster itself.
+            #[cfg(not(target_os = "none"))]
+            pub fn set_panic_hook(hook: impl Fn(&post_haste::agent::AgentPanic<$address_enum>) + Send + Sync + 'static) {
+                POSTMASTER.set_panic_hook(hook)
+            }
+
+            /// Deliver messages sent to addresses with no recipient registered to the given address instead, rather than failing with `PostmasterError::NoRecipient`.
+            /// The recipient can tell where each message was meant to go from `Message::intended_destination()`, e.g. for a proxy which answers on behalf of Agents which haven't been started yet.
+            /// Setting a new fallback replaces the previous one.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above...
+            ///
+            /// postmaster::set_fallback(Address::Proxy);
+            /// // Delivered to the proxy, with an intended destination of Address::Printer, until a printer Agent is registered
+            /// postmaster::send(Address::Printer, Address::Main, Payloads::Print(page)).await?;
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub fn set_fallback(address: $address_enum) {
+                POSTMASTER.set_fallback(address)
+            }
+
+            /// Stop redirecting messages to the fallback address set with `postmaster::set_fallback()`
+            #[cfg(not(target_os = "none"))]
+            pub fn clear_fallback() {
+                POSTMASTER.clear_fallback()
+            }
+
+            /// Add an interceptor, which sees every message before it is delivered and decides whether it is passed on, redirected, dropped or rejected.
+            /// Interceptors may also modify the message, and are applied in the order in which they were added.
+            /// See `post_haste::postmaster::Interceptor` for details.
+            #[cfg(not(target_os = "none"))]
+            pub fn add_interceptor(interceptor: impl post_haste::postmaster::Interceptor<$address_enum, $payload_enum> + 'static) {
+                POSTMASTER.add_interceptor(interceptor)
+            }
+
+            /// Remove all interceptors
+            #[cfg(not(target_os = "none"))]
+            pub fn clear_interceptors() {
+                POSTMASTER.clear_interceptors()
+            }
+
+            /// Only deliver the messages allowed by the given policy, which declares which source addresses may send which payload variants to which destinations, replacing any previous policy.
+            /// Any other message fails to send with `PostmasterError::Forbidden`, and is reported to the policy's violation hook and kept with the dead letters.
+            /// See `post_haste::postmaster::SendPolicy` for details.
+            #[cfg(not(target_os = "none"))]
+            pub fn set_send_policy(policy: post_haste::postmaster::SendPolicy<$address_enum, $payload_enum>) {
+                POSTMASTER.set_send_policy(policy)
+            }
+
+            /// Remove the send policy set with `postmaster::set_send_policy()`, so that every message may be sent again
+            #[cfg(not(target_os = "none"))]
+            pub fn clear_send_policy() {
+                POSTMASTER.clear_send_policy()
+            }
+
+            /// Limit the rate at which the given address may send messages, e.g. so that a chatty Agent can't flood a slow one.
+            /// Messages exceeding the limit either wait until they can be sent or fail with `PostmasterError::RateLimited`, depending on the limit (see `post_haste::postmaster::RateLimit`).
+            /// Any previous limit for the address is replaced.
+            #[cfg(not(target_os = "none"))]
+            pub fn set_rate_limit(source: $address_enum, limit: post_haste::postmaster::RateLimit) {
+                POSTMASTER.set_rate_limit(source, limit)
+            }
+
+            /// Remove the rate limit for the given address
+            #[cfg(not(target_os = "none"))]
+            pub fn remove_rate_limit(source: $address_enum) {
+                POSTMASTER.remove_rate_limit(source)
+            }
+
+            /// Change how long the IDs of messages delivered to the given address are remembered for, in order to discard duplicates of messages sent with `with_id()`.
+            /// The window defaults to `post_haste::postmaster::DEFAULT_DEDUP_WINDOW` (60 seconds).
+            #[cfg(not(target_os = "none"))]
+            pub fn set_dedup_window(address: $address_enum, window: Duration) {
+                POSTMASTER.set_dedup_window(address, window)
+            }
+
+            /// Run a future in its own task, and send its output to an Agent from itself (using the Postmaster's default timeout) once it completes.
+            /// This lets an Agent start asynchronous work, such as I/O, without blocking its main loop, and then handle the result as a message.
+            /// The returned handle can be used to abort the work, or awaited to find out whether the result was delivered.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Within an Agent...
+            ///
+            /// postmaster::pipe_to_self(self.address, async move {
+            ///     Payloads::Fetched(client.get(url).await)
+            /// });
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub fn pipe_to_self(
+                address: $address_enum,
+                future: impl Future<Output = $payload_enum> + Send + 'static,
+            ) -> post_haste::dependencies::task::JoinHandle<Result<(), PostmasterError>> {
+                POSTMASTER.pipe_to_self(address, future)
+            }
+
+            /// Forward every item from a stream (e.g. of input from a device or a socket) to an Agent, converting each item into a payload with `map`.
+            /// This lets an Agent handle external input as messages in its main loop, alongside the messages from other Agents.
+            /// Items are sent from `source` using the Postmaster's default timeout: an item which can't be delivered in time is dropped, and forwarding stops when the stream ends or once the destination is no longer registered.
+            /// The returned handle can be used to stop forwarding.
+            ///
+            /// # Example
+            /// ```rust
+            /// // Assuming Postmaster has been initialised above...
+            ///
+            /// let lines = tokio_stream::wrappers::LinesStream::new(BufReader::new(stdin()).lines());
+            /// postmaster::attach_stream(Address::Button, Address::Stdin, lines, |_line| Payloads::ButtonPress);
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub fn attach_stream<S>(
+                destination: $address_enum,
+                source: $address_enum,
+                stream: S,
+                map: impl FnMut(S::Item) -> $payload_enum + Send + 'static,
+            ) -> post_haste::dependencies::task::JoinHandle<()>
+            where
+                S: post_haste::dependencies::Stream + Send + 'static,
+                S::Item: Send,
+            {
+                POSTMASTER.attach_stream(destination, source, stream, map)
+            }
+
+            /// Forward every item received from a tokio mpsc channel to an Agent, converting each item into a payload with `map`.
+            /// This works in the same way as `attach_stream()`.
+            #[cfg(not(target_os = "none"))]
+            pub fn attach_receiver<T: Send + 'static>(
+                destination: $address_enum,
+                source: $address_enum,
+                receiver: post_haste::dependencies::Receiver<T>,
+                map: impl FnMut(T) -> $payload_enum + Send + 'static,
+            ) -> post_haste::dependencies::task::JoinHandle<()> {
+                POSTMASTER.attach_receiver(destination, source, receiver, map)
+            }
+
+            /// Set a timer which sends the payload to an Agent from itself once the delay has elapsed.
+            /// Setting a timer with the same key as one of the Agent's pending timers replaces it, so a stale timer can't overwrite newer state (as a delayed message to itself could).
+            ///
+            /// # Example
+            /// ```rust
+            /// // Within an Agent...
+            ///
+            /// postmaster::set_timer(self.address, "cross_end", CROSSING_LENGTH, Payloads::CrossEnd);
+            /// // If the crossing is extended, the earlier timer is replaced rather than firing early
+            /// postmaster::set_timer(self.address, "cross_end", CROSSING_LENGTH * 2, Payloads::CrossEnd);
+            /// ```
+            #[cfg(not(target_os = "none"))]
+            pub fn set_timer(address: $address_enum, key: &'static str, delay: Duration, payload: impl Into<$payload_enum>) {
+                POSTMASTER.set_timer(address, key, delay, payload.into())
+            }
+
+            /// Cancel one of an Agent's pending timers, returning false if it had no timer pending with the given key
+            #[cfg(not(target_os = "none"))]
+            pub fn cancel_timer(address: $address_enum, key: &'static str) -> bool {
+                POSTMASTER.cancel_timer(address, key)
+            }
+
+            /// Restart one of an Agent's pending timers so that it fires once the delay has elapsed from now, returning false if it had no timer pending with the given key
+            #[cfg(not(target_os = "none"))]
+            pub fn reset_timer(address: $address_enum, key: &'static str, delay: Duration) -> bool {
+                POSTMASTER.reset_timer(address, key, delay)
+            }
+
+            /// The structure of a message in the system.
+            /// This structure is automatically generated by the sending functions from the source address and the payload
+            pub type Message = post_haste::postmaster::Message<$address_enum, $payload_enum>;
+
+            /// A builder for configuring messages.
+            /// Provides methods for configuring the message before it is sent with the `send()` method
+            #[cfg(not(target_os = "none"))]
+            pub type MessageBuilder = post_haste::postmaster::MessageBuilder<'static, $address_enum, $payload_enum>;
+
+            /// Why a message sent with `try_send_returning()` couldn't be sent, holding the payload where possible
+            #[cfg(not(target_os = "none"))]
+            pub type TrySendError = post_haste::postmaster::TrySendError<$payload_enum>;
+
+            /// Why a message sent with `send_returning()` couldn't be sent, holding the payload where possible
+            #[cfg(not(target_os = "none"))]
+            pub type SendError = post_haste::postmaster::SendError<$payload_enum>;
+
+            pub use post_haste::postmaster::Diagnostics;
+
+            /// A snapshot of the Postmaster's metrics for each Agent
+            #[cfg(not(target_os = "none"))]
+            pub type Metrics = post_haste::postmaster::Metrics<$address_enum>;
+
+            /// A snapshot of the state of each address known to the Postmaster
+            #[cfg(not(target_os = "none"))]
+            pub type Status = post_haste::postmaster::Status<$address_enum>;
+
+            /// How far each Agent got through its queue while the Postmaster was draining
+            #[cfg(not(target_os = "none"))]
+            pub type DrainReport = post_haste::postmaster::DrainReport<$address_enum>;
+
+            /// The outcome of a shutdown started by a signal
+            #[cfg(not(target_os = "none"))]
+            pub type ShutdownReport = post_haste::postmaster::ShutdownReport<$address_enum>;
+
+            /// A builder for configuring messages.
+            /// Provides methods for configuring the message before it is sent with the `send()` method
+            #[cfg(target_os = "none")]
+            pub struct MessageBuilder {
+                destination: $address_enum,
+                message: Message,
+                timeout: Option<Duration>,
+                delay: Option<Duration>,
             }
 
+            #[cfg(target_os = "none")]
             impl MessageBuilder {
                 /// Add a custom timeout to the message.
                 /// When the message is sent, it will use this timeout to determine how long to wait before giving up, rather than the Postmaster's default timeout.
@@ -296,6 +1919,26 @@ macro_rules! init_postmaster {
                     self
                 }
 
+                /// Mark the message as part of a flow of messages, e.g. the handling of a particular request.
+                pub fn with_correlation_id(mut self, correlation_id: impl Into<post_haste::postmaster::CorrelationId>) -> Self {
+                    self.message.correlation_id = Some(correlation_id.into());
+                    self
+                }
+
+                /// Ask for replies to the message to be sent to the given address, rather than to the message's source.
+                pub fn reply_to(mut self, address: $address_enum) -> Self {
+                    self.message.reply_to = Some(address);
+                    self
+                }
+
+                /// Pass a received message's flow on to this message, copying its correlation ID and the address to reply to (its `reply_address()`).
+                /// This is used when handling a message involves sending messages on to other Agents, so that they can reply directly to the original sender.
+                pub fn forwarded_from(mut self, message: &Message) -> Self {
+                    self.message.correlation_id = message.correlation_id;
+                    self.message.reply_to = Some(message.reply_address());
+                    self
+                }
+
                 /// Send the configured message.
                 /// This function works in exactly the same way as `postmaster::send()`, except that the timeout scenario may be different depending on whether the timeout for the message was customised.
                 /// If a delay was set, the message will "send" immediately (meaning that the sender can continue executing), but the message won't be delivered until _at least_ the delay has elapsed.
@@ -307,264 +1950,271 @@ macro_rules! init_postmaster {
                 pub async fn send(self) -> Result<(), PostmasterError> {
                     match self.delay {
                         Some(delay) => {
-                            postmaster_internal::spawn_delayed_send(
-                                self.destination,
-                                self.message,
-                                delay,
-                                self.timeout,
-                            )
-                            .await
+                            POSTMASTER.spawn_delayed_send(self.destination, self.message, delay, self.timeout)
                         }
                         None => {
-                            postmaster_internal::send_internal(
-                                self.destination,
-                                self.message,
-                                self.timeout,
-                            )
-                            .await
+                            POSTMASTER
+                                .send_internal(self.destination, self.message, self.timeout)
+                                .await
                         }
                     }
                 }
             }
 
-            /// The structure of a message in the system.
-            /// This structure is automatically generated by the sending functions from the source address and the payload
-            pub struct Message {
-                /// The address from which the message originated
-                pub source: $address_enum,
-                /// The message contents
-                pub payload: $payload_enum,
-            }
-
-            /// A builder for configuring messages.
-            /// Provides methods for configuring the message before it is sent with the `send()` method
-            pub struct MessageBuilder {
-                destination: $address_enum,
-                message: Message,
-                timeout: Option<Duration>,
-                delay: Option<Duration>,
-            }
-
-            /// Contains diagnostic information for the Postmaster.
-            /// Obtained by calling postmaster::get_diagnostics()
-            pub struct Diagnostics {
-                /// The number of messages successfully sent since the Postmaster was initialised.
-                pub messages_sent: usize,
-                /// The number of messages which could not be sent since the Postmaster was initialised.
-                pub send_failures: usize,
-            }
-
+            #[cfg(target_os = "none")]
             mod postmaster_internal {
-                use super::{
-                    ADDRESS_COUNT, Message, PostmasterError, $address_enum, $payload_enum,
-                };
+                use super::{ADDRESS_COUNT, Diagnostics, Message, MessageBuilder, PostmasterError, $address_enum, $payload_enum};
+                use post_haste::address::{AddressIndex, AddressSpace};
                 use core::cell::RefCell;
                 use core::sync::atomic::Ordering;
                 use post_haste::dependencies::*;
                 #[post_haste::dependencies::env_item]
                 const DELAYED_MESSAGE_POOL_SIZE: usize = 8;
 
-                #[cfg(target_os = "none")]
                 type Mailbox = DynamicSender<'static, Message>;
-                #[cfg(not(target_os = "none"))]
-                type Mailbox = Sender<Message>;
-
-                pub(super) async fn register(
-                    address: $address_enum,
-                    mailbox: Mailbox,
-                ) -> Result<(), PostmasterError> {
-                    let mut senders = POSTMASTER.senders.lock().await;
-                    if senders[address as usize].is_none() {
-                        senders[address as usize].replace(mailbox);
-                        Ok(())
-                    } else {
-                        return Err(PostmasterError::AddressAlreadyTaken);
+
+                pub(super) struct Postmaster {
+                    senders: Mutex<NoopRawMutex, [Option<Mailbox>; ADDRESS_COUNT]>,
+                    timeout_us: AtomicU32,
+                    spawner: RefCell<Option<Spawner>>,
+                    messages_sent: AtomicUsize,
+                    send_failures: AtomicUsize,
+                }
+
+                unsafe impl Sync for Postmaster {}
+
+                pub(super) static POSTMASTER: Postmaster = Postmaster {
+                    senders: Mutex::new([const { None }; ADDRESS_COUNT]),
+                    timeout_us: AtomicU32::new(100),
+                    spawner: RefCell::new(None),
+                    messages_sent: AtomicUsize::new(0),
+                    send_failures: AtomicUsize::new(0),
+                };
+
+                /// Dynamic addresses are not supported on bare metal targets, so only static addresses have a slot in the senders table
+                fn slot(address: $address_enum) -> Option<usize> {
+                    match address.index() {
+                        AddressIndex::Static(index) => Some(index),
+                        AddressIndex::Dynamic(_) => None,
                     }
                 }
 
-                pub(super) async fn send_internal(
-                    destination: $address_enum,
-                    message: Message,
-                    timeout: Option<Duration>,
-                ) -> Result<(), PostmasterError> {
-                    let timeout = match timeout {
-                        Some(duration) => duration,
-                        None => Duration::from_micros(
-                            POSTMASTER.timeout_us.load(Ordering::Relaxed).into(),
-                        ),
-                    };
-                    #[cfg(not(target_os = "none"))]
-                    evaluate_diagnostics(tokio::time::timeout(timeout, async {
-                        match &POSTMASTER.senders.lock().await[destination as usize] {
-                            None => Err(PostmasterError::NoRecipient),
-                            Some(sender) => {
-                                sender.send(message).await?;
+                impl Postmaster {
+                    pub(super) async fn register(
+                        &self,
+                        address: $address_enum,
+                        mailbox: Mailbox,
+                    ) -> Result<(), PostmasterError> {
+                        let mut senders = self.senders.lock().await;
+                        match slot(address).and_then(|index| senders.get_mut(index)) {
+                            Some(route @ None) => {
+                                route.replace(mailbox);
                                 Ok(())
                             }
+                            _ => Err(PostmasterError::AddressAlreadyTaken),
                         }
-                    })
-                    .await
-                    .map_err(|_| PostmasterError::Timeout)?)?;
+                    }
+
+                    pub(super) async fn deregister(&self, address: $address_enum) -> Result<(), PostmasterError> {
+                        let mut senders = self.senders.lock().await;
+                        slot(address)
+                            .and_then(|index| senders.get_mut(index)?.take())
+                            .map(|_| ())
+                            .ok_or(PostmasterError::NoRecipient)
+                    }
+
+                    pub(super) async fn send(
+                        &self,
+                        destination: $address_enum,
+                        source: $address_enum,
+                        payload: $payload_enum,
+                    ) -> Result<(), PostmasterError> {
+                        self.send_internal(destination, Message { source, payload, correlation_id: None, reply_to: None }, None).await
+                    }
+
+                    pub(super) async fn send_matching(
+                        &self,
+                        pattern: impl Fn(&$address_enum) -> bool,
+                        source: $address_enum,
+                        payload: impl Into<$payload_enum> + Clone,
+                    ) -> Result<usize, PostmasterError> {
+                        let mut matched = 0;
+                        let mut delivered = 0;
+                        for destination in (0..ADDRESS_COUNT).filter_map(<$address_enum as AddressSpace>::static_address) {
+                            // There is no room to collect the destinations, so the lock is taken to check each one in turn
+                            if !pattern(&destination) || self.senders.lock().await[slot(destination).unwrap()].is_none() {
+                                continue;
+                            }
+                            matched += 1;
+                            if self.send(destination, source, payload.clone().into()).await.is_ok() {
+                                delivered += 1;
+                            }
+                        }
+                        if matched == 0 {
+                            return Err(PostmasterError::NoRecipient);
+                        }
+                        Ok(delivered)
+                    }
 
-                    #[cfg(target_os = "none")]
-                    evaluate_diagnostics(
-                        async {
-                            match &POSTMASTER.senders.lock().await[destination as usize] {
+                    pub(super) async fn send_all(
+                        &self,
+                        destination: $address_enum,
+                        source: $address_enum,
+                        payloads: impl IntoIterator<Item = $payload_enum>,
+                    ) -> Result<(), PostmasterError> {
+                        let timeout = Duration::from_micros(self.timeout_us.load(Ordering::Relaxed).into());
+                        let mut sent = 0;
+                        let result = async {
+                            let senders = self.senders.lock().await;
+                            match slot(destination).and_then(|index| senders.get(index)?.as_ref()) {
                                 None => Err(PostmasterError::NoRecipient),
                                 Some(sender) => {
-                                    sender.send(message).await;
+                                    for payload in payloads {
+                                        sender.send(Message { source, payload, correlation_id: None, reply_to: None }).await;
+                                        sent += 1;
+                                    }
                                     Ok(())
                                 }
                             }
                         }
                         .with_timeout(timeout)
-                        .await?,
-                    )?;
-                    Ok(())
-                }
-
-                pub(super) fn try_send_internal(
-                    destination: $address_enum,
-                    message: Message,
-                ) -> Result<(), PostmasterError> {
-                    evaluate_diagnostics(
-                        match &POSTMASTER.senders.try_lock()?[destination as usize] {
-                            None => Err(PostmasterError::NoRecipient),
-                            Some(sender) => {
-                                sender.try_send(message)?;
-                                Ok(())
-                            }
-                        },
-                    )
-                }
-
-                pub(super) async fn spawn_delayed_send(
-                    destination: $address_enum,
-                    message: Message,
-                    delay: Duration,
-                    timeout: Option<Duration>,
-                ) -> Result<(), PostmasterError> {
-
-                    #[cfg(not(target_os = "none"))]
-                    {
-                        let _ = tokio::spawn(delayed_send(destination, message, delay, timeout));
-                        Ok(())
+                        .await
+                        .unwrap_or(Err(PostmasterError::Timeout));
+                        self.messages_sent.fetch_add(sent, Ordering::Relaxed);
+                        result.inspect_err(|_| {
+                            self.send_failures.fetch_add(1, Ordering::Relaxed);
+                        })
                     }
-                    #[cfg(target_os = "none")]
-                    if let Some(spawner) = *POSTMASTER.spawner.borrow(){
-                            Ok(spawner.spawn(delayed_send(destination, message, delay, timeout))?)
-                        } else {
-                            Err(PostmasterError::SpawnerNotSet)
-                        }
 
-                }
+                    pub(super) fn try_send(
+                        &self,
+                        destination: $address_enum,
+                        source: $address_enum,
+                        payload: $payload_enum,
+                    ) -> Result<(), PostmasterError> {
+                        self.try_send_internal(destination, Message { source, payload, correlation_id: None, reply_to: None })
+                    }
 
-                #[cfg_attr(target_os = "none", task(pool_size=DELAYED_MESSAGE_POOL_SIZE))]
-                pub(super) async fn delayed_send(
-                    destination: $address_enum,
-                    message: Message,
-                    delay: Duration,
-                    timeout: Option<Duration>,
-                ) {
-                    sleep(delay).await;
-                    let source = message.source;
-                    match send_internal(destination, message, timeout).await {
-                        Ok(_) => (),
-                        Err(error) => (), // TODO: Can we find a way to convey back to the source that the sending failed?
+                    pub(super) fn message(
+                        &self,
+                        destination: $address_enum,
+                        source: $address_enum,
+                        payload: $payload_enum,
+                    ) -> MessageBuilder {
+                        MessageBuilder {
+                            destination,
+                            message: Message { source, payload, correlation_id: None, reply_to: None },
+                            timeout: None,
+                            delay: None,
+                        }
                     }
-                }
 
-                #[cfg_attr(target_os = "none", task(pool_size=DELAYED_MESSAGE_POOL_SIZE))]
-                pub(super) async fn delayed_try_send(
-                    destination: $address_enum,
-                    message: Message,
-                    delay: Duration,
-                ) {
-                    sleep(delay).await;
-                    match try_send_internal(destination, message) {
-                        Ok(_) => (),
-                        Err(error) => (), // TODO: Can we find a way to convey back to the source that the sending failed?
+                    pub(super) fn get_diagnostics(&self) -> Diagnostics {
+                        Diagnostics {
+                            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+                            send_failures: self.send_failures.load(Ordering::Relaxed),
+                        }
                     }
-                }
 
-                pub(super) fn get_diagnostics() -> super::Diagnostics{
-                    super::Diagnostics {
-                        messages_sent: POSTMASTER.messages_sent.load(Ordering::Relaxed),
-                        send_failures: POSTMASTER.send_failures.load(Ordering::Relaxed),
+                    pub(super) fn set_timeout(&self, timeout_us: u32) {
+                        self.timeout_us.store(timeout_us, Ordering::Relaxed)
                     }
-                }
 
-                pub(super) fn set_timeout(timeout_us: u32) {
-                    POSTMASTER.timeout_us.store(timeout_us, Ordering::Relaxed)
-                }
+                    pub(super) fn set_spawner(&self, spawner: Spawner) {
+                        if self.spawner.borrow().is_none() {
+                            self.spawner.replace(Some(spawner));
+                        }
+                    }
 
-                #[cfg(target_os = "none")]
-                pub(super) fn set_spawner(spawner: Spawner) {
-                    if POSTMASTER.spawner.borrow().is_none() {
-                        POSTMASTER.spawner.replace(Some(spawner));
+                    pub(super) async fn send_internal(
+                        &self,
+                        destination: $address_enum,
+                        message: Message,
+                        timeout: Option<Duration>,
+                    ) -> Result<(), PostmasterError> {
+                        let timeout = match timeout {
+                            Some(duration) => duration,
+                            None => Duration::from_micros(
+                                self.timeout_us.load(Ordering::Relaxed).into(),
+                            ),
+                        };
+                        self.evaluate_diagnostics(
+                            async {
+                                let senders = self.senders.lock().await;
+                                match slot(destination).and_then(|index| senders.get(index)?.as_ref()) {
+                                    None => Err(PostmasterError::NoRecipient),
+                                    Some(sender) => {
+                                        sender.send(message).await;
+                                        Ok(())
+                                    }
+                                }
+                            }
+                            .with_timeout(timeout)
+                            .await?,
+                        )
                     }
-                }
 
-                #[cfg(not(target_os = "none"))]
-                struct Postmaster {
-                    senders: Mutex<[Option<Mailbox>; ADDRESS_COUNT]>,
-                    timeout_us: AtomicU32,
-                    messages_sent: AtomicUsize,
-                    send_failures: AtomicUsize,
-                }
-                #[cfg(not(target_os = "none"))]
-                static POSTMASTER: Lazy<Postmaster> = Lazy::new(|| Postmaster {
-                    senders: Mutex::new([const { None }; ADDRESS_COUNT]),
-                    timeout_us: AtomicU32::new($timeout_us),
-                    messages_sent: AtomicUsize::new(0),
-                    send_failures: AtomicUsize::new(0),
-                });
-
-                #[cfg(target_os = "none")]
-                unsafe impl Sync for Postmaster<'_> {}
-                #[cfg(target_os = "none")]
-                struct Postmaster<'a> {
-                    senders:
-                        Mutex<NoopRawMutex, [Option<DynamicSender<'a, Message>>; ADDRESS_COUNT]>,
-                    timeout_us: AtomicU32,
-                    spawner: RefCell<Option<Spawner>>,
-                    messages_sent: AtomicUsize,
-                    send_failures: AtomicUsize,
-                }
+                    fn try_send_internal(
+                        &self,
+                        destination: $address_enum,
+                        message: Message,
+                    ) -> Result<(), PostmasterError> {
+                        let senders = self.senders.try_lock()?;
+                        self.evaluate_diagnostics(
+                            match slot(destination).and_then(|index| senders.get(index)?.as_ref()) {
+                                None => Err(PostmasterError::NoRecipient),
+                                Some(sender) => {
+                                    sender.try_send(message)?;
+                                    Ok(())
+                                }
+                            },
+                        )
+                    }
 
-                #[cfg(target_os = "none")]
-                static POSTMASTER: Postmaster = Postmaster {
-                    senders: Mutex::new([None; ADDRESS_COUNT]),
-                    timeout_us: AtomicU32::new(100),
-                    spawner: RefCell::new(None),
-                    messages_sent: AtomicUsize::new(0),
-                    send_failures: AtomicUsize::new(0),
-                };
+                    pub(super) fn spawn_delayed_send(
+                        &self,
+                        destination: $address_enum,
+                        message: Message,
+                        delay: Duration,
+                        timeout: Option<Duration>,
+                    ) -> Result<(), PostmasterError> {
+                        if let Some(spawner) = *self.spawner.borrow() {
+                            Ok(spawner.spawn(delayed_send(destination, message, delay, timeout))?)
+                        } else {
+                            Err(PostmasterError::SpawnerNotSet)
+                        }
+                    }
 
-                #[inline]
-                fn evaluate_diagnostics(
-                    result: Result<(), PostmasterError>,
-                ) -> Result<(), PostmasterError> {
-                    result
-                        .inspect(|_| {
-                            POSTMASTER.messages_sent.fetch_add(1, Ordering::Relaxed);
-                        })
-                        .inspect_err(|_| {
-                            POSTMASTER.send_failures.fetch_add(1, Ordering::Relaxed);
-                        })
+                    #[inline]
+                    fn evaluate_diagnostics(
+                        &self,
+                        result: Result<(), PostmasterError>,
+                    ) -> Result<(), PostmasterError> {
+                        result
+                            .inspect(|_| {
+                                self.messages_sent.fetch_add(1, Ordering::Relaxed);
+                            })
+                            .inspect_err(|_| {
+                                self.send_failures.fetch_add(1, Ordering::Relaxed);
+                            })
+                    }
                 }
 
-                async fn sleep(delay: Duration){
-                    #[cfg(target_os = "none")]
+                #[task(pool_size=DELAYED_MESSAGE_POOL_SIZE)]
+                async fn delayed_send(
+                    destination: $address_enum,
+                    message: Message,
+                    delay: Duration,
+                    timeout: Option<Duration>,
+                ) {
                     Timer::after(delay).await;
-                    #[cfg(not(target_os = "none"))]
-                    time::sleep(delay).await;
-
+                    // TODO: Can we find a way to convey back to the source that the sending failed?
+                    let _ = POSTMASTER.send_internal(destination, message, timeout).await;
                 }
             }
         }
     };
     ($address_enum:ty, $payload_enum:ty) => {
-        init_postmaster!($address_enum, $payload_enum, 1000);
+        $crate::init_postmaster!($address_enum, $payload_enum, 1000);
     };
 }
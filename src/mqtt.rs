@@ -0,0 +1,214 @@
+//! An Agent which bridges a Postmaster to an MQTT broker, e.g. for IoT deployments.
+//! Enabled with the `mqtt` feature.
+//!
+//! The `MqttBridge` subscribes to the configured topic filters, and sends each message published to them on to the address mapped to the filter.
+//! Every message sent to the bridge's own address is published to the broker, on the topic chosen by the project's `MqttCodec`.
+//! Messages are published and subscribed to at QoS 0 over MQTT 3.1.1, and only unencrypted connections are supported.
+
+mod protocol;
+
+use core::fmt::Debug;
+use std::io;
+use std::sync::Arc;
+
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::channel;
+use tokio::task;
+use tokio::time::{self, Duration, Instant};
+
+use crate::address::AddressSpace;
+use crate::agent::{Agent, Inbox};
+use crate::postmaster::{Message, Postmaster};
+use protocol::Outgoing;
+
+/// The keep alive interval used unless otherwise configured, within which the bridge pings the broker if it has nothing else to send
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// A message published to, or received from, the MQTT broker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttMessage {
+    /// The topic the message is published on
+    pub topic: String,
+    /// The contents of the message
+    pub payload: Vec<u8>,
+}
+
+/// Translates between payloads and the messages published over MQTT
+pub trait MqttCodec<P>: Send + Sync + 'static {
+    /// Encode a payload to be published, choosing its topic, or return `None` if the payload should not be published
+    fn encode(&self, payload: &P) -> Option<MqttMessage>;
+
+    /// Decode a message received from the broker, or return `None` if it is not a valid payload (in which case it is dropped)
+    fn decode(&self, message: MqttMessage) -> Option<P>;
+}
+
+/// The configuration of an `MqttBridge`
+pub struct MqttConfig<A, P, C> {
+    postmaster: Postmaster<A, P>,
+    broker: String,
+    client_id: String,
+    keep_alive: Duration,
+    subscriptions: Vec<(String, A)>,
+    codec: Arc<C>,
+}
+
+impl<A, P, C> MqttConfig<A, P, C> {
+    /// Connect to the broker at the given socket address (e.g. `broker.local:1883`) with the given client identifier, sending the payloads received to addresses through the given Postmaster
+    pub fn new(
+        postmaster: &Postmaster<A, P>,
+        broker: impl Into<String>,
+        client_id: impl Into<String>,
+        codec: C,
+    ) -> Self {
+        Self {
+            postmaster: postmaster.clone(),
+            broker: broker.into(),
+            client_id: client_id.into(),
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            subscriptions: Vec::new(),
+            codec: Arc::new(codec),
+        }
+    }
+
+    /// Subscribe to a topic filter (which may contain the `+` and `#` wildcards), sending the messages published to it on to the given destination.
+    /// If a topic matches more than one filter, the message is sent to the destination of the first.
+    pub fn subscribe(mut self, topic_filter: impl Into<String>, destination: A) -> Self {
+        self.subscriptions.push((topic_filter.into(), destination));
+        self
+    }
+
+    /// Change the keep alive interval, which is rounded down to whole seconds
+    pub fn with_keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+}
+
+impl<A: Copy, P, C> Clone for MqttConfig<A, P, C> {
+    fn clone(&self) -> Self {
+        Self {
+            postmaster: self.postmaster.clone(),
+            broker: self.broker.clone(),
+            client_id: self.client_id.clone(),
+            keep_alive: self.keep_alive,
+            subscriptions: self.subscriptions.clone(),
+            codec: self.codec.clone(),
+        }
+    }
+}
+
+/// An Agent which bridges a Postmaster to an MQTT broker.
+/// Messages published to the configured subscriptions are sent to their mapped addresses from the bridge's address, and every message sent to the bridge is published.
+///
+/// # Panics
+/// Panics if the connection can't be made, or once it fails, so it should be registered with a `RestartPolicy` if it should reconnect.
+///
+/// # Example
+/// ```rust,ignore
+/// let config = MqttConfig::new(postmaster::instance(), "broker.local:1883", "gateway", SensorCodec)
+///     .subscribe("sensors/+/temperature", Address::Thermostat)
+///     .subscribe("commands/#", Address::Controller);
+/// postmaster::register_agent!(Mqtt, MqttBridge<Address, Payloads, SensorCodec>, config, 16, RestartPolicy::always()).unwrap();
+/// ```
+pub struct MqttBridge<A, P, C> {
+    address: A,
+    config: MqttConfig<A, P, C>,
+}
+
+impl<A, P, C> Agent for MqttBridge<A, P, C>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+    C: MqttCodec<P>,
+{
+    type Address = A;
+    type Message = Message<A, P>;
+    type Config = MqttConfig<A, P, C>;
+
+    async fn create(address: Self::Address, config: Self::Config) -> Self {
+        Self { address, config }
+    }
+
+    async fn run(self, mut inbox: Inbox<Self::Message>) -> ! {
+        let config = self.config;
+        let broker = config.broker.clone();
+        let keep_alive_secs = u16::try_from(config.keep_alive.as_secs()).unwrap_or(u16::MAX);
+        let topic_filters: Vec<String> = config
+            .subscriptions
+            .iter()
+            .map(|(topic_filter, _)| topic_filter.clone())
+            .collect();
+        let mut stream = TcpStream::connect(&broker).await.unwrap_or_else(|error| {
+            panic!("Failed to connect to the MQTT broker {broker}: {error}")
+        });
+        protocol::connect(
+            &mut stream,
+            &config.client_id,
+            keep_alive_secs,
+            &topic_filters,
+        )
+        .await
+        .unwrap_or_else(|error| panic!("Failed to connect to the MQTT broker {broker}: {error}"));
+
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let (acknowledgements, mut acknowledgement) = channel(1);
+        let postmaster = config.postmaster.clone();
+        let codec = config.codec.clone();
+        let subscriptions = config.subscriptions.clone();
+        let source = self.address;
+        // Reading runs in its own task, as a packet which has only been partly read can't be cancelled
+        let mut reading = task::spawn(async move {
+            loop {
+                let publish = match protocol::read_publish(&mut reader).await {
+                    Ok(publish) => publish,
+                    Err(error) => return error,
+                };
+                if let Some(id) = publish.acknowledge
+                    && acknowledgements
+                        .send(Outgoing::PublishAck(id))
+                        .await
+                        .is_err()
+                {
+                    return io::Error::from(io::ErrorKind::BrokenPipe);
+                }
+                let Some(&(_, destination)) = subscriptions.iter().find(|(topic_filter, _)| {
+                    protocol::topic_matches(topic_filter, &publish.topic)
+                }) else {
+                    continue;
+                };
+                if let Some(payload) = codec.decode(MqttMessage {
+                    topic: publish.topic,
+                    payload: publish.payload,
+                }) {
+                    // A message which can't be delivered is dropped, as it would have been had it been sent locally
+                    let _ = postmaster.send(destination, source, payload).await;
+                }
+            }
+        });
+
+        // A keep alive of zero turns keep alive off
+        let mut keep_alive = time::interval_at(
+            Instant::now() + config.keep_alive,
+            config.keep_alive.max(Duration::from_secs(1)),
+        );
+        let error = loop {
+            let packet = tokio::select! {
+                message = inbox.recv() => {
+                    let message = message.expect("The Agent's inbox was closed");
+                    match config.codec.encode(&message.payload) {
+                        Some(MqttMessage { topic, payload }) => Outgoing::Publish { topic, payload },
+                        None => continue,
+                    }
+                }
+                Some(packet) = acknowledgement.recv() => packet,
+                _ = keep_alive.tick(), if keep_alive_secs > 0 => Outgoing::PingRequest,
+                result = &mut reading => break result.unwrap_or_else(io::Error::other),
+            };
+            if let Err(error) = protocol::write(&mut writer, packet).await {
+                break error;
+            }
+        };
+        reading.abort();
+        panic!("The connection to the MQTT broker {broker} failed: {error}")
+    }
+}
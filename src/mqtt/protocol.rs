@@ -0,0 +1,367 @@
+//! Just enough of the MQTT 3.1.1 protocol for the MQTT bridge: connecting, subscribing, and publishing and receiving messages at QoS 0.
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The largest packet which will be received, to bound the memory used by a misbehaving broker
+const MAX_PACKET_LENGTH: usize = 16 * 1024 * 1024;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xC0;
+
+/// A packet to be written to the connection
+pub(super) enum Outgoing {
+    Publish { topic: String, payload: Vec<u8> },
+    PublishAck(u16),
+    PingRequest,
+}
+
+/// A message published to one of the bridge's subscriptions
+pub(super) struct Publish {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    /// The packet identifier to acknowledge, if the message was published at QoS 1
+    pub acknowledge: Option<u16>,
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Connect to the broker with a clean session, then subscribe to the given topic filters
+pub(super) async fn connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    client_id: &str,
+    keep_alive_secs: u16,
+    topic_filters: &[String],
+) -> io::Result<()> {
+    let mut body = Vec::new();
+    put_string(&mut body, "MQTT")?;
+    // Protocol level 4 (3.1.1), with only the clean session flag set
+    body.extend_from_slice(&[4, 0x02]);
+    body.extend_from_slice(&keep_alive_secs.to_be_bytes());
+    put_string(&mut body, client_id)?;
+    write_packet(stream, CONNECT, &body).await?;
+
+    let (packet_type, body) = read_packet(stream).await?;
+    if packet_type & 0xF0 != CONNACK || body.len() != 2 {
+        return Err(invalid("Expected CONNACK from the MQTT broker"));
+    }
+    if body[1] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            "The MQTT broker refused the connection",
+        ));
+    }
+
+    if !topic_filters.is_empty() {
+        let mut body = 1u16.to_be_bytes().to_vec();
+        for topic_filter in topic_filters {
+            put_string(&mut body, topic_filter)?;
+            body.push(0);
+        }
+        write_packet(stream, SUBSCRIBE, &body).await?;
+    }
+    Ok(())
+}
+
+/// Read packets until a message is published to the bridge, skipping any other packets (e.g. acknowledgements of its subscriptions and pings)
+pub(super) async fn read_publish<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Publish> {
+    loop {
+        let (packet_type, body) = read_packet(reader).await?;
+        if packet_type & 0xF0 != PUBLISH {
+            continue;
+        }
+        let qos = (packet_type >> 1) & 0x03;
+        let mut rest = body.as_slice();
+        let topic = take_string(&mut rest)?;
+        let acknowledge = if qos > 0 {
+            let (id, remainder) = rest
+                .split_first_chunk::<2>()
+                .ok_or_else(|| invalid("Truncated MQTT PUBLISH"))?;
+            rest = remainder;
+            // Only QoS 1 is acknowledged with PUBACK; the bridge subscribes at QoS 0, so the broker should never send QoS 2
+            (qos == 1).then_some(u16::from_be_bytes(*id))
+        } else {
+            None
+        };
+        return Ok(Publish {
+            topic,
+            payload: rest.to_vec(),
+            acknowledge,
+        });
+    }
+}
+
+/// Write a packet to the connection
+pub(super) async fn write<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    outgoing: Outgoing,
+) -> io::Result<()> {
+    match outgoing {
+        Outgoing::Publish { topic, payload } => {
+            let mut body = Vec::with_capacity(topic.len() + payload.len() + 2);
+            put_string(&mut body, &topic)?;
+            body.extend_from_slice(&payload);
+            write_packet(writer, PUBLISH, &body).await
+        }
+        Outgoing::PublishAck(id) => write_packet(writer, PUBACK, &id.to_be_bytes()).await,
+        Outgoing::PingRequest => write_packet(writer, PINGREQ, &[]).await,
+    }
+}
+
+/// Whether a topic matches a topic filter, which may contain the `+` (single level) and `#` (all remaining levels) wildcards
+pub(super) fn topic_matches(topic_filter: &str, topic: &str) -> bool {
+    let mut filter_levels = topic_filter.split('/');
+    let mut topic_levels = topic.split('/');
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => (),
+            (Some(filter_level), Some(topic_level)) if filter_level == topic_level => (),
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+async fn write_packet<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    packet_type: u8,
+    body: &[u8],
+) -> io::Result<()> {
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    packet.push(packet_type);
+    // The remaining length is encoded in 7 bit groups, least significant first
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+    packet.extend_from_slice(body);
+    writer.write_all(&packet).await?;
+    writer.flush().await
+}
+
+async fn read_packet<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let packet_type = reader.read_u8().await?;
+    let mut length = 0;
+    for shift in (0..28).step_by(7) {
+        let byte = reader.read_u8().await?;
+        length |= usize::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            if length > MAX_PACKET_LENGTH {
+                return Err(invalid("MQTT packet too large"));
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).await?;
+            return Ok((packet_type, body));
+        }
+    }
+    Err(invalid("Malformed MQTT remaining length"))
+}
+
+fn put_string(buffer: &mut Vec<u8>, string: &str) -> io::Result<()> {
+    let length = u16::try_from(string.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "MQTT string too long"))?;
+    buffer.extend_from_slice(&length.to_be_bytes());
+    buffer.extend_from_slice(string.as_bytes());
+    Ok(())
+}
+
+fn take_string(bytes: &mut &[u8]) -> io::Result<String> {
+    let (length, rest) = bytes
+        .split_first_chunk::<2>()
+        .ok_or_else(|| invalid("Truncated MQTT string"))?;
+    let length = usize::from(u16::from_be_bytes(*length));
+    if rest.len() < length {
+        return Err(invalid("Truncated MQTT string"));
+    }
+    let (string, rest) = rest.split_at(length);
+    *bytes = rest;
+    String::from_utf8(string.to_vec()).map_err(|_| invalid("MQTT string is not UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn packet(packet_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::new();
+        write_packet(&mut packet, packet_type, body).await.unwrap();
+        packet
+    }
+
+    fn publish_body(topic: &str, id: Option<u16>, payload: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        put_string(&mut body, topic).unwrap();
+        body.extend(id.map(u16::to_be_bytes).into_iter().flatten());
+        body.extend_from_slice(payload);
+        body
+    }
+
+    #[tokio::test]
+    async fn remaining_lengths_are_written_and_read() {
+        // The longest lengths which fit in one, two and three bytes, and the shortest which don't
+        for (length, length_bytes) in [
+            (0, 1),
+            (127, 1),
+            (128, 2),
+            (16_383, 2),
+            (16_384, 3),
+            (2_097_151, 3),
+            (2_097_152, 4),
+        ] {
+            let body = vec![0x5a; length];
+            let packet = packet(PUBLISH, &body).await;
+            assert_eq!(packet.len(), 1 + length_bytes + length);
+            assert_eq!(
+                read_packet(&mut packet.as_slice()).await.unwrap(),
+                (PUBLISH, body)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn packets_split_across_reads_are_reassembled() {
+        let packets = [
+            packet(0x90, &[0, 1, 0]).await,
+            packet(PUBLISH, &publish_body("sensors/1", None, b"21.5")).await,
+        ]
+        .concat();
+        // A pipe which holds a single byte, so every read returns at most one byte
+        let (mut writer, mut reader) = tokio::io::duplex(1);
+        tokio::spawn(async move { writer.write_all(&packets).await });
+        let publish = read_publish(&mut reader).await.unwrap();
+        assert_eq!(publish.topic, "sensors/1");
+        assert_eq!(publish.payload, b"21.5");
+        assert_eq!(publish.acknowledge, None);
+    }
+
+    #[tokio::test]
+    async fn oversized_packets_are_refused_before_being_read() {
+        // Only the fixed header is sent, so the body would have to be allocated before anything more could be read
+        let length = MAX_PACKET_LENGTH + 1;
+        let header = [
+            PUBLISH,
+            (length & 0x7F) as u8 | 0x80,
+            (length >> 7 & 0x7F) as u8 | 0x80,
+            (length >> 14 & 0x7F) as u8 | 0x80,
+            (length >> 21 & 0x7F) as u8,
+        ];
+        let error = read_packet(&mut header.as_slice()).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        // More than four bytes of remaining length
+        let header = [PUBLISH, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        let error = read_packet(&mut header.as_slice()).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn truncated_packets_fail() {
+        let whole = packet(PUBLISH, &publish_body("sensors/1", None, &[0; 200])).await;
+        // Cut off in the remaining length and in the body
+        for cut in [1, 2, 10] {
+            let error = read_packet(&mut &whole[..cut]).await.unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_publishes_are_refused() {
+        let truncated_topic = packet(PUBLISH, &[0, 10, b'a']).await;
+        let missing_id = packet(PUBLISH | 0x02, &publish_body("a", None, b"")[..3]).await;
+        let not_utf8 = packet(PUBLISH, &[0, 2, 0xff, 0xfe]).await;
+        for packet in [truncated_topic, missing_id, not_utf8] {
+            let error = read_publish(&mut packet.as_slice()).await.err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[tokio::test]
+    async fn acknowledged_publishes_keep_their_packet_identifier() {
+        let packets = [
+            packet(0xD0, &[]).await,
+            packet(
+                PUBLISH | 0x02,
+                &publish_body("alarms", Some(0x1234), b"fire"),
+            )
+            .await,
+        ]
+        .concat();
+        let publish = read_publish(&mut packets.as_slice()).await.unwrap();
+        assert_eq!(publish.topic, "alarms");
+        assert_eq!(publish.payload, b"fire");
+        assert_eq!(publish.acknowledge, Some(0x1234));
+    }
+
+    #[tokio::test]
+    async fn connecting_checks_the_connack() {
+        let mut written = Vec::new();
+        let connack = packet(CONNACK, &[0, 0]).await;
+        let filters = ["sensors/#".to_string()];
+        connect(
+            &mut tokio::io::join(connack.as_slice(), &mut written),
+            "bridge",
+            30,
+            &filters,
+        )
+        .await
+        .unwrap();
+        let mut written = written.as_slice();
+        let (packet_type, connect_body) = read_packet(&mut written).await.unwrap();
+        assert_eq!(packet_type, CONNECT);
+        assert_eq!(&connect_body[..10], b"\0\x04MQTT\x04\x02\0\x1e");
+        let (packet_type, subscribe_body) = read_packet(&mut written).await.unwrap();
+        assert_eq!(packet_type, SUBSCRIBE);
+        assert_eq!(subscribe_body, b"\0\x01\0\x09sensors/#\0");
+
+        let refused = packet(CONNACK, &[0, 5]).await;
+        let error = connect(
+            &mut tokio::io::join(refused.as_slice(), Vec::new()),
+            "bridge",
+            30,
+            &[],
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+        let not_connack = packet(PUBLISH, &publish_body("a", None, b"")).await;
+        let error = connect(
+            &mut tokio::io::join(not_connack.as_slice(), Vec::new()),
+            "bridge",
+            30,
+            &[],
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn topic_filters_match_wildcards() {
+        for (filter, topic, matches) in [
+            ("sensors/1", "sensors/1", true),
+            ("sensors/1", "sensors/2", false),
+            ("sensors/+", "sensors/1", true),
+            ("sensors/+", "sensors/1/temperature", false),
+            ("sensors/+/temperature", "sensors/1/temperature", true),
+            ("sensors/#", "sensors", true),
+            ("sensors/#", "sensors/1/temperature", true),
+            ("#", "anything/at/all", true),
+            ("sensors", "sensors/1", false),
+        ] {
+            assert_eq!(topic_matches(filter, topic), matches, "{filter} {topic}");
+        }
+    }
+}
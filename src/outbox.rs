@@ -0,0 +1,261 @@
+//! A persistent outbox, for at-least-once delivery of messages which must not be lost if the process stops while they are in flight.
+//! Enabled with the `outbox` feature.
+//!
+//! Each message sent through an `Outbox` is appended to a journal file before it is sent, and is only marked as done once its recipient has handled it (as with `with_ack()`).
+//! Messages still in the journal when the process restarts are found again when the outbox is reopened, and are sent again with `redeliver()`.
+//! A message may therefore be delivered more than once, e.g. if the process stopped after the recipient handled the message but before the outbox marked it as done, so recipients of critical payloads should tolerate duplicates.
+//!
+//! Post-haste does not depend on any particular serialisation format, so the project provides an `OutboxCodec` to convert messages to and from the bytes stored in the journal.
+
+use core::fmt::Debug;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+
+use crate::PostmasterError;
+use crate::address::AddressSpace;
+use crate::postmaster::{Postmaster, RetryPolicy};
+
+/// A journal record of a message which has been accepted by the outbox
+const RECORD_PENDING: u8 = 1;
+/// A journal record of a message which has been handled by its recipient
+const RECORD_DONE: u8 = 2;
+
+/// A message held in the outbox until it has been handled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxMessage<A, P> {
+    /// The address the message is being sent to
+    pub destination: A,
+    /// The address which sent the message
+    pub source: A,
+    /// The payload of the message
+    pub payload: P,
+}
+
+/// Converts the messages held in the outbox to and from the bytes stored in its journal
+pub trait OutboxCodec<A, P>: Send + Sync + 'static {
+    /// Encode a message to be stored in the journal
+    fn encode(&self, message: &OutboxMessage<A, P>) -> Vec<u8>;
+
+    /// Decode a message stored in the journal, or return `None` if it is invalid (in which case it is discarded)
+    fn decode(&self, bytes: &[u8]) -> Option<OutboxMessage<A, P>>;
+}
+
+/// The journal file, along with the messages in it which are still pending
+struct Journal {
+    file: File,
+    pending: BTreeMap<u64, Vec<u8>>,
+    next_sequence: u64,
+}
+
+impl Journal {
+    async fn append(&mut self, kind: u8, sequence: u64, bytes: &[u8]) -> io::Result<()> {
+        self.file.write_all(&record(kind, sequence, bytes)?).await?;
+        self.file.flush().await?;
+        self.file.sync_data().await
+    }
+}
+
+/// A persistent outbox, through which critical messages are sent so that they are delivered at least once.
+/// Messages are sent `with_ack()`, retrying according to the outbox's `RetryPolicy`, and are only removed from the journal once their recipient has handled them.
+///
+/// # Example
+/// ```rust,ignore
+/// let outbox = Outbox::open(postmaster::instance(), "billing.outbox", BillingCodec).await?;
+/// // Once the Agents have been registered, resend the messages left over from before a restart
+/// outbox.redeliver().await;
+///
+/// outbox.send(Address::Billing, Address::Checkout, Payloads::Charge(order)).await?;
+/// ```
+pub struct Outbox<A, P, C> {
+    postmaster: Postmaster<A, P>,
+    codec: C,
+    retry: RetryPolicy,
+    path: PathBuf,
+    journal: Mutex<Journal>,
+}
+
+impl<A, P, C> Outbox<A, P, C>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Clone + Send + 'static,
+    C: OutboxCodec<A, P>,
+{
+    /// Open the outbox stored in the journal at the given path, creating it if it doesn't exist.
+    /// Any messages which were still pending when the journal was last used are kept, to be sent again with `redeliver()`, and the journal is compacted so that it only holds those messages.
+    pub async fn open(
+        postmaster: &Postmaster<A, P>,
+        path: impl AsRef<Path>,
+        codec: C,
+    ) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let journal = match fs::read(&path).await {
+            Ok(journal) => journal,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error),
+        };
+        let pending = read_pending(&journal);
+        let next_sequence = pending
+            .keys()
+            .next_back()
+            .map_or(0, |sequence| sequence + 1);
+
+        // Write the compacted journal alongside the old one, so that a crash while compacting leaves the old journal intact
+        let mut compacted = Vec::new();
+        for (&sequence, bytes) in &pending {
+            compacted.extend_from_slice(&record(RECORD_PENDING, sequence, bytes)?);
+        }
+        let compacted_path = path.with_extension("compacting");
+        let mut file = File::create(&compacted_path).await?;
+        file.write_all(&compacted).await?;
+        file.sync_all().await?;
+        drop(file);
+        fs::rename(&compacted_path, &path).await?;
+        let file = OpenOptions::new().append(true).open(&path).await?;
+
+        Ok(Self {
+            postmaster: postmaster.clone(),
+            codec,
+            retry: RetryPolicy::exponential(5, Duration::from_millis(10)),
+            path,
+            journal: Mutex::new(Journal {
+                file,
+                pending,
+                next_sequence,
+            }),
+        })
+    }
+
+    /// Change how messages are retried if they can't be delivered and handled (by default, up to 5 attempts starting 10 ms apart)
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// The path of the outbox's journal
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Send a message through the outbox, returning once its recipient has handled it.
+    /// The message is written to the journal before it is sent, so if this fails, or the process stops before it returns, the message is still pending and will be sent again by `redeliver()`.
+    /// Fails with `PostmasterError::OutboxFailed` if the message couldn't be written to the journal, in which case it has not been sent.
+    pub async fn send(&self, destination: A, source: A, payload: P) -> Result<(), PostmasterError> {
+        let message = OutboxMessage {
+            destination,
+            source,
+            payload,
+        };
+        let bytes = self.codec.encode(&message);
+        let sequence = {
+            let mut journal = self.journal.lock().await;
+            let sequence = journal.next_sequence;
+            journal
+                .append(RECORD_PENDING, sequence, &bytes)
+                .await
+                .map_err(|_| PostmasterError::OutboxFailed)?;
+            journal.next_sequence += 1;
+            journal.pending.insert(sequence, bytes);
+            sequence
+        };
+        self.deliver(sequence, message).await
+    }
+
+    /// Send every pending message again, e.g. those left over from before the process restarted, returning the number which have now been handled.
+    /// Messages which still can't be delivered remain pending.
+    pub async fn redeliver(&self) -> usize {
+        let pending: Vec<(u64, Vec<u8>)> = self
+            .journal
+            .lock()
+            .await
+            .pending
+            .iter()
+            .map(|(&sequence, bytes)| (sequence, bytes.clone()))
+            .collect();
+        let mut delivered = 0;
+        for (sequence, bytes) in pending {
+            let Some(message) = self.codec.decode(&bytes) else {
+                let _ = self.mark_done(sequence).await;
+                continue;
+            };
+            if self.deliver(sequence, message).await.is_ok() {
+                delivered += 1;
+            }
+        }
+        delivered
+    }
+
+    /// The number of messages which have not yet been handled by their recipients
+    pub async fn pending(&self) -> usize {
+        self.journal.lock().await.pending.len()
+    }
+
+    async fn deliver(
+        &self,
+        sequence: u64,
+        message: OutboxMessage<A, P>,
+    ) -> Result<(), PostmasterError> {
+        self.postmaster
+            .message(message.destination, message.source, message.payload)
+            .with_ack()
+            .with_retry(self.retry)
+            .send()
+            .await?;
+        self.mark_done(sequence).await
+    }
+
+    async fn mark_done(&self, sequence: u64) -> Result<(), PostmasterError> {
+        let mut journal = self.journal.lock().await;
+        if journal.pending.remove(&sequence).is_none() {
+            // Already handled, e.g. by a concurrent redelivery
+            return Ok(());
+        }
+        journal
+            .append(RECORD_DONE, sequence, &[])
+            .await
+            .map_err(|_| PostmasterError::OutboxFailed)
+    }
+}
+
+/// Encode a journal record: its kind, the message's sequence number, and the length of the encoded message followed by the message itself
+fn record(kind: u8, sequence: u64, bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let length = u32::try_from(bytes.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Message too large"))?;
+    let mut record = Vec::with_capacity(bytes.len() + 13);
+    record.push(kind);
+    record.extend_from_slice(&sequence.to_be_bytes());
+    record.extend_from_slice(&length.to_be_bytes());
+    record.extend_from_slice(bytes);
+    Ok(record)
+}
+
+/// Find the messages in a journal which were never marked as done.
+/// Reading stops at the first incomplete or invalid record, which is left by a crash part-way through writing it.
+fn read_pending(mut journal: &[u8]) -> BTreeMap<u64, Vec<u8>> {
+    let mut pending = BTreeMap::new();
+    while let Some((&kind, rest)) = journal.split_first()
+        && let Some((sequence, rest)) = rest.split_first_chunk::<8>()
+        && let Some((length, rest)) = rest.split_first_chunk::<4>()
+        && let Ok(length) = usize::try_from(u32::from_be_bytes(*length))
+        && rest.len() >= length
+    {
+        let sequence = u64::from_be_bytes(*sequence);
+        let (bytes, rest) = rest.split_at(length);
+        match kind {
+            RECORD_PENDING => {
+                pending.insert(sequence, bytes.to_vec());
+            }
+            RECORD_DONE => {
+                pending.remove(&sequence);
+            }
+            _ => break,
+        }
+        journal = rest;
+    }
+    pending
+}
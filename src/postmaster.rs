@@ -0,0 +1,377 @@
+#[cfg(not(target_os = "none"))]
+mod ack;
+#[cfg(not(target_os = "none"))]
+mod any;
+#[cfg(not(target_os = "none"))]
+mod coalesce;
+#[cfg(not(target_os = "none"))]
+mod config;
+#[cfg(all(feature = "cron", not(target_os = "none")))]
+mod cron;
+#[cfg(not(target_os = "none"))]
+mod dead_letter;
+#[cfg(not(target_os = "none"))]
+mod dedup;
+#[cfg(not(target_os = "none"))]
+mod drain;
+#[cfg(not(target_os = "none"))]
+mod hosted;
+#[cfg(not(target_os = "none"))]
+mod intercept;
+#[cfg(not(target_os = "none"))]
+mod metrics;
+#[cfg(not(target_os = "none"))]
+mod ordering;
+#[cfg(not(target_os = "none"))]
+mod policy;
+#[cfg(not(target_os = "none"))]
+mod rate;
+#[cfg(not(target_os = "none"))]
+mod rcu;
+#[cfg(not(target_os = "none"))]
+mod retry;
+#[cfg(not(target_os = "none"))]
+mod route;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(not(target_os = "none"))]
+mod shared;
+#[cfg(not(target_os = "none"))]
+mod shutdown;
+#[cfg(not(target_os = "none"))]
+mod startup;
+#[cfg(not(target_os = "none"))]
+mod status;
+#[cfg(not(target_os = "none"))]
+mod timer;
+#[cfg(all(feature = "tracing", not(target_os = "none")))]
+mod trace;
+#[cfg(not(target_os = "none"))]
+mod unsent;
+#[cfg(not(target_os = "none"))]
+mod watchdog;
+#[cfg(not(target_os = "none"))]
+mod wheel;
+#[cfg(not(target_os = "none"))]
+pub use any::AnyPayload;
+#[cfg(not(target_os = "none"))]
+pub use config::{DelayClock, MessageOrdering, OverflowPolicy, PostmasterConfig};
+#[cfg(all(feature = "cron", not(target_os = "none")))]
+pub use cron::{CatchUp, CronError, CronHandle, CronSchedule};
+#[cfg(not(target_os = "none"))]
+pub use dead_letter::{DEAD_LETTER_CAPACITY, DeadLetter};
+#[cfg(not(target_os = "none"))]
+pub use dedup::{DEFAULT_DEDUP_WINDOW, MessageId};
+#[cfg(not(target_os = "none"))]
+pub use drain::{AgentDrain, DrainReport};
+#[cfg(all(feature = "cron", not(target_os = "none")))]
+pub use hosted::CronMessage;
+#[cfg(not(target_os = "none"))]
+pub use hosted::{DEFAULT_TIMEOUT_US, MessageBuilder, Postmaster};
+#[cfg(not(target_os = "none"))]
+pub use intercept::{Interceptor, Verdict};
+#[cfg(not(target_os = "none"))]
+pub use metrics::{AgentMetrics, LATENCY_BUCKETS_US, LatencyHistogram, Metrics};
+#[cfg(not(target_os = "none"))]
+pub use ordering::{SequenceTracker, Sequenced};
+#[cfg(not(target_os = "none"))]
+pub use policy::{PolicyViolation, SendPolicy};
+#[cfg(not(target_os = "none"))]
+pub use post_haste_macros::AnyPayload;
+pub use post_haste_macros::{PayloadVariant, Payloads};
+#[cfg(not(target_os = "none"))]
+pub use rate::{RateLimit, RateLimitAction};
+#[cfg(not(target_os = "none"))]
+pub use retry::{DEFAULT_MAX_BACKOFF, RetryPolicy};
+#[cfg(not(target_os = "none"))]
+pub use route::{PoolRouting, RoutingKey};
+#[cfg(not(target_os = "none"))]
+pub use shared::SharedPayload;
+#[cfg(not(target_os = "none"))]
+pub use shutdown::{ShutdownReport, ShutdownSignal};
+#[cfg(not(target_os = "none"))]
+pub use startup::{Startup, StartupError};
+#[cfg(not(target_os = "none"))]
+pub use status::{AddressStatus, Status, TaskState};
+#[cfg(not(target_os = "none"))]
+pub use unsent::{SendError, TrySendError};
+#[cfg(not(target_os = "none"))]
+pub use watchdog::AgentStall;
+
+/// Identifies a flow of messages, such as a request and all of the messages sent while handling it, so that they can be tied together (e.g. in logs).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CorrelationId(pub u64);
+
+impl From<u64> for CorrelationId {
+    fn from(id: u64) -> Self {
+        Self(id)
+    }
+}
+
+/// The structure of a message in the system.
+/// This structure is automatically generated by the sending functions from the source address and the payload
+pub struct Message<A, P> {
+    /// The address from which the message originated
+    pub source: A,
+    /// The message contents
+    pub payload: P,
+    /// The flow the message belongs to, if set by the sender with `with_correlation_id()` (or carried over from another message with `forwarded_from()`)
+    pub correlation_id: Option<CorrelationId>,
+    /// The address to which replies should be sent, if the sender set one with `reply_to()`.
+    /// Otherwise replies should be sent to the source; `reply_address()` gives whichever applies.
+    pub reply_to: Option<A>,
+    #[cfg(not(target_os = "none"))]
+    pub(crate) id: Option<MessageId>,
+    #[cfg(not(target_os = "none"))]
+    pub(crate) enqueued_at: Option<tokio::time::Instant>,
+    #[cfg(not(target_os = "none"))]
+    pub(crate) ack: Option<Box<ack::Acknowledgement>>,
+    #[cfg(not(target_os = "none"))]
+    pub(crate) control: bool,
+    #[cfg(not(target_os = "none"))]
+    pub(crate) sequence: Option<core::num::NonZeroU64>,
+    /// The address the message was sent to, once it has been sent
+    #[cfg(not(target_os = "none"))]
+    pub(crate) destination: Option<A>,
+    /// The address the message was sent to, if it was delivered to the fallback address instead
+    #[cfg(not(target_os = "none"))]
+    pub(crate) intended_destination: Option<A>,
+    /// Who sent the message, as far as the Postmaster can tell, for checking it against the send policy
+    #[cfg(not(target_os = "none"))]
+    pub(crate) origin: policy::Origin,
+    #[cfg(all(feature = "tracing", not(target_os = "none")))]
+    pub(crate) trace: trace::MessageTrace,
+}
+
+impl<A: Copy, P> Message<A, P> {
+    /// The address to which replies to this message should be sent: the `reply_to` address if one was set, otherwise the source
+    pub fn reply_address(&self) -> A {
+        self.reply_to.unwrap_or(self.source)
+    }
+
+    /// The address the message was sent to, if no recipient was registered there and it was delivered to the fallback address set with `set_fallback()` instead.
+    /// Returns `None` for a message delivered to the address it was sent to.
+    #[cfg(not(target_os = "none"))]
+    pub fn intended_destination(&self) -> Option<A> {
+        self.intended_destination
+    }
+
+    /// The address the message was sent to, which for a received message is the address of the Agent that received it (or, for a message delivered to the fallback address, the address it was meant for).
+    /// Returns `None` for a message which hasn't been sent yet.
+    #[cfg(not(target_os = "none"))]
+    pub fn destination(&self) -> Option<A> {
+        self.destination
+    }
+}
+
+impl<A, P> Message<A, P> {
+    /// Convert the message's payload to another type, keeping everything else about the message, or give the message back unchanged if the payload can't be converted
+    // The message is handed back whole rather than boxed, as this is on the path of every message a typed inbox receives
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn try_map_payload<Q>(
+        self,
+        map: impl FnOnce(P) -> Result<Q, P>,
+    ) -> Result<Message<A, Q>, Self> {
+        let Self {
+            source,
+            payload,
+            correlation_id,
+            reply_to,
+            #[cfg(not(target_os = "none"))]
+            id,
+            #[cfg(not(target_os = "none"))]
+            enqueued_at,
+            #[cfg(not(target_os = "none"))]
+            ack,
+            #[cfg(not(target_os = "none"))]
+            control,
+            #[cfg(not(target_os = "none"))]
+            sequence,
+            #[cfg(not(target_os = "none"))]
+            destination,
+            #[cfg(not(target_os = "none"))]
+            intended_destination,
+            #[cfg(not(target_os = "none"))]
+            origin,
+            #[cfg(all(feature = "tracing", not(target_os = "none")))]
+            trace,
+        } = self;
+        macro_rules! rebuild {
+            ($payload:expr) => {
+                Message {
+                    source,
+                    payload: $payload,
+                    correlation_id,
+                    reply_to,
+                    #[cfg(not(target_os = "none"))]
+                    id,
+                    #[cfg(not(target_os = "none"))]
+                    enqueued_at,
+                    #[cfg(not(target_os = "none"))]
+                    ack,
+                    #[cfg(not(target_os = "none"))]
+                    control,
+                    #[cfg(not(target_os = "none"))]
+                    sequence,
+                    #[cfg(not(target_os = "none"))]
+                    destination,
+                    #[cfg(not(target_os = "none"))]
+                    intended_destination,
+                    #[cfg(not(target_os = "none"))]
+                    origin,
+                    #[cfg(all(feature = "tracing", not(target_os = "none")))]
+                    trace,
+                }
+            };
+        }
+        match map(payload) {
+            Ok(payload) => Ok(rebuild!(payload)),
+            Err(payload) => Err(rebuild!(payload)),
+        }
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl<A, P> Message<A, P> {
+    pub(crate) fn new(source: A, payload: P) -> Self {
+        Self {
+            source,
+            payload,
+            correlation_id: None,
+            reply_to: None,
+            id: None,
+            enqueued_at: None,
+            ack: None,
+            control: false,
+            sequence: None,
+            destination: None,
+            intended_destination: None,
+            origin: policy::Origin::current(),
+            #[cfg(feature = "tracing")]
+            trace: trace::MessageTrace::new(),
+        }
+    }
+
+    /// A copy of the message, e.g. to hand the same reply to several callers.
+    /// An acknowledgement requested by the sender stays with the original.
+    pub(crate) fn duplicate(&self) -> Self
+    where
+        A: Copy,
+        P: Clone,
+    {
+        self.duplicate_with(P::clone)
+    }
+
+    /// A copy of the message as with `duplicate()`, copying the payload with the given function
+    pub(crate) fn duplicate_with(&self, copy: impl FnOnce(&P) -> P) -> Self
+    where
+        A: Copy,
+    {
+        Self {
+            source: self.source,
+            payload: copy(&self.payload),
+            correlation_id: self.correlation_id,
+            reply_to: self.reply_to,
+            id: self.id,
+            enqueued_at: self.enqueued_at,
+            ack: None,
+            control: self.control,
+            sequence: self.sequence,
+            destination: self.destination,
+            intended_destination: self.intended_destination,
+            origin: self.origin.clone(),
+            #[cfg(feature = "tracing")]
+            trace: self.trace.clone(),
+        }
+    }
+
+    /// The ID given to the message by its sender with `with_id()`, if any
+    pub fn id(&self) -> Option<MessageId> {
+        self.id
+    }
+
+    /// Whether the message was sent as a control message, either by the Postmaster (e.g. a watch notification) or by a sender using `as_control()`, and so was received ahead of the recipient's regular messages
+    pub fn is_control(&self) -> bool {
+        self.control
+    }
+
+    /// The message's place in the order of the messages sent from its source to the address it was sent to, if the Postmaster keeps messages in order (see `PostmasterConfig::with_ordering()`).
+    /// Numbers count up from 1, and every message is numbered as it is sent, including any which are then lost (e.g. because they time out), so a gap in the numbers shows that messages went missing (see `SequenceTracker`).
+    /// Control messages skip ahead of regular messages, so they are not numbered.
+    pub fn sequence(&self) -> Option<u64> {
+        self.sequence.map(core::num::NonZeroU64::get)
+    }
+
+    /// When the message was placed on the recipient's queue, or `None` if it hasn't been delivered yet (e.g. when inspected by an interceptor)
+    pub fn enqueued_at(&self) -> Option<tokio::time::Instant> {
+        self.enqueued_at
+    }
+
+    /// How long the message has been waiting since it was placed on the recipient's queue.
+    /// Calling this as soon as a message is received shows how far an Agent is behind with its messages, e.g. to log a warning (or record a metric) when a command waited too long before being processed.
+    /// Returns zero if the message hasn't been delivered yet.
+    pub fn queued_for(&self) -> tokio::time::Duration {
+        self.enqueued_at
+            .map(|enqueued_at| enqueued_at.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// Tell the sender that the message has been handled, if it was sent `with_ack()`.
+    /// Otherwise the sender is told once the message is dropped, so this only needs to be called to acknowledge a message before the Agent has finished with it, e.g. as soon as it is received, or before handing it on to another task.
+    pub fn acknowledge(&mut self) {
+        if let Some(ack) = &mut self.ack {
+            ack.acknowledge();
+        }
+    }
+
+    /// The tracing span of the message, which is opened when the message is sent and closed once the message is dropped.
+    /// Its fields record the message's source, destination and payload variant (if the Postmaster is naming payloads with `trace_payload_variants()`), plus either the time taken to place the message on its recipient's queue or the reason delivery failed.
+    ///
+    /// An Agent can run the code handling a message inside the message's span, so that any messages sent in response are traced as children of it.
+    /// As the span's guard must not be held across an `.await`, use `tracing::Instrument`, e.g. `handle(message).instrument(span).await` where `span` is `message.span().clone()`.
+    #[cfg(feature = "tracing")]
+    pub fn span(&self) -> &tracing::Span {
+        &self.trace.span
+    }
+}
+
+/// Names the variant of a payload.
+/// When the `tracing` feature is enabled, a Postmaster configured with `trace_payload_variants()` records the variant of each message's payload in its span.
+/// This trait can be derived for an enum of payloads with `#[derive(PayloadVariant)]`.
+///
+/// # Example
+/// ```rust
+/// use post_haste::postmaster::PayloadVariant;
+///
+/// #[derive(PayloadVariant)]
+/// enum Payloads {
+///     Hello,
+///     Reading(f32),
+/// }
+///
+/// assert_eq!(Payloads::Reading(21.5).variant(), "Reading");
+/// ```
+pub trait PayloadVariant {
+    /// The name of the payload's variant
+    fn variant(&self) -> &'static str;
+}
+
+/// Contains diagnostic information for the Postmaster.
+/// Obtained by calling postmaster::get_diagnostics()
+#[derive(Debug, Clone, Copy)]
+pub struct Diagnostics {
+    /// The name given to the Postmaster with `PostmasterConfig::with_name()`, if any.
+    #[cfg(not(target_os = "none"))]
+    pub name: Option<&'static str>,
+    /// The number of messages successfully sent since the Postmaster was initialised.
+    pub messages_sent: usize,
+    /// The number of messages which could not be sent since the Postmaster was initialised.
+    pub send_failures: usize,
+    /// The number of Agent panics caught since the Postmaster was initialised.
+    #[cfg(not(target_os = "none"))]
+    pub agent_panics: usize,
+    /// The number of delayed messages waiting for their delay to elapse.
+    #[cfg(not(target_os = "none"))]
+    pub pending_delayed: usize,
+}
@@ -0,0 +1,58 @@
+use std::thread;
+
+use tokio::sync::oneshot;
+
+/// Whether the queue a message was delivered to has been closed, i.e. its Agent has stopped
+type QueueClosed = Box<dyn Fn() -> bool + Send + Sync>;
+
+/// Reports back to the sender of a message sent `with_ack()` once the message has been handled.
+/// The message is handled once its recipient acknowledges it, or otherwise drops it after receiving it.
+/// A message dropped without being handled (e.g. left on the queue of an Agent which stopped, or dropped while its Agent panicked) is reported as not acknowledged.
+pub(crate) struct Acknowledgement {
+    reply: Option<oneshot::Sender<bool>>,
+    queue_closed: Option<QueueClosed>,
+}
+
+impl Acknowledgement {
+    /// Create an acknowledgement, returning the receiver on which the sender waits for it
+    pub(super) fn new() -> (Self, oneshot::Receiver<bool>) {
+        let (reply, receiver) = oneshot::channel();
+        (
+            Self {
+                reply: Some(reply),
+                queue_closed: None,
+            },
+            receiver,
+        )
+    }
+
+    /// Record the queue the message has been placed on, so that a message dropped along with the queue isn't mistaken for one which has been handled
+    pub(super) fn delivered(&mut self, queue_closed: impl Fn() -> bool + Send + Sync + 'static) {
+        self.queue_closed = Some(Box::new(queue_closed));
+    }
+
+    /// Forget the queue the message was placed on, as it is being sent on to another address, so that a message dropped before it reaches the next queue isn't mistaken for one which has been handled
+    pub(super) fn forwarded(&mut self) {
+        self.queue_closed = None;
+    }
+
+    pub(crate) fn acknowledge(&mut self) {
+        if let Some(reply) = self.reply.take() {
+            let _ = reply.send(true);
+        }
+    }
+}
+
+impl Drop for Acknowledgement {
+    fn drop(&mut self) {
+        if let Some(reply) = self.reply.take() {
+            // A queue is closed before the messages still on it are dropped, whereas a message which has been received is dropped while its Agent is still running
+            let handled = !thread::panicking()
+                && self
+                    .queue_closed
+                    .as_ref()
+                    .is_some_and(|queue_closed| !queue_closed());
+            let _ = reply.send(handled);
+        }
+    }
+}
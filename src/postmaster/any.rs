@@ -0,0 +1,85 @@
+use core::any::{Any, type_name};
+use core::fmt::{self, Debug};
+
+use super::PayloadVariant;
+
+/// A payload of any type, for a system in which each Agent declares its own message type rather than sharing one enum of payloads between them all.
+/// With a shared enum, the enum depends on every Agent's message type, so changing any one of them rebuilds every Agent; with `AnyPayload` as the Postmaster's payload type, each Agent's message type only needs to be known to the Agent and to those which send to it.
+///
+/// Each message type derives `#[derive(AnyPayload)]`, which generates a `From` conversion into `AnyPayload` (so the message can be passed wherever a payload is expected) and a `TryFrom` conversion back out of it.
+/// The recipient takes its messages back out with `downcast()`, or (for a `TypedAgent`) wraps its inbox in a `TypedInbox`, which discards any message of another type.
+/// Declaring each Agent's address as a `Recipient` of its message type means that sending a message of the wrong type still fails to compile, as it would with a shared enum.
+///
+/// # Example
+/// ```rust,ignore
+/// // In lights.rs
+/// #[derive(Debug, AnyPayload)]
+/// pub enum LightsMessage {
+///     Display,
+/// }
+///
+/// pub const LIGHTS: Recipient<Addresses, LightsMessage> = Recipient::new(Addresses::LightsAgent);
+///
+/// // In main.rs, which doesn't need to know about any of the message types
+/// init_postmaster!(Addresses, AnyPayload);
+///
+/// // In sequencer.rs
+/// postmaster::send_to(LIGHTS, Addresses::SequencerAgent, LightsMessage::Display).await.unwrap();
+/// ```
+pub struct AnyPayload {
+    payload: Box<dyn Any + Send>,
+    type_name: &'static str,
+}
+
+impl AnyPayload {
+    /// Wrap a message of any type
+    pub fn new<M: Any + Send>(message: M) -> Self {
+        Self {
+            payload: Box::new(message),
+            type_name: type_name::<M>(),
+        }
+    }
+
+    /// Whether the payload is a message of type `M`
+    pub fn is<M: Any>(&self) -> bool {
+        self.payload.is::<M>()
+    }
+
+    /// Take the message out of the payload if it is of type `M`, or hand the payload back otherwise
+    pub fn downcast<M: Any>(self) -> Result<M, Self> {
+        let Self { payload, type_name } = self;
+        payload
+            .downcast()
+            .map(|message| *message)
+            .map_err(|payload| Self { payload, type_name })
+    }
+
+    /// A reference to the message in the payload, if it is of type `M`
+    pub fn downcast_ref<M: Any>(&self) -> Option<&M> {
+        self.payload.downcast_ref()
+    }
+
+    /// A mutable reference to the message in the payload, if it is of type `M`
+    pub fn downcast_mut<M: Any>(&mut self) -> Option<&mut M> {
+        self.payload.downcast_mut()
+    }
+
+    /// The full name of the type of the message in the payload, e.g. `my_project::lights::LightsMessage`
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+}
+
+impl Debug for AnyPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AnyPayload").field(&self.type_name).finish()
+    }
+}
+
+/// The variant of an `AnyPayload` is the name of the type of its message, without its path (e.g. `LightsMessage`), so that it can be named in a `SendPolicy` and is recorded in the spans of messages
+impl PayloadVariant for AnyPayload {
+    fn variant(&self) -> &'static str {
+        let name = self.type_name.split('<').next().unwrap_or(self.type_name);
+        name.rsplit("::").next().unwrap_or(name)
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Mutex as BlockingMutex;
+
+use tokio::sync::oneshot;
+
+use super::Message;
+use crate::PostmasterError;
+use crate::address::AddressIndex;
+
+/// The reply to a request, or the reason none was received
+pub(super) type Reply<A, P> = Result<Message<A, P>, PostmasterError>;
+
+/// Identifies identical requests: the destination they are sent to, and the hash of the caller's coalescing key
+pub(super) type CoalescingKey = (AddressIndex, u64);
+
+pub(super) fn coalescing_key(destination: AddressIndex, key: impl Hash) -> CoalescingKey {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (destination, hasher.finish())
+}
+
+/// The requests made with `request_coalesced()` which are in flight, along with the callers waiting to share their replies
+pub(super) struct InFlight<A, P>(BTreeMap<CoalescingKey, Vec<oneshot::Sender<Reply<A, P>>>>);
+
+impl<A, P> InFlight<A, P> {
+    pub(super) fn new() -> Self {
+        Self(BTreeMap::new())
+    }
+
+    /// Wait for the reply to an identical request which is already in flight, or return `None` (noting that the request is now in flight) if there isn't one, in which case the caller must make the request
+    pub(super) fn join(&mut self, key: CoalescingKey) -> Option<oneshot::Receiver<Reply<A, P>>> {
+        match self.0.get_mut(&key) {
+            Some(waiting) => {
+                let (sender, receiver) = oneshot::channel();
+                waiting.push(sender);
+                Some(receiver)
+            }
+            None => {
+                self.0.insert(key, Vec::new());
+                None
+            }
+        }
+    }
+}
+
+/// Held by the caller making a coalesced request, to share the reply with the callers waiting for it.
+/// If the caller gives up on the request (e.g. it is cancelled) without a reply, the request is withdrawn, and one of the waiting callers makes it instead.
+pub(super) struct Leading<'a, A, P> {
+    in_flight: &'a BlockingMutex<InFlight<A, P>>,
+    key: Option<CoalescingKey>,
+}
+
+impl<'a, A, P> Leading<'a, A, P> {
+    pub(super) fn new(in_flight: &'a BlockingMutex<InFlight<A, P>>, key: CoalescingKey) -> Self {
+        Self {
+            in_flight,
+            key: Some(key),
+        }
+    }
+
+    /// Send a copy of the reply (or the error) to each of the waiting callers
+    pub(super) fn share(mut self, reply: &Reply<A, P>)
+    where
+        A: Copy,
+        P: Clone,
+    {
+        for waiting in self.withdraw() {
+            let _ = waiting.send(match reply {
+                Ok(message) => Ok(message.duplicate()),
+                Err(error) => Err(*error),
+            });
+        }
+    }
+
+    /// Stop coalescing requests with this one, returning the callers which are waiting for it
+    fn withdraw(&mut self) -> Vec<oneshot::Sender<Reply<A, P>>> {
+        self.key
+            .take()
+            .and_then(|key| self.in_flight.lock().unwrap().0.remove(&key))
+            .unwrap_or_default()
+    }
+}
+
+impl<A, P> Drop for Leading<'_, A, P> {
+    fn drop(&mut self) {
+        // The waiting callers see their replies dropped, and retry the request themselves
+        self.withdraw();
+    }
+}
@@ -0,0 +1,128 @@
+use super::hosted::DEFAULT_TIMEOUT_US;
+
+/// What happens to a message sent to an Agent whose message queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for space on the queue, failing with `PostmasterError::Timeout` if none is freed before the send timeout expires (the default)
+    Wait,
+    /// Fail the send straight away with `PostmasterError::TrySendFailed`, as `try_send()` does
+    Reject,
+    /// Discard the message but report the send as successful, so that the sender carries on regardless (e.g. for sensor readings which are soon superseded).
+    /// Discarded messages are still counted as send failures in the diagnostics, and kept with the other dead letters.
+    DropNewest,
+    /// Spool the message to disk, to be placed on the queue once there is space for it, and report the send as successful straight away.
+    /// Requires a `Spillover` to have been opened for the Postmaster (with the `spillover` feature); until one is, messages wait for space as with `Wait`.
+    #[cfg(feature = "spillover")]
+    Spill,
+}
+
+/// The clock against which the delays of delayed messages are measured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DelayClock {
+    /// tokio's clock (the default).
+    /// This keeps to real time, except when tokio's clock is paused in tests (e.g. with `#[tokio::test(start_paused = true)]`), where delayed messages are delivered as soon as the clock is advanced past their delay.
+    Virtual,
+    /// The wall clock, so that delays take the same real time whether or not tokio's clock is paused, e.g. for a test which pauses time for its own timers while talking to real hardware.
+    /// On WebAssembly, which has no threads to time the delays on, this follows tokio's clock.
+    Real,
+}
+
+/// Whether the Postmaster keeps the messages from each sender to each destination in the order they were sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageOrdering {
+    /// Messages are delivered as soon as they can be, with no numbering (the default).
+    /// A single task sending to one destination still has its messages delivered in order, but messages sent concurrently from the same source may overtake each other, and a delayed message is delivered whenever its delay elapses.
+    Unordered,
+    /// Every message from a source to a destination is numbered, and delivered in the order it was numbered, even when sent concurrently from several tasks.
+    /// A delayed message is numbered once its delay has elapsed, so it takes its place after any messages sent in the meantime.
+    PerSender,
+    /// As with `PerSender`, except that a delayed message is numbered when it is sent, and keeps its place in the order.
+    /// Messages sent after it from the same source to the same destination wait (and so does their sender) until it has been delivered, so a delayed message can never be overtaken, e.g. by a command sent after it which it would otherwise undo.
+    /// A message waits for its turn no longer than its send timeout, failing with `PostmasterError::Timeout` if the delayed message is still waiting by then, so a message sent behind a long delay needs a timeout to match.
+    PerSenderStrict,
+}
+
+/// Options for a Postmaster, given to `Postmaster::with_config()` or to `init_postmaster!()` with the `config =` prefix.
+/// Every option has a default, so only the options which matter need to be set.
+///
+/// # Example
+/// ```rust
+/// use post_haste::postmaster::{OverflowPolicy, PostmasterConfig};
+///
+/// let config = PostmasterConfig::new()
+///     .with_name("telemetry")
+///     .with_timeout_us(5_000)
+///     .with_queue_size(16)
+///     .with_overflow_policy(OverflowPolicy::DropNewest);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct PostmasterConfig {
+    pub(super) name: Option<&'static str>,
+    pub(super) timeout_us: u32,
+    pub(super) queue_size: usize,
+    pub(super) overflow_policy: OverflowPolicy,
+    pub(super) delay_clock: DelayClock,
+    pub(super) ordering: MessageOrdering,
+}
+
+impl PostmasterConfig {
+    /// The default options: no name, a send timeout of 1 ms, a queue size of 1, waiting for space on full queues, tokio's clock for delays and no message ordering
+    pub const fn new() -> Self {
+        Self {
+            name: None,
+            timeout_us: DEFAULT_TIMEOUT_US,
+            queue_size: 1,
+            overflow_policy: OverflowPolicy::Wait,
+            delay_clock: DelayClock::Virtual,
+            ordering: MessageOrdering::Unordered,
+        }
+    }
+
+    /// Name the Postmaster, so that it can be told apart from others in the diagnostics, panic reports and traces
+    pub const fn with_name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Set the default send timeout (in microseconds)
+    pub const fn with_timeout_us(mut self, timeout_us: u32) -> Self {
+        self.timeout_us = timeout_us;
+        self
+    }
+
+    /// Set the size of the message queue given to Agents registered without a queue size.
+    /// Panics if `queue_size` is zero.
+    pub const fn with_queue_size(mut self, queue_size: usize) -> Self {
+        assert!(
+            queue_size > 0,
+            "A message queue must hold at least one message"
+        );
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// Set what happens to messages sent to a full queue.
+    /// The policy applies to sends using the default timeout; a send given its own timeout (e.g. with `with_timeout()`, `without_timeout()` or `send_with_backpressure()`) always waits for space.
+    pub const fn with_overflow_policy(mut self, overflow_policy: OverflowPolicy) -> Self {
+        self.overflow_policy = overflow_policy;
+        self
+    }
+
+    /// Set the clock against which the delays of delayed messages are measured
+    pub const fn with_delay_clock(mut self, delay_clock: DelayClock) -> Self {
+        self.delay_clock = delay_clock;
+        self
+    }
+
+    /// Set whether the messages from each sender to each destination are numbered and kept in the order they were sent (see `Message::sequence()`)
+    pub const fn with_ordering(mut self, ordering: MessageOrdering) -> Self {
+        self.ordering = ordering;
+        self
+    }
+}
+
+impl Default for PostmasterConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
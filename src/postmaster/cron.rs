@@ -0,0 +1,262 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::task::AbortHandle;
+use tokio::time::{self, Duration};
+
+/// The longest a recurring message sleeps before checking the wall clock again, so that a change of the clock (or a suspend, during which tokio's clock stops) is noticed promptly
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// How late a fire can be noticed before it counts as missed, e.g. because the machine was suspended
+const MISSED_AFTER: Duration = Duration::from_secs(1);
+
+/// How far ahead to look for the next fire before concluding that the schedule never fires again (long enough to reach the 29th of February from any date)
+const LOOKAHEAD_SECS: i64 = 9 * 366 * SECS_PER_DAY;
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// The field of a cron expression which could not be parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CronError {
+    /// The expression doesn't have five fields (minute, hour, day of month, month and day of week) or six fields (with seconds first)
+    FieldCount(usize),
+    /// A field isn't a valid list of values, ranges and steps, or is out of range for its field (e.g. a minute of 60)
+    InvalidField(&'static str),
+}
+
+/// The values of one field of a cron expression, as a bit for each value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Field(u64);
+
+impl Field {
+    /// Parse a comma-separated list of `*`, `value` or `first-last` items, each optionally followed by `/step`
+    fn parse(field: &str, name: &'static str, min: u32, max: u32) -> Result<Self, CronError> {
+        let invalid = CronError::InvalidField(name);
+        let mut bits = 0;
+        for item in field.split(',') {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => (range, step.parse().map_err(|_| invalid)?),
+                None => (item, 1),
+            };
+            let (first, last) = match range.split_once('-') {
+                _ if range == "*" => (min, max),
+                Some((first, last)) => (
+                    first.parse().map_err(|_| invalid)?,
+                    last.parse().map_err(|_| invalid)?,
+                ),
+                // A single value with a step runs from the value to the end of the field, as in other cron implementations
+                None => {
+                    let value = range.parse().map_err(|_| invalid)?;
+                    (value, if item.contains('/') { max } else { value })
+                }
+            };
+            if step == 0 || first < min || last > max || first > last {
+                return Err(invalid);
+            }
+            for value in (first..=last).step_by(step as usize) {
+                bits |= 1 << value;
+            }
+        }
+        Ok(Self(bits))
+    }
+
+    fn contains(self, value: u32) -> bool {
+        self.0 & (1 << value) != 0
+    }
+
+    /// Whether the field allows every value, which for the day fields changes how they combine
+    fn is_unrestricted(self, min: u32, max: u32) -> bool {
+        (min..=max).all(|value| self.contains(value))
+    }
+}
+
+/// A parsed cron expression, giving the wall-clock times at which a recurring message is sent.
+/// Times are in UTC.
+///
+/// An expression has six fields, `second minute hour day-of-month month day-of-week`, or five fields without the seconds (in which case messages are sent at the start of each minute).
+/// Each field is a comma-separated list of `*` (every value), single values, or ranges such as `1-5`, and any of them may be followed by a step, e.g. `*/15` for every fifteenth value.
+/// Days of the week run from 0 (Sunday) to 6, with 7 also meaning Sunday.
+/// As in other cron implementations, when both the day of the month and the day of the week are restricted, a day matching either of them is enough.
+///
+/// # Example
+/// ```rust
+/// use post_haste::postmaster::CronSchedule;
+///
+/// // Every five minutes, on the minute
+/// let every_five_minutes = CronSchedule::parse("0 */5 * * * *").unwrap();
+/// // Every day at 02:00
+/// let nightly = CronSchedule::parse("0 2 * * *").unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CronSchedule {
+    seconds: Field,
+    minutes: Field,
+    hours: Field,
+    days_of_month: Field,
+    months: Field,
+    days_of_week: Field,
+}
+
+impl CronSchedule {
+    /// Parse a cron expression with five or six fields
+    pub fn parse(expression: &str) -> Result<Self, CronError> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let (seconds, fields) = match fields.len() {
+            5 => ("0", fields.as_slice()),
+            6 => (fields[0], &fields[1..]),
+            count => return Err(CronError::FieldCount(count)),
+        };
+        let mut days_of_week = Field::parse(fields[4], "day of week", 0, 7)?;
+        if days_of_week.contains(7) {
+            days_of_week.0 |= 1;
+        }
+        Ok(Self {
+            seconds: Field::parse(seconds, "second", 0, 59)?,
+            minutes: Field::parse(fields[0], "minute", 0, 59)?,
+            hours: Field::parse(fields[1], "hour", 0, 23)?,
+            days_of_month: Field::parse(fields[2], "day of month", 1, 31)?,
+            months: Field::parse(fields[3], "month", 1, 12)?,
+            days_of_week,
+        })
+    }
+
+    /// The first time after the given time at which the schedule fires, or `None` if it never fires again (e.g. for the 30th of February)
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let start = i64::try_from(since_epoch.as_secs()).ok()?;
+        let mut secs = start + 1;
+        while secs - start <= LOOKAHEAD_SECS {
+            let days = secs.div_euclid(SECS_PER_DAY);
+            let (year, month, day) = civil_from_days(days);
+            let of_day = secs.rem_euclid(SECS_PER_DAY);
+            let (hour, minute, second) = (
+                (of_day / 3600) as u32,
+                (of_day / 60 % 60) as u32,
+                (of_day % 60) as u32,
+            );
+            if !self.months.contains(month) {
+                let (year, month) = if month == 12 {
+                    (year + 1, 1)
+                } else {
+                    (year, month + 1)
+                };
+                secs = days_from_civil(year, month, 1) * SECS_PER_DAY;
+            } else if !self.day_matches(day, (days + 4).rem_euclid(7) as u32) {
+                secs = (days + 1) * SECS_PER_DAY;
+            } else if !self.hours.contains(hour) {
+                secs = secs - of_day % 3600 + 3600;
+            } else if !self.minutes.contains(minute) {
+                secs = secs - of_day % 60 + 60;
+            } else if !self.seconds.contains(second) {
+                secs += 1;
+            } else {
+                return UNIX_EPOCH.checked_add(Duration::from_secs(u64::try_from(secs).ok()?));
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, day_of_month: u32, day_of_week: u32) -> bool {
+        let by_month = self.days_of_month.contains(day_of_month);
+        let by_week = self.days_of_week.contains(day_of_week);
+        match (
+            self.days_of_month.is_unrestricted(1, 31),
+            self.days_of_week.is_unrestricted(0, 6),
+        ) {
+            (false, false) => by_month || by_week,
+            _ => by_month && by_week,
+        }
+    }
+}
+
+/// What a recurring message does about the times it was due while it couldn't be sent, e.g. while the machine was suspended, or the clock jumped forwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatchUp {
+    /// Send a single message for all of the missed times, then carry on with the schedule (the default)
+    Once,
+    /// Send a message for every missed time
+    All,
+    /// Send nothing for the missed times, and carry on with the schedule from the next time in the future
+    Skip,
+}
+
+/// A handle to a recurring message started with `MessageBuilder::with_cron()`.
+/// The message keeps being sent until the handle is used to cancel it, or the schedule has no more times; dropping the handle does not cancel it.
+#[derive(Debug)]
+pub struct CronHandle {
+    task: AbortHandle,
+}
+
+impl CronHandle {
+    pub(super) fn new(task: AbortHandle) -> Self {
+        Self { task }
+    }
+
+    /// Stop sending the message
+    pub fn cancel(&self) {
+        self.task.abort();
+    }
+
+    /// Whether the recurring message has stopped, either because it was cancelled or because the schedule has no more times
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+/// Wait for the next time the schedule fires, returning the number of messages to send then and the time after that, or `None` once the schedule has no more times
+pub(super) async fn next_fire(
+    schedule: &CronSchedule,
+    catch_up: CatchUp,
+    due: SystemTime,
+) -> (usize, Option<SystemTime>) {
+    // The wait is measured against the wall clock, so a clock which jumps backwards delays the message rather than repeating times which have already passed
+    while let Ok(wait) = due.duration_since(SystemTime::now())
+        && !wait.is_zero()
+    {
+        time::sleep(wait.min(MAX_SLEEP)).await;
+    }
+    let now = SystemTime::now();
+    let mut times = 1;
+    let mut next = schedule.next_after(due);
+    while let Some(time) = next.filter(|time| *time <= now) {
+        times += 1;
+        next = schedule.next_after(time);
+    }
+    let on_time = times == 1 && now.duration_since(due).unwrap_or_default() <= MISSED_AFTER;
+    let sends = match catch_up {
+        _ if on_time => 1,
+        CatchUp::Once => 1,
+        CatchUp::All => times,
+        CatchUp::Skip => 0,
+    };
+    (sends, next)
+}
+
+/// The date (year, month and day) of the given number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The number of days since 1970-01-01 of the given date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = i64::from((month + 9) % 12);
+    let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
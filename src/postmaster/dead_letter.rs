@@ -0,0 +1,20 @@
+use tokio::time::Instant;
+
+use crate::PostmasterError;
+
+/// The number of recent dead letters kept by the Postmaster, after which the oldest are forgotten
+pub const DEAD_LETTER_CAPACITY: usize = 64;
+
+/// A message which the Postmaster failed to deliver.
+/// The payload itself isn't kept, so that a dead letter doesn't hold on to a large payload (or require payloads to implement `Clone`).
+#[derive(Debug, Clone, Copy)]
+pub struct DeadLetter<A> {
+    /// The address the message was sent from
+    pub source: A,
+    /// The address the message was sent to
+    pub destination: A,
+    /// Why the message couldn't be delivered
+    pub error: PostmasterError,
+    /// When the delivery failed
+    pub failed_at: Instant,
+}
@@ -0,0 +1,63 @@
+use std::collections::{HashSet, VecDeque};
+
+use tokio::time::{Duration, Instant};
+
+/// How long the Postmaster remembers the IDs of messages delivered to an address, unless otherwise configured
+pub const DEFAULT_DEDUP_WINDOW: Duration = Duration::from_secs(60);
+
+/// Identifies a message, so that the Postmaster can discard duplicates of it.
+/// A sender which retries a message should give every attempt the same ID, e.g. a sequence number or a hash of the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MessageId(pub u64);
+
+impl From<u64> for MessageId {
+    fn from(id: u64) -> Self {
+        Self(id)
+    }
+}
+
+/// The IDs of the messages recently delivered to an address
+pub(super) struct DedupWindow {
+    window: Duration,
+    seen: HashSet<MessageId>,
+    /// The IDs in the order they were seen, for forgetting them once the window has passed
+    history: VecDeque<(Instant, MessageId)>,
+}
+
+impl DedupWindow {
+    pub(super) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            seen: HashSet::new(),
+            history: VecDeque::new(),
+        }
+    }
+
+    pub(super) fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    /// Claim an ID for a message which is about to be delivered, returning false if the ID has been seen within the window
+    pub(super) fn claim(&mut self, id: MessageId) -> bool {
+        let now = Instant::now();
+        while let Some((seen_at, expired)) = self.history.front().copied()
+            && now.duration_since(seen_at) >= self.window
+        {
+            self.history.pop_front();
+            self.seen.remove(&expired);
+        }
+        if !self.seen.insert(id) {
+            return false;
+        }
+        self.history.push_back((now, id));
+        true
+    }
+
+    /// Release the claim on an ID whose message could not be delivered, so that a retry can be delivered instead
+    pub(super) fn release(&mut self, id: MessageId) {
+        if self.seen.remove(&id) {
+            self.history.retain(|(_, seen)| *seen != id);
+        }
+    }
+}
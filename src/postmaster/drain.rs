@@ -0,0 +1,32 @@
+use tokio::time::Duration;
+
+/// How far an Agent got through the messages waiting on its queue while the Postmaster was draining
+#[derive(Debug, Clone)]
+pub struct AgentDrain<A> {
+    /// The address of the Agent (or pool of Agents)
+    pub address: A,
+    /// The number of messages still waiting on the Agent's queue when the drain finished, which is zero if the Agent worked through all of them
+    pub remaining: usize,
+    /// How long the Agent took to empty its queue, or `None` if it still had messages waiting when the drain timed out
+    pub drained_after: Option<Duration>,
+}
+
+/// The outcome of draining the Postmaster, with an entry for each Agent which was registered when draining began.
+/// Obtained by calling postmaster::drain()
+#[derive(Debug, Clone)]
+pub struct DrainReport<A> {
+    /// How far each Agent got through its queue
+    pub agents: Vec<AgentDrain<A>>,
+}
+
+impl<A> DrainReport<A> {
+    /// Whether every Agent worked through all of the messages on its queue before the drain timed out
+    pub fn is_complete(&self) -> bool {
+        self.agents.iter().all(|agent| agent.remaining == 0)
+    }
+
+    /// The total number of messages left unprocessed on the Agents' queues
+    pub fn remaining(&self) -> usize {
+        self.agents.iter().map(|agent| agent.remaining).sum()
+    }
+}
@@ -0,0 +1,3254 @@
+use core::fmt::Debug;
+use core::sync::atomic::Ordering;
+use core::task::{Context, Poll};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::hash::Hash;
+#[cfg(not(target_family = "wasm"))]
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(not(target_family = "wasm"))]
+use std::sync::OnceLock;
+use std::sync::{Arc, Mutex as BlockingMutex, Weak};
+#[cfg(not(target_family = "wasm"))]
+use std::thread;
+#[cfg(feature = "cron")]
+use std::time::SystemTime;
+
+use futures_core::Stream;
+use portable_atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize};
+#[cfg(any(feature = "chaos", not(target_family = "wasm")))]
+use tokio::runtime;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::{Notify, oneshot};
+use tokio::task::{self, AbortHandle, JoinHandle};
+use tokio::time::{self, Duration};
+
+#[cfg(feature = "tracing")]
+use super::PayloadVariant;
+use super::ack::Acknowledgement;
+use super::coalesce::{InFlight, Leading, coalescing_key};
+use super::config::{DelayClock, MessageOrdering, OverflowPolicy, PostmasterConfig};
+#[cfg(feature = "cron")]
+use super::cron::{self, CatchUp, CronHandle, CronSchedule};
+use super::dead_letter::{DEAD_LETTER_CAPACITY, DeadLetter};
+use super::dedup::{DEFAULT_DEDUP_WINDOW, DedupWindow, MessageId};
+use super::drain::{AgentDrain, DrainReport};
+use super::intercept::{Interceptor, Verdict};
+use super::metrics::{Counters, Metrics};
+use super::ordering::{Sequences, Turn};
+use super::policy::{self, Origin, SendPolicy};
+use super::rate::{Bucket, RateLimit, RateLimitAction};
+use super::retry::RetryPolicy;
+use super::route::{Pool, PoolRouting, Route, Routes};
+#[cfg(not(target_family = "wasm"))]
+use super::shutdown::{ShutdownReport, Signals};
+use super::status::{AddressStatus, Status, TaskState};
+use super::timer::{self, Timer, Timers};
+#[cfg(feature = "tracing")]
+use super::trace::{self, MessageTrace, VariantName};
+use super::unsent::{SendError, TrySendError};
+use super::watchdog::{AgentStall, Progress};
+use super::wheel::TimerWheel;
+use super::{CorrelationId, Diagnostics, Message};
+use crate::PostmasterError;
+use crate::address::{AddressSpace, RoutingTable};
+#[cfg(not(target_family = "wasm"))]
+use crate::agent::ThreadedAgentTask;
+use crate::agent::snapshot::{SnapshotRequest, SystemSnapshot};
+use crate::agent::{
+    self, AgentPanic, AgentTask, AgentTerminated, Inbox, LocalAgentTask, Mailbox, MailboxPolicy,
+    Ping, RestartPolicy, Salvage, TerminationReason, panic_message,
+};
+#[cfg(feature = "chaos")]
+use crate::chaos::{ChaosState, Delivery};
+#[cfg(feature = "recording")]
+use crate::recording::{Envelope, Recording};
+#[cfg(feature = "spillover")]
+use crate::spillover::SpillState;
+
+/// The timeout (in microseconds) used when sending messages, unless otherwise configured
+pub const DEFAULT_TIMEOUT_US: u32 = 1000;
+
+/// How often `drain()` and `shutdown()` check whether the Agents have emptied their queues
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The runtime which drives messages sent with `blocking_send()`, started the first time one is sent.
+/// It only needs a timer (for send timeouts and rate limits), as the message queues themselves work across runtimes.
+#[cfg(not(target_family = "wasm"))]
+static BLOCKING_RUNTIME: OnceLock<runtime::Runtime> = OnceLock::new();
+
+type PanicHook<A> = Box<dyn Fn(&AgentPanic<A>) + Send + Sync>;
+type Notification<A, P> = fn(AgentTerminated<A>) -> P;
+/// A watch added with `watch()`: the watcher, the watched address and how to build the notification
+type Watch<A, P> = (A, A, Notification<A, P>);
+/// A child Agent linked to its parent with `link_child()`: the parent and the child
+type Link<A> = (A, A);
+/// A message along with the address it is being delivered to
+type Addressed<A, P> = (A, Message<A, P>);
+/// A message rejected by an interceptor, handed back so that its payload can be returned to the sender
+type Rejected<A, P> = (PostmasterError, Message<A, P>);
+/// The members of each group created with `create_group()`
+type Groups<A> = BTreeMap<&'static str, Vec<A>>;
+/// The retry policy of a message sent with `with_retry()`, along with how to copy its payload for each attempt
+type Retry<P> = (RetryPolicy, fn(&P) -> P);
+/// The interceptors applied to every message, in order
+type Interceptors<A, P> = Arc<Vec<Arc<dyn Interceptor<A, P>>>>;
+/// Messages held by a running `Simulation`, awaiting delivery to their destinations
+#[cfg(feature = "simulation")]
+type Held<A, P> = Vec<Addressed<A, P>>;
+
+/// The delayed messages waiting to be sent, along with the task which sends them once they are due
+struct Delayed<A, P> {
+    wheel: TimerWheel<DelayedSend<A, P>>,
+    driver: Option<JoinHandle<()>>,
+}
+
+/// A message sent `with_delay()`, waiting for its delay to elapse
+struct DelayedSend<A, P> {
+    destination: A,
+    message: Message<A, P>,
+    timeout: Option<Duration>,
+    /// The message's place in the order of messages from its source, if it was numbered when it was sent (with `MessageOrdering::PerSenderStrict`)
+    turn: Option<Turn>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+/// An instance of the Postmaster.
+/// `init_postmaster!()` generates a `postmaster` module which wraps a single global instance, and this is usually the most convenient way of using the Postmaster.
+/// However, a Postmaster can also be created directly, which allows several isolated systems of Agents to exist within one process (for example, one per test).
+///
+/// The Postmaster is a handle to shared state, so cloning it is cheap and all clones refer to the same instance.
+/// Agents are registered with an instance using the `post_haste::spawn_agent!()` macro.
+///
+/// # Example
+/// ```rust
+/// use post_haste::AddressSpace;
+/// use post_haste::postmaster::Postmaster;
+///
+/// #[derive(Debug, Clone, Copy, AddressSpace)]
+/// enum Address {
+///     Main,
+///     Worker,
+/// }
+///
+/// enum Payloads {
+///     Hello,
+/// }
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let postmaster = Postmaster::<Address, Payloads>::new();
+///     let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+///     postmaster.register(Address::Main, sender).await.unwrap();
+///
+///     postmaster.send(Address::Main, Address::Worker, Payloads::Hello).await.unwrap();
+///     let received_message = receiver.recv().await.unwrap();
+/// }
+/// ```
+pub struct Postmaster<A, P> {
+    inner: Arc<Inner<A, P>>,
+}
+
+struct Inner<A, P> {
+    routes: Routes<A, P>,
+    tasks: BlockingMutex<RoutingTable<Vec<AbortHandle>>>,
+    watchers: BlockingMutex<Vec<Watch<A, P>>>,
+    children: BlockingMutex<Vec<Link<A>>>,
+    groups: BlockingMutex<Groups<A>>,
+    panic_hook: BlockingMutex<Option<PanicHook<A>>>,
+    /// The address receiving messages sent to addresses with no recipient, if set with `set_fallback()`
+    fallback: BlockingMutex<Option<A>>,
+    config: PostmasterConfig,
+    /// The time on tokio's clock and on the wall clock when the Postmaster was created, from which the deadlines of delayed messages are measured with `DelayClock::Real`
+    epoch: (time::Instant, std::time::Instant),
+    timeout_us: AtomicU32,
+    /// Set once `drain()` has been called, after which only messages from Agents (and control messages) are delivered
+    draining: AtomicBool,
+    /// The phases in which `shutdown()` stops the Agents started by a `Startup`, first to last
+    shutdown_order: BlockingMutex<Vec<Vec<A>>>,
+    /// The task checking the Agents for stalls, if a watchdog has been started
+    watchdog: BlockingMutex<Option<AbortHandle>>,
+    messages_sent: AtomicUsize,
+    send_failures: AtomicUsize,
+    /// The most recent messages which couldn't be delivered, oldest first
+    dead_letters: BlockingMutex<VecDeque<DeadLetter<A>>>,
+    agent_panics: AtomicUsize,
+    next_dynamic_address: AtomicU64,
+    metrics: BlockingMutex<RoutingTable<Counters<A>>>,
+    interceptors: BlockingMutex<Interceptors<A, P>>,
+    /// The requests made with `request_coalesced()` which are awaiting their replies
+    in_flight: BlockingMutex<InFlight<A, P>>,
+    /// The allowlist of messages which may be sent, if one has been set
+    send_policy: BlockingMutex<Option<Arc<SendPolicy<A, P>>>>,
+    rate_limits: BlockingMutex<RoutingTable<Bucket>>,
+    /// The sequence numbers of the messages between each pair of addresses, if the Postmaster keeps messages in order
+    sequences: Sequences,
+    dedup: BlockingMutex<RoutingTable<DedupWindow>>,
+    timers: BlockingMutex<RoutingTable<Timers>>,
+    next_timer: AtomicU64,
+    delayed: BlockingMutex<Delayed<A, P>>,
+    /// Wakes the task delivering delayed messages when a message is due sooner than the ones it is waiting for
+    delayed_changed: Notify,
+    /// Messages held for delivery by a `Simulation`, while one is running
+    #[cfg(feature = "simulation")]
+    simulation: BlockingMutex<Option<Held<A, P>>>,
+    /// The faults injected into messages, while a `Chaos` is running
+    #[cfg(feature = "chaos")]
+    chaos: BlockingMutex<Option<ChaosState<A, P>>>,
+    /// Where the messages which overflow full queues are spooled, once a `Spillover` has been opened
+    #[cfg(feature = "spillover")]
+    spillover: BlockingMutex<Option<Arc<SpillState<A, P>>>>,
+    /// The log of delivered messages, while a `Recorder` is running
+    #[cfg(feature = "recording")]
+    recording: BlockingMutex<Option<Recording<A, P>>>,
+    /// Names the variants of payloads in the spans of messages, if configured with `trace_payload_variants()`
+    #[cfg(feature = "tracing")]
+    payload_variant: BlockingMutex<Option<VariantName<P>>>,
+}
+
+impl<A, P> Clone for Postmaster<A, P> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<A, P> Default for Postmaster<A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A, P> Postmaster<A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    /// Create a new Postmaster, using the default send timeout of 1 ms.
+    pub fn new() -> Self {
+        Self::with_timeout(DEFAULT_TIMEOUT_US)
+    }
+
+    /// Create a new Postmaster with the given default send timeout (in microseconds).
+    pub fn with_timeout(timeout_us: u32) -> Self {
+        Self::with_config(PostmasterConfig::new().with_timeout_us(timeout_us))
+    }
+
+    /// Create a new Postmaster with the given options.
+    /// See `PostmasterConfig` for details.
+    pub fn with_config(config: PostmasterConfig) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                routes: Routes::new(A::COUNT),
+                tasks: BlockingMutex::new(RoutingTable::new(A::COUNT)),
+                watchers: BlockingMutex::new(Vec::new()),
+                children: BlockingMutex::new(Vec::new()),
+                groups: BlockingMutex::new(BTreeMap::new()),
+                panic_hook: BlockingMutex::new(None),
+                fallback: BlockingMutex::new(None),
+                config,
+                epoch: (time::Instant::now(), std::time::Instant::now()),
+                timeout_us: AtomicU32::new(config.timeout_us),
+                draining: AtomicBool::new(false),
+                shutdown_order: BlockingMutex::new(Vec::new()),
+                watchdog: BlockingMutex::new(None),
+                messages_sent: AtomicUsize::new(0),
+                send_failures: AtomicUsize::new(0),
+                dead_letters: BlockingMutex::new(VecDeque::with_capacity(DEAD_LETTER_CAPACITY)),
+                agent_panics: AtomicUsize::new(0),
+                next_dynamic_address: AtomicU64::new(0),
+                metrics: BlockingMutex::new(RoutingTable::new(A::COUNT)),
+                interceptors: BlockingMutex::new(Arc::new(Vec::new())),
+                send_policy: BlockingMutex::new(None),
+                in_flight: BlockingMutex::new(InFlight::new()),
+                rate_limits: BlockingMutex::new(RoutingTable::new(A::COUNT)),
+                sequences: Sequences::new(),
+                dedup: BlockingMutex::new(RoutingTable::new(A::COUNT)),
+                timers: BlockingMutex::new(RoutingTable::new(A::COUNT)),
+                next_timer: AtomicU64::new(0),
+                delayed: BlockingMutex::new(Delayed {
+                    wheel: TimerWheel::new(),
+                    driver: None,
+                }),
+                delayed_changed: Notify::new(),
+                #[cfg(feature = "simulation")]
+                simulation: BlockingMutex::new(None),
+                #[cfg(feature = "chaos")]
+                chaos: BlockingMutex::new(None),
+                #[cfg(feature = "spillover")]
+                spillover: BlockingMutex::new(None),
+                #[cfg(feature = "recording")]
+                recording: BlockingMutex::new(None),
+                #[cfg(feature = "tracing")]
+                payload_variant: BlockingMutex::new(None),
+            }),
+        }
+    }
+
+    /// Register a standalone message queue at the given address.
+    /// Agents are registered with `post_haste::spawn_agent!()`, which creates their message queue automatically.
+    /// A standalone queue has no control queue, so control messages sent to it are placed on the queue along with the others.
+    pub async fn register(
+        &self,
+        address: A,
+        mailbox: Sender<Message<A, P>>,
+    ) -> Result<(), PostmasterError> {
+        self.register_inbox(address, mailbox.into()).await
+    }
+
+    /// Register the inbox of an Agent at the given address, including its control queue.
+    /// This is called by `spawn_agent!()` and should not need to be called directly.
+    #[doc(hidden)]
+    pub async fn register_inbox(
+        &self,
+        address: A,
+        mailbox: Mailbox<Message<A, P>>,
+    ) -> Result<(), PostmasterError> {
+        if !self
+            .inner
+            .routes
+            .insert(address.index(), Route::Mailbox(mailbox))
+        {
+            return Err(PostmasterError::AddressAlreadyTaken);
+        }
+        self.track(address);
+        Ok(())
+    }
+
+    /// Register a pool of message queues at the given address, across which messages are dispatched according to the given routing.
+    /// This is called by `spawn_agent_pool!()` and should not need to be called directly.
+    #[doc(hidden)]
+    pub async fn register_pool(
+        &self,
+        address: A,
+        mailboxes: Vec<Mailbox<Message<A, P>>>,
+        routing: PoolRouting<A, P>,
+    ) -> Result<(), PostmasterError> {
+        if !self
+            .inner
+            .routes
+            .insert(address.index(), Route::Pool(Pool::new(mailboxes, routing)))
+        {
+            return Err(PostmasterError::AddressAlreadyTaken);
+        }
+        self.track(address);
+        Ok(())
+    }
+
+    /// Add a message queue to the pool at the given address, taking the place of a stopped member.
+    /// This is used when restarting a member of a pool, and should not need to be called directly.
+    #[doc(hidden)]
+    pub async fn join_pool(
+        &self,
+        address: A,
+        mailbox: Mailbox<Message<A, P>>,
+    ) -> Result<(), PostmasterError> {
+        self.inner.routes.modify(address.index(), |routes, index| {
+            match routes.get_mut(index).map(Arc::make_mut) {
+                Some(Route::Pool(pool)) => {
+                    pool.join(mailbox);
+                    Ok(())
+                }
+                Some(Route::Mailbox(_)) => Err(PostmasterError::AddressAlreadyTaken),
+                // The pool has been deregistered while the member was restarting
+                None => Err(PostmasterError::NoRecipient),
+            }
+        })
+    }
+
+    /// Find the inbox for a new instance of an Agent which is being restarted after a panic.
+    /// With `MailboxPolicy::Preserve` this is the inbox of the instance which panicked, whose message queue is still registered; otherwise a fresh message queue of the given size is registered at the address, or added to its pool if `pooled`.
+    /// This is called by `spawn_agent!()` and should not need to be called directly.
+    #[doc(hidden)]
+    pub async fn restart_inbox(
+        &self,
+        address: A,
+        queue_size: usize,
+        salvage: &Salvage<Message<A, P>>,
+        pooled: bool,
+    ) -> Result<Inbox<Message<A, P>>, PostmasterError> {
+        if let Some(inbox) = salvage.recover() {
+            return Ok(inbox);
+        }
+        let (mailbox, inbox) = agent::inbox(queue_size);
+        if pooled {
+            self.join_pool(address, mailbox).await?;
+        } else {
+            // The queue of the instance which panicked was left registered to be taken over, but it wasn't kept, so it is replaced
+            if salvage.preserves() {
+                self.remove_stopped(address, false).await;
+            }
+            self.register_inbox(address, mailbox).await?;
+        }
+        Ok(salvage.keep(inbox))
+    }
+
+    /// Stop handing regular messages to the Agent (or pool of Agents) at the given address, e.g. to quiesce it while its configuration or an upstream dependency is swapped out.
+    /// Messages sent to a paused Agent wait on its queue, so senders are held up (and eventually time out) once the queue is full, while control messages are still received as normal.
+    /// The Agent stays paused until `resume()` is called, except that an Agent which is restarted after a panic starts out resumed, unless its restart policy preserves its mailbox.
+    /// Fails with `PostmasterError::NotAnAgent` if a standalone message queue is registered at the address.
+    pub fn pause(&self, address: A) -> Result<(), PostmasterError> {
+        self.set_paused(address, true)
+    }
+
+    /// Resume handing messages to an Agent paused with `pause()`, starting with the messages which have waited on its queue
+    pub fn resume(&self, address: A) -> Result<(), PostmasterError> {
+        self.set_paused(address, false)
+    }
+
+    fn set_paused(&self, address: A, paused: bool) -> Result<(), PostmasterError> {
+        self.inner.routes.inspect(address.index(), |route| {
+            let mailboxes = route.ok_or(PostmasterError::NoRecipient)?.mailboxes();
+            for mailbox in mailboxes {
+                mailbox
+                    .paused
+                    .as_ref()
+                    .ok_or(PostmasterError::NotAnAgent)?
+                    .send_replace(paused);
+            }
+            Ok(())
+        })
+    }
+
+    /// Check that the Agent (or every Agent in the pool) at the given address is alive, by sending it a ping which its inbox answers the next time the Agent receives from it.
+    /// Pings skip the Agent's queues, so no payload variant is needed for them, and they are answered while the Agent is paused, but an Agent which is stuck (e.g. deadlocked, or busy with a long computation) doesn't answer until it next receives a message.
+    /// Returns the time taken for the Agent to answer, or (for a pool) for the slowest member to answer.
+    /// Fails with `PostmasterError::Timeout` if the Agent doesn't answer in time, `PostmasterError::ReceiverClosed` if it has stopped, or `PostmasterError::NotAnAgent` if a standalone message queue is registered at the address.
+    pub async fn ping(&self, address: A, timeout: Duration) -> Result<Duration, PostmasterError> {
+        let started = time::Instant::now();
+        let answers = self.send_pings(address)?;
+        Self::await_answers(answers, started, timeout).await
+    }
+
+    /// Ping every Agent which is registered with the Postmaster, as with `ping()`, e.g. for a health check endpoint.
+    /// The Agents are pinged all at once, so the whole check takes no longer than the timeout.
+    pub async fn ping_all(&self, timeout: Duration) -> Vec<(A, Result<Duration, PostmasterError>)> {
+        let started = time::Instant::now();
+        let pings: Vec<_> = self
+            .agent_addresses()
+            .into_iter()
+            .map(|address| (address, self.send_pings(address)))
+            .collect();
+        let mut results = Vec::with_capacity(pings.len());
+        for (address, answers) in pings {
+            let result = match answers {
+                Ok(answers) => Self::await_answers(answers, started, timeout).await,
+                Err(error) => Err(error),
+            };
+            results.push((address, result));
+        }
+        results
+    }
+
+    /// Push a new config to the running Agent (or every Agent in the pool) at the given address, which applies it with `Reconfigure::on_reconfigure()` rather than being restarted.
+    /// The config skips the Agent's queues, like a ping, so it is applied the next time the Agent receives (even while it is paused), and this waits until it has been applied.
+    /// Fails with `PostmasterError::NotReconfigurable` if the Agent doesn't take configs of this type, `PostmasterError::ReceiverClosed` if it has stopped, or `PostmasterError::NotAnAgent` if a standalone message queue is registered at the address.
+    pub async fn reconfigure<C>(&self, address: A, config: C) -> Result<(), PostmasterError>
+    where
+        C: Clone + Send + 'static,
+    {
+        let answers = self.inner.routes.inspect(address.index(), |route| {
+            let mailboxes = route.ok_or(PostmasterError::NoRecipient)?.mailboxes();
+            mailboxes
+                .iter()
+                .map(|mailbox| {
+                    let (taken, answer) = oneshot::channel();
+                    mailbox
+                        .reconfigurations
+                        .as_ref()
+                        .ok_or(PostmasterError::NotAnAgent)?
+                        .send((Box::new(config.clone()), taken))?;
+                    Ok(answer)
+                })
+                .collect::<Result<Vec<_>, PostmasterError>>()
+        })?;
+        for answer in answers {
+            answer
+                .await
+                .map_err(|_| PostmasterError::ReceiverClosed)??;
+        }
+        Ok(())
+    }
+
+    /// Send a ping to each of the Agents at the address, returning the channels on which they will answer
+    fn send_pings(&self, address: A) -> Result<Vec<oneshot::Receiver<()>>, PostmasterError> {
+        self.inner.routes.inspect(address.index(), |route| {
+            let mailboxes = route.ok_or(PostmasterError::NoRecipient)?.mailboxes();
+            mailboxes
+                .iter()
+                .map(|mailbox| {
+                    let (ping, answer): (Ping, _) = oneshot::channel();
+                    mailbox
+                        .pings
+                        .as_ref()
+                        .ok_or(PostmasterError::NotAnAgent)?
+                        .send(ping)?;
+                    Ok(answer)
+                })
+                .collect()
+        })
+    }
+
+    /// Wait for every answer to a ping, returning the time taken since the ping was sent
+    async fn await_answers(
+        answers: Vec<oneshot::Receiver<()>>,
+        started: time::Instant,
+        timeout: Duration,
+    ) -> Result<Duration, PostmasterError> {
+        for answer in answers {
+            time::timeout_at(started + timeout, answer)
+                .await
+                .map_err(|_| PostmasterError::Timeout)?
+                .map_err(|_| PostmasterError::ReceiverClosed)?;
+        }
+        Ok(started.elapsed())
+    }
+
+    /// Allocate a new, unique dynamic address.
+    /// Fails with `PostmasterError::DynamicAddressesUnsupported` if the address type has no dynamic addresses.
+    pub fn allocate_address(&self) -> Result<A, PostmasterError> {
+        let id = self
+            .inner
+            .next_dynamic_address
+            .fetch_add(1, Ordering::Relaxed);
+        A::dynamic(id).ok_or(PostmasterError::DynamicAddressesUnsupported)
+    }
+
+    /// Remove the message queue registered at the given address, stopping its Agent (if any) and notifying any watchers.
+    pub async fn deregister(&self, address: A) -> Result<(), PostmasterError> {
+        self.inner.routes.modify(address.index(), |routes, index| {
+            if !routes.contains(index) {
+                return Err(PostmasterError::NoRecipient);
+            }
+            // The Agents' tasks must be stopped before their mailboxes are dropped, otherwise they would see their inboxes close
+            for agent_task in self
+                .inner
+                .tasks
+                .lock()
+                .unwrap()
+                .take(address.index())
+                .into_iter()
+                .flatten()
+            {
+                agent_task.abort();
+            }
+            routes.take(index);
+            Ok(())
+        })?;
+        self.inner.metrics.lock().unwrap().take(address.index());
+        self.inner.dedup.lock().unwrap().take(address.index());
+        self.inner.sequences.forget(address);
+        self.inner.timers.lock().unwrap().take(address.index());
+        for members in self.inner.groups.lock().unwrap().values_mut() {
+            members.retain(|member| member.index() != address.index());
+        }
+        self.terminated(address, TerminationReason::Deregistered)
+            .await;
+        Ok(())
+    }
+
+    /// Watch an address for termination, sending the watcher a notification (converted to a payload with `notification`) when the watched Agent terminates.
+    /// Each watch is triggered only once.
+    pub async fn watch(
+        &self,
+        watcher: A,
+        watched: A,
+        notification: Notification<A, P>,
+    ) -> Result<(), PostmasterError> {
+        if !self.inner.routes.contains(watched.index()) {
+            return Err(PostmasterError::NoRecipient);
+        }
+        self.inner
+            .watchers
+            .lock()
+            .unwrap()
+            .push((watcher, watched, notification));
+        Ok(())
+    }
+
+    /// Link a child Agent to its parent, so that the parent is sent a notification (converted to a payload with `notification`) when the child terminates, and the child is stopped when the parent terminates or is restarted.
+    /// Stopping a child deregisters it, which in turn stops any children of its own, so a whole tree of Agents is torn down along with its root.
+    /// Children are usually registered at a dynamic address and linked in one go with `spawn_child!()`.
+    /// Fails with `PostmasterError::NoRecipient` if either the parent or the child isn't registered.
+    pub async fn link_child(
+        &self,
+        parent: A,
+        child: A,
+        notification: Notification<A, P>,
+    ) -> Result<(), PostmasterError> {
+        if !self.inner.routes.contains(parent.index()) {
+            return Err(PostmasterError::NoRecipient);
+        }
+        self.watch(parent, child, notification).await?;
+        self.inner.children.lock().unwrap().push((parent, child));
+        Ok(())
+    }
+
+    /// The children currently linked to the given parent with `link_child()`
+    pub fn children(&self, parent: A) -> Vec<A> {
+        self.inner
+            .children
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(existing_parent, _)| existing_parent.index() == parent.index())
+            .map(|(_, child)| *child)
+            .collect()
+    }
+
+    /// Stop watching an address
+    pub fn unwatch(&self, watcher: A, watched: A) {
+        self.inner
+            .watchers
+            .lock()
+            .unwrap()
+            .retain(|(existing_watcher, existing_watched, _)| {
+                existing_watcher.index() != watcher.index()
+                    || existing_watched.index() != watched.index()
+            });
+    }
+
+    /// Create an empty group, which addresses can then join to receive the messages sent to the group.
+    /// Fails with `PostmasterError::GroupAlreadyExists` if the group has already been created.
+    pub fn create_group(&self, group: &'static str) -> Result<(), PostmasterError> {
+        let mut groups = self.inner.groups.lock().unwrap();
+        if groups.contains_key(group) {
+            return Err(PostmasterError::GroupAlreadyExists);
+        }
+        groups.insert(group, Vec::new());
+        Ok(())
+    }
+
+    /// Remove a group, along with its membership
+    pub fn delete_group(&self, group: &'static str) -> Result<(), PostmasterError> {
+        self.inner
+            .groups
+            .lock()
+            .unwrap()
+            .remove(group)
+            .map(|_| ())
+            .ok_or(PostmasterError::NoSuchGroup)
+    }
+
+    /// Add an address to a group, which has no effect if it is already a member.
+    /// An address leaves all of its groups when it is deregistered.
+    pub fn join_group(&self, group: &'static str, address: A) -> Result<(), PostmasterError> {
+        let mut groups = self.inner.groups.lock().unwrap();
+        let members = groups.get_mut(group).ok_or(PostmasterError::NoSuchGroup)?;
+        if !members
+            .iter()
+            .any(|member| member.index() == address.index())
+        {
+            members.push(address);
+        }
+        Ok(())
+    }
+
+    /// Remove an address from a group, which has no effect if it is not a member
+    pub fn leave_group(&self, group: &'static str, address: A) -> Result<(), PostmasterError> {
+        self.inner
+            .groups
+            .lock()
+            .unwrap()
+            .get_mut(group)
+            .ok_or(PostmasterError::NoSuchGroup)?
+            .retain(|member| member.index() != address.index());
+        Ok(())
+    }
+
+    /// Send a copy of a message to every member of a group, in the order they joined it.
+    /// Each copy is sent with the Postmaster's default timeout, and a copy which can't be delivered doesn't stop the others from being sent.
+    /// Returns the number of members the message was delivered to, or fails with `PostmasterError::NoSuchGroup` if the group hasn't been created.
+    /// The payload is cloned for each member, so a large payload can be wrapped in a `SharedPayload` to share it between the members instead.
+    pub async fn send_to_group(
+        &self,
+        group: &'static str,
+        source: A,
+        payload: impl Into<P> + Clone,
+    ) -> Result<usize, PostmasterError> {
+        let members = self
+            .inner
+            .groups
+            .lock()
+            .unwrap()
+            .get(group)
+            .ok_or(PostmasterError::NoSuchGroup)?
+            .clone();
+        let mut delivered = 0;
+        for member in members {
+            let message = Message::new(source, payload.clone().into());
+            if self.send_internal(member, message, None).await.is_ok() {
+                delivered += 1;
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Send a message using the Postmaster's default timeout
+    pub async fn send(&self, destination: A, source: A, payload: P) -> Result<(), PostmasterError> {
+        self.send_internal(destination, Message::new(source, payload), None)
+            .await
+    }
+
+    /// Send a message using the Postmaster's default timeout, handing the payload back if it can't be sent, e.g. `SendError::Timeout(payload)` when the recipient's queue stays full.
+    /// This lets the sender retry, persist or reroute a message which couldn't be delivered, without having to clone the payload before sending it.
+    pub async fn send_returning(
+        &self,
+        destination: A,
+        source: A,
+        payload: P,
+    ) -> Result<(), SendError<P>> {
+        self.send_returning_internal(destination, Message::new(source, payload), None, None)
+            .await
+    }
+
+    /// Send a message, giving up once the timeout has elapsed and handing the payload back as `SendError::Timeout(payload)` if it does.
+    /// Unlike the timeout of `send()` (or `MessageBuilder::with_timeout()`), which only limits how long the message waits for space on the recipient's queue, this bounds the whole send, including any wait for the sender's rate limit or (with `MessageOrdering`) for its earlier messages to be delivered.
+    /// A latency-sensitive sender, such as a control loop, is therefore never held up for longer than it can afford by a misbehaving recipient.
+    pub async fn send_with_timeout(
+        &self,
+        destination: A,
+        source: A,
+        payload: P,
+        timeout: Duration,
+    ) -> Result<(), SendError<P>> {
+        let deadline = time::Instant::now().checked_add(timeout);
+        self.send_returning_internal(destination, Message::new(source, payload), None, deadline)
+            .await
+    }
+
+    /// Send a message, waiting for as long as it takes for space on the recipient's queue rather than giving up after the Postmaster's default timeout.
+    /// A sender which produces messages faster than the recipient handles them is therefore slowed down to the recipient's pace (backpressure), rather than having its messages fail.
+    /// This can still fail immediately if there is no recipient registered at the destination address, or if the recipient stops while the message is waiting.
+    pub async fn send_with_backpressure(
+        &self,
+        destination: A,
+        source: A,
+        payload: P,
+    ) -> Result<(), PostmasterError> {
+        self.send_internal(
+            destination,
+            Message::new(source, payload),
+            Some(Duration::MAX),
+        )
+        .await
+    }
+
+    /// Send a message from synchronous code running outside of any tokio runtime (e.g. a C callback or a dedicated OS thread), blocking the thread until the message is sent or the Postmaster's default timeout expires.
+    /// The send is driven by a small runtime which the Postmaster starts the first time this is called, so the caller doesn't need a handle to the runtime which the Agents run on.
+    ///
+    /// Not available on WebAssembly, which has no threads to drive the send on.
+    ///
+    /// # Panics
+    /// Panics if called from within an asynchronous context, where `send()` should be awaited instead.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn blocking_send(
+        &self,
+        destination: A,
+        source: A,
+        payload: P,
+    ) -> Result<(), PostmasterError> {
+        BLOCKING_RUNTIME
+            .get_or_init(|| {
+                runtime::Builder::new_multi_thread()
+                    .worker_threads(1)
+                    .thread_name("post-haste-blocking")
+                    .enable_time()
+                    .build()
+                    .expect("Failed to build the runtime for blocking sends")
+            })
+            .block_on(self.send(destination, source, payload))
+    }
+
+    /// Attempt to send a message without waiting
+    pub fn try_send(&self, destination: A, source: A, payload: P) -> Result<(), PostmasterError> {
+        self.try_send_internal(destination, Message::new(source, payload))
+            .map_err(PostmasterError::from)
+    }
+
+    /// Attempt to send a message without waiting, handing the payload back if it can't be sent, e.g. `TrySendError::Full(payload)` when the recipient's queue is full.
+    /// This lets a sender which mustn't wait (e.g. one handling a burst of input) decide for itself what to do with a message the recipient has no room for, such as keeping it to retry later or merging it with the next one.
+    pub fn try_send_returning(
+        &self,
+        destination: A,
+        source: A,
+        payload: P,
+    ) -> Result<(), TrySendError<P>> {
+        self.try_send_internal(destination, Message::new(source, payload))
+    }
+
+    /// Send a batch of messages with the same source and destination, placing them on the recipient's queue all at once.
+    /// Space for the whole batch is reserved before any message is placed on the queue, so messages from other senders are not interleaved with the batch, and the routing state is only looked up once for the batch.
+    /// For a pool, the whole batch is delivered to the member selected for the first message.
+    ///
+    /// Each message is still subject to the send policy, the sender's rate limit and the interceptors, and every message is checked before any of them is delivered.
+    /// So if any message is refused or rejected, the whole batch fails and none of it is sent (although the messages ahead of it still count against the sender's rate limit).
+    /// A message redirected by an interceptor is delivered on its own, in its place in the batch: the messages ahead of it are placed on the recipient's queue first, and those behind it are placed together once it has been delivered.
+    ///
+    /// The send timeout applies to the batch as a whole (or to each part of it, when a message has been redirected), and the batch fails with `PostmasterError::BatchTooLarge` if it is larger than the recipient's queue.
+    pub async fn send_all(
+        &self,
+        destination: A,
+        source: A,
+        payloads: impl IntoIterator<Item = P>,
+    ) -> Result<(), PostmasterError> {
+        // The whole batch is sent in a single turn, so that no other messages from the source are interleaved with it
+        let mut turn = before(
+            self.turn_deadline(None, None),
+            self.take_turn(source, destination, false),
+        )
+        .await?;
+        let mut checked = Vec::new();
+        for payload in payloads {
+            let mut message = Message::new(source, payload);
+            message.sequence = turn.as_mut().map(Turn::number);
+            self.admit(destination, &message)?;
+            if let Some(send_at) = self.limit_rate(source, destination, true)? {
+                time::sleep_until(send_at).await;
+            }
+            if let Some(addressed) = self
+                .intercept(destination, message)
+                .map_err(|(error, _)| error)?
+            {
+                checked.push(addressed);
+            }
+        }
+        let mut batch = Vec::new();
+        for (redirected, message) in checked {
+            #[cfg(feature = "tracing")]
+            let message = self.start_trace(redirected, message);
+            #[cfg(feature = "chaos")]
+            let Some(message) = self.inject_faults(redirected, message) else {
+                continue;
+            };
+            #[cfg(feature = "simulation")]
+            let message = match self.hold_for_simulation(redirected, message) {
+                Ok(()) => continue,
+                Err(message) => message,
+            };
+            if redirected.index() == destination.index() {
+                batch.push(message);
+            } else {
+                self.deliver_batch(destination, core::mem::take(&mut batch))
+                    .await?;
+                self.deliver(redirected, message, None).await?;
+            }
+        }
+        self.deliver_batch(destination, batch).await
+    }
+
+    /// Send a copy of a message to every registered static address which matches a pattern, e.g. `|address| matches!(address, Address::Sensors(_))` to reach a whole subtree of nested addresses.
+    /// Each copy is sent with the Postmaster's default timeout, and a copy which can't be delivered doesn't stop the others from being sent.
+    /// Returns the number of Agents the message was delivered to, or fails with `PostmasterError::NoRecipient` if no registered address matches the pattern.
+    /// The payload is cloned for each Agent, so a large payload can be wrapped in a `SharedPayload` to share it between them instead.
+    pub async fn send_matching(
+        &self,
+        pattern: impl Fn(&A) -> bool,
+        source: A,
+        payload: impl Into<P> + Clone,
+    ) -> Result<usize, PostmasterError> {
+        let destinations: Vec<A> = (0..A::COUNT)
+            .filter_map(A::static_address)
+            .filter(|address| pattern(address) && self.inner.routes.contains(address.index()))
+            .collect();
+        if destinations.is_empty() {
+            return Err(PostmasterError::NoRecipient);
+        }
+        let mut delivered = 0;
+        for destination in destinations {
+            let message = Message::new(source, payload.clone().into());
+            if self.send_internal(destination, message, None).await.is_ok() {
+                delivered += 1;
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Begin building a message with custom settings
+    pub fn message(&self, destination: A, source: A, payload: P) -> MessageBuilder<'_, A, P> {
+        MessageBuilder {
+            postmaster: self,
+            destination,
+            message: Message::new(source, payload),
+            timeout: None,
+            delay: None,
+            ack: false,
+            retry: None,
+            deadline: None,
+        }
+    }
+
+    /// Send a received message on to another address, keeping its source, correlation ID, reply-to address and ID, so that the new recipient replies to the original sender rather than to the Agent passing the message on (e.g. a proxy or load balancer).
+    /// An acknowledgement requested by the original sender travels with the message, and reports on its handling by the new recipient.
+    /// The message is sent as if by its source, so it counts towards the source's rate limit, and is refused while draining unless its source is an Agent.
+    pub async fn forward(
+        &self,
+        destination: A,
+        mut message: Message<A, P>,
+    ) -> Result<(), PostmasterError> {
+        message.destination = None;
+        message.intended_destination = None;
+        message.enqueued_at = None;
+        if let Some(ack) = &mut message.ack {
+            ack.forwarded();
+        }
+        self.send_internal(destination, message, None).await
+    }
+
+    /// Begin building a reply to a message, addressed to its `reply_address()` and carrying its correlation ID
+    pub fn reply(
+        &self,
+        request: &Message<A, P>,
+        source: A,
+        payload: P,
+    ) -> MessageBuilder<'_, A, P> {
+        let mut builder = self.message(request.reply_address(), source, payload);
+        builder.message.correlation_id = request.correlation_id;
+        builder
+    }
+
+    /// Send a request and wait for its reply.
+    /// A temporary message queue is registered at `source` (which must be vacant, e.g. a dynamic address from `allocate_address()`) and the payload is sent to the destination from that address, with the first message sent back to `source` taken as the reply.
+    /// The timeout covers sending the request as well as waiting for the reply.
+    /// Fails with `PostmasterError::Timeout` if no reply is received before the timeout expires, or with `PostmasterError::AddressAlreadyTaken` if `source` is already registered.
+    pub async fn request(
+        &self,
+        destination: A,
+        source: A,
+        payload: impl Into<P>,
+        timeout: Duration,
+    ) -> Result<Message<A, P>, PostmasterError> {
+        let deadline = time::Instant::now() + timeout;
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+        self.register(source, sender).await?;
+        let sent = self
+            .send_returning_internal(
+                destination,
+                Message::new(source, payload.into()),
+                None,
+                Some(deadline),
+            )
+            .await;
+        let reply = match sent.map_err(PostmasterError::from) {
+            Ok(()) => match time::timeout_at(deadline, receiver.recv()).await {
+                Ok(reply) => reply.ok_or(PostmasterError::ReceiverClosed),
+                Err(_) => Err(PostmasterError::Timeout),
+            },
+            Err(error) => Err(error),
+        };
+        let _ = self.deregister(source).await;
+        reply
+    }
+
+    /// Send a request and wait for its reply as `request()` does, unless an identical request (one to the same destination with an equal coalescing key) is already in flight, in which case its reply is shared instead.
+    /// This saves an Agent answering an idempotent query (e.g. a config lookup) from being asked the same thing by many callers at once.
+    /// Only the caller which makes the request uses its `source` and `payload`, and every caller waiting for the same request receives a copy of its reply (or its error), subject to its own timeout.
+    /// Once the reply has arrived, the next identical request is sent afresh, so replies are never reused after the fact.
+    pub async fn request_coalesced(
+        &self,
+        destination: A,
+        source: A,
+        key: impl Hash,
+        payload: impl Into<P>,
+        timeout: Duration,
+    ) -> Result<Message<A, P>, PostmasterError>
+    where
+        P: Clone,
+    {
+        let deadline = time::Instant::now() + timeout;
+        let key = coalescing_key(destination.index(), key);
+        loop {
+            let joined = self.inner.in_flight.lock().unwrap().join(key);
+            let Some(reply) = joined else {
+                break;
+            };
+            match time::timeout_at(deadline, reply).await {
+                Ok(Ok(reply)) => return reply,
+                // The caller making the request gave up on it, so this caller makes it instead
+                Ok(Err(_)) => continue,
+                Err(_) => return Err(PostmasterError::Timeout),
+            }
+        }
+        let leading = Leading::new(&self.inner.in_flight, key);
+        let timeout = deadline.saturating_duration_since(time::Instant::now());
+        let reply = self.request(destination, source, payload, timeout).await;
+        leading.share(&reply);
+        reply
+    }
+
+    /// Send a request to several addresses and gather their replies.
+    /// A temporary message queue is registered at `source` (which must be vacant, e.g. a dynamic address from `allocate_address()`) and the payload is sent to each destination from that address.
+    /// Replies sent back to `source` are collected until one has been received for each successfully sent request, or until the timeout expires, whichever is first.
+    /// The replies received so far are returned in the order they arrived, so a timeout results in a partial set of replies rather than an error.
+    /// Fails with `PostmasterError::AddressAlreadyTaken` if `source` is already registered.
+    pub async fn scatter_gather(
+        &self,
+        destinations: impl IntoIterator<Item = A>,
+        source: A,
+        payload: impl Into<P> + Clone,
+        timeout: Duration,
+    ) -> Result<Vec<Message<A, P>>, PostmasterError> {
+        let deadline = time::Instant::now() + timeout;
+        let destinations: Vec<A> = destinations.into_iter().collect();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(destinations.len().max(1));
+        self.register(source, sender).await?;
+
+        let mut pending = 0;
+        for destination in destinations {
+            let request = Message::new(source, payload.clone().into());
+            // Destinations which cannot be reached will never reply, so they are not waited for
+            if self.send_internal(destination, request, None).await.is_ok() {
+                pending += 1;
+            }
+        }
+
+        let mut replies = Vec::with_capacity(pending);
+        while replies.len() < pending {
+            match time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(reply)) => replies.push(reply),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        let _ = self.deregister(source).await;
+        Ok(replies)
+    }
+
+    /// Ask every registered Agent for a snapshot of its state, sending each a `SnapshotRequest` (wrapped in a payload by `request`) from `source`, and collect the responses.
+    /// Agents which don't respond before the timeout (such as standalone message queues, or Agents which can't be snapshotted) are left out of the `SystemSnapshot`.
+    /// A pool of Agents shares an address, so only the member which receives the request is snapshotted.
+    pub async fn snapshot_all(
+        &self,
+        source: A,
+        request: fn(SnapshotRequest) -> P,
+        timeout: Duration,
+    ) -> SystemSnapshot<A> {
+        let deadline = time::Instant::now() + timeout;
+        let addresses: Vec<A> = self
+            .inner
+            .metrics
+            .lock()
+            .unwrap()
+            .values()
+            .map(Counters::address)
+            .collect();
+        let mut responses = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            let (snapshot_request, response) = SnapshotRequest::new();
+            let message = Message::new(source, request(snapshot_request));
+            if self.send_internal(address, message, None).await.is_ok() {
+                responses.push((address, response));
+            }
+        }
+        let mut agents = Vec::with_capacity(responses.len());
+        for (address, response) in responses {
+            if let Ok(Ok(snapshot)) = time::timeout_at(deadline, response).await {
+                agents.push((address, snapshot));
+            }
+        }
+        SystemSnapshot { agents }
+    }
+
+    /// Send each Agent in a `SystemSnapshot` its snapshot (wrapped in a payload by `restore`) from `source`, so that it can restore its state.
+    /// Returns the number of Agents the snapshots were delivered to.
+    pub async fn restore_all(
+        &self,
+        snapshot: &SystemSnapshot<A>,
+        source: A,
+        restore: fn(Vec<u8>) -> P,
+    ) -> usize {
+        let mut delivered = 0;
+        for (address, agent_snapshot) in &snapshot.agents {
+            let message = Message::new(source, restore(agent_snapshot.clone()));
+            if self.send_internal(*address, message, None).await.is_ok() {
+                delivered += 1;
+            }
+        }
+        delivered
+    }
+
+    /// The name given to the Postmaster with `PostmasterConfig::with_name()`, if any
+    pub fn name(&self) -> Option<&'static str> {
+        self.inner.config.name
+    }
+
+    /// The size of the message queue given to Agents registered without a queue size
+    pub fn default_queue_size(&self) -> usize {
+        self.inner.config.queue_size
+    }
+
+    /// Retrieve diagnostic information for the Postmaster
+    pub fn get_diagnostics(&self) -> Diagnostics {
+        Diagnostics {
+            name: self.inner.config.name,
+            messages_sent: self.inner.messages_sent.load(Ordering::Relaxed),
+            send_failures: self.inner.send_failures.load(Ordering::Relaxed),
+            agent_panics: self.inner.agent_panics.load(Ordering::Relaxed),
+            pending_delayed: self.inner.delayed.lock().unwrap().wheel.len(),
+        }
+    }
+
+    /// Take a snapshot of the Postmaster's metrics for each address
+    pub async fn metrics(&self) -> Metrics<A> {
+        let agents = self
+            .inner
+            .metrics
+            .lock()
+            .unwrap()
+            .values()
+            .map(|counters| {
+                let (queue_depth, queue_capacity) =
+                    self.inner.routes.queue_usage(counters.address().index());
+                counters.snapshot(queue_depth, queue_capacity)
+            })
+            .collect();
+        Metrics { agents }
+    }
+
+    /// The most recent messages which couldn't be delivered (up to `DEAD_LETTER_CAPACITY` of them), oldest first
+    pub fn dead_letters(&self) -> Vec<DeadLetter<A>> {
+        self.inner
+            .dead_letters
+            .lock()
+            .unwrap()
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    /// Take a snapshot of the state of each address known to the Postmaster: whether it is registered, whether its Agent's task is still running, how many messages are waiting for it and how many its Agent has processed, and how many delayed messages and timers are pending for it
+    pub async fn status(&self) -> Status<A> {
+        let mut addresses: RoutingTable<AddressStatus<A>> = RoutingTable::new(A::COUNT);
+        for address in self
+            .inner
+            .metrics
+            .lock()
+            .unwrap()
+            .values()
+            .map(Counters::address)
+        {
+            addresses.get_or_insert_with(address.index(), || AddressStatus::new(address));
+        }
+        for delayed in self.inner.delayed.lock().unwrap().wheel.iter() {
+            let address = delayed.destination;
+            if let Some(status) =
+                addresses.get_or_insert_with(address.index(), || AddressStatus::new(address))
+            {
+                status.delayed_messages += 1;
+            }
+        }
+        let tasks = self.inner.tasks.lock().unwrap();
+        let timers = self.inner.timers.lock().unwrap();
+        let mut addresses: Vec<_> = addresses.values().cloned().collect();
+        for status in &mut addresses {
+            let index = status.address.index();
+            self.inner.routes.inspect(index, |route| {
+                if let Some(route) = route {
+                    status.registered = true;
+                    (status.queue_depth, status.queue_capacity) = route.queue_usage();
+                    status.messages_processed = route.is_agent().then(|| route.received());
+                }
+            });
+            status.task = tasks.get(index).map(|agent_tasks| {
+                if agent_tasks.iter().all(AbortHandle::is_finished) {
+                    TaskState::Terminated
+                } else {
+                    TaskState::Running
+                }
+            });
+            status.timers = timers.get(index).map_or(0, Timers::len);
+        }
+        Status { addresses }
+    }
+
+    /// Drain the Postmaster as part of an orderly shutdown, waiting for the Agents to work through the messages already on their queues.
+    /// From now on, messages are only delivered if they are sent by an Agent (e.g. passing work on down a pipeline) or are control messages, and anything else fails with `PostmasterError::Draining`, whether sent now or (for delayed messages) due later.
+    /// Draining finishes once every Agent's queue is empty (although an Agent may still be handling the last message it received), or when the timeout expires, and the report gives the number of messages left on each Agent's queue, so that an Agent which stopped with messages unprocessed can be told apart from one which finished its work.
+    /// The Postmaster keeps draining afterwards, so the Agents can then be deregistered (or the program exit) without more work arriving.
+    pub async fn drain(&self, timeout: Duration) -> DrainReport<A> {
+        self.inner.draining.store(true, Ordering::Relaxed);
+        let started = time::Instant::now();
+        DrainReport {
+            agents: self
+                .wait_until_drained(self.agent_addresses(), started, started + timeout)
+                .await,
+        }
+    }
+
+    /// Drain the Postmaster as with `drain()`, then stop the Agents started by a `Startup` in the reverse of the order they were started, so that (for example) an Agent storing the results of others is stopped after them.
+    /// The Agents are stopped in phases: the Agents in each phase are given until their queues are empty (and they have finished handling the message they were handling) before they are deregistered, and only then are the Agents they depend on drained.
+    /// Agents which weren't started by a `Startup` are drained along with the first phase, but are left registered.
+    /// The timeout covers the whole shutdown, after which the remaining phases are stopped straight away, still in order.
+    pub async fn shutdown(&self, timeout: Duration) -> DrainReport<A> {
+        self.inner.draining.store(true, Ordering::Relaxed);
+        let started = time::Instant::now();
+        let deadline = started + timeout;
+        let phases = self.inner.shutdown_order.lock().unwrap().clone();
+        let ordered: BTreeSet<_> = phases.iter().flatten().map(AddressSpace::index).collect();
+        let unordered = self
+            .agent_addresses()
+            .into_iter()
+            .filter(|address| !ordered.contains(&address.index()))
+            .collect();
+        let mut agents = self.wait_until_drained(unordered, started, deadline).await;
+        for phase in phases {
+            agents.extend(
+                self.wait_until_drained(phase.clone(), started, deadline)
+                    .await,
+            );
+            for address in phase {
+                // An Agent answers a ping once it next receives, so it isn't stopped part of the way through handling a message
+                let _ = self
+                    .ping(
+                        address,
+                        deadline.saturating_duration_since(time::Instant::now()),
+                    )
+                    .await;
+                let _ = self.deregister(address).await;
+            }
+        }
+        DrainReport { agents }
+    }
+
+    /// Record the order in which `shutdown()` stops the Agents started by a `Startup`
+    pub(super) fn set_shutdown_order(&self, phases: Vec<Vec<A>>) {
+        *self.inner.shutdown_order.lock().unwrap() = phases;
+    }
+
+    /// Wait for the given Agents to empty their queues, or for the deadline to pass
+    async fn wait_until_drained(
+        &self,
+        addresses: Vec<A>,
+        started: time::Instant,
+        deadline: time::Instant,
+    ) -> Vec<AgentDrain<A>> {
+        let mut drained_after = vec![None; addresses.len()];
+        loop {
+            let mut empty = true;
+            for (address, drained_after) in addresses.iter().zip(&mut drained_after) {
+                let (queue_depth, _) = self.inner.routes.queue_usage(address.index());
+                // An Agent's queue can fill up again while Agents further up a pipeline are still draining
+                *drained_after =
+                    (queue_depth == 0).then(|| drained_after.unwrap_or(started.elapsed()));
+                empty &= queue_depth == 0;
+            }
+            if empty || time::Instant::now() >= deadline {
+                break;
+            }
+            time::sleep(DRAIN_POLL_INTERVAL.min(deadline - time::Instant::now())).await;
+        }
+        addresses
+            .into_iter()
+            .zip(drained_after)
+            .map(|(address, drained_after)| AgentDrain {
+                address,
+                remaining: self.inner.routes.queue_usage(address.index()).0,
+                drained_after,
+            })
+            .collect()
+    }
+
+    /// Whether `drain()` has been called
+    pub fn is_draining(&self) -> bool {
+        self.inner.draining.load(Ordering::Relaxed)
+    }
+
+    /// Keep the program running until it is asked to stop with ctrl-c (or, on Unix, SIGTERM), then shut down gracefully with `shutdown()`, draining the Postmaster for up to `drain_timeout` and stopping the Agents started by a `Startup` in reverse order.
+    /// A second signal while draining cuts the drain short, for when the Agents are taking too long to finish.
+    /// The signals are handled from when this is called, so they no longer kill the process straight away; the returned report gives the exit code for the program to exit with once it has finished cleaning up.
+    /// Not available on WebAssembly, which has no signals; call `shutdown()` instead.
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn run_until_shutdown(&self, drain_timeout: Duration) -> ShutdownReport<A> {
+        let mut signals = Signals::listen();
+        let signal = signals.next().await;
+        let uptime = self.inner.epoch.1.elapsed();
+        let started = time::Instant::now();
+        let mut drain = core::pin::pin!(self.shutdown(drain_timeout));
+        let (drain, forced) = tokio::select! {
+            drain = &mut drain => (drain, false),
+            _ = signals.next() => {
+                // The drain is only cut short, so the report still gives the state of each Agent's queue, and the remaining Agents are still stopped in order
+                (self.shutdown(Duration::ZERO).await, true)
+            }
+        };
+        ShutdownReport {
+            signal,
+            forced,
+            uptime,
+            drained_in: started.elapsed(),
+            drain,
+            diagnostics: self.get_diagnostics(),
+        }
+    }
+
+    /// The addresses at which Agents (rather than standalone message queues) are registered
+    fn agent_addresses(&self) -> Vec<A> {
+        self.inner
+            .metrics
+            .lock()
+            .unwrap()
+            .values()
+            .map(Counters::address)
+            .filter(|address| {
+                self.inner
+                    .routes
+                    .inspect(address.index(), |route| route.is_some_and(Route::is_agent))
+            })
+            .collect()
+    }
+
+    /// Start a watchdog which calls `sink` whenever an Agent with messages waiting on its queue goes for `stall_after` without receiving any of them, e.g. because it is deadlocked or stuck in a long computation, so that a stalled Agent doesn't go unnoticed while its queue fills up.
+    /// Each stall is reported once, and an Agent is only reported again once it has received a message and then stalled again.
+    /// The Agents are checked four times per `stall_after`, so a stall is noticed (and the time an Agent last made progress is known) to within a quarter of the threshold.
+    /// Starting a watchdog replaces any watchdog already running, and the watchdog runs until `stop_watchdog()` is called or every handle to the Postmaster has been dropped.
+    pub fn start_watchdog(
+        &self,
+        stall_after: Duration,
+        sink: impl Fn(&AgentStall<A>) + Send + Sync + 'static,
+    ) {
+        let watchdog = task::spawn(Self::watch_for_stalls(
+            Arc::downgrade(&self.inner),
+            stall_after,
+            sink,
+        ));
+        if let Some(previous) = self
+            .inner
+            .watchdog
+            .lock()
+            .unwrap()
+            .replace(watchdog.abort_handle())
+        {
+            previous.abort();
+        }
+    }
+
+    /// Stop the watchdog started with `start_watchdog()`, if one is running
+    pub fn stop_watchdog(&self) {
+        if let Some(watchdog) = self.inner.watchdog.lock().unwrap().take() {
+            watchdog.abort();
+        }
+    }
+
+    /// Check the Agents for stalls until the Postmaster is dropped.
+    /// Only a weak reference is held between checks, so that the watchdog doesn't keep the Postmaster alive.
+    async fn watch_for_stalls(
+        inner: Weak<Inner<A, P>>,
+        stall_after: Duration,
+        sink: impl Fn(&AgentStall<A>),
+    ) {
+        let mut interval = time::interval((stall_after / 4).max(Duration::from_millis(1)));
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        let mut progress = RoutingTable::new(A::COUNT);
+        loop {
+            interval.tick().await;
+            let Some(inner) = inner.upgrade() else {
+                return;
+            };
+            let postmaster = Self { inner };
+            let now = time::Instant::now();
+            let mut checked = RoutingTable::new(A::COUNT);
+            for address in postmaster.agent_addresses() {
+                let Some((received, queue_depth)) =
+                    postmaster.inner.routes.inspect(address.index(), |route| {
+                        route.map(|route| (route.received(), route.queue_usage().0))
+                    })
+                else {
+                    continue;
+                };
+                let mut agent: Progress = progress
+                    .take(address.index())
+                    .unwrap_or_else(|| Progress::new(received, now));
+                if let Some(stalled_for) = agent.check(received, queue_depth, now, stall_after) {
+                    sink(&AgentStall {
+                        address,
+                        queue_depth,
+                        last_progress: agent.since(),
+                        stalled_for,
+                    });
+                }
+                let _ = checked.insert(address.index(), agent);
+            }
+            // Agents which have been deregistered since the last check are forgotten
+            progress = checked;
+        }
+    }
+
+    /// Change the Postmaster's default timeout for sending messages
+    pub fn set_timeout(&self, timeout_us: u32) {
+        self.inner.timeout_us.store(timeout_us, Ordering::Relaxed)
+    }
+
+    /// Set a hook to be called whenever an Agent panics, replacing any previous hook.
+    /// Once a hook is set, panics are no longer logged by the Postmaster itself.
+    pub fn set_panic_hook(&self, hook: impl Fn(&AgentPanic<A>) + Send + Sync + 'static) {
+        self.inner
+            .panic_hook
+            .lock()
+            .unwrap()
+            .replace(Box::new(hook));
+    }
+
+    /// Deliver messages sent to addresses with no recipient registered to the given address instead, rather than failing with `PostmasterError::NoRecipient`, replacing any previous fallback.
+    /// The recipient can tell where each message was meant to go from `Message::intended_destination()`, e.g. for a proxy which answers on behalf of Agents which haven't been started yet.
+    /// Messages sent to the fallback address itself are not redirected, and still fail if nothing is registered there.
+    pub fn set_fallback(&self, address: A) {
+        self.inner.fallback.lock().unwrap().replace(address);
+    }
+
+    /// Stop redirecting messages to the fallback address, so that messages to addresses with no recipient fail with `PostmasterError::NoRecipient` again
+    pub fn clear_fallback(&self) {
+        self.inner.fallback.lock().unwrap().take();
+    }
+
+    /// Add an interceptor, which is applied to every message sent through the Postmaster after any interceptors which were added before it.
+    /// See `Interceptor` for details.
+    pub fn add_interceptor(&self, interceptor: impl Interceptor<A, P> + 'static) {
+        let mut interceptors = self.inner.interceptors.lock().unwrap();
+        // Messages which are being sent hold on to the current list, so a new list is made rather than changing it in place
+        let mut updated = Vec::clone(&interceptors);
+        updated.push(Arc::new(interceptor));
+        *interceptors = Arc::new(updated);
+    }
+
+    /// Remove all interceptors
+    pub fn clear_interceptors(&self) {
+        *self.inner.interceptors.lock().unwrap() = Arc::new(Vec::new());
+    }
+
+    /// Only deliver the messages which the given policy allows, replacing any previous policy.
+    /// See `SendPolicy` for details.
+    pub fn set_send_policy(&self, policy: SendPolicy<A, P>) {
+        self.inner
+            .send_policy
+            .lock()
+            .unwrap()
+            .replace(Arc::new(policy));
+    }
+
+    /// Remove the send policy, so that every message may be sent again
+    pub fn clear_send_policy(&self) {
+        self.inner.send_policy.lock().unwrap().take();
+    }
+
+    /// Limit the rate at which the given address may send messages, replacing any previous limit.
+    /// See `RateLimit` for details.
+    pub fn set_rate_limit(&self, source: A, limit: RateLimit) {
+        let mut rate_limits = self.inner.rate_limits.lock().unwrap();
+        rate_limits.take(source.index());
+        let _ = rate_limits.insert(source.index(), Bucket::new(limit));
+    }
+
+    /// Remove the rate limit for the given address
+    pub fn remove_rate_limit(&self, source: A) {
+        self.inner.rate_limits.lock().unwrap().take(source.index());
+    }
+
+    /// Change how long the IDs of messages delivered to the given address are remembered for, in order to discard duplicates.
+    /// The window defaults to `DEFAULT_DEDUP_WINDOW`, and must be longer than the time it may take a sender to give up retrying a message.
+    pub fn set_dedup_window(&self, address: A, window: Duration) {
+        if let Some(dedup) = self
+            .inner
+            .dedup
+            .lock()
+            .unwrap()
+            .get_or_insert_with(address.index(), || DedupWindow::new(window))
+        {
+            dedup.set_window(window);
+        }
+    }
+
+    /// Run a future in its own task, and send its output to an Agent from itself once it completes.
+    /// This lets an Agent start some asynchronous work (e.g. I/O) without blocking its main loop, and handle the result as a message.
+    /// The returned handle can be used to abort the work, or to find out whether the result was delivered.
+    pub fn pipe_to_self<F>(&self, address: A, future: F) -> JoinHandle<Result<(), PostmasterError>>
+    where
+        F: Future<Output = P> + Send + 'static,
+    {
+        let postmaster = self.clone();
+        let pipe = async move {
+            let payload = future.await;
+            postmaster
+                .send_internal(address, Message::new(address, payload), None)
+                .await
+        };
+        #[cfg(feature = "tracing")]
+        let pipe = tracing::Instrument::in_current_span(pipe);
+        task::spawn(pipe)
+    }
+
+    /// Forward every item from a stream to an Agent, converting each item into a payload with `map`.
+    /// Items are sent from `source` with the default timeout: an item which can't be delivered in time is dropped, and forwarding stops when the stream ends or once the destination is no longer registered.
+    /// Aborting the returned handle stops forwarding.
+    pub fn attach_stream<S>(
+        &self,
+        destination: A,
+        source: A,
+        stream: S,
+        map: impl FnMut(S::Item) -> P + Send + 'static,
+    ) -> JoinHandle<()>
+    where
+        S: Stream + Send + 'static,
+        S::Item: Send,
+    {
+        let mut stream = Box::pin(stream);
+        self.forward_items(
+            destination,
+            source,
+            move |context| stream.as_mut().poll_next(context),
+            map,
+        )
+    }
+
+    /// Forward every item received from a channel to an Agent, converting each item into a payload with `map`.
+    /// This works in the same way as `attach_stream()`.
+    pub fn attach_receiver<T: Send + 'static>(
+        &self,
+        destination: A,
+        source: A,
+        mut receiver: Receiver<T>,
+        map: impl FnMut(T) -> P + Send + 'static,
+    ) -> JoinHandle<()> {
+        self.forward_items(
+            destination,
+            source,
+            move |context| receiver.poll_recv(context),
+            map,
+        )
+    }
+
+    /// Spawn a task which sends a message for each item polled from `next`, until it runs out of items
+    fn forward_items<T: Send>(
+        &self,
+        destination: A,
+        source: A,
+        mut next: impl FnMut(&mut Context<'_>) -> Poll<Option<T>> + Send + 'static,
+        mut map: impl FnMut(T) -> P + Send + 'static,
+    ) -> JoinHandle<()> {
+        let postmaster = self.clone();
+        spawn_named(|| format!("{source:?} to {destination:?}"), async move {
+            while let Some(item) = core::future::poll_fn(&mut next).await {
+                let message = Message::new(source, map(item));
+                if let Err(PostmasterError::NoRecipient | PostmasterError::ReceiverClosed) =
+                    postmaster.send_internal(destination, message, None).await
+                {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Set a timer which sends the given payload to an Agent from itself once the delay has elapsed, e.g. to move a state machine on to its next state.
+    /// Each of an Agent's timers is identified by a key, and setting a timer with the same key as a pending timer replaces it, so that a stale timer can never fire after a newer one has been set.
+    /// Pending timers are cancelled when the Agent is deregistered or terminates.
+    ///
+    /// Once a timer has fired its message is on its way to the Agent, so cancelling or replacing the timer after that point has no effect on the message.
+    pub fn set_timer(&self, address: A, key: &'static str, delay: Duration, payload: P) {
+        let generation = self.inner.next_timer.fetch_add(1, Ordering::Relaxed);
+        let (timer, deadline) = Timer::new(generation, time::Instant::now() + delay);
+        let postmaster = self.clone();
+        let fire = async move {
+            if timer::wait(deadline).await && postmaster.take_timer(address, key, generation) {
+                // As with delayed messages, there is no one to report a failure to
+                let _ = postmaster
+                    .send_internal(address, Message::new(address, payload), None)
+                    .await;
+            }
+        };
+        #[cfg(feature = "tracing")]
+        let fire = tracing::Instrument::in_current_span(fire);
+        // The lock is held until the timer has been stored, so that a timer which fires straight away can find itself
+        let mut timers = self.inner.timers.lock().unwrap();
+        task::spawn(fire);
+        if let Some(timers) = timers.get_or_insert_with(address.index(), Timers::new) {
+            timers.insert(key, timer);
+        }
+    }
+
+    /// Cancel one of an Agent's pending timers, returning false if there was no timer pending with the given key
+    pub fn cancel_timer(&self, address: A, key: &'static str) -> bool {
+        self.inner
+            .timers
+            .lock()
+            .unwrap()
+            .get_mut(address.index())
+            .and_then(|timers| timers.remove(key))
+            .is_some()
+    }
+
+    /// Restart one of an Agent's pending timers, so that it fires once the given delay has elapsed from now.
+    /// Returns false if there was no timer pending with the given key.
+    pub fn reset_timer(&self, address: A, key: &'static str, delay: Duration) -> bool {
+        match self
+            .inner
+            .timers
+            .lock()
+            .unwrap()
+            .get_mut(address.index())
+            .and_then(|timers| timers.get(key))
+        {
+            Some(timer) => {
+                timer.reset(time::Instant::now() + delay);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove a timer which has fired, returning false if it has since been cancelled or replaced
+    fn take_timer(&self, address: A, key: &'static str, generation: u64) -> bool {
+        let mut timers = self.inner.timers.lock().unwrap();
+        let Some(timers) = timers.get_mut(address.index()) else {
+            return false;
+        };
+        if timers.get(key).map(Timer::generation) != Some(generation) {
+            return false;
+        }
+        timers.remove(key);
+        true
+    }
+
+    /// Record the variant of each message's payload (as named by its `PayloadVariant` implementation) in the message's tracing span
+    #[cfg(feature = "tracing")]
+    pub fn trace_payload_variants(&self)
+    where
+        P: PayloadVariant,
+    {
+        self.inner
+            .payload_variant
+            .lock()
+            .unwrap()
+            .replace(trace::variant_name::<P>());
+    }
+
+    /// Spawn an Agent's main loop and monitor it for panics.
+    /// This is called by `spawn_agent!()` and should not need to be called directly.
+    /// The returned task finishes once the Agent has terminated for good, i.e. it has been deregistered, or it has panicked and is not being restarted.
+    #[doc(hidden)]
+    pub fn supervise(
+        &self,
+        address: A,
+        agent_task: AgentTask,
+        restart_policy: RestartPolicy,
+        restart: Option<impl FnMut() -> AgentTask + Send + 'static>,
+    ) -> JoinHandle<()> {
+        let postmaster = self.clone();
+        let id = self.id();
+        spawn_named(
+            || format!("{address:?} supervisor"),
+            postmaster.run_supervisor(
+                address,
+                agent_task,
+                restart_policy,
+                restart,
+                move |agent_task| {
+                    spawn_named(
+                        || format!("{address:?}"),
+                        policy::run_as_agent(id, address.index(), agent_task),
+                    )
+                },
+            ),
+        )
+    }
+
+    /// Spawn the main loop of an Agent which isn't `Send` onto the current `LocalSet`, and monitor it for panics.
+    /// This is called by `spawn_agent_local!()` and should not need to be called directly.
+    /// The Agent and its supervising task both run on the `LocalSet`, so this must be called from within one.
+    #[doc(hidden)]
+    pub fn supervise_local(
+        &self,
+        address: A,
+        agent_task: LocalAgentTask,
+        restart_policy: RestartPolicy,
+        restart: Option<impl FnMut() -> LocalAgentTask + 'static>,
+    ) -> JoinHandle<()> {
+        let postmaster = self.clone();
+        let id = self.id();
+        task::spawn_local(postmaster.run_supervisor(
+            address,
+            agent_task,
+            restart_policy,
+            restart,
+            move |agent_task| {
+                task::spawn_local(policy::run_as_agent(id, address.index(), agent_task))
+            },
+        ))
+    }
+
+    /// Spawn an Agent's main loop onto a dedicated thread with its own runtime, and monitor it for panics.
+    /// This is called by `spawn_agent_threaded!()` and should not need to be called directly.
+    #[doc(hidden)]
+    #[cfg(not(target_family = "wasm"))]
+    pub fn supervise_threaded(
+        &self,
+        address: A,
+        agent_task: ThreadedAgentTask,
+        restart_policy: RestartPolicy,
+        restart: Option<impl FnMut() -> ThreadedAgentTask + Send + 'static>,
+    ) -> JoinHandle<()> {
+        let postmaster = self.clone();
+        let id = self.id();
+        spawn_named(
+            || format!("{address:?} supervisor"),
+            postmaster.run_supervisor(
+                address,
+                agent_task,
+                restart_policy,
+                restart,
+                move |agent_task: ThreadedAgentTask| {
+                    let agent_task: ThreadedAgentTask = Box::new(move || {
+                        Box::pin(policy::run_as_agent(id, address.index(), agent_task()))
+                    });
+                    spawn_named(
+                        || format!("{address:?}"),
+                        run_on_thread(format!("{address:?}"), agent_task),
+                    )
+                },
+            ),
+        )
+    }
+
+    /// Run an Agent's main loop (spawning it with `spawn`), restarting it according to the restart policy if it panics
+    async fn run_supervisor<T>(
+        self,
+        address: A,
+        agent_task: T,
+        restart_policy: RestartPolicy,
+        mut restart: Option<impl FnMut() -> T>,
+        spawn: impl Fn(T) -> JoinHandle<()>,
+    ) {
+        let mut agent_task = self.track_agent_task(address, spawn(agent_task));
+        let mut restarts = 0;
+        // The Agent's main loop never returns, so the task only finishes if it panics or is aborted.
+        // An aborted task has already been deregistered, so there is nothing more to do.
+        while let Err(error) = agent_task.await
+            && error.is_panic()
+        {
+            let restart = restart
+                .as_mut()
+                .filter(|_| restart_policy.permits(restarts));
+            // A preserved message queue stays registered, to be taken over by the new instance
+            let preserved =
+                restart.is_some() && restart_policy.mailbox_policy() == MailboxPolicy::Preserve;
+            let terminated = !preserved && self.remove_stopped(address, restart.is_some()).await;
+            let message = panic_message(error.into_panic());
+            self.report_panic(AgentPanic {
+                address,
+                message: message.clone(),
+                restarting: restart.is_some(),
+            });
+            match restart {
+                Some(restart) => {
+                    // The children belonged to the instance which panicked, so the new instance starts without any
+                    self.stop_children(address).await;
+                    restarts += 1;
+                    agent_task = self.track_agent_task(address, spawn(restart()));
+                }
+                None => {
+                    // Other members of a pool may still be running, in which case the address has not terminated
+                    if terminated {
+                        self.terminated(address, TerminationReason::Panicked(message))
+                            .await;
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Keep hold of a spawned Agent task, so that it can be aborted when the Agent is deregistered
+    fn track_agent_task(&self, address: A, agent_task: JoinHandle<()>) -> JoinHandle<()> {
+        let mut tasks = self.inner.tasks.lock().unwrap();
+        match tasks.get_mut(address.index()) {
+            Some(agent_tasks) => {
+                agent_tasks.retain(|agent_task| !agent_task.is_finished());
+                agent_tasks.push(agent_task.abort_handle());
+            }
+            None => {
+                let _ = tasks.insert(address.index(), vec![agent_task.abort_handle()]);
+            }
+        }
+        agent_task
+    }
+
+    /// Remove the message queue of an Agent which has stopped, returning whether the address is no longer registered.
+    /// A pool remains registered while any of its members are still running, or while the stopped member is being restarted.
+    async fn remove_stopped(&self, address: A, restarting: bool) -> bool {
+        let removed = self.inner.routes.modify(address.index(), |routes, index| {
+            if let Some(Route::Pool(pool)) = routes.get(index).map(|route| &**route)
+                && (restarting || pool.is_running())
+            {
+                return false;
+            }
+            routes.take(index);
+            true
+        });
+        if !removed {
+            return false;
+        }
+        self.inner.metrics.lock().unwrap().take(address.index());
+        self.inner.dedup.lock().unwrap().take(address.index());
+        self.inner.sequences.forget(address);
+        self.inner.timers.lock().unwrap().take(address.index());
+        true
+    }
+
+    /// Report an Agent's panic to the panic hook, or log it if no hook has been set (through `tracing` when that feature is enabled, otherwise to stderr)
+    fn report_panic(&self, agent_panic: AgentPanic<A>) {
+        self.inner.agent_panics.fetch_add(1, Ordering::Relaxed);
+        if let Some(hook) = self.inner.panic_hook.lock().unwrap().as_ref() {
+            hook(&agent_panic);
+            return;
+        }
+        let name = self
+            .inner
+            .config
+            .name
+            .map(|name| format!("[{name}] "))
+            .unwrap_or_default();
+        let restarting = if agent_panic.restarting {
+            " (restarting)"
+        } else {
+            ""
+        };
+        #[cfg(feature = "tracing")]
+        tracing::error!(
+            "{name}Agent {:?} panicked: {}{restarting}",
+            agent_panic.address,
+            agent_panic.message
+        );
+        #[cfg(not(feature = "tracing"))]
+        eprintln!(
+            "{name}Agent {:?} panicked: {}{restarting}",
+            agent_panic.address, agent_panic.message
+        );
+    }
+
+    /// Handle the new instance of an Agent which panicked failing to register, e.g. because another Agent took its address while it was restarting.
+    /// The address is terminated, so its watchers are notified with `TerminationReason::RestartFailed`.
+    /// This is called by the registration macros and should not need to be called directly.
+    #[doc(hidden)]
+    pub async fn restart_failed(&self, address: A, error: PostmasterError) {
+        self.terminated(address, TerminationReason::RestartFailed(error))
+            .await;
+    }
+
+    /// Handle an address terminating for good: notify its watchers (including its parent, if it is a child), and stop its children
+    async fn terminated(&self, address: A, reason: TerminationReason) {
+        self.notify_watchers(address, reason).await;
+        self.inner
+            .children
+            .lock()
+            .unwrap()
+            .retain(|(_, child)| child.index() != address.index());
+        self.stop_children(address).await;
+    }
+
+    /// Deregister every child linked to the given parent
+    async fn stop_children(&self, parent: A) {
+        let children: Vec<_> = self
+            .inner
+            .children
+            .lock()
+            .unwrap()
+            .extract_if(.., |(existing_parent, _)| {
+                existing_parent.index() == parent.index()
+            })
+            .collect();
+        for (_, child) in children {
+            // Deregistering the child stops its own children in turn, so the recursion is boxed
+            // The child may already have terminated by itself
+            let _ = Box::pin(self.deregister(child)).await;
+        }
+    }
+
+    async fn notify_watchers(&self, address: A, reason: TerminationReason) {
+        let watchers: Vec<_> = self
+            .inner
+            .watchers
+            .lock()
+            .unwrap()
+            .extract_if(.., |(_, watched, _)| watched.index() == address.index())
+            .collect();
+        for (watcher, _, notification) in watchers {
+            let payload = notification(AgentTerminated {
+                address,
+                reason: reason.clone(),
+            });
+            // Notifications are control messages, so that a backlogged watcher is still told promptly
+            let mut message = Message::new(address, payload);
+            message.control = true;
+            message.origin = Origin::postmaster();
+            // A watcher which has itself terminated cannot be notified, so failures are ignored
+            let _ = self.send_internal(watcher, message, None).await;
+        }
+    }
+
+    /// Send a message configured with a `MessageBuilder`, waiting for it to be acknowledged if `ack` is set, and giving up once the deadline (if any) has passed
+    async fn send_configured(
+        &self,
+        destination: A,
+        mut message: Message<A, P>,
+        timeout: Option<Duration>,
+        delay: Option<Duration>,
+        ack: bool,
+        deadline: Option<time::Instant>,
+    ) -> Result<(), PostmasterError> {
+        let acknowledged = ack.then(|| {
+            let (ack, acknowledged) = Acknowledgement::new();
+            message.ack = Some(Box::new(ack));
+            acknowledged
+        });
+        match delay {
+            Some(delay) => {
+                let mut turn = match self.inner.config.ordering {
+                    MessageOrdering::PerSenderStrict => {
+                        before(
+                            self.turn_deadline(deadline, timeout),
+                            self.take_turn(message.source, destination, message.control),
+                        )
+                        .await?
+                    }
+                    MessageOrdering::Unordered | MessageOrdering::PerSender => None,
+                };
+                message.sequence = turn.as_mut().map(Turn::number);
+                self.spawn_delayed_send(destination, message, delay, timeout, turn)
+            }
+            None => self
+                .send_returning_internal(destination, message, timeout, deadline)
+                .await
+                .map_err(PostmasterError::from),
+        }?;
+        match acknowledged {
+            Some(acknowledged) => match before(deadline, acknowledged).await? {
+                Ok(true) => Ok(()),
+                Ok(false) | Err(_) => Err(PostmasterError::NotAcknowledged),
+            },
+            None => Ok(()),
+        }
+    }
+
+    async fn send_internal(
+        &self,
+        destination: A,
+        message: Message<A, P>,
+        timeout: Option<Duration>,
+    ) -> Result<(), PostmasterError> {
+        self.send_returning_internal(destination, message, timeout, None)
+            .await
+            .map_err(PostmasterError::from)
+    }
+
+    /// Send a message, giving up with `PostmasterError::Timeout` once the deadline (if any) has passed, however the time is being spent
+    async fn send_returning_internal(
+        &self,
+        destination: A,
+        mut message: Message<A, P>,
+        timeout: Option<Duration>,
+        deadline: Option<time::Instant>,
+    ) -> Result<(), SendError<P>> {
+        let turn = before(
+            self.turn_deadline(deadline, timeout),
+            self.take_turn(message.source, destination, message.control),
+        )
+        .await;
+        let mut turn = match turn {
+            Ok(turn) => turn,
+            Err(error) => {
+                let _ = self.evaluate_diagnostics(
+                    message.source,
+                    destination,
+                    time::Instant::now(),
+                    Err(error),
+                );
+                return Err(SendError::new(error, Some(message.payload)));
+            }
+        };
+        message.sequence = turn.as_mut().map(Turn::number);
+        self.send_in_turn(destination, message, timeout, deadline, turn)
+            .await
+    }
+
+    /// Send a message which has already taken its turn (if the Postmaster keeps messages in order), holding the turn until the message has been delivered
+    async fn send_in_turn(
+        &self,
+        destination: A,
+        message: Message<A, P>,
+        timeout: Option<Duration>,
+        deadline: Option<time::Instant>,
+        _turn: Option<Turn>,
+    ) -> Result<(), SendError<P>> {
+        let admitted = self
+            .admit(destination, &message)
+            .and_then(|()| self.limit_rate(message.source, destination, true));
+        match admitted {
+            // The rate limit would hold the message past its deadline, so it is given up straight away rather than waiting to fail
+            Ok(Some(send_at)) if deadline.is_some_and(|deadline| send_at > deadline) => {
+                let _ = self.evaluate_diagnostics(
+                    message.source,
+                    destination,
+                    time::Instant::now(),
+                    Err(PostmasterError::Timeout),
+                );
+                return Err(SendError::new(
+                    PostmasterError::Timeout,
+                    Some(message.payload),
+                ));
+            }
+            Ok(Some(send_at)) => time::sleep_until(send_at).await,
+            Ok(None) => (),
+            Err(error) => return Err(SendError::new(error, Some(message.payload))),
+        }
+        let Some((destination, message)) = self
+            .intercept(destination, message)
+            .map_err(|(error, message)| SendError::new(error, Some(message.payload)))?
+        else {
+            return Ok(());
+        };
+        #[cfg(feature = "tracing")]
+        let message = self.start_trace(destination, message);
+        #[cfg(feature = "chaos")]
+        let Some(message) = self.inject_faults(destination, message) else {
+            return Ok(());
+        };
+        #[cfg(feature = "simulation")]
+        let message = match self.hold_for_simulation(destination, message) {
+            Ok(()) => return Ok(()),
+            Err(message) => message,
+        };
+        // Whatever is left before the deadline is given to waiting for space on the queue
+        let timeout = deadline
+            .map(|deadline| deadline.saturating_duration_since(time::Instant::now()))
+            .or(timeout);
+        self.deliver_returning(destination, message, timeout).await
+    }
+
+    async fn deliver(
+        &self,
+        destination: A,
+        message: Message<A, P>,
+        timeout: Option<Duration>,
+    ) -> Result<(), PostmasterError> {
+        self.deliver_returning(destination, message, timeout)
+            .await
+            .map_err(PostmasterError::from)
+    }
+
+    async fn deliver_returning(
+        &self,
+        destination: A,
+        mut message: Message<A, P>,
+        timeout: Option<Duration>,
+    ) -> Result<(), SendError<P>> {
+        let destination = self.fall_back(destination, &mut message);
+        // The overflow policy only applies to sends using the default timeout, as a send given its own timeout has asked to wait
+        let (timeout, overflow_policy) = match timeout {
+            Some(duration) => (duration, OverflowPolicy::Wait),
+            None => (
+                Duration::from_micros(self.inner.timeout_us.load(Ordering::Relaxed).into()),
+                self.inner.config.overflow_policy,
+            ),
+        };
+        if !self.claim_id(destination, message.id) {
+            return Ok(());
+        }
+        #[cfg(feature = "recording")]
+        let envelope = self.copy_for_recording(destination, &message);
+        #[cfg(feature = "tracing")]
+        let trace = message.trace.clone();
+        let source = message.source;
+        let id = message.id;
+        let started = time::Instant::now();
+        // The message is only taken once it is placed on the queue, so that its payload can be handed back if it can't be
+        let mut unsent = Some(message);
+        let result = self.evaluate_diagnostics(
+            source,
+            destination,
+            started,
+            time::timeout(
+                timeout,
+                self.place(destination, &mut unsent, overflow_policy),
+            )
+            .await
+            .unwrap_or(Err(PostmasterError::Timeout)),
+        );
+        if result.is_err() {
+            self.release_id(destination, id);
+        }
+        #[cfg(feature = "recording")]
+        self.record(envelope, &result);
+        #[cfg(feature = "tracing")]
+        trace.delivered(&result);
+        overflowed(result, overflow_policy)
+            .map_err(|error| SendError::new(error, unsent.map(|message| message.payload)))
+    }
+
+    /// Place a message on the recipient's queue, taking it from `unsent` once there is space for it
+    async fn place(
+        &self,
+        destination: A,
+        unsent: &mut Option<Message<A, P>>,
+        overflow_policy: OverflowPolicy,
+    ) -> Result<(), PostmasterError> {
+        let Some(message) = unsent.as_ref() else {
+            return Ok(());
+        };
+        // The route is only locked while it is looked up, rather than while waiting for space on the queue
+        match self.inner.routes.select(destination.index(), message) {
+            None => Err(PostmasterError::NoRecipient),
+            // A control message skips the bound on the recipient's queue, so that it reaches the recipient however far behind it is
+            Some(Mailbox {
+                messages,
+                control: Some(control),
+                ..
+            }) => match unsent.take() {
+                Some(message) => control.send(enqueued(message, &messages)).map_err(
+                    |mpsc::error::SendError(message)| {
+                        *unsent = Some(message);
+                        PostmasterError::ReceiverClosed
+                    },
+                ),
+                None => Ok(()),
+            },
+            Some(Mailbox { messages, .. }) => {
+                #[cfg(feature = "spillover")]
+                if let Some(spillover) =
+                    self.spillover(overflow_policy, core::slice::from_ref(message))
+                {
+                    let mut spilled: Vec<_> = unsent.take().into_iter().collect();
+                    let result = spillover.place(destination.index(), &messages, &mut spilled);
+                    *unsent = spilled.pop();
+                    return result;
+                }
+                let permit = match overflow_policy {
+                    OverflowPolicy::Wait => messages.reserve().await?,
+                    #[cfg(feature = "spillover")]
+                    OverflowPolicy::Spill => messages.reserve().await?,
+                    OverflowPolicy::Reject | OverflowPolicy::DropNewest => {
+                        messages.try_reserve().map_err(reserve_failed)?
+                    }
+                };
+                if let Some(message) = unsent.take() {
+                    permit.send(enqueued(message, &messages));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Deliver a batch of messages to the same destination, reserving space on the queue for all of them before any are delivered
+    async fn deliver_batch(
+        &self,
+        destination: A,
+        mut messages: Vec<Message<A, P>>,
+    ) -> Result<(), PostmasterError> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+        // Every message in the batch has the same destination, so they are all redirected together
+        let mut redirected = destination;
+        for message in &mut messages {
+            redirected = self.fall_back(destination, message);
+        }
+        let destination = redirected;
+        let timeout = Duration::from_micros(self.inner.timeout_us.load(Ordering::Relaxed).into());
+        let overflow_policy = self.inner.config.overflow_policy;
+        let sources: Vec<A> = messages.iter().map(|message| message.source).collect();
+        #[cfg(feature = "recording")]
+        let envelopes: Vec<_> = messages
+            .iter()
+            .map(|message| self.copy_for_recording(destination, message))
+            .collect();
+        #[cfg(feature = "tracing")]
+        let traces: Vec<_> = messages
+            .iter()
+            .map(|message| message.trace.clone())
+            .collect();
+        let started = time::Instant::now();
+        let result = time::timeout(timeout, async {
+            match self.inner.routes.select(destination.index(), &messages[0]) {
+                None => Err(PostmasterError::NoRecipient),
+                Some(Mailbox {
+                    messages: sender, ..
+                }) if messages.len() > sender.max_capacity() => Err(PostmasterError::BatchTooLarge),
+                Some(Mailbox {
+                    messages: sender, ..
+                }) => {
+                    #[cfg(feature = "spillover")]
+                    if let Some(spillover) = self.spillover(overflow_policy, &messages) {
+                        return spillover.place(destination.index(), &sender, &mut messages);
+                    }
+                    let permits = match overflow_policy {
+                        OverflowPolicy::Wait => sender.reserve_many(messages.len()).await?,
+                        #[cfg(feature = "spillover")]
+                        OverflowPolicy::Spill => sender.reserve_many(messages.len()).await?,
+                        OverflowPolicy::Reject | OverflowPolicy::DropNewest => sender
+                            .try_reserve_many(messages.len())
+                            .map_err(reserve_failed)?,
+                    };
+                    let enqueued_at = Some(time::Instant::now());
+                    for (permit, mut message) in permits.zip(messages) {
+                        message.enqueued_at = enqueued_at;
+                        permit.send(message);
+                    }
+                    Ok(())
+                }
+            }
+        })
+        .await
+        .unwrap_or(Err(PostmasterError::Timeout));
+        // Each message in the batch is counted, so the diagnostics and metrics match sending the messages one at a time
+        for source in sources {
+            let _ = self.evaluate_diagnostics(source, destination, started, result);
+        }
+        #[cfg(feature = "recording")]
+        for envelope in envelopes {
+            self.record(envelope, &result);
+        }
+        #[cfg(feature = "tracing")]
+        for trace in traces {
+            trace.delivered(&result);
+        }
+        overflowed(result, overflow_policy)
+    }
+
+    fn try_send_internal(
+        &self,
+        destination: A,
+        mut message: Message<A, P>,
+    ) -> Result<(), TrySendError<P>> {
+        // A message can't wait for its turn, so it is treated as though the queue were full while an earlier message from the same source is still being delivered
+        let mut turn = match self.try_take_turn(message.source, destination, message.control) {
+            Ok(turn) => turn,
+            Err(_) => return Err(TrySendError::Full(message.payload)),
+        };
+        message.sequence = turn.as_mut().map(Turn::number);
+        if let Err(error) = self.admit(destination, &message).and_then(|()| {
+            self.limit_rate(message.source, destination, false)
+                .map(drop)
+        }) {
+            return Err(TrySendError::Failed(error, Some(message.payload)));
+        }
+        let Some((destination, message)) = self
+            .intercept(destination, message)
+            .map_err(|(error, message)| TrySendError::Failed(error, Some(message.payload)))?
+        else {
+            return Ok(());
+        };
+        #[cfg(feature = "tracing")]
+        let message = self.start_trace(destination, message);
+        #[cfg(feature = "chaos")]
+        let Some(message) = self.inject_faults(destination, message) else {
+            return Ok(());
+        };
+        #[cfg(feature = "simulation")]
+        let message = match self.hold_for_simulation(destination, message) {
+            Ok(()) => return Ok(()),
+            Err(message) => message,
+        };
+        let mut message = message;
+        let destination = self.fall_back(destination, &mut message);
+        if !self.claim_id(destination, message.id) {
+            return Ok(());
+        }
+        #[cfg(feature = "recording")]
+        let envelope = self.copy_for_recording(destination, &message);
+        #[cfg(feature = "tracing")]
+        let trace = message.trace.clone();
+        let source = message.source;
+        let id = message.id;
+        let started = time::Instant::now();
+        // The message is kept until it has been placed on the queue, so that its payload can be handed back if it can't be
+        let (result, unsent) = match self.inner.routes.select(destination.index(), &message) {
+            None => (Err(PostmasterError::NoRecipient), Some(message)),
+            Some(Mailbox {
+                messages,
+                control: Some(control),
+                ..
+            }) => match control.send(enqueued(message, &messages)) {
+                Ok(()) => (Ok(()), None),
+                Err(mpsc::error::SendError(message)) => {
+                    (Err(PostmasterError::ReceiverClosed), Some(message))
+                }
+            },
+            Some(Mailbox { messages, .. }) => match messages.try_reserve() {
+                Ok(permit) => {
+                    permit.send(enqueued(message, &messages));
+                    (Ok(()), None)
+                }
+                Err(mpsc::error::TrySendError::Full(())) => {
+                    (Err(PostmasterError::TrySendFailed), Some(message))
+                }
+                Err(mpsc::error::TrySendError::Closed(())) => {
+                    (Err(PostmasterError::ReceiverClosed), Some(message))
+                }
+            },
+        };
+        let result = self.evaluate_diagnostics(source, destination, started, result);
+        if result.is_err() {
+            self.release_id(destination, id);
+        }
+        #[cfg(feature = "recording")]
+        self.record(envelope, &result);
+        #[cfg(feature = "tracing")]
+        trace.delivered(&result);
+        match (result, unsent) {
+            (Ok(()), _) => Ok(()),
+            (Err(PostmasterError::TrySendFailed), Some(message)) => {
+                Err(TrySendError::Full(message.payload))
+            }
+            (Err(error), message) => Err(TrySendError::Failed(
+                error,
+                message.map(|message| message.payload),
+            )),
+        }
+    }
+
+    fn spawn_delayed_send(
+        &self,
+        destination: A,
+        message: Message<A, P>,
+        delay: Duration,
+        timeout: Option<Duration>,
+        turn: Option<Turn>,
+    ) -> Result<(), PostmasterError> {
+        // The deadline is fixed now, so that the delay is measured from the point of sending.
+        // This also keeps delays exact when tokio's clock is paused and advanced manually in tests.
+        let deadline = self.delay_clock_now() + delay;
+        let delayed_send = DelayedSend {
+            destination,
+            message,
+            timeout,
+            turn,
+            // The message is traced as part of whatever was happening when it was sent, rather than when its delay expires
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::current(),
+        };
+        let mut delayed = self.inner.delayed.lock().unwrap();
+        let sooner = delayed
+            .wheel
+            .next_deadline()
+            .is_none_or(|next| deadline < next);
+        delayed.wheel.insert(deadline, delayed_send);
+        // The driver stops once there are no more delayed messages, or if its runtime shuts down
+        if delayed.driver.as_ref().is_none_or(JoinHandle::is_finished) {
+            delayed.driver = Some(task::spawn(self.clone().drive_delayed()));
+        } else if sooner {
+            self.inner.delayed_changed.notify_one();
+        }
+        Ok(())
+    }
+
+    /// The current time as measured by the clock used for delayed messages.
+    /// With `DelayClock::Real`, the time is measured on the wall clock from when the Postmaster was created, but given as a point on tokio's clock so that it can be compared with the deadlines in the wheel.
+    fn delay_clock_now(&self) -> time::Instant {
+        match self.inner.config.delay_clock {
+            DelayClock::Virtual => time::Instant::now(),
+            DelayClock::Real => {
+                let (runtime_epoch, wall_epoch) = self.inner.epoch;
+                runtime_epoch + wall_epoch.elapsed()
+            }
+        }
+    }
+
+    /// Deliver delayed messages as they fall due, until there are none left
+    async fn drive_delayed(self) {
+        loop {
+            let next = {
+                let mut delayed = self.inner.delayed.lock().unwrap();
+                for delayed_send in delayed.wheel.expire(self.delay_clock_now()) {
+                    let postmaster = self.clone();
+                    #[cfg(feature = "tracing")]
+                    let span = delayed_send.span.clone();
+                    let send = async move {
+                        let DelayedSend {
+                            destination,
+                            mut message,
+                            timeout,
+                            turn,
+                            ..
+                        } = delayed_send;
+                        // A message which kept its place in the order when it was sent is delivered in that place, while any other takes its turn now that it is due
+                        let turn = match turn {
+                            Some(turn) => Some(turn),
+                            None => {
+                                let mut turn = postmaster
+                                    .take_turn(message.source, destination, message.control)
+                                    .await;
+                                message.sequence = turn.as_mut().map(Turn::number);
+                                turn
+                            }
+                        };
+                        // TODO: Can we find a way to convey back to the source that the sending failed?
+                        let _ = postmaster
+                            .send_in_turn(destination, message, timeout, None, turn)
+                            .await;
+                    };
+                    #[cfg(feature = "tracing")]
+                    let send = tracing::Instrument::instrument(send, span);
+                    task::spawn(send);
+                }
+                match delayed.wheel.next_deadline() {
+                    Some(next) => next,
+                    None => {
+                        delayed.driver = None;
+                        return;
+                    }
+                }
+            };
+            let due = async {
+                match self.inner.config.delay_clock {
+                    DelayClock::Virtual => time::sleep_until(next).await,
+                    DelayClock::Real => {
+                        sleep_real(next.saturating_duration_since(self.delay_clock_now())).await
+                    }
+                }
+            };
+            tokio::select! {
+                () = due => {}
+                () = self.inner.delayed_changed.notified() => {}
+            }
+        }
+    }
+
+    /// If the Postmaster keeps messages in order, wait until the messages sent earlier from the source to the destination have been delivered, returning the turn to hold while the next message is delivered.
+    /// Control messages skip ahead of regular messages, so they don't take turns.
+    async fn take_turn(&self, source: A, destination: A, control: bool) -> Option<Turn> {
+        match self.inner.config.ordering {
+            MessageOrdering::Unordered => None,
+            _ if control => None,
+            MessageOrdering::PerSender | MessageOrdering::PerSenderStrict => {
+                Some(self.inner.sequences.take_turn(source, destination).await)
+            }
+        }
+    }
+
+    /// The time until which a message may wait for its turn: its deadline if it has one, or otherwise its send timeout (or the Postmaster's) from now.
+    /// This keeps a send from waiting indefinitely behind an earlier message, such as a delayed message which keeps its place with `MessageOrdering::PerSenderStrict`.
+    fn turn_deadline(
+        &self,
+        deadline: Option<time::Instant>,
+        timeout: Option<Duration>,
+    ) -> Option<time::Instant> {
+        deadline.or_else(|| {
+            let timeout = timeout.unwrap_or_else(|| {
+                Duration::from_micros(self.inner.timeout_us.load(Ordering::Relaxed).into())
+            });
+            time::Instant::now().checked_add(timeout)
+        })
+    }
+
+    /// Take a turn as with `take_turn()`, but without waiting, failing with `PostmasterError::TrySendFailed` if an earlier message is still being delivered
+    fn try_take_turn(
+        &self,
+        source: A,
+        destination: A,
+        control: bool,
+    ) -> Result<Option<Turn>, PostmasterError> {
+        match self.inner.config.ordering {
+            MessageOrdering::Unordered => Ok(None),
+            _ if control => Ok(None),
+            MessageOrdering::PerSender | MessageOrdering::PerSenderStrict => self
+                .inner
+                .sequences
+                .try_take_turn(source, destination)
+                .map(Some)
+                .ok_or(PostmasterError::TrySendFailed),
+        }
+    }
+
+    /// Claim the ID of a message which is about to be delivered, returning false if the message is a duplicate and should be discarded
+    fn claim_id(&self, destination: A, id: Option<MessageId>) -> bool {
+        let Some(id) = id else {
+            return true;
+        };
+        self.inner
+            .dedup
+            .lock()
+            .unwrap()
+            .get_or_insert_with(destination.index(), || {
+                DedupWindow::new(DEFAULT_DEDUP_WINDOW)
+            })
+            .is_none_or(|dedup| dedup.claim(id))
+    }
+
+    /// Release the ID of a message which could not be delivered, so that it can be retried
+    fn release_id(&self, destination: A, id: Option<MessageId>) {
+        if let Some(id) = id
+            && let Some(dedup) = self
+                .inner
+                .dedup
+                .lock()
+                .unwrap()
+                .get_mut(destination.index())
+        {
+            dedup.release(id);
+        }
+    }
+
+    /// Apply the source's rate limit (if any) to a message, returning when the message may be sent if it has to wait.
+    /// Fails with `PostmasterError::RateLimited` if the limit is exceeded and the message cannot wait, either because the limit rejects messages or because `wait` is false.
+    fn limit_rate(
+        &self,
+        source: A,
+        destination: A,
+        wait: bool,
+    ) -> Result<Option<time::Instant>, PostmasterError> {
+        let now = time::Instant::now();
+        let mut rate_limits = self.inner.rate_limits.lock().unwrap();
+        let Some(bucket) = rate_limits.get_mut(source.index()) else {
+            return Ok(None);
+        };
+        let wait = wait && bucket.action() == RateLimitAction::Wait;
+        match bucket.take(now, wait) {
+            Some(send_at) => Ok((send_at > now).then_some(send_at)),
+            None => {
+                drop(rate_limits);
+                self.evaluate_diagnostics(
+                    source,
+                    destination,
+                    now,
+                    Err(PostmasterError::RateLimited),
+                )
+                .map(|()| None)
+            }
+        }
+    }
+
+    /// Check that a message may be sent: it must be allowed by the send policy (if one is set), and while the Postmaster is draining only messages from Agents (which may be passing on the work already queued) and control messages are let through
+    fn admit(&self, destination: A, message: &Message<A, P>) -> Result<(), PostmasterError> {
+        self.check_policy(destination, message)?;
+        if !self.inner.draining.load(Ordering::Relaxed)
+            || message.control
+            || self.inner.routes.inspect(message.source.index(), |route| {
+                route.is_some_and(Route::is_agent)
+            })
+        {
+            return Ok(());
+        }
+        self.evaluate_diagnostics(
+            message.source,
+            destination,
+            time::Instant::now(),
+            Err(PostmasterError::Draining),
+        )
+    }
+
+    /// Check that the send policy (if one is set) allows a message to be sent to the destination
+    fn check_policy(&self, destination: A, message: &Message<A, P>) -> Result<(), PostmasterError> {
+        let send_policy = self.inner.send_policy.lock().unwrap().clone();
+        match send_policy {
+            Some(send_policy) if !send_policy.permits(self.id(), destination, message) => self
+                .evaluate_diagnostics(
+                    message.source,
+                    destination,
+                    time::Instant::now(),
+                    Err(PostmasterError::Forbidden),
+                ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Identifies this Postmaster (shared by all of its clones), so that a message can be attributed to an Agent registered with it
+    fn id(&self) -> usize {
+        Arc::as_ptr(&self.inner).addr()
+    }
+
+    /// Redirect a message sent to an address with no recipient to the fallback address, if one is set, noting the address it was sent to
+    fn fall_back(&self, destination: A, message: &mut Message<A, P>) -> A {
+        message.destination = Some(destination);
+        if self.inner.routes.contains(destination.index()) {
+            return destination;
+        }
+        match *self.inner.fallback.lock().unwrap() {
+            Some(fallback) if fallback.index() != destination.index() => {
+                message.intended_destination.get_or_insert(destination);
+                fallback
+            }
+            _ => destination,
+        }
+    }
+
+    /// Apply the interceptors to a message, returning where it should be delivered, or `None` if it has been dropped.
+    /// A rejected message is handed back with the error, so that its payload can be returned to the sender.
+    // The message is handed back whole rather than boxed, as this is on every send's path
+    #[allow(clippy::result_large_err)]
+    fn intercept(
+        &self,
+        mut destination: A,
+        mut message: Message<A, P>,
+    ) -> Result<Option<Addressed<A, P>>, Rejected<A, P>> {
+        let interceptors = self.inner.interceptors.lock().unwrap().clone();
+        let sent_to = destination;
+        for interceptor in interceptors.iter() {
+            match interceptor.intercept(destination, &mut message) {
+                Verdict::Pass => (),
+                Verdict::Redirect(redirected) => destination = redirected,
+                Verdict::Drop => return Ok(None),
+                Verdict::Reject => {
+                    return self
+                        .evaluate_diagnostics(
+                            message.source,
+                            destination,
+                            time::Instant::now(),
+                            Err(PostmasterError::Rejected),
+                        )
+                        .map(|()| None)
+                        .map_err(|error| (error, message));
+                }
+            }
+        }
+        // A redirected message must also be allowed to reach its new destination
+        if destination.index() != sent_to.index()
+            && let Err(error) = self.check_policy(destination, &message)
+        {
+            return Err((error, message));
+        }
+        Ok(Some((destination, message)))
+    }
+
+    /// Open the tracing span of a message which is being sent
+    #[cfg(feature = "tracing")]
+    fn start_trace(&self, destination: A, mut message: Message<A, P>) -> Message<A, P> {
+        let variant = *self.inner.payload_variant.lock().unwrap();
+        MessageTrace::start(&mut message, &destination, variant, self.inner.config.name);
+        message
+    }
+
+    /// While a `Simulation` is running, hold the message for the simulation to deliver, otherwise hand it back
+    #[cfg(feature = "simulation")]
+    // The message is handed back whole rather than boxed, as this is on every send's path
+    #[allow(clippy::result_large_err)]
+    fn hold_for_simulation(
+        &self,
+        destination: A,
+        message: Message<A, P>,
+    ) -> Result<(), Message<A, P>> {
+        match self.inner.simulation.lock().unwrap().as_mut() {
+            Some(pending) => {
+                pending.push((destination, message));
+                Ok(())
+            }
+            None => Err(message),
+        }
+    }
+
+    /// Start or stop holding messages for a `Simulation`.
+    /// Stopping the simulation discards any messages which have not yet been delivered.
+    #[cfg(feature = "simulation")]
+    pub(crate) fn set_simulating(&self, simulating: bool) {
+        *self.inner.simulation.lock().unwrap() = simulating.then(Vec::new);
+    }
+
+    /// The number of messages held for delivery by the `Simulation`
+    #[cfg(feature = "simulation")]
+    pub(crate) fn simulated_pending(&self) -> usize {
+        self.inner
+            .simulation
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, Vec::len)
+    }
+
+    /// Take one of the messages held for the `Simulation`, with `choose` picking its index from the number of messages held
+    #[cfg(feature = "simulation")]
+    pub(crate) fn take_simulated(
+        &self,
+        choose: impl FnOnce(usize) -> usize,
+    ) -> Option<(A, Message<A, P>)> {
+        let mut simulation = self.inner.simulation.lock().unwrap();
+        let pending = simulation.as_mut().filter(|pending| !pending.is_empty())?;
+        let index = choose(pending.len());
+        Some(pending.remove(index))
+    }
+
+    /// Deliver a message taken from the `Simulation`
+    #[cfg(feature = "simulation")]
+    pub(crate) async fn deliver_simulated(
+        &self,
+        destination: A,
+        message: Message<A, P>,
+    ) -> Result<(), PostmasterError> {
+        self.deliver(destination, message, None).await
+    }
+
+    /// While a `Chaos` is running, inject faults into the message, returning it if it is still to be delivered as usual.
+    /// Messages which are delayed, duplicated or reordered are delivered in the background, and control messages are left alone.
+    #[cfg(feature = "chaos")]
+    fn inject_faults(&self, destination: A, message: Message<A, P>) -> Option<Message<A, P>> {
+        if message.control {
+            return Some(message);
+        }
+        let injected = match self.inner.chaos.lock().unwrap().as_mut() {
+            Some(chaos) => chaos.inject(destination, message),
+            None => return Some(message),
+        };
+        self.deliver_later(injected.later);
+        if let Some((route, hold, max_hold)) = injected.held {
+            let postmaster = self.clone();
+            task::spawn(async move {
+                time::sleep(max_hold).await;
+                let held = postmaster
+                    .inner
+                    .chaos
+                    .lock()
+                    .unwrap()
+                    .as_mut()
+                    .and_then(|chaos| chaos.release(route, hold));
+                if let Some((destination, message)) = held {
+                    let _ = postmaster.deliver(destination, message, None).await;
+                }
+            });
+        }
+        injected.now
+    }
+
+    /// Deliver messages in the background, each once its delay (from now) has elapsed
+    #[cfg(feature = "chaos")]
+    fn deliver_later(&self, mut deliveries: Vec<Delivery<A, P>>) {
+        // Without a runtime (e.g. when a `Chaos` is dropped after its test's runtime), the messages can only be discarded
+        let Ok(runtime) = runtime::Handle::try_current() else {
+            return;
+        };
+        if deliveries.is_empty() {
+            return;
+        }
+        deliveries.sort_by_key(|(delay, ..)| *delay);
+        let started = time::Instant::now();
+        let postmaster = self.clone();
+        runtime.spawn(async move {
+            for (delay, destination, message) in deliveries {
+                time::sleep_until(started + delay).await;
+                let _ = postmaster.deliver(destination, message, None).await;
+            }
+        });
+    }
+
+    /// Start or stop injecting faults for a `Chaos`.
+    /// Messages still held back to be reordered by the previous `Chaos` are delivered.
+    #[cfg(feature = "chaos")]
+    pub(crate) fn set_chaos(&self, chaos: Option<ChaosState<A, P>>) {
+        let previous = core::mem::replace(&mut *self.inner.chaos.lock().unwrap(), chaos);
+        if let Some(previous) = previous {
+            self.deliver_later(previous.release_all());
+        }
+    }
+
+    /// Access the state of the running `Chaos`, if any
+    #[cfg(feature = "chaos")]
+    pub(crate) fn with_chaos<R>(&self, f: impl FnOnce(&mut ChaosState<A, P>) -> R) -> Option<R> {
+        self.inner.chaos.lock().unwrap().as_mut().map(f)
+    }
+
+    /// Spool the messages which overflow full queues with the given `Spillover`
+    #[cfg(feature = "spillover")]
+    pub(crate) fn set_spillover(&self, spillover: Arc<SpillState<A, P>>) {
+        *self.inner.spillover.lock().unwrap() = Some(spillover);
+    }
+
+    /// The `Spillover` through which messages are placed on a full queue, if the overflow policy is to spill, one has been opened, and none of the messages need acknowledging
+    #[cfg(feature = "spillover")]
+    fn spillover(
+        &self,
+        overflow_policy: OverflowPolicy,
+        messages: &[Message<A, P>],
+    ) -> Option<Arc<SpillState<A, P>>> {
+        if overflow_policy != OverflowPolicy::Spill
+            || messages.iter().any(|message| message.ack.is_some())
+        {
+            return None;
+        }
+        self.inner.spillover.lock().unwrap().clone()
+    }
+
+    /// Replace the recording of delivered messages, returning the previous recording
+    #[cfg(feature = "recording")]
+    pub(crate) fn set_recording(
+        &self,
+        recording: Option<Recording<A, P>>,
+    ) -> Option<Recording<A, P>> {
+        core::mem::replace(&mut *self.inner.recording.lock().unwrap(), recording)
+    }
+
+    /// Access the recording of delivered messages, if a `Recorder` is running
+    #[cfg(feature = "recording")]
+    pub(crate) fn with_recording<R>(&self, f: impl FnOnce(&mut Recording<A, P>) -> R) -> Option<R> {
+        self.inner.recording.lock().unwrap().as_mut().map(f)
+    }
+
+    /// Copy a message before it is sent, if a `Recorder` is running
+    #[cfg(feature = "recording")]
+    fn copy_for_recording(
+        &self,
+        destination: A,
+        message: &Message<A, P>,
+    ) -> Option<Envelope<A, P>> {
+        self.with_recording(|recording| recording.envelope(destination, message))
+    }
+
+    /// Log a copied message once it has been delivered
+    #[cfg(feature = "recording")]
+    fn record(&self, envelope: Option<Envelope<A, P>>, result: &Result<(), PostmasterError>) {
+        if let Some(envelope) = envelope
+            && result.is_ok()
+        {
+            self.with_recording(|recording| recording.log(envelope));
+        }
+    }
+
+    /// Start keeping metrics for an address
+    fn track(&self, address: A) {
+        self.inner
+            .metrics
+            .lock()
+            .unwrap()
+            .get_or_insert_with(address.index(), || Counters::new(address));
+    }
+
+    #[inline]
+    fn evaluate_diagnostics(
+        &self,
+        source: A,
+        destination: A,
+        started: time::Instant,
+        result: Result<(), PostmasterError>,
+    ) -> Result<(), PostmasterError> {
+        let mut metrics = self.inner.metrics.lock().unwrap();
+        match &result {
+            Ok(()) => {
+                self.inner.messages_sent.fetch_add(1, Ordering::Relaxed);
+                if let Some(counters) =
+                    metrics.get_or_insert_with(source.index(), || Counters::new(source))
+                {
+                    counters.sent();
+                }
+                if let Some(counters) =
+                    metrics.get_or_insert_with(destination.index(), || Counters::new(destination))
+                {
+                    counters.received(started.elapsed());
+                }
+            }
+            Err(error) => {
+                self.inner.send_failures.fetch_add(1, Ordering::Relaxed);
+                let mut dead_letters = self.inner.dead_letters.lock().unwrap();
+                if dead_letters.len() == DEAD_LETTER_CAPACITY {
+                    dead_letters.pop_front();
+                }
+                dead_letters.push_back(DeadLetter {
+                    source,
+                    destination,
+                    error: *error,
+                    failed_at: time::Instant::now(),
+                });
+                drop(dead_letters);
+                // Messages to addresses which were never registered are not tracked, so that misaddressed messages don't accumulate metrics
+                if let Some(counters) = metrics.get_mut(destination.index()) {
+                    counters.dropped();
+                }
+            }
+        }
+        drop(metrics);
+        result
+    }
+}
+
+/// Report a message dropped under `OverflowPolicy::DropNewest` as sent, once it has been counted as a failure
+fn overflowed(
+    result: Result<(), PostmasterError>,
+    overflow_policy: OverflowPolicy,
+) -> Result<(), PostmasterError> {
+    match (result, overflow_policy) {
+        (Err(PostmasterError::TrySendFailed), OverflowPolicy::DropNewest) => Ok(()),
+        (result, _) => result,
+    }
+}
+
+/// Wait for a future to complete, giving up with `PostmasterError::Timeout` once the deadline (if any) has passed
+async fn before<T>(
+    deadline: Option<time::Instant>,
+    future: impl Future<Output = T>,
+) -> Result<T, PostmasterError> {
+    match deadline {
+        Some(deadline) => time::timeout_at(deadline, future)
+            .await
+            .map_err(|_| PostmasterError::Timeout),
+        None => Ok(future.await),
+    }
+}
+
+/// The error for a full or closed queue on which space couldn't be reserved without waiting
+fn reserve_failed(error: mpsc::error::TrySendError<()>) -> PostmasterError {
+    match error {
+        mpsc::error::TrySendError::Full(()) => PostmasterError::TrySendFailed,
+        mpsc::error::TrySendError::Closed(()) => PostmasterError::ReceiverClosed,
+    }
+}
+
+/// Wait for the given time to pass on the wall clock, whether or not tokio's clock is paused.
+/// The wait is timed by a separate thread, as all of tokio's timers follow its own clock.
+/// Dropping the future (e.g. when the delayed messages change and the wait is cancelled) wakes the thread so that it finishes straight away, rather than each cancelled wait leaving a thread asleep until it would have elapsed.
+#[cfg(not(target_family = "wasm"))]
+async fn sleep_real(duration: Duration) {
+    let (elapsed, wait) = oneshot::channel();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let deadline = std::time::Instant::now().checked_add(duration);
+    let timer = {
+        let cancelled = cancelled.clone();
+        thread::spawn(move || {
+            while !cancelled.load(Ordering::Acquire) {
+                match deadline {
+                    Some(deadline) => match deadline
+                        .checked_duration_since(std::time::Instant::now())
+                    {
+                        Some(remaining) if !remaining.is_zero() => thread::park_timeout(remaining),
+                        _ => {
+                            let _ = elapsed.send(());
+                            return;
+                        }
+                    },
+                    None => thread::park(),
+                }
+            }
+        })
+    };
+    let _cancel = CancelSleep {
+        cancelled,
+        timer: timer.thread().clone(),
+    };
+    let _ = wait.await;
+}
+
+/// Wait for the given time to pass.
+/// WebAssembly has no threads to time the wait on, so it follows tokio's clock, as with `DelayClock::Virtual`.
+#[cfg(target_family = "wasm")]
+async fn sleep_real(duration: Duration) {
+    time::sleep(duration).await
+}
+
+/// Stops the thread timing a `sleep_real()` when the wait finishes or is cancelled
+#[cfg(not(target_family = "wasm"))]
+struct CancelSleep {
+    cancelled: Arc<AtomicBool>,
+    timer: thread::Thread,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Drop for CancelSleep {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Release);
+        self.timer.unpark();
+    }
+}
+
+/// Prepare a message for placing on a recipient's queue, noting when it was enqueued and (if it was sent `with_ack()`) how to tell whether the recipient has stopped
+fn enqueued<A: Send + 'static, P: Send + 'static>(
+    mut message: Message<A, P>,
+    queue: &Sender<Message<A, P>>,
+) -> Message<A, P> {
+    message.enqueued_at = Some(time::Instant::now());
+    if let Some(ack) = &mut message.ack {
+        let queue = queue.clone();
+        ack.delivered(move || queue.is_closed());
+    }
+    message
+}
+
+/// Spawn a task, which is named for tokio-console when the `console` feature is enabled and tokio is built with `--cfg tokio_unstable`
+#[cfg_attr(not(all(feature = "console", tokio_unstable)), allow(unused_variables))]
+fn spawn_named<F>(name: impl FnOnce() -> String, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(feature = "console", tokio_unstable))]
+    return task::Builder::new()
+        .name(&name())
+        .spawn(future)
+        .expect("Failed to spawn task");
+    #[cfg(not(all(feature = "console", tokio_unstable)))]
+    task::spawn(future)
+}
+
+/// Run an Agent's main loop on a new thread, returning a future which finishes when the Agent does.
+/// If the Agent panics, the future resumes the panic, so that the task awaiting it reports the panic as if the Agent had been running within that task.
+/// Dropping the future (e.g. when its task is aborted) stops the Agent.
+#[cfg(not(target_family = "wasm"))]
+fn run_on_thread(name: String, agent_task: ThreadedAgentTask) -> impl Future<Output = ()> + Send {
+    let (stop, stopped) = oneshot::channel::<()>();
+    let (finished, done) = oneshot::channel();
+    thread::Builder::new()
+        .name(name)
+        .spawn(move || {
+            let runtime = runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to build the Agent's runtime");
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                runtime.block_on(async move {
+                    tokio::select! {
+                        () = agent_task() => (),
+                        _ = stopped => (),
+                    }
+                })
+            }));
+            let _ = finished.send(result);
+        })
+        .expect("Failed to spawn the Agent's thread");
+    async move {
+        let _stop = stop;
+        if let Ok(Err(payload)) = done.await {
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+/// A builder for configuring messages.
+/// Provides methods for configuring the message before it is sent with the `send()` method
+pub struct MessageBuilder<'a, A, P> {
+    postmaster: &'a Postmaster<A, P>,
+    destination: A,
+    message: Message<A, P>,
+    timeout: Option<Duration>,
+    delay: Option<Duration>,
+    ack: bool,
+    retry: Option<Retry<P>>,
+    deadline: Option<Duration>,
+}
+
+impl<A, P> MessageBuilder<'_, A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    /// Add a custom timeout to the message.
+    /// When the message is sent, it will use this timeout to determine how long to wait before giving up, rather than the Postmaster's default timeout.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout.replace(timeout);
+        self
+    }
+
+    /// Give up on the message once the given time has passed since `send()` was called, however the time is being spent: waiting for space on the recipient's queue, for the sender's rate limit, for earlier messages to be delivered (with `MessageOrdering`), for an acknowledgement (with `with_ack()`) or between retries (with `with_retry()`).
+    /// `send()` then fails with `PostmasterError::Timeout`, so that a latency-sensitive sender is never held up for longer than it can afford by a misbehaving recipient.
+    /// For a delayed message, the deadline covers handing the message over to the Postmaster, but not the delay itself.
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline.replace(deadline);
+        self
+    }
+
+    /// Wait for as long as it takes for space on the recipient's queue, rather than giving up after a timeout, so that the sender is held back to the recipient's pace (as with `send_with_backpressure()`)
+    pub fn without_timeout(mut self) -> Self {
+        self.timeout.replace(Duration::MAX);
+        self
+    }
+
+    /// Add a delay to the message.
+    /// The message is sent immediately, but the Postmaster will not attempt to push the message onto the recipient's queue until the delay has elapsed.
+    /// **Please note** that if a delay is added to the message, but after the delay has elapsed the Postmaster is unable to deliver the message, there is no way for the Postmaster to relay this failure back to the sender.
+    ///
+    /// Delays are measured with tokio's clock, so in tests using `tokio::time::pause()` (or `#[tokio::test(start_paused = true)]`), delayed messages are delivered as soon as the clock is advanced past the delay, rather than after the real time has elapsed.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay.replace(delay);
+        self
+    }
+
+    /// Mark the message as part of a flow of messages, e.g. the handling of a particular request.
+    /// The correlation ID is carried with the message (including through delays), and is recorded in the message's tracing span when the `tracing` feature is enabled.
+    pub fn with_correlation_id(mut self, correlation_id: impl Into<CorrelationId>) -> Self {
+        self.message.correlation_id = Some(correlation_id.into());
+        self
+    }
+
+    /// Ask for replies to the message to be sent to the given address, rather than to the message's source
+    pub fn reply_to(mut self, address: A) -> Self {
+        self.message.reply_to = Some(address);
+        self
+    }
+
+    /// Pass a received message's flow on to this message, copying its correlation ID and the address to reply to (its `reply_address()`).
+    /// This is used when handling a message involves sending messages on to other Agents, so that they can reply directly to the original sender.
+    pub fn forwarded_from(mut self, message: &Message<A, P>) -> Self {
+        self.message.correlation_id = message.correlation_id;
+        self.message.reply_to = Some(message.reply_address());
+        self
+    }
+
+    /// Give the message an ID, so that the Postmaster delivers the message to its destination at most once.
+    /// Further messages with the same ID sent to the same destination are discarded (while appearing to have been sent successfully), until the destination's deduplication window has passed (see `set_dedup_window()`).
+    /// This allows a sender to safely retry a message which it isn't sure was delivered.
+    /// If the message cannot be delivered, its ID is forgotten so that a retry can be delivered instead.
+    pub fn with_id(mut self, id: impl Into<MessageId>) -> Self {
+        self.message.id = Some(id.into());
+        self
+    }
+
+    /// Send the message on the recipient's control queue, which is unbounded and received from before its regular messages, so that it reaches the recipient however far behind the recipient is (e.g. a command to stop).
+    /// The message is therefore never held up by a full queue, and its timeout only comes into play if it is delayed by a rate limit.
+    /// A standalone message queue (registered with `register()`) has no control queue, so a control message sent to it is placed on the queue as normal.
+    pub fn as_control(mut self) -> Self {
+        self.message.control = true;
+        self
+    }
+
+    /// Wait for the recipient to handle the message, rather than only for the message to be placed on its queue.
+    /// The message has been handled once the recipient calls `Message::acknowledge()` on it, or otherwise once the recipient drops it after receiving it.
+    /// `send()` then waits for the acknowledgement (with no timeout, so wrap it in `tokio::time::timeout()` to give up on a slow recipient), and fails with `PostmasterError::NotAcknowledged` if the message is dropped without being handled, e.g. because the recipient stopped or panicked, an interceptor dropped the message, or a delayed message couldn't be delivered.
+    ///
+    /// A message handed to another process by a remote bridge is acknowledged once the bridge has sent it on.
+    pub fn with_ack(mut self) -> Self {
+        self.ack = true;
+        self
+    }
+
+    /// Send the configured message.
+    /// This function works in exactly the same way as `postmaster::send()`, except that the timeout scenario may be different depending on whether the timeout for the message was customised.
+    /// If a delay was set, the message will "send" immediately (meaning that the sender can continue executing), but the message won't be delivered until _at least_ the delay has elapsed.
+    /// This function can fail for the following reasons:
+    /// - The message queue being consistently full for longer than the timeout
+    /// - There being no recipient registered at the destination address
+    pub async fn send(self) -> Result<(), PostmasterError> {
+        let Self {
+            postmaster,
+            destination,
+            message,
+            timeout,
+            delay,
+            ack,
+            retry,
+            deadline,
+        } = self;
+        let deadline = deadline.and_then(|deadline| time::Instant::now().checked_add(deadline));
+        let Some((policy, copy_payload)) = retry else {
+            return postmaster
+                .send_configured(destination, message, timeout, delay, ack, deadline)
+                .await;
+        };
+        let mut retries = 0;
+        loop {
+            let mut attempt = Message::new(message.source, copy_payload(&message.payload));
+            attempt.correlation_id = message.correlation_id;
+            attempt.reply_to = message.reply_to;
+            attempt.id = message.id;
+            attempt.control = message.control;
+            attempt.origin = message.origin.clone();
+            match postmaster
+                .send_configured(destination, attempt, timeout, delay, ack, deadline)
+                .await
+            {
+                Err(error) => match policy.backoff(retries, error) {
+                    // The next attempt would only be made after the deadline
+                    Some(backoff)
+                        if deadline
+                            .is_some_and(|deadline| time::Instant::now() + backoff > deadline) =>
+                    {
+                        return Err(PostmasterError::Timeout);
+                    }
+                    Some(backoff) => time::sleep(backoff).await,
+                    None => return Err(error),
+                },
+                Ok(()) => return Ok(()),
+            }
+            retries += 1;
+        }
+    }
+}
+
+impl<'a, A, P> MessageBuilder<'a, A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Clone + Send + 'static,
+{
+    /// Retry the message according to the given policy if it can't be delivered, e.g. because the recipient hasn't started yet or its queue is full.
+    /// Each attempt is sent in the same way (with the same timeout, delay and ID), and `send()` only fails once the policy is exhausted, with the error from the last attempt.
+    /// Combined with `with_ack()`, a message which is dropped without being handled is also retried.
+    ///
+    /// As a delayed message is handed over to the Postmaster without waiting for it to be delivered, a delayed message is only retried if it is also sent `with_ack()`.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some((policy, P::clone));
+        self
+    }
+
+    /// Send the message over and over, at the wall-clock times given by a cron schedule (e.g. every day at 02:00), rather than just once.
+    /// See `CronMessage` for details.
+    #[cfg(feature = "cron")]
+    pub fn with_cron(self, schedule: CronSchedule) -> CronMessage<'a, A, P> {
+        CronMessage {
+            builder: self,
+            schedule,
+            catch_up: CatchUp::Once,
+        }
+    }
+}
+
+/// A message which is sent repeatedly on a cron schedule, configured with `MessageBuilder::with_cron()` and started with `start()`.
+/// Unlike a timer which is set again each time it fires, the times are taken from the wall clock, so they don't drift, and can express schedules such as "every weekday at 09:00".
+///
+/// Each time is sent a copy of the message (with its timeout, correlation ID and reply-to address), but not its ID, delay, acknowledgement or retry policy, and as with delayed messages, a copy which can't be delivered is dropped without telling anyone.
+/// The times the schedule was due while the message couldn't be sent (e.g. while the machine was suspended) are handled according to the `CatchUp` setting.
+///
+/// # Example
+/// ```rust,ignore
+/// // Rotate the logs every day at 02:00 (UTC), until the handle is cancelled
+/// let rotation = postmaster::message(Address::Logger, Address::Main, Payloads::Rotate)
+///     .with_cron(CronSchedule::parse("0 2 * * *").unwrap())
+///     .with_catch_up(CatchUp::Skip)
+///     .start();
+/// ```
+#[cfg(feature = "cron")]
+pub struct CronMessage<'a, A, P> {
+    builder: MessageBuilder<'a, A, P>,
+    schedule: CronSchedule,
+    catch_up: CatchUp,
+}
+
+#[cfg(feature = "cron")]
+impl<A, P> CronMessage<'_, A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Clone + Send + 'static,
+{
+    /// Set what is sent for the times which were missed, which defaults to `CatchUp::Once`
+    pub fn with_catch_up(mut self, catch_up: CatchUp) -> Self {
+        self.catch_up = catch_up;
+        self
+    }
+
+    /// Start sending the message on its schedule, returning a handle with which it can be cancelled.
+    /// Must be called from within the tokio runtime.
+    pub fn start(self) -> CronHandle {
+        let Self {
+            builder,
+            schedule,
+            catch_up,
+        } = self;
+        let MessageBuilder {
+            postmaster,
+            destination,
+            message,
+            timeout,
+            ..
+        } = builder;
+        let postmaster = postmaster.clone();
+        let recurring = async move {
+            let mut next = schedule.next_after(SystemTime::now());
+            while let Some(due) = next {
+                let (sends, following) = cron::next_fire(&schedule, catch_up, due).await;
+                for _ in 0..sends {
+                    let mut copy = Message::new(message.source, message.payload.clone());
+                    copy.correlation_id = message.correlation_id;
+                    copy.reply_to = message.reply_to;
+                    copy.control = message.control;
+                    copy.origin = message.origin.clone();
+                    let _ = postmaster.send_internal(destination, copy, timeout).await;
+                }
+                next = following;
+            }
+        };
+        #[cfg(feature = "tracing")]
+        let recurring = tracing::Instrument::in_current_span(recurring);
+        CronHandle::new(task::spawn(recurring).abort_handle())
+    }
+}
@@ -0,0 +1,49 @@
+use super::Message;
+
+/// What an `Interceptor` decides should happen to a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict<A> {
+    /// Deliver the message to its destination (as modified by the interceptor, if at all)
+    Pass,
+    /// Deliver the message to a different address instead
+    Redirect(A),
+    /// Silently discard the message, as if it were lost in transit.
+    /// The sender is still told that the message was sent, which makes this useful for injecting faults.
+    Drop,
+    /// Refuse to deliver the message, failing the send with `PostmasterError::Rejected`
+    Reject,
+}
+
+/// Inspects every message before it is delivered, with the opportunity to modify, redirect or discard it.
+/// Interceptors are added to a Postmaster with `add_interceptor()`, and are applied in the order they were added, each one seeing the message (and destination) as left by the previous one.
+/// As soon as an interceptor drops or rejects a message, the remaining interceptors are skipped.
+///
+/// This allows behaviour which applies to every Agent, such as logging, access control or fault injection, to be written once as a layer around the Postmaster.
+/// Interceptors are called synchronously while the message is being sent, so they should be quick, and may only send messages of their own with `try_send()`.
+///
+/// Any `Fn(A, &mut Message<A, P>) -> Verdict<A>` closure can be used as an interceptor, receiving the message's destination and the message itself.
+///
+/// # Example
+/// ```rust,ignore
+/// // Only the Sequencer may tell the Lights what to do
+/// postmaster::add_interceptor(|destination, message: &mut postmaster::Message| {
+///     match (destination, message.source) {
+///         (Address::Lights, Address::Sequencer) => Verdict::Pass,
+///         (Address::Lights, _) => Verdict::Reject,
+///         _ => Verdict::Pass,
+///     }
+/// });
+/// ```
+pub trait Interceptor<A, P>: Send + Sync {
+    /// Decide what should happen to a message which is about to be delivered to `destination`
+    fn intercept(&self, destination: A, message: &mut Message<A, P>) -> Verdict<A>;
+}
+
+impl<A, P, F> Interceptor<A, P> for F
+where
+    F: Fn(A, &mut Message<A, P>) -> Verdict<A> + Send + Sync,
+{
+    fn intercept(&self, destination: A, message: &mut Message<A, P>) -> Verdict<A> {
+        self(destination, message)
+    }
+}
@@ -0,0 +1,229 @@
+use tokio::time::Duration;
+
+/// The upper bounds (in microseconds) of the buckets of a `LatencyHistogram`, with a final bucket for anything slower
+pub const LATENCY_BUCKETS_US: [u64; 6] = [10, 100, 1_000, 10_000, 100_000, 1_000_000];
+
+/// A histogram of the time taken to deliver messages
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LatencyHistogram {
+    buckets: [u64; LATENCY_BUCKETS_US.len() + 1],
+    total_us: u64,
+}
+
+impl LatencyHistogram {
+    pub(super) fn observe(&mut self, latency: Duration) {
+        let latency_us = latency.as_micros().try_into().unwrap_or(u64::MAX);
+        let bucket = LATENCY_BUCKETS_US
+            .iter()
+            .position(|bound| latency_us <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_US.len());
+        self.buckets[bucket] += 1;
+        self.total_us = self.total_us.saturating_add(latency_us);
+    }
+
+    /// The number of messages in each bucket, paired with the bucket's upper bound (`None` for the final, unbounded bucket)
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        LATENCY_BUCKETS_US
+            .iter()
+            .map(|bound| Some(Duration::from_micros(*bound)))
+            .chain([None])
+            .zip(self.buckets.iter().copied())
+    }
+
+    /// The number of messages observed
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// The total time taken to deliver all of the messages observed
+    pub fn total(&self) -> Duration {
+        Duration::from_micros(self.total_us)
+    }
+
+    /// The mean time taken to deliver a message, or `None` if no messages have been observed
+    pub fn mean(&self) -> Option<Duration> {
+        let count = self.count();
+        (count > 0).then(|| Duration::from_micros(self.total_us / count))
+    }
+}
+
+/// A snapshot of the metrics for a single address.
+/// Counters are kept from when the address is registered (or first sends a message) until it is deregistered or its Agent terminates.
+#[derive(Debug, Clone)]
+pub struct AgentMetrics<A> {
+    /// The address the metrics relate to
+    pub address: A,
+    /// The number of messages sent from this address which were successfully delivered
+    pub messages_sent: u64,
+    /// The number of messages delivered to this address's queue
+    pub messages_received: u64,
+    /// The number of messages addressed to this address which could not be delivered (e.g. because its queue stayed full until the send timed out)
+    pub messages_dropped: u64,
+    /// The number of messages currently waiting in this address's queue (summed across the members of a pool)
+    pub queue_depth: usize,
+    /// The maximum number of messages this address's queue can hold (summed across the members of a pool)
+    pub queue_capacity: usize,
+    /// The time senders waited for messages to be placed on this address's queue.
+    /// An Agent which cannot keep up with its messages fills its queue, so this grows for the Agent which is the bottleneck.
+    pub delivery_latency: LatencyHistogram,
+}
+
+/// A snapshot of the Postmaster's per-address metrics.
+/// Obtained by calling postmaster::metrics()
+#[derive(Debug, Clone)]
+pub struct Metrics<A> {
+    /// The metrics for each address which has sent or received messages, or which has a registered queue
+    pub agents: Vec<AgentMetrics<A>>,
+}
+
+#[cfg(feature = "prometheus")]
+impl<A: core::fmt::Debug> Metrics<A> {
+    /// Render the metrics in the Prometheus text exposition format, with each address as an `address` label.
+    /// The output can be served from a project's own HTTP endpoint for Prometheus to scrape.
+    pub fn to_prometheus(&self) -> String {
+        use core::fmt::Write;
+
+        let mut output = String::new();
+        let labels: Vec<String> = self
+            .agents
+            .iter()
+            .map(|agent| {
+                format!(
+                    "address=\"{}\"",
+                    escape_label(&format!("{:?}", agent.address))
+                )
+            })
+            .collect();
+        let mut family =
+            |name: &str, kind: &str, help: &str, value: fn(&AgentMetrics<A>) -> u64| {
+                let _ = writeln!(output, "# HELP post_haste_{name} {help}");
+                let _ = writeln!(output, "# TYPE post_haste_{name} {kind}");
+                for (agent, labels) in self.agents.iter().zip(&labels) {
+                    let _ = writeln!(output, "post_haste_{name}{{{labels}}} {}", value(agent));
+                }
+            };
+        family(
+            "messages_sent_total",
+            "counter",
+            "Messages sent from the address which were delivered.",
+            |agent| agent.messages_sent,
+        );
+        family(
+            "messages_received_total",
+            "counter",
+            "Messages delivered to the address's queue.",
+            |agent| agent.messages_received,
+        );
+        family(
+            "messages_dropped_total",
+            "counter",
+            "Messages to the address which could not be delivered.",
+            |agent| agent.messages_dropped,
+        );
+        family(
+            "queue_depth",
+            "gauge",
+            "Messages waiting in the address's queue.",
+            |agent| agent.queue_depth as u64,
+        );
+        family(
+            "queue_capacity",
+            "gauge",
+            "Messages the address's queue can hold.",
+            |agent| agent.queue_capacity as u64,
+        );
+
+        let _ = writeln!(
+            output,
+            "# HELP post_haste_delivery_latency_seconds Time senders waited for messages to be placed on the address's queue."
+        );
+        let _ = writeln!(
+            output,
+            "# TYPE post_haste_delivery_latency_seconds histogram"
+        );
+        for (agent, labels) in self.agents.iter().zip(&labels) {
+            let histogram = &agent.delivery_latency;
+            let mut cumulative = 0;
+            for (bound, count) in histogram.buckets() {
+                cumulative += count;
+                let bound = match bound {
+                    Some(bound) => bound.as_secs_f64().to_string(),
+                    None => "+Inf".to_string(),
+                };
+                let _ = writeln!(
+                    output,
+                    "post_haste_delivery_latency_seconds_bucket{{{labels},le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                output,
+                "post_haste_delivery_latency_seconds_sum{{{labels}}} {}",
+                histogram.total().as_secs_f64()
+            );
+            let _ = writeln!(
+                output,
+                "post_haste_delivery_latency_seconds_count{{{labels}}} {cumulative}"
+            );
+        }
+        output
+    }
+}
+
+/// Escape a Prometheus label value
+#[cfg(feature = "prometheus")]
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// The running counters for a single address
+pub(super) struct Counters<A> {
+    address: A,
+    sent: u64,
+    received: u64,
+    dropped: u64,
+    delivery_latency: LatencyHistogram,
+}
+
+impl<A: Copy> Counters<A> {
+    pub(super) fn new(address: A) -> Self {
+        Self {
+            address,
+            sent: 0,
+            received: 0,
+            dropped: 0,
+            delivery_latency: LatencyHistogram::default(),
+        }
+    }
+
+    pub(super) fn address(&self) -> A {
+        self.address
+    }
+
+    pub(super) fn sent(&mut self) {
+        self.sent += 1;
+    }
+
+    pub(super) fn received(&mut self, latency: Duration) {
+        self.received += 1;
+        self.delivery_latency.observe(latency);
+    }
+
+    pub(super) fn dropped(&mut self) {
+        self.dropped += 1;
+    }
+
+    pub(super) fn snapshot(&self, queue_depth: usize, queue_capacity: usize) -> AgentMetrics<A> {
+        AgentMetrics {
+            address: self.address,
+            messages_sent: self.sent,
+            messages_received: self.received,
+            messages_dropped: self.dropped,
+            queue_depth,
+            queue_capacity,
+            delivery_latency: self.delivery_latency,
+        }
+    }
+}
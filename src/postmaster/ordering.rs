@@ -0,0 +1,126 @@
+use core::num::NonZeroU64;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex as BlockingMutex};
+
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use super::Message;
+use crate::address::{AddressIndex, AddressSpace};
+
+/// The number of messages sent so far from a source to a destination, locked by whichever message is being delivered
+type Sequence = Arc<Mutex<u64>>;
+
+/// The sequence numbers of the messages between each pair of addresses, for a Postmaster which keeps messages in order
+pub(super) struct Sequences(BlockingMutex<BTreeMap<(AddressIndex, AddressIndex), Sequence>>);
+
+impl Sequences {
+    pub(super) fn new() -> Self {
+        Self(BlockingMutex::new(BTreeMap::new()))
+    }
+
+    fn sequence<A: AddressSpace>(&self, source: A, destination: A) -> Sequence {
+        self.0
+            .lock()
+            .unwrap()
+            .entry((source.index(), destination.index()))
+            .or_default()
+            .clone()
+    }
+
+    /// Wait until the messages sent earlier from the source to the destination have been delivered.
+    /// tokio's mutex is fair, so turns are given out in the order they are asked for.
+    pub(super) async fn take_turn<A: AddressSpace>(&self, source: A, destination: A) -> Turn {
+        Turn(self.sequence(source, destination).lock_owned().await)
+    }
+
+    /// Forget the sequences of the messages sent from or to the given address, e.g. once it has been deregistered, so that the sequences of addresses which come and go (such as dynamic addresses) don't build up.
+    /// Messages sent between the same addresses later are numbered from 1 again.
+    pub(super) fn forget<A: AddressSpace>(&self, address: A) {
+        self.0.lock().unwrap().retain(|(source, destination), _| {
+            *source != address.index() && *destination != address.index()
+        });
+    }
+
+    /// Take a turn without waiting, or return `None` if a message from the source to the destination is still being delivered
+    pub(super) fn try_take_turn<A: AddressSpace>(&self, source: A, destination: A) -> Option<Turn> {
+        self.sequence(source, destination)
+            .try_lock_owned()
+            .ok()
+            .map(Turn)
+    }
+}
+
+/// A message's place in the order of the messages from its source to its destination.
+/// Later messages wait until the turn is dropped, once the message has been delivered (or has failed to be).
+pub(super) struct Turn(OwnedMutexGuard<u64>);
+
+impl Turn {
+    /// Take the next sequence number, counting from 1
+    pub(super) fn number(&mut self) -> NonZeroU64 {
+        let sequence = NonZeroU64::MIN.saturating_add(*self.0);
+        *self.0 += 1;
+        sequence
+    }
+}
+
+/// The result of checking a message's sequence number with a `SequenceTracker`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sequenced {
+    /// The message follows on from the previous message received from its source (or is the first received from it)
+    InOrder,
+    /// The given number of messages from the source were lost between the previous message and this one, e.g. because they timed out, were refused or were dropped by an interceptor
+    Gap(u64),
+    /// The message is numbered before (or the same as) one already received from its source, which can happen if messages sent to different addresses are redirected to the same Agent
+    OutOfOrder,
+    /// The message has no sequence number, because it is a control message or the Postmaster doesn't keep messages in order
+    Unsequenced,
+}
+
+/// Checks the sequence numbers of the messages an Agent receives, to spot messages from a sender which were lost on the way.
+/// Sequence numbers are only given to messages by a Postmaster configured with `PostmasterConfig::with_ordering()`.
+///
+/// The first message received from each source is taken to be in order, so an Agent which is restarted (or which starts after other Agents have begun sending to its address) isn't told about messages sent before it was listening.
+/// Likewise, a message numbered 1 is always in order, as the numbering starts again once either address has been deregistered.
+///
+/// # Example
+/// ```rust,ignore
+/// let mut sequences = SequenceTracker::new();
+/// loop {
+///     let message = inbox.recv().await.unwrap();
+///     if let Sequenced::Gap(missed) = sequences.check(&message) {
+///         println!("Lost {missed} messages from {:?}", message.source);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SequenceTracker {
+    last: BTreeMap<AddressIndex, u64>,
+}
+
+impl SequenceTracker {
+    /// A tracker which has not yet received any messages
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check a received message's sequence number against the previous message received from its source
+    pub fn check<A: AddressSpace, P>(&mut self, message: &Message<A, P>) -> Sequenced {
+        let Some(sequence) = message.sequence() else {
+            return Sequenced::Unsequenced;
+        };
+        match self.last.insert(message.source.index(), sequence) {
+            _ if sequence == 1 => Sequenced::InOrder,
+            Some(last) if sequence <= last => {
+                self.last.insert(message.source.index(), last);
+                Sequenced::OutOfOrder
+            }
+            Some(last) if sequence > last + 1 => Sequenced::Gap(sequence - last - 1),
+            _ => Sequenced::InOrder,
+        }
+    }
+
+    /// Forget the messages received from a source, so that the next message from it is taken to be in order
+    pub fn forget<A: AddressSpace>(&mut self, source: A) {
+        self.last.remove(&source.index());
+    }
+}
@@ -0,0 +1,216 @@
+use std::sync::Arc;
+
+use super::{Message, PayloadVariant};
+use crate::address::{AddressIndex, AddressSpace};
+
+/// The hook called with each message refused by a `SendPolicy`
+type ViolationHook<A> = Box<dyn Fn(&PolicyViolation<A>) + Send + Sync>;
+
+tokio::task_local! {
+    /// The Agent whose main loop is running in the current task
+    static SENDING_AGENT: Arc<Sender>;
+}
+
+/// A sender which the Postmaster knows of for certain
+#[derive(Debug)]
+enum Sender {
+    /// The Agent at this address, registered with the Postmaster identified by the `usize`
+    Agent(usize, AddressIndex),
+    /// The Postmaster itself, e.g. sending a notification to a watcher, which the policy doesn't apply to
+    Postmaster,
+}
+
+/// Who sent a message, as far as the Postmaster can tell, which decides the address a `SendPolicy` checks it against.
+/// A message sent from outside any Agent's main loop (e.g. from the program's main task, or a task spawned by an Agent) has no known sender, so only the source given by the sender can be checked.
+/// The sender is shared rather than copied into every message, to keep messages small.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Origin(Option<Arc<Sender>>);
+
+impl Origin {
+    /// The origin of a message created in the current task
+    pub(crate) fn current() -> Self {
+        Self(SENDING_AGENT.try_with(Arc::clone).ok())
+    }
+
+    /// The origin of a notification sent by the Postmaster itself
+    pub(crate) fn postmaster() -> Self {
+        Self(Some(Arc::new(Sender::Postmaster)))
+    }
+}
+
+/// Run an Agent's main loop, so that the messages it sends are attributed to the Agent whatever source they give
+pub(crate) fn run_as_agent<F: Future>(
+    postmaster: usize,
+    address: AddressIndex,
+    main_loop: F,
+) -> impl Future<Output = F::Output> {
+    SENDING_AGENT.scope(Arc::new(Sender::Agent(postmaster, address)), main_loop)
+}
+
+/// A message which was refused by a `SendPolicy`, as given to the policy's violation hook
+#[derive(Debug, Clone, Copy)]
+pub struct PolicyViolation<A> {
+    /// The address the message was checked against: the Agent which sent it, if it was sent from an Agent's main loop, otherwise the source given by the sender
+    pub source: A,
+    /// The source given by the sender, which differs from `source` when an Agent sent the message as though it came from another address
+    pub sent_as: A,
+    /// The address the message was sent to
+    pub destination: A,
+    /// The variant of the message's payload, as named by its `PayloadVariant` implementation
+    pub variant: &'static str,
+}
+
+/// The payload variants which a rule allows to be sent
+#[derive(Debug, Clone, Copy)]
+enum Variants {
+    Any,
+    Only(&'static [&'static str]),
+}
+
+/// A single allowance in a `SendPolicy`, where an unset source or destination matches any address
+#[derive(Debug, Clone, Copy)]
+struct Rule {
+    source: Option<AddressIndex>,
+    destination: Option<AddressIndex>,
+    variants: Variants,
+}
+
+impl Rule {
+    fn allows(&self, source: AddressIndex, destination: AddressIndex, variant: &str) -> bool {
+        self.source.is_none_or(|allowed| allowed == source)
+            && self
+                .destination
+                .is_none_or(|allowed| allowed == destination)
+            && match self.variants {
+                Variants::Any => true,
+                Variants::Only(variants) => variants.contains(&variant),
+            }
+    }
+}
+
+/// An allowlist of the messages which may be sent through a Postmaster: which source addresses may send which payload variants to which destinations.
+/// Once a policy is installed with `set_send_policy()`, a message is only delivered if at least one of the policy's rules allows it, and any other message fails to send with `PostmasterError::Forbidden`.
+/// Refused messages are counted as send failures and kept with the other dead letters, and are also passed to the hook set with `on_violation()`, e.g. to log them or raise an alarm.
+///
+/// Payload variants are named by the payload type's `PayloadVariant` implementation, which can be derived with `#[derive(PayloadVariant)]`.
+/// The policy applies to every message sent through the Postmaster, including control messages sent with `as_control()`; only the notifications the Postmaster sends itself (such as those to watchers) are exempt.
+///
+/// A message sent from an Agent's main loop is checked against the address of that Agent, whatever source it gives, so one Agent can't get round the policy by sending as though it were another (e.g. a plugin posing as the Sequencer to drive the Lights).
+/// Any other message, such as one sent from the program's main task or from a task which an Agent has spawned, is checked against the source given by the sender.
+/// The policy is checked before any interceptors are applied, and again if an interceptor redirects the message, against its new destination.
+///
+/// # Example
+/// ```rust,ignore
+/// let policy = SendPolicy::new()
+///     // Only the Sequencer may tell the Lights what to do, and only to turn on or off
+///     .allow(Address::Sequencer, Address::Lights, &["On", "Off"])
+///     // Main is trusted to send anything to anyone
+///     .allow_from(Address::Main)
+///     // Anyone may report to the Logger
+///     .allow_to(Address::Logger)
+///     .on_violation(|violation| eprintln!("Refused {violation:?}"));
+/// postmaster::set_send_policy(policy);
+/// ```
+pub struct SendPolicy<A, P> {
+    rules: Vec<Rule>,
+    variant: fn(&P) -> &'static str,
+    on_violation: Option<ViolationHook<A>>,
+}
+
+impl<A, P: PayloadVariant> SendPolicy<A, P> {
+    /// A policy with no rules, which refuses every message until rules are added to allow them
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            variant: P::variant,
+            on_violation: None,
+        }
+    }
+}
+
+impl<A, P: PayloadVariant> Default for SendPolicy<A, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: AddressSpace, P> SendPolicy<A, P> {
+    /// Allow the source to send payloads of the named variants to the destination
+    pub fn allow(self, source: A, destination: A, variants: &'static [&'static str]) -> Self {
+        self.with_rule(Some(source), Some(destination), Variants::Only(variants))
+    }
+
+    /// Allow the source to send payloads of any variant to the destination
+    pub fn allow_all(self, source: A, destination: A) -> Self {
+        self.with_rule(Some(source), Some(destination), Variants::Any)
+    }
+
+    /// Allow the source to send payloads of any variant to any destination, e.g. for a trusted supervisor
+    pub fn allow_from(self, source: A) -> Self {
+        self.with_rule(Some(source), None, Variants::Any)
+    }
+
+    /// Allow any source to send payloads of any variant to the destination, e.g. for a logger which everyone reports to
+    pub fn allow_to(self, destination: A) -> Self {
+        self.with_rule(None, Some(destination), Variants::Any)
+    }
+
+    /// Set a hook to be called with each message which the policy refuses, replacing any previous hook.
+    /// The hook is called synchronously while the message is being sent, so it should be quick.
+    pub fn on_violation(
+        mut self,
+        hook: impl Fn(&PolicyViolation<A>) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_violation = Some(Box::new(hook));
+        self
+    }
+
+    fn with_rule(mut self, source: Option<A>, destination: Option<A>, variants: Variants) -> Self {
+        self.rules.push(Rule {
+            source: source.map(|source| source.index()),
+            destination: destination.map(|destination| destination.index()),
+            variants,
+        });
+        self
+    }
+
+    /// Check whether a message may be sent to the destination through the given Postmaster, calling the violation hook if it may not
+    pub(super) fn permits(
+        &self,
+        postmaster: usize,
+        destination: A,
+        message: &Message<A, P>,
+    ) -> bool {
+        let source_index = match message.origin.0.as_deref() {
+            Some(Sender::Postmaster) => return true,
+            Some(&Sender::Agent(sender, address)) if sender == postmaster => address,
+            Some(Sender::Agent(..)) | None => message.source.index(),
+        };
+        let variant = (self.variant)(&message.payload);
+        let destination_index = destination.index();
+        if self
+            .rules
+            .iter()
+            .any(|rule| rule.allows(source_index, destination_index, variant))
+        {
+            return true;
+        }
+        if let Some(hook) = &self.on_violation {
+            hook(&PolicyViolation {
+                source: address_at(source_index).unwrap_or(message.source),
+                sent_as: message.source,
+                destination,
+                variant,
+            });
+        }
+        false
+    }
+}
+
+/// The address with the given index, if the address space can construct it
+fn address_at<A: AddressSpace>(index: AddressIndex) -> Option<A> {
+    match index {
+        AddressIndex::Static(index) => A::static_address(index),
+        AddressIndex::Dynamic(id) => A::dynamic(id),
+    }
+}
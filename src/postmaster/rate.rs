@@ -0,0 +1,176 @@
+use tokio::time::{Duration, Instant};
+
+/// What happens when a sender exceeds its rate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitAction {
+    /// Wait until the sender is within its limit again before sending the message.
+    /// `try_send()` cannot wait, so it fails with `PostmasterError::RateLimited` instead.
+    Wait,
+    /// Fail the send with `PostmasterError::RateLimited`
+    Reject,
+}
+
+/// A limit on the rate at which an address may send messages, applied by the Postmaster as a token bucket.
+/// A sender may send a burst of messages at once (by default, just one), after which it can only send at the limited rate.
+///
+/// # Example
+/// ```rust
+/// use post_haste::postmaster::RateLimit;
+/// use std::time::Duration;
+///
+/// // Up to 100 messages per second, in bursts of up to 10, failing any messages over the limit
+/// let limit = RateLimit::per_second(100).with_burst(10).rejecting();
+/// // One message every 5 seconds, waiting for the next opportunity to send
+/// let limit = RateLimit::new(1, Duration::from_secs(5));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    interval: Duration,
+    burst: u32,
+    action: RateLimitAction,
+}
+
+impl RateLimit {
+    /// Allow `messages` messages every `period`.
+    /// Panics if `messages` is zero.
+    pub fn new(messages: u32, period: Duration) -> Self {
+        assert!(messages > 0, "A rate limit must allow at least one message");
+        Self {
+            interval: period / messages,
+            burst: 1,
+            action: RateLimitAction::Wait,
+        }
+    }
+
+    /// Allow `messages` messages per second.
+    /// Panics if `messages` is zero.
+    pub fn per_second(messages: u32) -> Self {
+        Self::new(messages, Duration::from_secs(1))
+    }
+
+    /// Allow bursts of up to `burst` messages to be sent at once
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    /// Fail messages which exceed the limit, rather than waiting
+    pub fn rejecting(mut self) -> Self {
+        self.action = RateLimitAction::Reject;
+        self
+    }
+
+    /// Wait until messages which exceed the limit can be sent (the default)
+    pub fn waiting(mut self) -> Self {
+        self.action = RateLimitAction::Wait;
+        self
+    }
+
+    /// What happens to messages which exceed the limit
+    pub fn action(&self) -> RateLimitAction {
+        self.action
+    }
+}
+
+/// The state of a sender's rate limit
+pub(super) struct Bucket {
+    limit: RateLimit,
+    /// When the bucket will next be full, had no further messages been sent
+    full_at: Instant,
+}
+
+impl Bucket {
+    pub(super) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            full_at: Instant::now(),
+        }
+    }
+
+    pub(super) fn action(&self) -> RateLimitAction {
+        self.limit.action
+    }
+
+    /// Take a token from the bucket for a message sent at `now`, returning when the message may be sent.
+    /// If the message would have to wait and `wait` is false, no token is taken and `None` is returned.
+    pub(super) fn take(&mut self, now: Instant, wait: bool) -> Option<Instant> {
+        let full_at = self.full_at.max(now);
+        // The bucket holds `burst` tokens, so a message may be sent as long as the bucket fills within the time taken to refill all but one of them
+        let tolerance = self.limit.interval * (self.limit.burst - 1);
+        let send_at = full_at
+            .checked_sub(tolerance)
+            .map_or(now, |send_at| send_at.max(now));
+        if send_at > now && !wait {
+            return None;
+        }
+        self.full_at = full_at + self.limit.interval;
+        Some(send_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(start: Instant, millis: u64) -> Instant {
+        start + Duration::from_millis(millis)
+    }
+
+    /// Take as many tokens as the bucket allows at `now` without waiting
+    fn drain(bucket: &mut Bucket, now: Instant) -> usize {
+        core::iter::from_fn(|| bucket.take(now, false)).count()
+    }
+
+    #[test]
+    fn a_full_bucket_allows_a_burst() {
+        let mut bucket = Bucket::new(RateLimit::per_second(10).with_burst(3));
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(bucket.take(start, false), Some(start));
+        }
+        assert_eq!(bucket.take(start, false), None);
+        // A refused message takes no token, so it doesn't delay the next one
+        assert_eq!(bucket.take(millis(start, 99), false), None);
+        assert_eq!(
+            bucket.take(millis(start, 100), false),
+            Some(millis(start, 100))
+        );
+    }
+
+    #[test]
+    fn tokens_refill_at_the_limited_rate_up_to_the_burst() {
+        let mut bucket = Bucket::new(RateLimit::per_second(10).with_burst(3));
+        let start = Instant::now();
+        assert_eq!(drain(&mut bucket, start), 3);
+        assert_eq!(drain(&mut bucket, millis(start, 250)), 2);
+        assert_eq!(drain(&mut bucket, millis(start, 299)), 0);
+        assert_eq!(drain(&mut bucket, millis(start, 300)), 1);
+        // However long the sender is idle, the bucket holds no more than the burst
+        assert_eq!(drain(&mut bucket, millis(start, 60_000)), 3);
+    }
+
+    #[test]
+    fn waiting_messages_are_spaced_by_the_interval() {
+        let mut bucket = Bucket::new(RateLimit::new(1, Duration::from_millis(100)).with_burst(2));
+        let start = Instant::now();
+        let sends: Vec<_> = (0..5).map(|_| bucket.take(start, true).unwrap()).collect();
+        assert_eq!(
+            sends,
+            [0, 0, 100, 200, 300].map(|send_at| millis(start, send_at))
+        );
+        // Messages waiting for the bucket use up its tokens, so a later message queues behind them
+        assert_eq!(bucket.take(millis(start, 150), false), None);
+        assert_eq!(
+            bucket.take(millis(start, 150), true),
+            Some(millis(start, 400))
+        );
+    }
+
+    #[test]
+    fn a_burst_of_zero_allows_one_message() {
+        let mut bucket = Bucket::new(RateLimit::per_second(10).with_burst(0));
+        let start = Instant::now();
+        assert_eq!(drain(&mut bucket, start), 1);
+        assert_eq!(drain(&mut bucket, millis(start, 100)), 1);
+    }
+}
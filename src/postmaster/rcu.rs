@@ -0,0 +1,88 @@
+use core::marker::PhantomData;
+use core::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::thread;
+
+use portable_atomic::{AtomicPtr, AtomicUsize};
+
+/// A value which is read without taking a lock, and changed by publishing a modified copy of it (read-copy-update).
+/// Readers announce themselves on one of two counters, chosen by the current phase, before loading the value.
+/// A writer publishes its copy and then waits for the readers on each counter in turn to finish, flipping the phase before each wait so that new readers don't delay it, before dropping the old value.
+/// Reads should therefore be brief (e.g. looking up and cloning a handle), as writers wait for them.
+pub(super) struct Rcu<T> {
+    value: AtomicPtr<T>,
+    phase: AtomicUsize,
+    readers: [AtomicUsize; 2],
+    writer: Mutex<()>,
+    _value: PhantomData<T>,
+}
+
+// Readers share the value between threads, and writers may drop it on any thread
+unsafe impl<T: Send + Sync> Send for Rcu<T> {}
+unsafe impl<T: Send + Sync> Sync for Rcu<T> {}
+
+/// Marks a reader as finished when dropped, even if the read panics
+struct Reader<'a>(&'a AtomicUsize);
+
+impl Drop for Reader<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<T> Rcu<T> {
+    pub(super) fn new(value: T) -> Self {
+        Self {
+            value: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            phase: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            writer: Mutex::new(()),
+            _value: PhantomData,
+        }
+    }
+
+    /// Read the current value
+    pub(super) fn read<R>(&self, read: impl FnOnce(&T) -> R) -> R {
+        let readers = &self.readers[self.phase.load(Ordering::SeqCst) & 1];
+        readers.fetch_add(1, Ordering::SeqCst);
+        let _reader = Reader(readers);
+        // SAFETY: the value is only dropped by a writer once every reader which could have loaded it has finished
+        read(unsafe { &*self.value.load(Ordering::SeqCst) })
+    }
+
+    /// Change the value by modifying a copy of it, which replaces the value once the modification is complete.
+    /// Writers are serialised, and wait for any readers of the old value to finish before returning.
+    pub(super) fn update<R>(&self, update: impl FnOnce(&mut T) -> R) -> R
+    where
+        T: Clone,
+    {
+        let _writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        // SAFETY: only writers drop the value, and they are serialised
+        let mut value = unsafe { &*self.value.load(Ordering::SeqCst) }.clone();
+        let result = update(&mut value);
+        let old = self
+            .value
+            .swap(Box::into_raw(Box::new(value)), Ordering::SeqCst);
+        // A reader which loaded the old value announced itself beforehand, on one of the counters.
+        // Waiting for each counter to drain (after flipping the phase, so that new readers use the other counter) therefore waits out every such reader.
+        for _ in 0..2 {
+            let phase = self.phase.fetch_xor(1, Ordering::SeqCst) & 1;
+            while self.readers[phase].load(Ordering::Acquire) != 0 {
+                thread::yield_now();
+            }
+        }
+        // SAFETY: the old value is no longer visible to new readers, and every reader which could have loaded it has finished
+        drop(unsafe { Box::from_raw(old) });
+        result
+    }
+}
+
+impl<T> Drop for Rcu<T> {
+    fn drop(&mut self) {
+        // SAFETY: there can be no readers or writers while the Rcu is being dropped
+        drop(unsafe { Box::from_raw(*self.value.get_mut()) });
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use tokio::time::Duration;
+
+use crate::PostmasterError;
+
+/// The longest wait between attempts, unless otherwise configured
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How the Postmaster retries a message sent `with_retry()` which can't be delivered.
+/// The wait before each retry doubles from the initial backoff up to a maximum, and is randomised (by default) so that senders which failed together don't all retry together.
+///
+/// Only failures which may clear up by themselves are retried: a full queue (`Timeout` or `TrySendFailed`), an unregistered or stopped recipient (`NoRecipient` or `ReceiverClosed`, e.g. while waiting for a late-starting Agent), `RateLimited` and `NotAcknowledged`.
+///
+/// # Example
+/// ```rust
+/// use post_haste::postmaster::RetryPolicy;
+/// use std::time::Duration;
+///
+/// // Up to 5 attempts, waiting around 10 ms, 20 ms, 40 ms and then 80 ms between them
+/// let policy = RetryPolicy::exponential(5, Duration::from_millis(10));
+/// // Up to 20 attempts, waiting exactly 100 ms, 200 ms and so on, but never more than a second
+/// let policy = RetryPolicy::exponential(20, Duration::from_millis(100))
+///     .with_max_backoff(Duration::from_secs(1))
+///     .without_jitter();
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+}
+
+impl RetryPolicy {
+    /// Make up to `max_attempts` attempts (including the first) to send the message, waiting `initial_backoff` before the first retry and doubling the wait before each retry after that
+    pub const fn exponential(max_attempts: u32, initial_backoff: Duration) -> Self {
+        Self {
+            max_attempts,
+            initial_backoff,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            jitter: true,
+        }
+    }
+
+    /// Limit the wait between attempts to `max_backoff`
+    pub const fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Wait exactly the backoff between attempts, rather than a random time between half of it and all of it
+    pub const fn without_jitter(mut self) -> Self {
+        self.jitter = false;
+        self
+    }
+
+    /// The wait before a retry, given the number of retries made so far, or `None` if the failure shouldn't be retried
+    pub(super) fn backoff(&self, retries: u32, error: PostmasterError) -> Option<Duration> {
+        let retryable = matches!(
+            error,
+            PostmasterError::Timeout
+                | PostmasterError::TrySendFailed
+                | PostmasterError::NoRecipient
+                | PostmasterError::ReceiverClosed
+                | PostmasterError::RateLimited
+                | PostmasterError::NotAcknowledged
+        );
+        if !retryable || retries.saturating_add(1) >= self.max_attempts {
+            return None;
+        }
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retries))
+            .min(self.max_backoff);
+        if !self.jitter {
+            return Some(backoff);
+        }
+        // Only needs to spread out the retries, so doesn't need to be cryptographically random
+        let random = RandomState::new().build_hasher().finish();
+        let fraction = (random >> 11) as f64 / (1u64 << 53) as f64;
+        Some(backoff.mul_f64(0.5 + fraction / 2.0))
+    }
+}
@@ -0,0 +1,336 @@
+use core::hash::{Hash, Hasher};
+use core::sync::atomic::Ordering;
+use std::hash::DefaultHasher;
+use std::sync::Arc;
+
+use portable_atomic::AtomicUsize;
+use tokio::sync::mpsc::Sender;
+
+use super::Message;
+use super::rcu::Rcu;
+use crate::address::{AddressIndex, RoutingTable};
+use crate::agent::Mailbox;
+
+/// The number of shards the Postmaster's routes are split across
+const SHARDS: usize = 16;
+
+/// Extracts the key used to route a payload to a member of a pool.
+/// When a pool is registered with `PoolRouting::by_key()`, all messages whose payloads have the same key are delivered to the same member of the pool, so that any state associated with the key (e.g. a session) can be kept local to that Agent.
+///
+/// # Example
+/// ```rust
+/// use post_haste::postmaster::RoutingKey;
+///
+/// enum Payloads {
+///     Request { session_id: u64 },
+///     Shutdown,
+/// }
+///
+/// impl RoutingKey for Payloads {
+///     type Key = Option<u64>;
+///
+///     fn routing_key(&self) -> Self::Key {
+///         match self {
+///             Payloads::Request { session_id } => Some(*session_id),
+///             Payloads::Shutdown => None,
+///         }
+///     }
+/// }
+/// ```
+pub trait RoutingKey {
+    /// The type of the key
+    type Key: Hash;
+
+    /// The key for this payload
+    fn routing_key(&self) -> Self::Key;
+}
+
+/// Hashes the routing key of a message
+type KeyHasher<A, P> = fn(&Message<A, P>) -> u64;
+
+/// Determines how messages sent to a pool are dispatched across its members
+pub struct PoolRouting<A, P> {
+    key: Option<KeyHasher<A, P>>,
+}
+
+impl<A, P> PoolRouting<A, P> {
+    /// Dispatch messages across the members of the pool in turn
+    pub fn round_robin() -> Self {
+        Self { key: None }
+    }
+}
+
+impl<A, P: RoutingKey> PoolRouting<A, P> {
+    /// Dispatch messages to the member of the pool chosen by hashing the payload's `RoutingKey`.
+    /// Messages with the same key are always delivered to the same member while it is running.
+    /// If that member stops, only the keys which were routed to it move to another member.
+    pub fn by_key() -> Self {
+        Self {
+            key: Some(hash_routing_key::<A, P>),
+        }
+    }
+}
+
+impl<A, P> Clone for PoolRouting<A, P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<A, P> Copy for PoolRouting<A, P> {}
+
+impl<A, P> Default for PoolRouting<A, P> {
+    fn default() -> Self {
+        Self::round_robin()
+    }
+}
+
+fn hash_routing_key<A, P: RoutingKey>(message: &Message<A, P>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    message.payload.routing_key().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Where the messages sent to an address are delivered
+pub(super) enum Route<A, P> {
+    /// A single message queue, belonging to either an Agent or a standalone receiver
+    Mailbox(Mailbox<Message<A, P>>),
+    /// A pool of identical Agents sharing the address
+    Pool(Pool<A, P>),
+}
+
+impl<A, P> Clone for Route<A, P> {
+    fn clone(&self) -> Self {
+        match self {
+            Route::Mailbox(mailbox) => Route::Mailbox(mailbox.clone()),
+            Route::Pool(pool) => Route::Pool(pool.clone()),
+        }
+    }
+}
+
+impl<A, P> Route<A, P> {
+    /// Every message queue at the route
+    pub(super) fn mailboxes(&self) -> &[Mailbox<Message<A, P>>] {
+        match self {
+            Route::Mailbox(mailbox) => core::slice::from_ref(mailbox),
+            Route::Pool(pool) => &pool.members,
+        }
+    }
+
+    /// Whether the route belongs to an Agent (or a pool of Agents), rather than a standalone message queue
+    pub(super) fn is_agent(&self) -> bool {
+        self.mailboxes()
+            .iter()
+            .all(|mailbox| mailbox.control.is_some())
+    }
+
+    /// The number of messages received by the Agents at the route, which is zero for a standalone message queue
+    pub(super) fn received(&self) -> u64 {
+        self.mailboxes()
+            .iter()
+            .filter_map(|mailbox| mailbox.dequeued.as_ref())
+            .map(|dequeued| dequeued.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Choose the message queue which the message should be delivered to
+    pub(super) fn select(&self, message: &Message<A, P>) -> Option<&Mailbox<Message<A, P>>> {
+        match self {
+            Route::Mailbox(mailbox) => Some(mailbox),
+            Route::Pool(pool) => pool.select(message),
+        }
+    }
+
+    /// The number of messages waiting in the route's queues, and the number of messages the queues can hold
+    pub(super) fn queue_usage(&self) -> (usize, usize) {
+        match self {
+            Route::Mailbox(mailbox) => queue_usage(&mailbox.messages),
+            Route::Pool(pool) => pool
+                .members
+                .iter()
+                .filter(|member| !member.messages.is_closed())
+                .map(|member| queue_usage(&member.messages))
+                .fold(
+                    (0, 0),
+                    |(depth, capacity), (member_depth, member_capacity)| {
+                        (depth + member_depth, capacity + member_capacity)
+                    },
+                ),
+        }
+    }
+}
+
+/// The number of messages waiting in a queue, and the number of messages it can hold
+fn queue_usage<M>(sender: &Sender<M>) -> (usize, usize) {
+    (
+        sender.max_capacity() - sender.capacity(),
+        sender.max_capacity(),
+    )
+}
+
+/// The message queues of the Agents in a pool.
+/// Each member keeps its position in the pool, with a restarted member taking the place of the one it replaces, so that routing by key is unaffected by other members stopping.
+pub(super) struct Pool<A, P> {
+    members: Vec<Mailbox<Message<A, P>>>,
+    next: AtomicUsize,
+    routing: PoolRouting<A, P>,
+}
+
+impl<A, P> Clone for Pool<A, P> {
+    fn clone(&self) -> Self {
+        Self {
+            members: self.members.clone(),
+            next: AtomicUsize::new(self.next.load(Ordering::Relaxed)),
+            routing: self.routing,
+        }
+    }
+}
+
+impl<A, P> Pool<A, P> {
+    pub(super) fn new(members: Vec<Mailbox<Message<A, P>>>, routing: PoolRouting<A, P>) -> Self {
+        Self {
+            members,
+            next: AtomicUsize::new(0),
+            routing,
+        }
+    }
+
+    /// Add a member to the pool, taking the place of a stopped member if there is one
+    pub(super) fn join(&mut self, member: Mailbox<Message<A, P>>) {
+        match self
+            .members
+            .iter_mut()
+            .find(|existing| existing.messages.is_closed())
+        {
+            Some(stopped) => *stopped = member,
+            None => self.members.push(member),
+        }
+    }
+
+    /// Whether any members of the pool are still running
+    pub(super) fn is_running(&self) -> bool {
+        self.members
+            .iter()
+            .any(|member| !member.messages.is_closed())
+    }
+
+    fn select(&self, message: &Message<A, P>) -> Option<&Mailbox<Message<A, P>>> {
+        let count = self.members.len();
+        if count == 0 {
+            return None;
+        }
+        let first = match self.routing.key {
+            Some(key) => (key(message) % count as u64) as usize,
+            None => self.next.fetch_add(1, Ordering::Relaxed) % count,
+        };
+        // Stopped members are skipped over, moving their messages on to the next running member
+        (0..count)
+            .map(|offset| &self.members[(first + offset) % count])
+            .find(|member| !member.messages.is_closed())
+    }
+}
+
+/// A shard of the routes, holding the addresses which map to it.
+/// Each route is shared between the copies of the shard made when it changes, so that only the shard's table is copied.
+type Shard<A, P> = Rcu<RoutingTable<Arc<Route<A, P>>>>;
+
+/// The routes of every registered address, split across shards.
+/// Looking up a route never takes a lock: registering or deregistering an address publishes a new copy of its shard, so senders are never held up by changes to the routes.
+/// Senders wait for space on a queue with their own handle to it, so a full queue never holds up messages to other addresses either.
+/// As each change copies a shard's table (though not its routes), changing the routes is slower than looking them up, particularly with many dynamic addresses.
+pub(super) struct Routes<A, P> {
+    shards: Box<[Shard<A, P>]>,
+}
+
+impl<A, P> Routes<A, P> {
+    pub(super) fn new(static_count: usize) -> Self {
+        Self {
+            shards: (0..SHARDS)
+                .map(|_| Rcu::new(RoutingTable::new(static_count.div_ceil(SHARDS))))
+                .collect(),
+        }
+    }
+
+    /// The shard holding an address, along with the address's index within the shard
+    fn shard(&self, index: AddressIndex) -> (&Shard<A, P>, AddressIndex) {
+        match index {
+            AddressIndex::Static(index) => (
+                &self.shards[index % SHARDS],
+                AddressIndex::Static(index / SHARDS),
+            ),
+            AddressIndex::Dynamic(id) => (
+                &self.shards[(id % SHARDS as u64) as usize],
+                AddressIndex::Dynamic(id),
+            ),
+        }
+    }
+
+    /// Whether a route is registered at the address
+    pub(super) fn contains(&self, index: AddressIndex) -> bool {
+        let (shard, index) = self.shard(index);
+        shard.read(|routes| routes.contains(index))
+    }
+
+    /// Register a route at a vacant address, returning false if the address is taken
+    pub(super) fn insert(&self, index: AddressIndex, route: Route<A, P>) -> bool {
+        self.modify(index, |routes, index| {
+            routes.insert(index, Arc::new(route)).is_ok()
+        })
+    }
+
+    /// Change the routes in an address's shard, publishing the change once it is complete.
+    /// Changes to the routes are serialised, with each change waiting for any lookups of the previous routes to finish.
+    pub(super) fn modify<R>(
+        &self,
+        index: AddressIndex,
+        modify: impl FnOnce(&mut RoutingTable<Arc<Route<A, P>>>, AddressIndex) -> R,
+    ) -> R {
+        let (shard, index) = self.shard(index);
+        shard.update(|routes| modify(routes, index))
+    }
+
+    /// Choose the message queue at the address which the message should be delivered to, returning a handle to it.
+    /// The handle only includes the recipient's control queue if the message is a control message.
+    pub(super) fn select(
+        &self,
+        index: AddressIndex,
+        message: &Message<A, P>,
+    ) -> Option<Mailbox<Message<A, P>>> {
+        let (shard, index) = self.shard(index);
+        shard.read(|routes| {
+            let mailbox = routes.get(index)?.select(message)?;
+            Some(Mailbox {
+                messages: mailbox.messages.clone(),
+                control: mailbox
+                    .control
+                    .as_ref()
+                    .filter(|_| message.control)
+                    .cloned(),
+                paused: None,
+                dequeued: None,
+                pings: None,
+                reconfigurations: None,
+            })
+        })
+    }
+
+    /// Look at the route registered at the address, if any
+    pub(super) fn inspect<R>(
+        &self,
+        index: AddressIndex,
+        inspect: impl FnOnce(Option<&Route<A, P>>) -> R,
+    ) -> R {
+        let (shard, index) = self.shard(index);
+        shard.read(|routes| inspect(routes.get(index).map(|route| &**route)))
+    }
+
+    /// The number of messages waiting at the address, and the number of messages its queues can hold
+    pub(super) fn queue_usage(&self, index: AddressIndex) -> (usize, usize) {
+        let (shard, index) = self.shard(index);
+        shard.read(|routes| {
+            routes
+                .get(index)
+                .map_or((0, 0), |route| route.queue_usage())
+        })
+    }
+}
@@ -0,0 +1,97 @@
+//! Serialisation of messages with serde, enabled with the `serde` feature.
+//! Only the message's envelope and payload are serialised: the state the Postmaster attaches to a message while delivering it (such as when it was placed on a queue) belongs to the process which sent it.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[cfg(not(target_os = "none"))]
+use super::MessageId;
+use super::{CorrelationId, Message};
+
+/// The serialised form of a message, borrowed from the message being serialised
+#[derive(Serialize)]
+#[serde(rename = "Message")]
+struct SerializeMessage<'a, A, P> {
+    source: &'a A,
+    #[cfg(not(target_os = "none"))]
+    destination: &'a Option<A>,
+    #[cfg(not(target_os = "none"))]
+    intended_destination: &'a Option<A>,
+    payload: &'a P,
+    correlation_id: &'a Option<CorrelationId>,
+    reply_to: &'a Option<A>,
+    #[cfg(not(target_os = "none"))]
+    id: &'a Option<MessageId>,
+    #[cfg(not(target_os = "none"))]
+    control: bool,
+}
+
+/// The serialised form of a message, as it is deserialised.
+/// Everything but the source and payload may be left out, e.g. by a sender in another language.
+#[derive(Deserialize)]
+#[serde(rename = "Message")]
+struct DeserializeMessage<A, P> {
+    source: A,
+    #[cfg(not(target_os = "none"))]
+    #[serde(default = "Option::default")]
+    destination: Option<A>,
+    #[cfg(not(target_os = "none"))]
+    #[serde(default = "Option::default")]
+    intended_destination: Option<A>,
+    payload: P,
+    #[serde(default)]
+    correlation_id: Option<CorrelationId>,
+    #[serde(default = "Option::default")]
+    reply_to: Option<A>,
+    #[cfg(not(target_os = "none"))]
+    #[serde(default)]
+    id: Option<MessageId>,
+    #[cfg(not(target_os = "none"))]
+    #[serde(default)]
+    control: bool,
+}
+
+/// Serialises the message's source, destination (once it has been sent), payload, correlation ID and reply-to address, along with (with tokio) the address it was meant for if it was delivered to the fallback address, its ID and whether it is a control message
+impl<A: Serialize, P: Serialize> Serialize for Message<A, P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializeMessage {
+            source: &self.source,
+            #[cfg(not(target_os = "none"))]
+            destination: &self.destination,
+            #[cfg(not(target_os = "none"))]
+            intended_destination: &self.intended_destination,
+            payload: &self.payload,
+            correlation_id: &self.correlation_id,
+            reply_to: &self.reply_to,
+            #[cfg(not(target_os = "none"))]
+            id: &self.id,
+            #[cfg(not(target_os = "none"))]
+            control: self.control,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Deserialises a message as serialised by its `Serialize` implementation, ready to be sent on (e.g. with `Postmaster::forward()`)
+impl<'de, A: Deserialize<'de>, P: Deserialize<'de>> Deserialize<'de> for Message<A, P> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let message = DeserializeMessage::<A, P>::deserialize(deserializer)?;
+        #[cfg(not(target_os = "none"))]
+        return Ok({
+            let mut deserialized = Message::new(message.source, message.payload);
+            deserialized.destination = message.destination;
+            deserialized.intended_destination = message.intended_destination;
+            deserialized.correlation_id = message.correlation_id;
+            deserialized.reply_to = message.reply_to;
+            deserialized.id = message.id;
+            deserialized.control = message.control;
+            deserialized
+        });
+        #[cfg(target_os = "none")]
+        Ok(Message {
+            source: message.source,
+            payload: message.payload,
+            correlation_id: message.correlation_id,
+            reply_to: message.reply_to,
+        })
+    }
+}
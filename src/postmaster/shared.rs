@@ -0,0 +1,80 @@
+use core::fmt::{self, Debug};
+use core::ops::Deref;
+use std::sync::Arc;
+
+/// A payload shared between all of the recipients of a message sent to more than one address, e.g. with `send_to_group()` or `send_matching()`.
+/// Sending to several addresses needs a copy of the payload for each of them, so rather than cloning a large payload (or requiring it to implement `Clone` at all), the payload is wrapped in an `Arc` once and each recipient receives a reference to it.
+/// A payload variant holds the shared payload, e.g. `Payloads::Announcement(SharedPayload<Announcement>)`, and recipients read it through `Deref`.
+///
+/// # Example
+/// ```rust
+/// use post_haste::postmaster::SharedPayload;
+///
+/// struct Announcement {
+///     text: String,
+/// }
+///
+/// let announcement = SharedPayload::new(Announcement { text: "Shutting down".into() });
+/// // Each copy only adds a reference to the same announcement
+/// let copy = announcement.clone();
+/// assert!(SharedPayload::ptr_eq(&announcement, &copy));
+/// assert_eq!(copy.text, "Shutting down");
+/// ```
+pub struct SharedPayload<T>(Arc<T>);
+
+impl<T> SharedPayload<T> {
+    /// Wrap a payload so that it can be shared between recipients
+    pub fn new(payload: T) -> Self {
+        Self(Arc::new(payload))
+    }
+
+    /// Take ownership of the payload if this is the only remaining reference to it (e.g. the other recipients have already dropped their messages), or hand it back otherwise
+    pub fn try_into_inner(self) -> Result<T, Self> {
+        Arc::try_unwrap(self.0).map_err(Self)
+    }
+
+    /// Whether two shared payloads refer to the same payload
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Arc::ptr_eq(&this.0, &other.0)
+    }
+}
+
+impl<T> Clone for SharedPayload<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<T> Deref for SharedPayload<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> From<T> for SharedPayload<T> {
+    fn from(payload: T) -> Self {
+        Self::new(payload)
+    }
+}
+
+impl<T> From<Arc<T>> for SharedPayload<T> {
+    fn from(payload: Arc<T>) -> Self {
+        Self(payload)
+    }
+}
+
+impl<T: Debug> Debug for SharedPayload<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T: PartialEq> PartialEq for SharedPayload<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<T: Eq> Eq for SharedPayload<T> {}
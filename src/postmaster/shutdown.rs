@@ -0,0 +1,81 @@
+use tokio::time::Duration;
+
+use super::Diagnostics;
+use super::drain::DrainReport;
+
+/// The signal which asked the process to shut down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownSignal {
+    /// Ctrl-C was pressed (SIGINT on Unix)
+    Interrupt,
+    /// The process was asked to terminate with SIGTERM, e.g. by a service manager or container runtime (Unix only)
+    Terminate,
+}
+
+/// The outcome of a shutdown started by a signal, with which the program can decide how to exit.
+/// Obtained by calling postmaster::run_until_shutdown()
+#[derive(Debug, Clone)]
+pub struct ShutdownReport<A> {
+    /// The signal which started the shutdown
+    pub signal: ShutdownSignal,
+    /// Whether a second signal arrived while the Postmaster was draining, cutting the drain short
+    pub forced: bool,
+    /// How long the Postmaster ran before the signal arrived
+    pub uptime: Duration,
+    /// How long the Agents took to drain after the signal arrived
+    pub drained_in: Duration,
+    /// How far each Agent got through its queue before the drain finished
+    pub drain: DrainReport<A>,
+    /// The Postmaster's diagnostics once the drain had finished
+    pub diagnostics: Diagnostics,
+}
+
+impl<A> ShutdownReport<A> {
+    /// Whether the shutdown was graceful: every Agent worked through the messages on its queue, without the drain timing out or being cut short by a second signal
+    pub fn is_clean(&self) -> bool {
+        !self.forced && self.drain.is_complete()
+    }
+
+    /// The exit code for the process: 0 after a clean shutdown, and 1 if work was left unfinished
+    pub fn exit_code(&self) -> i32 {
+        if self.is_clean() { 0 } else { 1 }
+    }
+}
+
+/// Listens for the signals which ask the process to shut down
+#[cfg(not(target_family = "wasm"))]
+pub(super) struct Signals {
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Signals {
+    /// Start listening, so that a signal which arrives from now on isn't missed (or left to kill the process)
+    pub(super) fn listen() -> Self {
+        Self {
+            #[cfg(unix)]
+            terminate: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("Failed to listen for SIGTERM"),
+        }
+    }
+
+    /// Wait for the next signal
+    pub(super) async fn next(&mut self) -> ShutdownSignal {
+        let interrupt = async {
+            tokio::signal::ctrl_c()
+                .await
+                .expect("Failed to listen for ctrl-c");
+            ShutdownSignal::Interrupt
+        };
+        #[cfg(unix)]
+        {
+            tokio::select! {
+                signal = interrupt => signal,
+                _ = self.terminate.recv() => ShutdownSignal::Terminate,
+            }
+        }
+        #[cfg(not(unix))]
+        interrupt.await
+    }
+}
@@ -0,0 +1,191 @@
+use core::fmt::Debug;
+use core::pin::Pin;
+use std::collections::BTreeMap;
+
+use super::Postmaster;
+use crate::PostmasterError;
+use crate::address::{AddressIndex, AddressSpace};
+
+/// The registration of an Agent, run once the Agents it depends on are ready
+type Start = Pin<Box<dyn Future<Output = Result<(), PostmasterError>> + Send>>;
+
+type ReadyHook<A> = Box<dyn Fn(A) + Send + Sync>;
+
+/// Why a `Startup` could not start every Agent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StartupError<A> {
+    /// The Agent at `address` depends on an address which isn't part of the startup
+    UnknownDependency {
+        /// The address of the Agent with the dependency
+        address: A,
+        /// The address it depends on
+        dependency: A,
+    },
+    /// The Agents at these addresses can't be started, as they depend on each other in a cycle (or depend on an Agent which does)
+    Cycle(Vec<A>),
+    /// Registering the Agent at `address` failed, e.g. with `PostmasterError::AddressAlreadyTaken`
+    Failed {
+        /// The address of the Agent which failed to start
+        address: A,
+        /// The error returned by its registration
+        error: PostmasterError,
+    },
+}
+
+struct Stage<A> {
+    address: A,
+    dependencies: Vec<A>,
+    start: Start,
+}
+
+/// Starts a system of Agents in the order of their dependencies, and records that order so that the Postmaster's `shutdown()` (and `run_until_shutdown()`) stops them in reverse.
+/// Each Agent is added with its registration (e.g. a call to `register_agent!()` in an async block) and the addresses of the Agents it depends on, and is only registered once all of those Agents are ready.
+/// An Agent is ready once its registration has returned, i.e. once its `create()` has finished and its address accepts messages, so an Agent which needs to set up a connection before others can rely on it should do so in `create()`.
+///
+/// Independent Agents are started in the order they were added.
+/// If an Agent fails to start, the Agents already started are stopped again (in reverse order) and the error is returned.
+///
+/// # Example
+/// ```rust,ignore
+/// postmaster::startup()
+///     .with_agent(Addresses::Storage, [], async {
+///         postmaster::register_agent!(Storage, StorageAgent, config)
+///     })
+///     .with_agent(Addresses::Ingest, [Addresses::Storage], async {
+///         postmaster::register_agent!(Ingest, IngestAgent, ())
+///     })
+///     .on_ready(|address| println!("{address:?} is ready"))
+///     .start()
+///     .await
+///     .unwrap();
+///
+/// // Ingest stops (once it has worked through its queue) before Storage does
+/// let report = postmaster::run_until_shutdown(Duration::from_secs(5)).await;
+/// ```
+pub struct Startup<A, P> {
+    postmaster: Postmaster<A, P>,
+    stages: Vec<Stage<A>>,
+    ready_hooks: Vec<ReadyHook<A>>,
+}
+
+impl<A, P> Startup<A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    /// A startup for Agents registered with the given Postmaster, to which Agents are added with `with_agent()`
+    pub fn new(postmaster: &Postmaster<A, P>) -> Self {
+        Self {
+            postmaster: postmaster.clone(),
+            stages: Vec::new(),
+            ready_hooks: Vec::new(),
+        }
+    }
+
+    /// Add the Agent at the given address, registered by `start` once every Agent in `dependencies` is ready.
+    /// The registration's result is only checked for an error, so any of the registration macros (or a function registering several Agents) can be used.
+    pub fn with_agent<R>(
+        mut self,
+        address: A,
+        dependencies: impl IntoIterator<Item = A>,
+        start: impl Future<Output = Result<R, PostmasterError>> + Send + 'static,
+    ) -> Self {
+        self.stages.push(Stage {
+            address,
+            dependencies: dependencies.into_iter().collect(),
+            start: Box::pin(async move { start.await.map(|_| ()) }),
+        });
+        self
+    }
+
+    /// Call `hook` with the address of each Agent as it becomes ready, before any Agent depending on it is started
+    pub fn on_ready(mut self, hook: impl Fn(A) + Send + Sync + 'static) -> Self {
+        self.ready_hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Start the Agents in the order of their dependencies, returning once every Agent is ready
+    pub async fn start(self) -> Result<(), StartupError<A>> {
+        let phases = phases(&self.stages)?;
+        let addresses: Vec<A> = self.stages.iter().map(|stage| stage.address).collect();
+        let mut stages: Vec<_> = self.stages.into_iter().map(Some).collect();
+        let mut started = Vec::new();
+        for phase in &phases {
+            for stage in phase.iter().filter_map(|&position| stages[position].take()) {
+                if let Err(error) = stage.start.await {
+                    for &address in started.iter().rev() {
+                        let _ = self.postmaster.deregister(address).await;
+                    }
+                    return Err(StartupError::Failed {
+                        address: stage.address,
+                        error,
+                    });
+                }
+                for hook in &self.ready_hooks {
+                    hook(stage.address);
+                }
+                started.push(stage.address);
+            }
+        }
+        // The Agents which were started last are the first to be stopped
+        self.postmaster.set_shutdown_order(
+            phases
+                .iter()
+                .rev()
+                .map(|phase| phase.iter().map(|&position| addresses[position]).collect())
+                .collect(),
+        );
+        Ok(())
+    }
+}
+
+/// Group the stages into phases, each of which only depends on the earlier phases, giving the position of each stage in the order they were added
+fn phases<A: AddressSpace>(stages: &[Stage<A>]) -> Result<Vec<Vec<usize>>, StartupError<A>> {
+    let positions: BTreeMap<AddressIndex, usize> = stages
+        .iter()
+        .enumerate()
+        .map(|(position, stage)| (stage.address.index(), position))
+        .collect();
+    let mut dependencies = Vec::with_capacity(stages.len());
+    for stage in stages {
+        let mut positions_of = Vec::with_capacity(stage.dependencies.len());
+        for dependency in &stage.dependencies {
+            match positions.get(&dependency.index()) {
+                Some(&position) => positions_of.push(position),
+                None => {
+                    return Err(StartupError::UnknownDependency {
+                        address: stage.address,
+                        dependency: *dependency,
+                    });
+                }
+            }
+        }
+        dependencies.push(positions_of);
+    }
+    let mut placed = vec![false; stages.len()];
+    let mut phases: Vec<Vec<usize>> = Vec::new();
+    while placed.contains(&false) {
+        // A stage joins the next phase once all of its dependencies are in earlier phases
+        let phase: Vec<usize> = (0..stages.len())
+            .filter(|&position| {
+                !placed[position]
+                    && dependencies[position]
+                        .iter()
+                        .all(|&dependency| placed[dependency])
+            })
+            .collect();
+        if phase.is_empty() {
+            return Err(StartupError::Cycle(
+                (0..stages.len())
+                    .filter(|&position| !placed[position])
+                    .map(|position| stages[position].address)
+                    .collect(),
+            ));
+        }
+        for &position in &phase {
+            placed[position] = true;
+        }
+        phases.push(phase);
+    }
+    Ok(phases)
+}
@@ -0,0 +1,54 @@
+/// The state of the task running the Agent at an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// The Agent's task (or, for a pool, at least one member's task) is still running
+    Running,
+    /// The Agent's task has finished but the Agent is still registered, e.g. because it panicked and is waiting to be restarted.
+    /// An Agent which isn't restarted is deregistered once its task finishes, so no longer appears in the status.
+    Terminated,
+}
+
+/// The state of a single address, as seen by the Postmaster
+#[derive(Debug, Clone)]
+pub struct AddressStatus<A> {
+    /// The address the status relates to
+    pub address: A,
+    /// Whether a message queue is registered at the address
+    pub registered: bool,
+    /// The state of the task running the Agent at the address, or `None` if no task was spawned by the Postmaster (e.g. for a standalone message queue registered with `register()`)
+    pub task: Option<TaskState>,
+    /// The number of messages currently waiting in the address's queue (summed across the members of a pool)
+    pub queue_depth: usize,
+    /// The maximum number of messages the address's queue can hold (summed across the members of a pool)
+    pub queue_capacity: usize,
+    /// The number of messages the Agent has received from its inbox since it was last started, or `None` for a standalone message queue, whose receiver isn't tracked
+    pub messages_processed: Option<u64>,
+    /// The number of messages sent `with_delay()` to the address which haven't fallen due yet
+    pub delayed_messages: usize,
+    /// The number of timers set with `set_timer()` which are pending for the address
+    pub timers: usize,
+}
+
+impl<A> AddressStatus<A> {
+    /// The status of an address which hasn't been looked up yet
+    pub(super) fn new(address: A) -> Self {
+        Self {
+            address,
+            registered: false,
+            task: None,
+            queue_depth: 0,
+            queue_capacity: 0,
+            messages_processed: None,
+            delayed_messages: 0,
+            timers: 0,
+        }
+    }
+}
+
+/// A snapshot of the state of every address known to the Postmaster, e.g. for an operations console.
+/// Obtained by calling postmaster::status()
+#[derive(Debug, Clone)]
+pub struct Status<A> {
+    /// The status of each address which is registered, has sent or received messages, or has delayed messages pending
+    pub addresses: Vec<AddressStatus<A>>,
+}
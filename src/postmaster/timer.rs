@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use tokio::sync::watch;
+use tokio::time::{self, Instant};
+
+/// The timers pending for an address, by key
+pub(super) type Timers = HashMap<&'static str, Timer>;
+
+/// A timer set with `set_timer()`, which is pending until it fires or is cancelled.
+/// Dropping the timer cancels it.
+pub(super) struct Timer {
+    generation: u64,
+    deadline: watch::Sender<Instant>,
+}
+
+impl Timer {
+    /// Create a timer, returning the receiver to be passed to `wait()` by the task which sends the timer's message
+    pub(super) fn new(generation: u64, deadline: Instant) -> (Self, watch::Receiver<Instant>) {
+        let (deadline, receiver) = watch::channel(deadline);
+        (
+            Self {
+                generation,
+                deadline,
+            },
+            receiver,
+        )
+    }
+
+    /// Distinguishes this timer from earlier and later timers which have been set with the same key
+    pub(super) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Move the timer's deadline
+    pub(super) fn reset(&self, deadline: Instant) {
+        self.deadline.send_replace(deadline);
+    }
+}
+
+/// Wait until a timer's deadline, returning false if the timer is cancelled first
+pub(super) async fn wait(mut deadline: watch::Receiver<Instant>) -> bool {
+    loop {
+        let until = *deadline.borrow_and_update();
+        tokio::select! {
+            () = time::sleep_until(until) => return true,
+            changed = deadline.changed() => {
+                if changed.is_err() {
+                    return false;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use core::fmt::Debug;
+
+use tokio::time::Instant;
+use tracing::Span;
+use tracing::field::Empty;
+
+use super::{Message, PayloadVariant};
+use crate::PostmasterError;
+
+/// Names the variant of a payload
+pub(super) type VariantName<P> = fn(&P) -> &'static str;
+
+pub(super) fn variant_name<P: PayloadVariant>() -> VariantName<P> {
+    P::variant
+}
+
+/// The tracing context carried by a message: the message's span, and when it was sent
+#[derive(Clone)]
+pub(crate) struct MessageTrace {
+    pub(super) span: Span,
+    sent_at: Instant,
+}
+
+impl MessageTrace {
+    pub(super) fn new() -> Self {
+        Self {
+            span: Span::none(),
+            sent_at: Instant::now(),
+        }
+    }
+
+    /// Open the span for a message which is being sent.
+    /// The span is a child of the current span, so a message sent while handling another message (or sent with a delay) is traced as part of the same flow.
+    pub(super) fn start<A: Debug, P>(
+        message: &mut Message<A, P>,
+        destination: &A,
+        variant: Option<VariantName<P>>,
+        postmaster: Option<&'static str>,
+    ) {
+        message.trace = Self {
+            span: tracing::debug_span!(
+                "message",
+                postmaster,
+                source = ?message.source,
+                destination = ?destination,
+                payload = variant.map(|variant| variant(&message.payload)),
+                correlation_id = message.correlation_id.map(|id| id.0),
+                queue_latency_us = Empty,
+                error = Empty,
+            ),
+            sent_at: Instant::now(),
+        };
+    }
+
+    /// Record the outcome of delivering the message
+    pub(super) fn delivered(&self, result: &Result<(), PostmasterError>) {
+        match result {
+            Ok(()) => {
+                self.span.record(
+                    "queue_latency_us",
+                    self.sent_at.elapsed().as_micros() as u64,
+                );
+            }
+            Err(error) => {
+                self.span.record("error", tracing::field::debug(error));
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use core::fmt::{self, Debug};
+
+use crate::PostmasterError;
+
+/// Why a message sent with `try_send_returning()` couldn't be placed on its recipient's queue, handing the payload back where possible so that the sender can keep it (e.g. to retry later, or to shed load some other way).
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TrySendError<P> {
+    /// The recipient's message queue is full
+    Full(P),
+    /// The message couldn't be sent for another reason, e.g. there being no recipient registered at the destination address.
+    /// The payload is handed back, unless the message had already been placed on the recipient's queue when the send failed.
+    Failed(PostmasterError, Option<P>),
+}
+
+impl<P> TrySendError<P> {
+    /// The `PostmasterError` which `try_send()` would have returned, which is `PostmasterError::TrySendFailed` for a full queue
+    pub fn error(&self) -> PostmasterError {
+        match self {
+            Self::Full(_) => PostmasterError::TrySendFailed,
+            Self::Failed(error, _) => *error,
+        }
+    }
+
+    /// Take back the payload of the message, if it wasn't placed on the queue
+    pub fn into_payload(self) -> Option<P> {
+        match self {
+            Self::Full(payload) => Some(payload),
+            Self::Failed(_, payload) => payload,
+        }
+    }
+}
+
+impl<P> Debug for TrySendError<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(_) => f.write_str("Full(..)"),
+            Self::Failed(error, _) => f.debug_tuple("Failed").field(error).finish(),
+        }
+    }
+}
+
+impl<P> From<TrySendError<P>> for PostmasterError {
+    fn from(error: TrySendError<P>) -> Self {
+        error.error()
+    }
+}
+
+/// Why a message sent with `send_returning()` couldn't be delivered, handing the payload back where possible so that the sender can retry, persist or reroute it.
+/// The common causes have their own variants, so that the sender can tell (for example) a recipient which isn't running yet from one which is overwhelmed.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SendError<P> {
+    /// No recipient is registered at the destination address
+    NoRecipient(P),
+    /// The recipient's message queue stayed full until the send timed out
+    Timeout(P),
+    /// The recipient's message queue was full, and the Postmaster's overflow policy is to reject messages rather than wait for space
+    Full(P),
+    /// The recipient stopped before the message could be placed on its queue
+    ReceiverClosed(P),
+    /// The Postmaster is draining (see `postmaster::drain()`), so it only accepts messages sent by Agents, and control messages
+    Draining(P),
+    /// The message couldn't be sent for another reason, e.g. the sender exceeding its rate limit.
+    /// The payload is handed back, unless the message had already been placed on the recipient's queue when the send failed.
+    Failed(PostmasterError, Option<P>),
+}
+
+impl<P> SendError<P> {
+    pub(super) fn new(error: PostmasterError, payload: Option<P>) -> Self {
+        match (error, payload) {
+            (PostmasterError::NoRecipient, Some(payload)) => Self::NoRecipient(payload),
+            (PostmasterError::Timeout, Some(payload)) => Self::Timeout(payload),
+            (PostmasterError::TrySendFailed, Some(payload)) => Self::Full(payload),
+            (PostmasterError::ReceiverClosed, Some(payload)) => Self::ReceiverClosed(payload),
+            (PostmasterError::Draining, Some(payload)) => Self::Draining(payload),
+            (error, payload) => Self::Failed(error, payload),
+        }
+    }
+
+    /// The `PostmasterError` which `send()` would have returned
+    pub fn error(&self) -> PostmasterError {
+        match self {
+            Self::NoRecipient(_) => PostmasterError::NoRecipient,
+            Self::Timeout(_) => PostmasterError::Timeout,
+            Self::Full(_) => PostmasterError::TrySendFailed,
+            Self::ReceiverClosed(_) => PostmasterError::ReceiverClosed,
+            Self::Draining(_) => PostmasterError::Draining,
+            Self::Failed(error, _) => *error,
+        }
+    }
+
+    /// Take back the payload of the message, if it wasn't placed on the queue
+    pub fn into_payload(self) -> Option<P> {
+        match self {
+            Self::NoRecipient(payload)
+            | Self::Timeout(payload)
+            | Self::Full(payload)
+            | Self::ReceiverClosed(payload)
+            | Self::Draining(payload) => Some(payload),
+            Self::Failed(_, payload) => payload,
+        }
+    }
+}
+
+impl<P> Debug for SendError<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoRecipient(_) => f.write_str("NoRecipient(..)"),
+            Self::Timeout(_) => f.write_str("Timeout(..)"),
+            Self::Full(_) => f.write_str("Full(..)"),
+            Self::ReceiverClosed(_) => f.write_str("ReceiverClosed(..)"),
+            Self::Draining(_) => f.write_str("Draining(..)"),
+            Self::Failed(error, _) => f.debug_tuple("Failed").field(error).finish(),
+        }
+    }
+}
+
+impl<P> From<SendError<P>> for PostmasterError {
+    fn from(error: SendError<P>) -> Self {
+        error.error()
+    }
+}
@@ -0,0 +1,59 @@
+use tokio::time::{Duration, Instant};
+
+/// An Agent which has stopped receiving the messages waiting on its queue, as reported by the Postmaster's watchdog.
+/// Started with postmaster::start_watchdog()
+#[derive(Debug, Clone)]
+pub struct AgentStall<A> {
+    /// The address of the stalled Agent (or pool of Agents)
+    pub address: A,
+    /// The number of messages waiting on the Agent's queue
+    pub queue_depth: usize,
+    /// When the Agent was last seen to receive a message (or to have nothing waiting for it), as observed by the watchdog's checks
+    pub last_progress: Instant,
+    /// How long the Agent has gone without receiving a message while messages were waiting for it
+    pub stalled_for: Duration,
+}
+
+/// The watchdog's record of an Agent's progress between checks
+pub(super) struct Progress {
+    /// The number of messages the Agent had received at the last check
+    received: u64,
+    since: Instant,
+    reported: bool,
+}
+
+impl Progress {
+    pub(super) fn new(received: u64, now: Instant) -> Self {
+        Self {
+            received,
+            since: now,
+            reported: false,
+        }
+    }
+
+    /// Update the record with the latest check, returning how long the Agent has been stalled if it has just passed the threshold.
+    /// Each stall is only reported once, until the Agent makes progress again.
+    pub(super) fn check(
+        &mut self,
+        received: u64,
+        queue_depth: usize,
+        now: Instant,
+        stall_after: Duration,
+    ) -> Option<Duration> {
+        // A restarted Agent starts counting again, so any change counts as progress
+        if received != self.received || queue_depth == 0 {
+            *self = Self::new(received, now);
+            return None;
+        }
+        let stalled_for = now.saturating_duration_since(self.since);
+        if self.reported || stalled_for < stall_after {
+            return None;
+        }
+        self.reported = true;
+        Some(stalled_for)
+    }
+
+    pub(super) fn since(&self) -> Instant {
+        self.since
+    }
+}
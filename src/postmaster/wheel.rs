@@ -0,0 +1,252 @@
+use core::array;
+use core::time::Duration;
+
+use tokio::time::Instant;
+
+/// The number of slots in each level of the wheel
+const SLOTS: usize = 64;
+/// The number of bits of a tick which select its slot in a level
+const SLOT_BITS: u32 = SLOTS.trailing_zeros();
+/// Enough levels to hold any tick, with each level spanning 64 times as long as the one below it
+const LEVELS: usize = u64::BITS.div_ceil(SLOT_BITS) as usize;
+
+/// An item in the wheel, along with the exact time it is due
+struct Entry<T> {
+    deadline: Instant,
+    tick: u64,
+    item: T,
+}
+
+/// A hierarchical timer wheel, holding items (e.g. delayed messages) until they are due.
+/// Time is divided into ticks of a millisecond, and each level of the wheel has a slot for each of the next 64 ticks (or groups of ticks) of its span, so inserting an item and finding the next item due take constant time however many items are pending.
+/// An item in an outer level is moved inwards once its slot comes round, until it reaches the innermost level, where it expires at its exact deadline rather than at the end of its tick.
+pub(super) struct TimerWheel<T> {
+    start: Option<Instant>,
+    current: u64,
+    levels: [[Vec<Entry<T>>; SLOTS]; LEVELS],
+    len: usize,
+}
+
+impl<T> TimerWheel<T> {
+    pub(super) fn new() -> Self {
+        Self {
+            start: None,
+            current: 0,
+            levels: array::from_fn(|_| array::from_fn(|_| Vec::new())),
+            len: 0,
+        }
+    }
+
+    /// The number of items which are pending
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Every pending item, in no particular order
+    pub(super) fn iter(&self) -> impl Iterator<Item = &T> {
+        self.levels
+            .iter()
+            .flatten()
+            .flatten()
+            .map(|entry| &entry.item)
+    }
+
+    /// Add an item which is due at the given deadline
+    pub(super) fn insert(&mut self, deadline: Instant, item: T) {
+        // Ticks are counted from the first item inserted, so that they are measured with the same (possibly paused) clock as the deadlines
+        let start = *self.start.get_or_insert(deadline);
+        let tick = u64::try_from(deadline.saturating_duration_since(start).as_millis())
+            .unwrap_or(u64::MAX)
+            .max(self.current);
+        self.len += 1;
+        self.place(Entry {
+            deadline,
+            tick,
+            item,
+        });
+    }
+
+    /// The exact time at which the next item is due, if any are pending
+    pub(super) fn next_deadline(&self) -> Option<Instant> {
+        let (level, slot) = self.first_occupied()?;
+        self.levels[level][slot]
+            .iter()
+            .map(|entry| entry.deadline)
+            .min()
+    }
+
+    /// Remove every item which is due at or before the given time, in order of their deadlines
+    pub(super) fn expire(&mut self, now: Instant) -> Vec<T> {
+        let mut expired = Vec::new();
+        while let Some((level, slot)) = self.first_occupied() {
+            let shift = level as u32 * SLOT_BITS;
+            // The first tick covered by the slot
+            let base = ((self.current >> shift) & !(SLOTS as u64 - 1) | slot as u64) << shift;
+            let entries = core::mem::take(&mut self.levels[level][slot]);
+            if level == 0 {
+                let (mut due, pending): (Vec<_>, Vec<_>) =
+                    entries.into_iter().partition(|entry| entry.deadline <= now);
+                self.levels[0][slot] = pending;
+                if due.is_empty() {
+                    break;
+                }
+                self.current = base;
+                due.sort_by_key(|entry| entry.deadline);
+                self.len -= due.len();
+                expired.extend(due.into_iter().map(|entry| entry.item));
+                if !self.levels[0][slot].is_empty() {
+                    break;
+                }
+            } else if self
+                .start
+                .and_then(|start| start.checked_add(Duration::from_millis(base)))
+                .is_some_and(|slot_start| slot_start <= now)
+            {
+                // The slot has come round, so its items move inwards
+                self.current = base;
+                for entry in entries {
+                    self.place(entry);
+                }
+            } else {
+                self.levels[level][slot] = entries;
+                break;
+            }
+        }
+        expired
+    }
+
+    /// Put an entry in the slot for its tick, relative to the current tick
+    fn place(&mut self, entry: Entry<T>) {
+        let level = match entry.tick ^ self.current {
+            0 => 0,
+            differing => ((u64::BITS - 1 - differing.leading_zeros()) / SLOT_BITS) as usize,
+        };
+        let slot = ((entry.tick >> (level as u32 * SLOT_BITS)) as usize) & (SLOTS - 1);
+        self.levels[level][slot].push(entry);
+    }
+
+    /// The level and slot holding the earliest items.
+    /// Items in an inner level are always due before those in an outer level, and within a level the slots from the current one onwards are in order.
+    fn first_occupied(&self) -> Option<(usize, usize)> {
+        self.levels.iter().enumerate().find_map(|(level, slots)| {
+            let current = ((self.current >> (level as u32 * SLOT_BITS)) as usize) & (SLOTS - 1);
+            (current..SLOTS)
+                .find(|&slot| !slots[slot].is_empty())
+                .map(|slot| (level, slot))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(start: Instant, millis: u64) -> Instant {
+        start + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn items_expire_in_order_across_the_levels() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new();
+        // Deadlines either side of the span of each of the first four levels, inserted out of order
+        let deadlines = [262_145, 4_096, 0, 64, 63, 1, 262_144, 4_095, 65, 262_143];
+        for deadline in deadlines {
+            wheel.insert(millis(start, deadline), deadline);
+        }
+        let mut sorted = deadlines;
+        sorted.sort_unstable();
+        for deadline in sorted {
+            assert_eq!(wheel.next_deadline(), Some(millis(start, deadline)));
+            // Nothing is due a moment early, however far it had to cascade inwards
+            if deadline > 0 {
+                assert!(wheel.expire(millis(start, deadline - 1)).is_empty());
+            }
+            assert_eq!(wheel.expire(millis(start, deadline)), [deadline]);
+        }
+        assert_eq!(wheel.len(), 0);
+        assert_eq!(wheel.next_deadline(), None);
+    }
+
+    #[test]
+    fn slots_wrap_around_as_time_moves_on() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new();
+        wheel.insert(start, 0);
+        assert_eq!(wheel.expire(millis(start, 60)), [0]);
+        // From tick 60, the next few ticks wrap round to the start of the innermost level, and later ones are in the next rotation
+        for deadline in [61, 63, 64, 66, 127, 128, 200] {
+            wheel.insert(millis(start, deadline), deadline);
+        }
+        let mut expired = Vec::new();
+        for now in 61..=200 {
+            let due = wheel.expire(millis(start, now));
+            assert!(due.iter().all(|&deadline| deadline == now));
+            expired.extend(due);
+        }
+        assert_eq!(expired, [61, 63, 64, 66, 127, 128, 200]);
+    }
+
+    #[test]
+    fn items_expire_at_their_exact_deadline() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new();
+        wheel.insert(start, "first");
+        let deadline = start + Duration::from_micros(5_700);
+        wheel.insert(deadline, "within a tick");
+        assert_eq!(wheel.expire(start), ["first"]);
+        assert!(
+            wheel
+                .expire(start + Duration::from_micros(5_200))
+                .is_empty()
+        );
+        assert_eq!(wheel.next_deadline(), Some(deadline));
+        assert_eq!(wheel.expire(deadline), ["within a tick"]);
+    }
+
+    #[test]
+    fn overdue_items_expire_straight_away() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new();
+        wheel.insert(millis(start, 100_000), 1);
+        wheel.insert(start, 0);
+        assert_eq!(wheel.expire(millis(start, 100_000)), [0, 1]);
+        // An item inserted with a deadline which has already passed is placed in the current tick
+        wheel.insert(millis(start, 50), 2);
+        assert_eq!(wheel.next_deadline(), Some(millis(start, 50)));
+        assert_eq!(wheel.expire(millis(start, 100_000)), [2]);
+    }
+
+    #[test]
+    fn many_items_expire_as_they_fall_due() {
+        let start = Instant::now();
+        let mut wheel = TimerWheel::new();
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let mut pending = Vec::new();
+        for _ in 0..2_000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            // Spread over about 5 minutes, so that items land on every level up to the fourth
+            let deadline = seed % 300_000;
+            wheel.insert(millis(start, deadline), deadline);
+            pending.push(deadline);
+        }
+        pending.sort_unstable();
+        let mut now = 0;
+        let mut step = 1;
+        while !pending.is_empty() {
+            let due = pending.partition_point(|&deadline| deadline <= now);
+            assert_eq!(wheel.expire(millis(start, now)), pending[..due]);
+            pending.drain(..due);
+            assert_eq!(wheel.len(), pending.len());
+            assert_eq!(
+                wheel.next_deadline(),
+                pending.first().map(|&deadline| millis(start, deadline))
+            );
+            // Uneven steps, so that some expiries cross several slots (or levels) at once
+            now += step;
+            step = step % 997 + 37;
+        }
+    }
+}
@@ -0,0 +1,16 @@
+//! The seeded random number generator behind the `simulation` and `chaos` features, so that a run can be repeated exactly from its seed.
+
+/// The SplitMix64 generator, which is small, fast and entirely determined by its seed
+pub(crate) fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Whether an event with the given probability happens, drawing a number from the generator only if it might
+pub(crate) fn chance(state: &mut u64, probability: f64) -> bool {
+    // The top 53 bits give a uniformly distributed f64 in [0, 1)
+    probability > 0.0 && ((next_random(state) >> 11) as f64 / (1u64 << 53) as f64) < probability
+}
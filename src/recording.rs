@@ -0,0 +1,301 @@
+//! Recording of the messages sent through a Postmaster, for replaying them into another system or drawing them as sequence diagrams.
+//! Enabled with the `recording` feature.
+
+use core::fmt::Debug;
+use core::ops::RangeBounds;
+
+use tokio::time::{self, Duration, Instant};
+
+use crate::PostmasterError;
+use crate::address::AddressSpace;
+use crate::postmaster::{CorrelationId, Message, Postmaster};
+
+/// A message recorded as it was delivered, along with its destination and when it was sent
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Envelope<A, P> {
+    /// The address which sent the message
+    pub source: A,
+    /// The address which the message was delivered to
+    pub destination: A,
+    /// The payload of the message
+    pub payload: P,
+    /// The flow the message belonged to, if it had a correlation ID
+    pub correlation_id: Option<CorrelationId>,
+    /// The address to which replies to the message were to be sent, if the sender set one
+    pub reply_to: Option<A>,
+    /// When the message was sent, relative to the start of the recording
+    pub timestamp: Duration,
+}
+
+/// The log held by the Postmaster while a `Recorder` is running
+pub(crate) struct Recording<A, P> {
+    started: Instant,
+    copy: fn(&P) -> P,
+    envelopes: Vec<Envelope<A, P>>,
+}
+
+impl<A, P> Recording<A, P> {
+    /// Copy a message which is about to be sent, so that it can be logged once it has been delivered
+    pub(crate) fn envelope(&self, destination: A, message: &Message<A, P>) -> Envelope<A, P>
+    where
+        A: Copy,
+    {
+        Envelope {
+            source: message.source,
+            destination,
+            payload: (self.copy)(&message.payload),
+            correlation_id: message.correlation_id,
+            reply_to: message.reply_to,
+            timestamp: self.started.elapsed(),
+        }
+    }
+
+    pub(crate) fn log(&mut self, envelope: Envelope<A, P>) {
+        self.envelopes.push(envelope);
+    }
+}
+
+/// Records every message delivered by a Postmaster, so that the traffic can be inspected or replayed later with a `Replayer`.
+/// Only messages which are successfully delivered are recorded, in the order in which they were delivered.
+/// Recording requires the payload type to implement `Clone`, as each payload is copied before it is delivered.
+///
+/// The fields of each `Envelope` are public, so a recording can be converted into a project's own format in order to be saved, e.g. to capture traffic from a deployed system.
+///
+/// # Example
+/// ```rust,ignore
+/// let recorder = Recorder::start(postmaster::instance());
+/// // Let the system run...
+/// let envelopes = recorder.stop();
+/// ```
+pub struct Recorder<A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    postmaster: Postmaster<A, P>,
+}
+
+impl<A, P> Recorder<A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Clone + Send + 'static,
+{
+    /// Start recording the messages delivered by the given Postmaster.
+    /// If the Postmaster was already being recorded, the previous recording is discarded.
+    pub fn start(postmaster: &Postmaster<A, P>) -> Self {
+        postmaster.set_recording(Some(Recording {
+            started: Instant::now(),
+            copy: P::clone,
+            envelopes: Vec::new(),
+        }));
+        Self {
+            postmaster: postmaster.clone(),
+        }
+    }
+
+    /// A copy of the messages recorded so far, leaving the recording running
+    pub fn envelopes(&self) -> Vec<Envelope<A, P>> {
+        self.postmaster
+            .with_recording(|recording| recording.envelopes.clone())
+            .unwrap_or_default()
+    }
+
+    /// Stop recording, returning the recorded messages
+    pub fn stop(self) -> Vec<Envelope<A, P>> {
+        self.postmaster
+            .set_recording(None)
+            .map(|recording| recording.envelopes)
+            .unwrap_or_default()
+    }
+}
+
+impl<A, P> Drop for Recorder<A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    fn drop(&mut self) {
+        self.postmaster.set_recording(None);
+    }
+}
+
+/// Sends recorded messages into a Postmaster, e.g. to reproduce a bug by replaying the traffic from a deployed system.
+/// Each message is sent from its original source to its original destination (with its original correlation ID and reply-to address), in the order it was recorded.
+/// By default messages are sent one after the other as quickly as possible; `with_timing()` preserves the spacing between them instead.
+///
+/// To test a single Agent in isolation, register only that Agent and use `only_to()` to replay just the messages which were delivered to it.
+///
+/// # Example
+/// ```rust,ignore
+/// let postmaster = Postmaster::<Address, Payloads>::new();
+/// post_haste::spawn_agent!(&postmaster, Address::Sequencer, SequencerAgent, ()).unwrap();
+/// Replayer::new(envelopes)
+///     .only_to(Address::Sequencer)
+///     .run(&postmaster)
+///     .await
+///     .unwrap();
+/// ```
+pub struct Replayer<A, P> {
+    envelopes: Vec<Envelope<A, P>>,
+    destination: Option<A>,
+    timing: bool,
+}
+
+impl<A, P> Replayer<A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    /// Create a replayer for the given recorded messages
+    pub fn new(envelopes: impl IntoIterator<Item = Envelope<A, P>>) -> Self {
+        Self {
+            envelopes: envelopes.into_iter().collect(),
+            destination: None,
+            timing: false,
+        }
+    }
+
+    /// Only replay the messages which were delivered to the given address
+    pub fn only_to(mut self, destination: A) -> Self {
+        self.destination = Some(destination);
+        self
+    }
+
+    /// Send each message at the same time (relative to the start of the replay) as it was sent in the recording
+    pub fn with_timing(mut self) -> Self {
+        self.timing = true;
+        self
+    }
+
+    /// Send the recorded messages into the given Postmaster, returning the number of messages sent.
+    /// Stops at the first message which could not be sent, returning the error.
+    pub async fn run(self, postmaster: &Postmaster<A, P>) -> Result<usize, PostmasterError> {
+        let started = Instant::now();
+        let mut sent = 0;
+        for envelope in self.envelopes {
+            if let Some(destination) = self.destination
+                && destination.index() != envelope.destination.index()
+            {
+                continue;
+            }
+            if self.timing {
+                time::sleep_until(started + envelope.timestamp).await;
+            }
+            let mut message =
+                postmaster.message(envelope.destination, envelope.source, envelope.payload);
+            if let Some(correlation_id) = envelope.correlation_id {
+                message = message.with_correlation_id(correlation_id);
+            }
+            if let Some(reply_to) = envelope.reply_to {
+                message = message.reply_to(reply_to);
+            }
+            message.send().await?;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+}
+
+/// The formats in which a sequence diagram can be written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagramFormat {
+    /// A [Mermaid](https://mermaid.js.org/syntax/sequenceDiagram.html) sequence diagram, which can be embedded in Markdown
+    Mermaid,
+    /// A [PlantUML](https://plantuml.com/sequence-diagram) sequence diagram
+    PlantUml,
+}
+
+/// Write recorded messages as a sequence diagram, e.g. to document or review the protocol between a set of Agents.
+/// Only the messages sent within the given window (relative to the start of the recording) are included, so `..` includes every message and `Duration::from_secs(5)..Duration::from_secs(10)` includes just those sent in that period.
+/// Each address is a participant in the diagram, in the order in which they first appear, and each message is labelled with its payload (both using their `Debug` representations).
+///
+/// # Example
+/// ```rust,ignore
+/// let recorder = Recorder::start(postmaster::instance());
+/// // Let the system run...
+/// println!("{}", sequence_diagram(&recorder.stop(), .., DiagramFormat::Mermaid));
+/// ```
+pub fn sequence_diagram<A: Debug, P: Debug>(
+    envelopes: &[Envelope<A, P>],
+    window: impl RangeBounds<Duration>,
+    format: DiagramFormat,
+) -> String {
+    let envelopes: Vec<_> = envelopes
+        .iter()
+        .filter(|envelope| window.contains(&envelope.timestamp))
+        .collect();
+
+    // Participants are given plain identifiers, as addresses (e.g. dynamic addresses) may contain characters which the formats don't allow
+    let mut participants: Vec<String> = Vec::new();
+    let mut participant = |address: &A| {
+        let name = format!("{address:?}");
+        match participants.iter().position(|existing| *existing == name) {
+            Some(index) => index,
+            None => {
+                participants.push(name);
+                participants.len() - 1
+            }
+        }
+    };
+    let messages: Vec<_> = envelopes
+        .iter()
+        .map(|envelope| {
+            (
+                participant(&envelope.source),
+                participant(&envelope.destination),
+                format!("{:?}", envelope.payload),
+            )
+        })
+        .collect();
+
+    let mut diagram = String::new();
+    match format {
+        DiagramFormat::Mermaid => {
+            diagram.push_str("sequenceDiagram\n");
+            for (index, name) in participants.iter().enumerate() {
+                diagram.push_str(&format!(
+                    "    participant P{index} as {}\n",
+                    mermaid_text(name)
+                ));
+            }
+            for (source, destination, payload) in messages {
+                diagram.push_str(&format!(
+                    "    P{source}->>P{destination}: {}\n",
+                    mermaid_text(&payload)
+                ));
+            }
+        }
+        DiagramFormat::PlantUml => {
+            diagram.push_str("@startuml\n");
+            for (index, name) in participants.iter().enumerate() {
+                diagram.push_str(&format!(
+                    "participant \"{}\" as P{index}\n",
+                    name.replace('"', "'")
+                ));
+            }
+            for (source, destination, payload) in messages {
+                diagram.push_str(&format!(
+                    "P{source} -> P{destination} : {}\n",
+                    payload.replace('\n', "\\n")
+                ));
+            }
+            diagram.push_str("@enduml\n");
+        }
+    }
+    diagram
+}
+
+/// Escape the characters which Mermaid treats specially in participant names and message text
+fn mermaid_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '#' => escaped.push_str("#35;"),
+            ';' => escaped.push_str("#59;"),
+            '\n' => escaped.push_str("<br>"),
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
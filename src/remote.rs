@@ -0,0 +1,465 @@
+//! A bridge between the Postmasters of two processes, so that an Agent system can be split across machines without changing the Agents.
+//! Enabled with the `remote` feature.
+
+use core::fmt::Debug;
+#[cfg(unix)]
+use std::fs;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc::{Receiver, channel};
+use tokio::sync::{Mutex, oneshot};
+use tokio::task::{self, JoinHandle, JoinSet};
+
+use crate::PostmasterError;
+use crate::address::{AddressSpace, NodeAddress};
+use crate::postmaster::{CorrelationId, Message, Postmaster};
+
+#[cfg(feature = "quic")]
+mod quic;
+#[cfg(feature = "quic")]
+pub use quic::{QuicSender, QuicTransport};
+
+/// The number of messages which can be waiting to be sent to each remote address before senders have to wait
+const BRIDGE_QUEUE_SIZE: usize = 64;
+
+/// The largest encoded message a transport accepts from the other end, unless configured otherwise with `with_max_frame_length()`.
+/// A longer frame fails the connection rather than being allocated, so a corrupt or malicious length can't exhaust the process's memory.
+pub const DEFAULT_MAX_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+
+/// A message on its way across a bridge, as encoded and decoded by a `Codec`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RemoteMessage<A, P> {
+    /// The address which sent the message
+    pub source: A,
+    /// The address which the message is being sent to
+    pub destination: A,
+    /// The payload of the message
+    pub payload: P,
+    /// The flow the message belongs to, if it has a correlation ID
+    pub correlation_id: Option<CorrelationId>,
+    /// The address to which replies to the message should be sent, if the sender set one
+    pub reply_to: Option<A>,
+}
+
+/// Converts messages to and from the bytes sent across a bridge.
+/// Post-haste does not depend on any particular serialisation format, so the project provides the codec, e.g. using serde (with the `serde` feature, which implements `Serialize` and `Deserialize` for `RemoteMessage`) with the format of its choice.
+/// Both ends of a bridge must use compatible codecs, and must agree on the meaning of each address.
+pub trait Codec<A, P>: Send + Sync + 'static {
+    /// Encode a message which is to be sent to the remote Postmaster
+    fn encode(&self, message: &RemoteMessage<A, P>) -> Vec<u8>;
+
+    /// Decode a message received from the remote Postmaster, returning `None` if the bytes are not a valid message.
+    /// A message which cannot be decoded closes the connection, as the two ends no longer agree on the format.
+    fn decode(&self, bytes: &[u8]) -> Option<RemoteMessage<A, P>>;
+}
+
+/// Connects a Postmaster to the Postmaster of another process (over TCP, or a `LocalListener` for a process on the same machine), so that Agents on either side can send messages to each other as if they were local.
+/// Each address hosted by the other process is mapped to the bridge with `remote()`.
+/// When the bridge is attached to a connection, it registers itself at each of these addresses, so that messages sent to them are encoded and shipped across the connection.
+/// Messages arriving from the other process are sent on into the local Postmaster from their original source, with their original correlation ID and reply-to address, so replies are routed back across the bridge.
+///
+/// The bridge runs over any `Transport`, and `attach()` runs it over a single connection with a `StreamTransport`.
+/// A message which arrives for a local address without a recipient is dropped, just as if it had been sent locally.
+///
+/// # Example
+/// ```rust,ignore
+/// // On the frontend, where the storage Agent is hosted by the backend
+/// let stream = tokio::net::TcpStream::connect("backend:7000").await?;
+/// let bridge = Bridge::new(postmaster::instance(), JsonCodec)
+///     .remote(Address::Storage)
+///     .attach(stream)
+///     .await
+///     .unwrap();
+///
+/// // On the backend, where the display Agent is hosted by the frontend
+/// let (stream, _) = listener.accept().await?;
+/// let bridge = Bridge::new(postmaster::instance(), JsonCodec)
+///     .remote(Address::Display)
+///     .attach(stream)
+///     .await
+///     .unwrap();
+/// ```
+pub struct Bridge<A, P, C>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    postmaster: Postmaster<A, P>,
+    codec: C,
+    remote: Vec<A>,
+}
+
+impl<A, P, C> Bridge<A, P, C>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+    C: Codec<A, P>,
+{
+    /// Create a bridge for the given Postmaster, which encodes messages with the given codec
+    pub fn new(postmaster: &Postmaster<A, P>, codec: C) -> Self {
+        Self {
+            postmaster: postmaster.clone(),
+            codec,
+            remote: Vec::new(),
+        }
+    }
+
+    /// Map the given address to the other process, so that messages sent to it are shipped across the bridge
+    pub fn remote(mut self, address: A) -> Self {
+        self.remote.push(address);
+        self
+    }
+
+    /// Register the bridge at each of its remote addresses, and start exchanging messages over the given connection (such as a `tokio::net::TcpStream`).
+    /// Messages to every remote address share the connection, so this is equivalent to calling `attach_transport()` with a `StreamTransport`.
+    /// To accept messages longer than `DEFAULT_MAX_FRAME_LENGTH`, call `attach_transport()` with a `StreamTransport` configured with `with_max_frame_length()` instead.
+    pub async fn attach<S>(self, connection: S) -> Result<BridgeHandle, PostmasterError>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        self.attach_transport(StreamTransport::new(connection))
+            .await
+    }
+
+    /// Register the bridge at each of its remote addresses, and start exchanging messages through the given transport.
+    /// Fails if any of the remote addresses has already been taken, in which case none of them are registered.
+    /// The remote addresses are deregistered again when the transport closes or fails, after which messages sent to them fail with `PostmasterError::NoRecipient`.
+    pub async fn attach_transport<T: Transport>(
+        self,
+        transport: T,
+    ) -> Result<BridgeHandle, PostmasterError> {
+        let mut mailboxes = Vec::with_capacity(self.remote.len());
+        for &address in &self.remote {
+            let (sender, receiver) = channel(BRIDGE_QUEUE_SIZE);
+            if let Err(error) = self.postmaster.register(address, sender).await {
+                for &(registered, _) in &mailboxes {
+                    let _ = self.postmaster.deregister(registered).await;
+                }
+                return Err(error);
+            }
+            mailboxes.push((address, receiver));
+        }
+
+        let (shutdown, stop) = oneshot::channel();
+        let postmaster = self.postmaster;
+        let codec = Arc::new(self.codec);
+        let remote = self.remote;
+        let task = task::spawn(async move {
+            let mut forwarders = JoinSet::new();
+            let result = run(
+                &postmaster,
+                codec,
+                transport,
+                mailboxes,
+                &mut forwarders,
+                stop,
+            )
+            .await;
+            // Stop forwarding, then free the remote addresses
+            forwarders.shutdown().await;
+            for address in remote {
+                let _ = postmaster.deregister(address).await;
+            }
+            result
+        });
+        Ok(BridgeHandle { task, shutdown })
+    }
+}
+
+impl<N, A, P, C> Bridge<NodeAddress<N, A>, P, C>
+where
+    N: AddressSpace + Debug + Send + Sync + 'static,
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+    C: Codec<NodeAddress<N, A>, P>,
+{
+    /// Map every static address on the given node to the other process, so that the bridge carries all of the traffic for that node.
+    /// The other process may be the node itself, or another bridge further along the way to it.
+    pub fn remote_node(mut self, node: N) -> Self {
+        self.remote.extend(
+            (0..A::COUNT)
+                .filter_map(A::static_address)
+                .map(|address| NodeAddress::new(node, address)),
+        );
+        self
+    }
+}
+
+/// A bridge which has been attached to a connection, returned by `Bridge::attach()`.
+/// Dropping the handle leaves the bridge running until the connection closes.
+pub struct BridgeHandle {
+    task: JoinHandle<io::Result<()>>,
+    shutdown: oneshot::Sender<()>,
+}
+
+impl BridgeHandle {
+    /// Wait for the connection to close, returning the error which closed it (if any)
+    pub async fn join(self) -> io::Result<()> {
+        let Self { task, shutdown } = self;
+        // Keep the bridge running until the connection closes
+        let _shutdown = shutdown;
+        task.await
+            .unwrap_or_else(|error| Err(io::Error::other(error)))
+    }
+
+    /// Close the connection and deregister the bridge's remote addresses
+    pub async fn close(self) -> io::Result<()> {
+        let _ = self.shutdown.send(());
+        self.task
+            .await
+            .unwrap_or_else(|error| Err(io::Error::other(error)))
+    }
+}
+
+/// Carries encoded messages between the two ends of a bridge.
+/// The bridge opens a separate `FrameSender` for each of its remote addresses, so a transport which multiplexes independent streams over one connection can give each destination its own stream.
+/// That way a slow destination doesn't hold up the messages to any other.
+///
+/// `QuicTransport` (with the `quic` feature) does this, opening a unidirectional QUIC stream in `open_sender()`, and reading frames from every stream opened by the other side in `receive()`.
+/// `StreamTransport` is the transport for a single ordered byte stream, such as a TCP connection, over which the messages to every destination are interleaved.
+pub trait Transport: Send + 'static {
+    /// Sends the encoded messages to a single remote address
+    type Sender: FrameSender;
+
+    /// Open a sender for the messages to one of the bridge's remote addresses
+    fn open_sender(&mut self) -> impl Future<Output = io::Result<Self::Sender>> + Send;
+
+    /// Receive the next encoded message from the other end, or `None` once the other end has closed the connection
+    fn receive(&mut self) -> impl Future<Output = io::Result<Option<Vec<u8>>>> + Send;
+}
+
+/// Sends the encoded messages to one remote address, as opened by `Transport::open_sender()`
+pub trait FrameSender: Send + 'static {
+    /// Send an encoded message, which must arrive at the other end as a whole
+    fn send(&mut self, frame: Vec<u8>) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+/// A transport over a single ordered byte stream, such as a `tokio::net::TcpStream`.
+/// Each message is sent as a 32 bit big-endian length, followed by the bytes produced by the `Codec`.
+/// As the messages to every remote address share the stream, a message which is slow to send holds up those behind it.
+///
+/// A message longer than the maximum frame length (`DEFAULT_MAX_FRAME_LENGTH` unless set with `with_max_frame_length()`) fails the connection with `io::ErrorKind::InvalidData`.
+/// The connection is only closed cleanly if the other end closes it in between messages; closing it part way through a message fails with `io::ErrorKind::UnexpectedEof`.
+pub struct StreamTransport<S> {
+    reader: ReadHalf<S>,
+    writer: Arc<Mutex<WriteHalf<S>>>,
+    max_frame_length: usize,
+}
+
+impl<S: AsyncRead + AsyncWrite> StreamTransport<S> {
+    /// Create a transport over the given connection
+    pub fn new(connection: S) -> Self {
+        let (reader, writer) = tokio::io::split(connection);
+        Self {
+            reader,
+            writer: Arc::new(Mutex::new(writer)),
+            max_frame_length: DEFAULT_MAX_FRAME_LENGTH,
+        }
+    }
+
+    /// Accept messages of up to the given length (in bytes, once encoded) from the other end, rather than `DEFAULT_MAX_FRAME_LENGTH`
+    pub fn with_max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Send + 'static> Transport for StreamTransport<S> {
+    type Sender = StreamSender<S>;
+
+    async fn open_sender(&mut self) -> io::Result<Self::Sender> {
+        Ok(StreamSender {
+            writer: self.writer.clone(),
+        })
+    }
+
+    async fn receive(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut length = [0; 4];
+        // The connection has only been closed cleanly if it ends before the first byte of a message
+        if self.reader.read(&mut length[..1]).await? == 0 {
+            return Ok(None);
+        }
+        self.reader.read_exact(&mut length[1..]).await?;
+        let length = frame_length(length, self.max_frame_length)?;
+        let mut frame = vec![0; length];
+        self.reader.read_exact(&mut frame).await?;
+        Ok(Some(frame))
+    }
+}
+
+/// The length of the message announced by a frame's length prefix, failing if it is longer than the maximum
+fn frame_length(prefix: [u8; 4], max_frame_length: usize) -> io::Result<usize> {
+    let length = u32::from_be_bytes(prefix) as usize;
+    if length > max_frame_length {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Received a message of {length} bytes, longer than the maximum of {max_frame_length}"
+            ),
+        ));
+    }
+    Ok(length)
+}
+
+/// Writes encoded messages to a `StreamTransport`, holding the stream while each message is written so that messages are never interleaved
+pub struct StreamSender<S> {
+    writer: Arc<Mutex<WriteHalf<S>>>,
+}
+
+impl<S: AsyncWrite + Send + 'static> FrameSender for StreamSender<S> {
+    async fn send(&mut self, frame: Vec<u8>) -> io::Result<()> {
+        let length = u32::try_from(frame.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Encoded message is too large to send",
+            )
+        })?;
+        let mut writer = self.writer.lock().await;
+        writer.write_u32(length).await?;
+        writer.write_all(&frame).await?;
+        writer.flush().await
+    }
+}
+
+/// Listens for bridge connections from co-located processes on a Unix domain socket, which avoids the overhead of the network stack.
+/// Any stale socket left at the path by a process which exited without cleaning up is replaced, and the socket is removed when the listener is dropped.
+/// The other process connects with `tokio::net::UnixStream::connect()`, and both ends pass their stream to `Bridge::attach()`.
+///
+/// On Windows, tokio's named pipes (`tokio::net::windows::named_pipe`) can be passed to `Bridge::attach()` in the same way.
+///
+/// # Example
+/// ```rust,ignore
+/// // In the supervising process, which hosts everything except the driver
+/// let listener = LocalListener::bind("/run/app/driver.sock")?;
+/// let bridge = Bridge::new(postmaster::instance(), Codec)
+///     .remote(Address::Driver)
+///     .attach(listener.accept().await?)
+///     .await
+///     .unwrap();
+///
+/// // In the driver's process
+/// let stream = tokio::net::UnixStream::connect("/run/app/driver.sock").await?;
+/// let bridge = Bridge::new(postmaster::instance(), Codec)
+///     .remote(Address::Controller)
+///     .attach(stream)
+///     .await
+///     .unwrap();
+/// ```
+#[cfg(unix)]
+pub struct LocalListener {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl LocalListener {
+    /// Listen on the Unix domain socket at the given path.
+    /// Fails if something other than a socket already exists at the path.
+    pub fn bind(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        Ok(Self {
+            listener: UnixListener::bind(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Wait for the next process to connect
+    pub async fn accept(&self) -> io::Result<UnixStream> {
+        let (stream, _) = self.listener.accept().await?;
+        Ok(stream)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for LocalListener {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Forward the messages sent to the remote addresses, and deliver the messages arriving from the other end, until the transport closes or fails
+async fn run<A, P, C, T>(
+    postmaster: &Postmaster<A, P>,
+    codec: Arc<C>,
+    mut transport: T,
+    mailboxes: Vec<(A, Receiver<Message<A, P>>)>,
+    forwarders: &mut JoinSet<io::Result<()>>,
+    stop: oneshot::Receiver<()>,
+) -> io::Result<()>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+    C: Codec<A, P>,
+    T: Transport,
+{
+    for (destination, mut mailbox) in mailboxes {
+        let codec = codec.clone();
+        let mut sender = transport.open_sender().await?;
+        forwarders.spawn(async move {
+            while let Some(message) = mailbox.recv().await {
+                let frame = codec.encode(&RemoteMessage {
+                    source: message.source,
+                    destination,
+                    payload: message.payload,
+                    correlation_id: message.correlation_id,
+                    reply_to: message.reply_to,
+                });
+                sender.send(frame).await?;
+            }
+            Ok(())
+        });
+    }
+
+    let receive = async {
+        while let Some(frame) = transport.receive().await? {
+            let message = codec.decode(&frame).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Failed to decode a message from the remote Postmaster",
+                )
+            })?;
+            let mut builder =
+                postmaster.message(message.destination, message.source, message.payload);
+            if let Some(correlation_id) = message.correlation_id {
+                builder = builder.with_correlation_id(correlation_id);
+            }
+            if let Some(reply_to) = message.reply_to {
+                builder = builder.reply_to(reply_to);
+            }
+            // A message which can't be delivered is dropped, as it would have been had it been sent locally
+            let _ = builder.send().await;
+        }
+        Ok(())
+    };
+    // Dropping the bridge's handle doesn't stop it
+    let stop = async {
+        if stop.await.is_err() {
+            core::future::pending::<()>().await;
+        }
+    };
+    tokio::pin!(receive, stop);
+    loop {
+        tokio::select! {
+            result = &mut receive => return result,
+            () = &mut stop => return Ok(()),
+            // A forwarder which finishes cleanly was deregistered, but messages may still arrive for the others
+            Some(finished) = forwarders.join_next() => {
+                if let Ok(Err(error)) = finished {
+                    return Err(error);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! A `Transport` running a bridge over QUIC (built on quinn), with a stream of its own for the messages to each remote address.
+//! Enabled with the `quic` feature.
+
+use core::sync::atomic::Ordering;
+use std::io;
+use std::sync::Arc;
+
+use portable_atomic::AtomicUsize;
+use quinn::{Connection, ConnectionError, ReadError, ReadExactError, RecvStream, SendStream};
+use tokio::sync::mpsc::{Receiver, Sender, channel};
+use tokio::task::{self, AbortHandle, JoinSet};
+
+use super::{DEFAULT_MAX_FRAME_LENGTH, FrameSender, Transport, frame_length};
+
+/// The number of messages received from the other end which can be waiting to be delivered before the streams they arrive on are held up
+const RECEIVE_QUEUE_SIZE: usize = 64;
+
+/// A transport over a QUIC connection, such as one made with `quinn::Endpoint::connect()` or accepted with `quinn::Endpoint::accept()`.
+/// The messages to each remote address are sent on a unidirectional stream of their own, so a destination which is slow to take its messages (or a lost packet) only holds up the messages to that destination.
+/// Each message is sent as a 32 bit big-endian length, followed by the bytes produced by the `Codec`, as with `StreamTransport`.
+/// As with `StreamTransport`, a message longer than the maximum frame length (`DEFAULT_MAX_FRAME_LENGTH` unless set with `with_max_frame_length()`) fails the connection with `io::ErrorKind::InvalidData`.
+///
+/// The streams opened by the other end are read from as soon as the transport is created, so it must be created from within a tokio runtime.
+/// The connection is left open when the bridge closes, so that whoever made it can close it with an error code of their choosing.
+///
+/// # Example
+/// ```rust,ignore
+/// // On the frontend, where the storage and archive Agents are hosted by the backend
+/// let connection = endpoint.connect(backend, "backend")?.await?;
+/// let bridge = Bridge::new(postmaster::instance(), JsonCodec)
+///     .remote(Address::Storage)
+///     .remote(Address::Archive)
+///     .attach_transport(QuicTransport::new(connection))
+///     .await
+///     .unwrap();
+/// ```
+pub struct QuicTransport {
+    connection: Connection,
+    frames: Receiver<io::Result<Vec<u8>>>,
+    readers: AbortHandle,
+    /// Shared with the tasks reading the streams, which start before the maximum can be configured
+    max_frame_length: Arc<AtomicUsize>,
+}
+
+impl QuicTransport {
+    /// Create a transport over the given connection, and start reading the streams opened by the other end
+    pub fn new(connection: Connection) -> Self {
+        let (sender, frames) = channel(RECEIVE_QUEUE_SIZE);
+        let max_frame_length = Arc::new(AtomicUsize::new(DEFAULT_MAX_FRAME_LENGTH));
+        let readers = task::spawn(accept_streams(
+            connection.clone(),
+            sender,
+            max_frame_length.clone(),
+        ))
+        .abort_handle();
+        Self {
+            connection,
+            frames,
+            readers,
+            max_frame_length,
+        }
+    }
+
+    /// Accept messages of up to the given length (in bytes, once encoded) from the other end, rather than `DEFAULT_MAX_FRAME_LENGTH`
+    pub fn with_max_frame_length(self, max_frame_length: usize) -> Self {
+        self.max_frame_length
+            .store(max_frame_length, Ordering::Relaxed);
+        self
+    }
+}
+
+impl Drop for QuicTransport {
+    fn drop(&mut self) {
+        self.readers.abort();
+    }
+}
+
+impl Transport for QuicTransport {
+    type Sender = QuicSender;
+
+    async fn open_sender(&mut self) -> io::Result<Self::Sender> {
+        Ok(QuicSender {
+            stream: self.connection.open_uni().await?,
+        })
+    }
+
+    async fn receive(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.frames.recv().await.transpose()
+    }
+}
+
+/// Writes encoded messages to the QUIC stream belonging to one remote address.
+/// The stream is finished when the sender is dropped.
+pub struct QuicSender {
+    stream: SendStream,
+}
+
+impl FrameSender for QuicSender {
+    async fn send(&mut self, frame: Vec<u8>) -> io::Result<()> {
+        let length = u32::try_from(frame.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Encoded message is too large to send",
+            )
+        })?;
+        self.stream.write_all(&length.to_be_bytes()).await?;
+        self.stream.write_all(&frame).await?;
+        Ok(())
+    }
+}
+
+/// Read the messages from each stream opened by the other end, until the connection closes or fails
+async fn accept_streams(
+    connection: Connection,
+    frames: Sender<io::Result<Vec<u8>>>,
+    max_frame_length: Arc<AtomicUsize>,
+) {
+    let mut readers = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = connection.accept_uni() => match accepted {
+                Ok(stream) => {
+                    readers.spawn(read_frames(stream, frames.clone(), max_frame_length.clone()));
+                }
+                Err(error) if closed(&error) => break,
+                Err(error) => {
+                    let _ = frames.send(Err(error.into())).await;
+                    return;
+                }
+            },
+            Some(_) = readers.join_next() => {}
+        }
+    }
+    // The transport is closed once the messages which had already arrived on each stream have been read
+    drop(frames);
+    while readers.join_next().await.is_some() {}
+}
+
+/// Read the messages from one stream, until it finishes or fails
+async fn read_frames(
+    mut stream: RecvStream,
+    frames: Sender<io::Result<Vec<u8>>>,
+    max_frame_length: Arc<AtomicUsize>,
+) {
+    loop {
+        let max_frame_length = max_frame_length.load(Ordering::Relaxed);
+        let (frame, last) = match read_frame(&mut stream, max_frame_length).await {
+            Ok(Some(frame)) => (Ok(frame), false),
+            Ok(None) => return,
+            Err(error) => (Err(error), true),
+        };
+        if frames.send(frame).await.is_err() || last {
+            return;
+        }
+    }
+}
+
+/// Read the next message from a stream, or `None` if the stream (or the connection) was closed in between messages
+async fn read_frame(
+    stream: &mut RecvStream,
+    max_frame_length: usize,
+) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    match stream.read_exact(&mut length).await {
+        Ok(()) => {}
+        Err(ReadExactError::FinishedEarly(0)) => return Ok(None),
+        Err(ReadExactError::ReadError(ReadError::ConnectionLost(error))) if closed(&error) => {
+            return Ok(None);
+        }
+        Err(error) => return Err(read_failed(error)),
+    }
+    let mut frame = vec![0; frame_length(length, max_frame_length)?];
+    stream.read_exact(&mut frame).await.map_err(read_failed)?;
+    Ok(Some(frame))
+}
+
+/// Whether the connection was closed deliberately by either end, rather than failing
+fn closed(error: &ConnectionError) -> bool {
+    matches!(
+        error,
+        ConnectionError::ApplicationClosed(_) | ConnectionError::LocallyClosed
+    )
+}
+
+fn read_failed(error: ReadExactError) -> io::Error {
+    match error {
+        ReadExactError::FinishedEarly(_) => io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Stream finished part way through a message",
+        ),
+        ReadExactError::ReadError(error) => error.into(),
+    }
+}
@@ -0,0 +1,352 @@
+//! Delayed messages which survive restarts, for schedules running over hours or days.
+//! Enabled with the `persistence` feature.
+//!
+//! A message sent with `with_delay()` only exists in the memory of the process, so it is lost if the process restarts before its delay has elapsed.
+//! Each message sent through a `Schedule` is instead appended to a journal file along with the absolute time at which it is due, and is only marked as done once it has been sent.
+//! When the process restarts, the messages still in the journal are found again when the schedule is reopened, and `rearm()` sends each of them at its original time (or straight away, if that time passed while the process was stopped).
+//! The due times are measured with the system clock, so that they still hold after a restart, and a message may be sent more than once if the process stopped just after sending it.
+//!
+//! Post-haste does not depend on any particular serialisation format, so the project provides a `ScheduleCodec` to convert messages to and from the bytes stored in the journal.
+
+use core::fmt::Debug;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tokio::task::{self, AbortHandle};
+use tokio::time::{self, Duration};
+
+use crate::PostmasterError;
+use crate::address::AddressSpace;
+use crate::postmaster::Postmaster;
+
+/// A journal record of a message which has been scheduled
+const RECORD_PENDING: u8 = 1;
+/// A journal record of a message which has been sent or cancelled
+const RECORD_DONE: u8 = 2;
+
+/// A message held in the schedule until it is due
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledMessage<A, P> {
+    /// The address the message is being sent to
+    pub destination: A,
+    /// The address which sent the message
+    pub source: A,
+    /// The payload of the message
+    pub payload: P,
+}
+
+/// Converts the messages held in the schedule to and from the bytes stored in its journal
+pub trait ScheduleCodec<A, P>: Send + Sync + 'static {
+    /// Encode a message to be stored in the journal
+    fn encode(&self, message: &ScheduledMessage<A, P>) -> Vec<u8>;
+
+    /// Decode a message stored in the journal, or return `None` if it is invalid (in which case it is discarded)
+    fn decode(&self, bytes: &[u8]) -> Option<ScheduledMessage<A, P>>;
+}
+
+/// Identifies a message in the schedule, so that it can be cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ScheduleId(u64);
+
+/// A message in the journal which hasn't been sent yet
+struct Pending {
+    due: SystemTime,
+    bytes: Vec<u8>,
+}
+
+/// The journal file, along with the messages in it which are still pending and the tasks waiting to send them
+struct Journal {
+    file: File,
+    pending: BTreeMap<u64, Pending>,
+    armed: HashMap<u64, AbortHandle>,
+    next_sequence: u64,
+}
+
+impl Journal {
+    async fn append(
+        &mut self,
+        kind: u8,
+        sequence: u64,
+        due: SystemTime,
+        bytes: &[u8],
+    ) -> io::Result<()> {
+        self.file
+            .write_all(&record(kind, sequence, due, bytes)?)
+            .await?;
+        self.file.flush().await?;
+        self.file.sync_data().await
+    }
+}
+
+struct Inner<A, P, C> {
+    postmaster: Postmaster<A, P>,
+    codec: C,
+    path: PathBuf,
+    journal: Mutex<Journal>,
+}
+
+/// A persistent schedule of delayed messages, which are sent at the time they are due even if the process has restarted in the meantime.
+/// The schedule can be cloned to share it between Agents.
+///
+/// # Example
+/// ```rust,ignore
+/// let schedule = Schedule::open(postmaster::instance(), "reminders.schedule", ReminderCodec).await?;
+/// // Once the Agents have been registered, re-arm the messages left over from before a restart
+/// schedule.rearm().await;
+///
+/// let id = schedule.send_after(Address::Reminders, Address::Booking, Payloads::Remind(booking), Duration::from_secs(24 * 60 * 60)).await?;
+/// // If the booking is cancelled...
+/// schedule.cancel(id).await?;
+/// ```
+pub struct Schedule<A, P, C> {
+    inner: Arc<Inner<A, P, C>>,
+}
+
+impl<A, P, C> Clone for Schedule<A, P, C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<A, P, C> Schedule<A, P, C>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+    C: ScheduleCodec<A, P>,
+{
+    /// Open the schedule stored in the journal at the given path, creating it if it doesn't exist.
+    /// Any messages which were still pending when the journal was last used are kept, to be re-armed with `rearm()`, and the journal is compacted so that it only holds those messages.
+    pub async fn open(
+        postmaster: &Postmaster<A, P>,
+        path: impl AsRef<Path>,
+        codec: C,
+    ) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let journal = match fs::read(&path).await {
+            Ok(journal) => journal,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error),
+        };
+        let pending = read_pending(&journal);
+        let next_sequence = pending
+            .keys()
+            .next_back()
+            .map_or(0, |sequence| sequence + 1);
+
+        // Write the compacted journal alongside the old one, so that a crash while compacting leaves the old journal intact
+        let mut compacted = Vec::new();
+        for (&sequence, message) in &pending {
+            compacted.extend_from_slice(&record(
+                RECORD_PENDING,
+                sequence,
+                message.due,
+                &message.bytes,
+            )?);
+        }
+        let compacted_path = path.with_extension("compacting");
+        let mut file = File::create(&compacted_path).await?;
+        file.write_all(&compacted).await?;
+        file.sync_all().await?;
+        drop(file);
+        fs::rename(&compacted_path, &path).await?;
+        let file = OpenOptions::new().append(true).open(&path).await?;
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                postmaster: postmaster.clone(),
+                codec,
+                path,
+                journal: Mutex::new(Journal {
+                    file,
+                    pending,
+                    armed: HashMap::new(),
+                    next_sequence,
+                }),
+            }),
+        })
+    }
+
+    /// The path of the schedule's journal
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    /// Schedule a message to be sent once the delay has elapsed from now
+    pub async fn send_after(
+        &self,
+        destination: A,
+        source: A,
+        payload: P,
+        delay: Duration,
+    ) -> Result<ScheduleId, PostmasterError> {
+        self.send_at(destination, source, payload, SystemTime::now() + delay)
+            .await
+    }
+
+    /// Schedule a message to be sent at the given time, or straight away if it has already passed.
+    /// The message is written to the journal before this returns, so it will still be sent if the process restarts in the meantime (as long as the schedule is re-armed).
+    /// Fails with `PostmasterError::ScheduleFailed` if the message couldn't be written to the journal, in which case it has not been scheduled.
+    pub async fn send_at(
+        &self,
+        destination: A,
+        source: A,
+        payload: P,
+        due: SystemTime,
+    ) -> Result<ScheduleId, PostmasterError> {
+        let message = ScheduledMessage {
+            destination,
+            source,
+            payload,
+        };
+        let bytes = self.inner.codec.encode(&message);
+        let mut journal = self.inner.journal.lock().await;
+        let sequence = journal.next_sequence;
+        journal
+            .append(RECORD_PENDING, sequence, due, &bytes)
+            .await
+            .map_err(|_| PostmasterError::ScheduleFailed)?;
+        journal.next_sequence += 1;
+        journal.pending.insert(sequence, Pending { due, bytes });
+        // The lock is held until the message has been armed, so that a message which is due straight away can find itself
+        self.arm(&mut journal, sequence, due, message);
+        Ok(ScheduleId(sequence))
+    }
+
+    /// Arm every pending message which isn't already armed, e.g. those left over from before the process restarted, returning the number armed.
+    /// This should be called once the messages' recipients have been registered, as messages which fell due while the process was stopped are sent straight away.
+    pub async fn rearm(&self) -> usize {
+        let mut journal = self.inner.journal.lock().await;
+        let unarmed: Vec<u64> = journal
+            .pending
+            .keys()
+            .copied()
+            .filter(|sequence| !journal.armed.contains_key(sequence))
+            .collect();
+        let mut armed = 0;
+        for sequence in unarmed {
+            let Pending { due, bytes } = &journal.pending[&sequence];
+            let due = *due;
+            match self.inner.codec.decode(bytes) {
+                Some(message) => {
+                    self.arm(&mut journal, sequence, due, message);
+                    armed += 1;
+                }
+                None => {
+                    journal.pending.remove(&sequence);
+                    let _ = journal.append(RECORD_DONE, sequence, due, &[]).await;
+                }
+            }
+        }
+        armed
+    }
+
+    /// Cancel a scheduled message, returning false if it has already been sent (or cancelled).
+    /// Fails with `PostmasterError::ScheduleFailed` if the cancellation couldn't be written to the journal, in which case the message is no longer armed but will be re-armed after a restart.
+    pub async fn cancel(&self, id: ScheduleId) -> Result<bool, PostmasterError> {
+        let mut journal = self.inner.journal.lock().await;
+        if let Some(armed) = journal.armed.remove(&id.0) {
+            armed.abort();
+        }
+        let Some(message) = journal.pending.remove(&id.0) else {
+            return Ok(false);
+        };
+        journal
+            .append(RECORD_DONE, id.0, message.due, &[])
+            .await
+            .map_err(|_| PostmasterError::ScheduleFailed)?;
+        Ok(true)
+    }
+
+    /// The number of messages which haven't been sent yet
+    pub async fn pending(&self) -> usize {
+        self.inner.journal.lock().await.pending.len()
+    }
+
+    /// Spawn a task which sends the message once it is due, and then marks it as done
+    fn arm(
+        &self,
+        journal: &mut Journal,
+        sequence: u64,
+        due: SystemTime,
+        message: ScheduledMessage<A, P>,
+    ) {
+        let remaining = due
+            .duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO);
+        let deadline = time::Instant::now() + remaining;
+        let schedule = self.clone();
+        let armed = task::spawn(async move {
+            time::sleep_until(deadline).await;
+            // As with delayed messages, there is no one to report a failure to
+            let _ = schedule
+                .inner
+                .postmaster
+                .send(message.destination, message.source, message.payload)
+                .await;
+            let mut journal = schedule.inner.journal.lock().await;
+            journal.armed.remove(&sequence);
+            if journal.pending.remove(&sequence).is_some() {
+                let _ = journal.append(RECORD_DONE, sequence, due, &[]).await;
+            }
+        });
+        journal.armed.insert(sequence, armed.abort_handle());
+    }
+}
+
+/// Encode a journal record: its kind, the message's sequence number, the time the message is due (in milliseconds since the Unix epoch), and the length of the encoded message followed by the message itself
+fn record(kind: u8, sequence: u64, due: SystemTime, bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let length = u32::try_from(bytes.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Message too large"))?;
+    let due = due
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |due| u64::try_from(due.as_millis()).unwrap_or(u64::MAX));
+    let mut record = Vec::with_capacity(bytes.len() + 21);
+    record.push(kind);
+    record.extend_from_slice(&sequence.to_be_bytes());
+    record.extend_from_slice(&due.to_be_bytes());
+    record.extend_from_slice(&length.to_be_bytes());
+    record.extend_from_slice(bytes);
+    Ok(record)
+}
+
+/// Find the messages in a journal which were never marked as done.
+/// Reading stops at the first incomplete or invalid record, which is left by a crash part-way through writing it.
+fn read_pending(mut journal: &[u8]) -> BTreeMap<u64, Pending> {
+    let mut pending = BTreeMap::new();
+    while let Some((&kind, rest)) = journal.split_first()
+        && let Some((sequence, rest)) = rest.split_first_chunk::<8>()
+        && let Some((due, rest)) = rest.split_first_chunk::<8>()
+        && let Some((length, rest)) = rest.split_first_chunk::<4>()
+        && let Ok(length) = usize::try_from(u32::from_be_bytes(*length))
+        && rest.len() >= length
+    {
+        let sequence = u64::from_be_bytes(*sequence);
+        let due = UNIX_EPOCH + Duration::from_millis(u64::from_be_bytes(*due));
+        let (bytes, rest) = rest.split_at(length);
+        match kind {
+            RECORD_PENDING => {
+                pending.insert(
+                    sequence,
+                    Pending {
+                        due,
+                        bytes: bytes.to_vec(),
+                    },
+                );
+            }
+            RECORD_DONE => {
+                pending.remove(&sequence);
+            }
+            _ => break,
+        }
+        journal = rest;
+    }
+    pending
+}
@@ -0,0 +1,121 @@
+//! Deterministic simulation of a system of Agents.
+//! Enabled with the `simulation` feature.
+
+use core::fmt::Debug;
+
+use tokio::time::{self, Duration};
+
+use crate::postmaster::Postmaster;
+use crate::random::next_random;
+
+/// Drives message delivery for a Postmaster in a deterministic, seeded order.
+/// While a simulation is running, every message sent through the Postmaster is held rather than being delivered straight away.
+/// Each call to `step()` then lets the Agents run until they are idle, picks one of the held messages using a random number generator seeded with the simulation's seed, and delivers it.
+/// As the order of delivery depends only on the seed, an interleaving of messages which causes a failure can be replayed exactly by running the simulation again with the same seed.
+///
+/// The simulation relies on tokio's clock being paused (e.g. with `#[tokio::test(start_paused = true)]`, which requires tokio's `test-util` feature) in order to detect when the Agents are idle.
+/// Delayed messages are held once their delay has elapsed, so the clock must be advanced for them to be delivered.
+/// Whilst messages are held, sending always succeeds: any failure to deliver a message is instead counted in the Postmaster's diagnostics as a send failure.
+///
+/// Dropping the simulation returns the Postmaster to delivering messages immediately, discarding any messages which have not yet been delivered.
+///
+/// # Example
+/// ```rust,ignore
+/// #[tokio::test(start_paused = true)]
+/// async fn button_press_during_cross_ending() {
+///     for seed in 0..100 {
+///         let postmaster = Postmaster::<Addresses, Payloads>::new();
+///         let mut simulation = Simulation::new(&postmaster, seed);
+///         // Register Agents and send the initial messages...
+///         simulation.run(1000).await;
+///         // Assert on the final state, reporting the seed on failure...
+///     }
+/// }
+/// ```
+pub struct Simulation<A, P>
+where
+    A: crate::AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    postmaster: Postmaster<A, P>,
+    seed: u64,
+    state: u64,
+    delivered: usize,
+}
+
+impl<A, P> Simulation<A, P>
+where
+    A: crate::AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    /// Start simulating the given Postmaster, with delivery order determined by the seed
+    pub fn new(postmaster: &Postmaster<A, P>, seed: u64) -> Self {
+        postmaster.set_simulating(true);
+        Self {
+            postmaster: postmaster.clone(),
+            seed,
+            state: seed,
+            delivered: 0,
+        }
+    }
+
+    /// The seed the simulation was started with
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The number of messages delivered by the simulation so far
+    pub fn delivered(&self) -> usize {
+        self.delivered
+    }
+
+    /// The number of messages currently awaiting delivery
+    pub fn pending(&self) -> usize {
+        self.postmaster.simulated_pending()
+    }
+
+    /// Let the Agents run until they are idle, then deliver one of the messages awaiting delivery.
+    /// Returns `false` if there were no messages to deliver.
+    pub async fn step(&mut self) -> bool {
+        settle().await;
+        let Some((destination, message)) = self
+            .postmaster
+            .take_simulated(|count| (next_random(&mut self.state) % count as u64) as usize)
+        else {
+            return false;
+        };
+        let _ = self
+            .postmaster
+            .deliver_simulated(destination, message)
+            .await;
+        self.delivered += 1;
+        settle().await;
+        true
+    }
+
+    /// Deliver messages until there are none awaiting delivery, or until `max_steps` messages have been delivered (as Agents may exchange messages indefinitely).
+    /// Returns the number of messages delivered.
+    pub async fn run(&mut self, max_steps: usize) -> usize {
+        let mut steps = 0;
+        while steps < max_steps && self.step().await {
+            steps += 1;
+        }
+        steps
+    }
+}
+
+impl<A, P> Drop for Simulation<A, P>
+where
+    A: crate::AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    fn drop(&mut self) {
+        self.postmaster.set_simulating(false);
+    }
+}
+
+/// Wait until every other task is idle.
+/// With tokio's clock paused, the clock is only advanced to this timer once no other task has work to do.
+async fn settle() {
+    time::sleep(Duration::from_nanos(1)).await;
+}
@@ -0,0 +1,425 @@
+//! Spooling to disk the messages which overflow an Agent's message queue, so that a burst larger than the queue (or than memory) is neither dropped nor left blocking its senders.
+//! Enabled with the `spillover` feature.
+//!
+//! Once a `Spillover` is opened for a Postmaster configured with `OverflowPolicy::Spill`, a message sent to a full queue is appended to a spool file for its destination instead, and the send succeeds straight away.
+//! The spooled messages are streamed back onto the queue, in the order they were sent, as the Agent catches up.
+//! While an address has messages spooled, any further messages sent to it join the end of the spool, so that they don't overtake the messages already waiting there.
+//! Only the number of messages spooled is kept in memory, so a spool can hold far more messages than the queue.
+//!
+//! A spool is a buffer rather than a journal: it isn't synced to disk, and the spool files left over from a previous run are discarded when a `Spillover` is opened.
+//! Messages which must survive a restart should be sent through an `Outbox` (with the `outbox` feature) instead.
+//! A message sent `with_ack()` waits for space on the queue rather than being spooled, as its acknowledgement can't be written to disk.
+//!
+//! Post-haste does not depend on any particular serialisation format, so the project provides a `SpillCodec` to convert messages to and from the bytes stored in the spool.
+
+use core::fmt::Debug;
+use core::num::NonZeroU64;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as BlockingMutex};
+
+use portable_atomic::{AtomicUsize, Ordering};
+use tokio::sync::mpsc::Sender;
+use tokio::time::Instant;
+
+use crate::PostmasterError;
+use crate::address::{AddressIndex, AddressSpace};
+use crate::postmaster::{CorrelationId, Message, MessageId, Postmaster};
+
+/// The extension of the spool files in a spillover's directory
+const SPOOL_EXTENSION: &str = "spool";
+
+/// A spooled message has an ID
+const FLAG_ID: u8 = 1;
+/// A spooled message has a correlation ID
+const FLAG_CORRELATION_ID: u8 = 2;
+/// A spooled message has a sequence number
+const FLAG_SEQUENCE: u8 = 4;
+
+/// The length of the header written before each message in a spool: the flags, then the message's ID, correlation ID and sequence number
+const HEADER_LEN: usize = 1 + 3 * 8;
+
+/// The parts of a message which are converted to bytes by a `SpillCodec`.
+/// The message's ID, correlation ID and sequence number are stored in the spool alongside the bytes, so they don't need to be encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpilledMessage<A, P> {
+    /// The address which sent the message
+    pub source: A,
+    /// The address to which replies should be sent, if the sender set one with `reply_to()`
+    pub reply_to: Option<A>,
+    /// The payload of the message
+    pub payload: P,
+}
+
+/// Converts the messages spooled by a `Spillover` to and from the bytes stored in its spool files
+pub trait SpillCodec<A, P>: Send + Sync + 'static {
+    /// Encode a message to be stored in a spool
+    fn encode(&self, message: &SpilledMessage<A, P>) -> Vec<u8>;
+
+    /// Decode a message stored in a spool, or return `None` if it is invalid (in which case it is discarded)
+    fn decode(&self, bytes: &[u8]) -> Option<SpilledMessage<A, P>>;
+}
+
+/// The number of messages a `Spillover` has handled so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpillStats {
+    /// The number of messages written to a spool
+    pub spooled: usize,
+    /// The number of spooled messages placed back on their recipient's queue
+    pub replayed: usize,
+    /// The number of spooled messages which couldn't be decoded, and were discarded
+    pub discarded: usize,
+}
+
+/// Spools the messages which overflow the message queues of a Postmaster's Agents to files in a directory, one file for each address.
+/// The Postmaster keeps spooling messages for as long as it is running, even once the `Spillover` has been dropped.
+///
+/// # Example
+/// ```rust,ignore
+/// init_postmaster!(
+///     config = PostmasterConfig::new().with_overflow_policy(OverflowPolicy::Spill),
+///     Address,
+///     Payloads
+/// );
+///
+/// let spillover = Spillover::open(postmaster::instance(), "/var/spool/ingest", IngestCodec)?;
+/// // Later, e.g. from a health check
+/// println!("{} readings waiting on disk", spillover.spooled(Address::IngestAgent));
+/// ```
+pub struct Spillover<A, P> {
+    state: Arc<SpillState<A, P>>,
+}
+
+impl<A, P> Spillover<A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    /// Start spooling the messages which overflow the Postmaster's queues to files in the given directory, creating the directory if it doesn't exist.
+    /// Any spool files already in the directory are removed.
+    /// Replaces any `Spillover` already opened for the Postmaster, whose spooled messages are still delivered.
+    pub fn open(
+        postmaster: &Postmaster<A, P>,
+        directory: impl AsRef<Path>,
+        codec: impl SpillCodec<A, P>,
+    ) -> io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+        for entry in fs::read_dir(&directory)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == SPOOL_EXTENSION)
+            {
+                fs::remove_file(path)?;
+            }
+        }
+        let state = Arc::new(SpillState {
+            directory,
+            codec: Box::new(codec),
+            spools: BlockingMutex::new(BTreeMap::new()),
+            stats: BlockingMutex::new(SpillStats::default()),
+        });
+        postmaster.set_spillover(state.clone());
+        Ok(Self { state })
+    }
+
+    /// The directory holding the spool files
+    pub fn directory(&self) -> &Path {
+        &self.state.directory
+    }
+
+    /// The number of messages spooled for the given address, waiting for space on its queue
+    pub fn spooled(&self, address: A) -> usize {
+        self.state
+            .spools
+            .lock()
+            .unwrap()
+            .get(&address.index())
+            .map_or(0, |spool| spool.pending.load(Ordering::Relaxed))
+    }
+
+    /// The number of messages handled so far
+    pub fn stats(&self) -> SpillStats {
+        *self.state.stats.lock().unwrap()
+    }
+}
+
+/// The spool file of a single address
+struct Spool {
+    file: File,
+    /// Where the next message to be replayed starts
+    read_at: u64,
+    /// Where the next message to be spooled is written
+    write_at: u64,
+    /// Whether a task is replaying the spooled messages onto the queue
+    replaying: bool,
+}
+
+/// A spool, along with the number of messages in it (which can be read without waiting for the spool)
+struct SpoolHandle {
+    spool: BlockingMutex<Spool>,
+    pending: AtomicUsize,
+}
+
+/// Marks a spool as replaying for as long as its replay task is running.
+/// This is created before the task is spawned and moved into it, so if the task is dropped before it finishes, even before it first runs (e.g. because the runtime it was spawned on, such as a threaded Agent's, has shut down), the spool is marked as no longer replaying, so that the next message sent to the address starts replaying it again.
+struct Replaying {
+    handle: Arc<SpoolHandle>,
+    /// Whether the task has finished, having marked the spool itself while holding its lock
+    finished: bool,
+}
+
+impl Drop for Replaying {
+    fn drop(&mut self) {
+        if !self.finished
+            && let Ok(mut spool) = self.handle.spool.lock()
+        {
+            spool.replaying = false;
+        }
+    }
+}
+
+/// The state of a `Spillover`, shared with the Postmaster
+pub(crate) struct SpillState<A, P> {
+    directory: PathBuf,
+    codec: Box<dyn SpillCodec<A, P>>,
+    spools: BlockingMutex<BTreeMap<AddressIndex, Arc<SpoolHandle>>>,
+    stats: BlockingMutex<SpillStats>,
+}
+
+impl<A, P> SpillState<A, P>
+where
+    A: Send + 'static,
+    P: Send + 'static,
+{
+    /// Place messages on the recipient's queue if there is space for all of them and none are already spooled, or append them to the recipient's spool otherwise, taking them from `messages` once they are placed.
+    /// Files are read and written without yielding, so that a send which times out can't leave a message half written.
+    pub(crate) fn place(
+        self: &Arc<Self>,
+        destination: AddressIndex,
+        queue: &Sender<Message<A, P>>,
+        messages: &mut Vec<Message<A, P>>,
+    ) -> Result<(), PostmasterError> {
+        let handle = self.spool(destination)?;
+        let mut spool = handle.spool.lock().unwrap();
+        if handle.pending.load(Ordering::Relaxed) == 0
+            && let Ok(permits) = queue.try_reserve_many(messages.len())
+        {
+            let enqueued_at = Some(Instant::now());
+            for (permit, mut message) in permits.zip(messages.drain(..)) {
+                message.enqueued_at = enqueued_at;
+                permit.send(message);
+            }
+            return Ok(());
+        }
+        if queue.is_closed() {
+            return Err(PostmasterError::ReceiverClosed);
+        }
+        let spilled: Vec<_> = messages.drain(..).map(Header::split).collect();
+        let mut bytes = Vec::new();
+        for (header, message) in &spilled {
+            let encoded = self.codec.encode(message);
+            bytes.extend_from_slice(&((HEADER_LEN + encoded.len()) as u32).to_le_bytes());
+            header.write(&mut bytes);
+            bytes.extend_from_slice(&encoded);
+        }
+        if spool.append(&bytes).is_err() {
+            // The messages are handed back, so that the sender gets their payloads back with the error
+            messages.extend(
+                spilled
+                    .into_iter()
+                    .map(|(header, message)| header.join(message)),
+            );
+            return Err(PostmasterError::SpillFailed);
+        }
+        handle.pending.add(spilled.len(), Ordering::Relaxed);
+        self.stats.lock().unwrap().spooled += spilled.len();
+        if !spool.replaying {
+            spool.replaying = true;
+            let replaying = Replaying {
+                handle: handle.clone(),
+                finished: false,
+            };
+            // The spool is unlocked first, as the guard locks it if it is dropped while spawning
+            drop(spool);
+            tokio::spawn(self.clone().replay(replaying, queue.clone()));
+        }
+        Ok(())
+    }
+
+    /// The spool of the given address, creating it the first time a message is spooled for it
+    fn spool(&self, destination: AddressIndex) -> Result<Arc<SpoolHandle>, PostmasterError> {
+        let mut spools = self.spools.lock().unwrap();
+        if let Some(handle) = spools.get(&destination) {
+            return Ok(handle.clone());
+        }
+        let name = match destination {
+            AddressIndex::Static(index) => format!("static-{index}.{SPOOL_EXTENSION}"),
+            AddressIndex::Dynamic(id) => format!("dynamic-{id}.{SPOOL_EXTENSION}"),
+        };
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(self.directory.join(name))
+            .map_err(|_| PostmasterError::SpillFailed)?;
+        let handle = Arc::new(SpoolHandle {
+            spool: BlockingMutex::new(Spool {
+                file,
+                read_at: 0,
+                write_at: 0,
+                replaying: false,
+            }),
+            pending: AtomicUsize::new(0),
+        });
+        spools.insert(destination, handle.clone());
+        Ok(handle)
+    }
+
+    /// Move the spooled messages back onto the queue as space is freed on it.
+    /// Replaying stops once the spool is empty, or if the queue is closed (e.g. because the Agent is being restarted), in which case it starts again with the next message sent to the address.
+    async fn replay(self: Arc<Self>, mut replaying: Replaying, queue: Sender<Message<A, P>>) {
+        let handle = replaying.handle.clone();
+        loop {
+            let permit = queue.reserve().await;
+            let mut spool = handle.spool.lock().unwrap();
+            let Ok(permit) = permit else {
+                spool.replaying = false;
+                replaying.finished = true;
+                return;
+            };
+            let message = loop {
+                if handle.pending.load(Ordering::Relaxed) == 0 {
+                    break None;
+                }
+                let bytes = spool.pop();
+                handle.pending.sub(1, Ordering::Relaxed);
+                match bytes.ok().and_then(|bytes| self.decode(&bytes)) {
+                    Some(message) => break Some(message),
+                    None => self.stats.lock().unwrap().discarded += 1,
+                }
+            };
+            if handle.pending.load(Ordering::Relaxed) == 0 {
+                // An empty spool is truncated, so that its file doesn't keep growing across bursts
+                let _ = spool.clear();
+            }
+            let Some(mut message) = message else {
+                spool.replaying = false;
+                replaying.finished = true;
+                return;
+            };
+            message.enqueued_at = Some(Instant::now());
+            permit.send(message);
+            self.stats.lock().unwrap().replayed += 1;
+        }
+    }
+
+    /// Rebuild a spooled message from its header and the bytes from the codec
+    fn decode(&self, bytes: &[u8]) -> Option<Message<A, P>> {
+        let (header, encoded) = bytes.split_at_checked(HEADER_LEN)?;
+        Some(Header::read(header)?.join(self.codec.decode(encoded)?))
+    }
+}
+
+/// The parts of a spooled message which are stored by the spool rather than by the codec
+struct Header {
+    id: Option<u64>,
+    correlation_id: Option<u64>,
+    sequence: Option<u64>,
+}
+
+impl Header {
+    /// Take a message apart, into its header and the parts encoded by the codec
+    fn split<A, P>(message: Message<A, P>) -> (Self, SpilledMessage<A, P>) {
+        let header = Self {
+            id: message.id.map(|MessageId(id)| id),
+            correlation_id: message.correlation_id.map(|CorrelationId(id)| id),
+            sequence: message.sequence.map(NonZeroU64::get),
+        };
+        let Message {
+            source,
+            reply_to,
+            payload,
+            ..
+        } = message;
+        (
+            header,
+            SpilledMessage {
+                source,
+                reply_to,
+                payload,
+            },
+        )
+    }
+
+    /// Put a message back together from its header and the parts decoded by the codec
+    fn join<A, P>(self, spilled: SpilledMessage<A, P>) -> Message<A, P> {
+        let mut message = Message::new(spilled.source, spilled.payload);
+        message.reply_to = spilled.reply_to;
+        message.id = self.id.map(MessageId);
+        message.correlation_id = self.correlation_id.map(CorrelationId);
+        message.sequence = self.sequence.and_then(NonZeroU64::new);
+        message
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        let fields = [
+            (self.id, FLAG_ID),
+            (self.correlation_id, FLAG_CORRELATION_ID),
+            (self.sequence, FLAG_SEQUENCE),
+        ];
+        bytes.push(
+            fields
+                .iter()
+                .filter(|(value, _)| value.is_some())
+                .fold(0, |flags, (_, flag)| flags | flag),
+        );
+        for (value, _) in fields {
+            bytes.extend_from_slice(&value.unwrap_or(0).to_le_bytes());
+        }
+    }
+
+    fn read(header: &[u8]) -> Option<Self> {
+        let flags = *header.first()?;
+        let value = |position: usize, flag: u8| {
+            let start = 1 + position * 8;
+            let value = u64::from_le_bytes(header.get(start..start + 8)?.try_into().ok()?);
+            (flags & flag != 0).then_some(value)
+        };
+        Some(Self {
+            id: value(0, FLAG_ID),
+            correlation_id: value(1, FLAG_CORRELATION_ID),
+            sequence: value(2, FLAG_SEQUENCE),
+        })
+    }
+}
+
+impl Spool {
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.write_at))?;
+        self.file.write_all(bytes)?;
+        self.write_at += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Read the next spooled message, returning the bytes after its length
+    fn pop(&mut self) -> io::Result<Vec<u8>> {
+        self.file.seek(SeekFrom::Start(self.read_at))?;
+        let mut len = [0; 4];
+        self.file.read_exact(&mut len)?;
+        let mut bytes = vec![0; u32::from_le_bytes(len) as usize];
+        self.file.read_exact(&mut bytes)?;
+        self.read_at += (len.len() + bytes.len()) as u64;
+        Ok(bytes)
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.read_at = 0;
+        self.write_at = 0;
+        self.file.set_len(0)
+    }
+}
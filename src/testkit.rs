@@ -0,0 +1,158 @@
+//! Utilities for testing Agents.
+//! Enabled with the `testkit` feature.
+
+use core::fmt::Debug;
+
+use tokio::sync::mpsc::{Receiver, channel};
+use tokio::time::{self, Duration};
+
+use crate::PostmasterError;
+use crate::postmaster::{Message, Postmaster};
+
+/// The time a `TestProbe` waits for an expected message, unless otherwise configured
+pub const DEFAULT_EXPECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The maximum number of messages a `TestProbe` can hold before senders have to wait
+const PROBE_QUEUE_SIZE: usize = 64;
+
+/// A stand-in for an Agent, which records the messages sent to its address so that tests can make assertions about them.
+/// The probe is registered at an address in place of an Agent, and the Agent under test is given the probe's address to send to.
+/// Expectations consume the messages in the order they were received, and panic (failing the test) if they are not met.
+///
+/// # Example
+/// ```rust,ignore
+/// #[tokio::test]
+/// async fn sequencer_turns_lights_red() {
+///     let mut lights = TestProbe::register(postmaster::instance(), Address::Lights).await.unwrap();
+///     postmaster::register_agent!(Sequencer, SequencerAgent, ()).unwrap();
+///
+///     postmaster::send(Address::Sequencer, Address::Button, Payloads::ButtonPressed).await.unwrap();
+///     lights.expect_payload(Payloads::Lights(LightsMessage::Red)).await;
+///     lights.expect_no_message_within(Duration::from_millis(100)).await;
+/// }
+/// ```
+pub struct TestProbe<A, P> {
+    address: A,
+    receiver: Receiver<Message<A, P>>,
+    timeout: Duration,
+}
+
+impl<A, P> TestProbe<A, P>
+where
+    A: crate::AddressSpace + Debug + Send + Sync + 'static,
+    P: Debug + Send + 'static,
+{
+    /// Create a probe and register it at the given address
+    pub async fn register(
+        postmaster: &Postmaster<A, P>,
+        address: A,
+    ) -> Result<Self, PostmasterError> {
+        let (sender, receiver) = channel(PROBE_QUEUE_SIZE);
+        postmaster.register(address, sender).await?;
+        Ok(Self {
+            address,
+            receiver,
+            timeout: DEFAULT_EXPECT_TIMEOUT,
+        })
+    }
+
+    /// The address the probe is registered at
+    pub fn address(&self) -> A {
+        self.address
+    }
+
+    /// Change how long the probe waits for an expected message before failing
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Wait for the next message, returning `None` if no message arrives within the probe's timeout
+    pub async fn receive(&mut self) -> Option<Message<A, P>> {
+        time::timeout(self.timeout, self.receiver.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Take all of the messages which have already been received, without waiting
+    pub fn received(&mut self) -> Vec<Message<A, P>> {
+        let mut messages = Vec::new();
+        while let Ok(message) = self.receiver.try_recv() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    /// Expect a message to arrive within the probe's timeout, returning it.
+    /// Panics if no message arrives.
+    pub async fn expect_message(&mut self) -> Message<A, P> {
+        match self.receive().await {
+            Some(message) => message,
+            None => panic!(
+                "Probe {:?} expected a message, but none arrived within {:?}",
+                self.address, self.timeout
+            ),
+        }
+    }
+
+    /// Expect the next message to have the given payload, returning the message.
+    /// Panics if no message arrives, or if the next message has a different payload.
+    pub async fn expect_payload(&mut self, expected: P) -> Message<A, P>
+    where
+        P: PartialEq,
+    {
+        let message = self.expect_message().await;
+        assert_eq!(
+            message.payload, expected,
+            "Probe {:?} received an unexpected payload from {:?}",
+            self.address, message.source
+        );
+        message
+    }
+
+    /// Expect the next message to have a payload for which the predicate holds, returning the message.
+    /// This is useful for payloads which don't implement `PartialEq`, e.g. `probe.expect_payload_matching(|payload| matches!(payload, Payloads::Hello)).await`.
+    /// Panics if no message arrives, or if the predicate does not hold for the next message.
+    pub async fn expect_payload_matching(
+        &mut self,
+        predicate: impl FnOnce(&P) -> bool,
+    ) -> Message<A, P> {
+        let message = self.expect_message().await;
+        assert!(
+            predicate(&message.payload),
+            "Probe {:?} received an unexpected payload from {:?}: {:?}",
+            self.address,
+            message.source,
+            message.payload
+        );
+        message
+    }
+
+    /// Expect the next messages to have the given payloads, in order, returning the messages.
+    /// Each message must arrive within the probe's timeout of the previous one.
+    /// Panics if any of the messages does not arrive or has a different payload.
+    pub async fn expect_payloads(
+        &mut self,
+        expected: impl IntoIterator<Item = P>,
+    ) -> Vec<Message<A, P>>
+    where
+        P: PartialEq,
+    {
+        let mut messages = Vec::new();
+        for payload in expected {
+            messages.push(self.expect_payload(payload).await);
+        }
+        messages
+    }
+
+    /// Expect no message to arrive within the given duration.
+    /// Panics if a message arrives.
+    pub async fn expect_no_message_within(&mut self, duration: Duration) {
+        if let Ok(Some(message)) = time::timeout(duration, self.receiver.recv()).await {
+            panic!(
+                "Probe {:?} expected no message within {:?}, but received {:?} from {:?}",
+                self.address, duration, message.payload, message.source
+            );
+        }
+    }
+}
@@ -0,0 +1,370 @@
+//! A live dashboard of an Agent system, drawn in the terminal, for watching a system run during development.
+//! Enabled with the `tui` feature.
+//!
+//! A `Dashboard` is an Agent which redraws the terminal at a regular interval with a row for each address known to the Postmaster, showing:
+//! - whether its Agent is running, or has terminated (or that it is a standalone message queue)
+//! - the depth of its message queue, out of the queue's capacity
+//! - the rate at which messages are being delivered to it
+//! - the most recent message delivered to it: how long ago, where from, and the payload (in its `Debug` format)
+//!
+//! along with the Postmaster's diagnostics.
+//!
+//! The dashboard is drawn on the terminal's alternate screen, so the project's own output is left untouched underneath it, and reappears when the dashboard is hidden.
+//! It can be shown and hidden at runtime through the `DashboardToggle` obtained from `DashboardConfig::toggle()`, e.g. from a key binding or a command line flag.
+//! Recording the most recent messages formats every payload which is sent, so this is only done while the dashboard is visible.
+
+use core::fmt::Debug;
+use std::collections::BTreeMap;
+use std::io::{self, Stdout};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex as BlockingMutex};
+
+use ratatui::Terminal;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::{execute, terminal};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Cell, Paragraph, Row, Table};
+use tokio::time::{self, Duration, Instant};
+
+use crate::address::{AddressIndex, AddressSpace};
+use crate::agent::{Agent, Inbox};
+use crate::postmaster::{AddressStatus, Message, Postmaster, TaskState, Verdict};
+
+/// How often the dashboard is redrawn, unless changed with `DashboardConfig::with_refresh()`
+pub const DEFAULT_REFRESH: Duration = Duration::from_millis(250);
+
+/// The most recent message delivered to an address, as shown on the dashboard
+struct Envelope {
+    received_at: Instant,
+    source: String,
+    payload: String,
+}
+
+/// Shows and hides a `Dashboard` while it is running
+#[derive(Debug, Clone)]
+pub struct DashboardToggle {
+    visible: Arc<AtomicBool>,
+}
+
+impl DashboardToggle {
+    /// Show the dashboard, from its next redraw
+    pub fn show(&self) {
+        self.visible.store(true, Ordering::Relaxed);
+    }
+
+    /// Hide the dashboard, returning the terminal to the project's own output
+    pub fn hide(&self) {
+        self.visible.store(false, Ordering::Relaxed);
+    }
+
+    /// Show the dashboard if it is hidden, or hide it if it is shown
+    pub fn toggle(&self) {
+        self.visible.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    /// Whether the dashboard is shown
+    pub fn is_visible(&self) -> bool {
+        self.visible.load(Ordering::Relaxed)
+    }
+}
+
+/// The configuration of a `Dashboard`
+pub struct DashboardConfig<A, P> {
+    postmaster: Postmaster<A, P>,
+    refresh: Duration,
+    toggle: DashboardToggle,
+    /// The most recent message delivered to each address, while the dashboard is visible
+    recent: Arc<BlockingMutex<BTreeMap<AddressIndex, Envelope>>>,
+    /// Whether the interceptor recording the most recent messages has been added, so that a restarted dashboard doesn't add another
+    recording: Arc<AtomicBool>,
+}
+
+impl<A, P> DashboardConfig<A, P> {
+    /// Show the state of the Agents of the given Postmaster, visible from the start and redrawn every `DEFAULT_REFRESH`
+    pub fn new(postmaster: &Postmaster<A, P>) -> Self {
+        Self {
+            postmaster: postmaster.clone(),
+            refresh: DEFAULT_REFRESH,
+            toggle: DashboardToggle {
+                visible: Arc::new(AtomicBool::new(true)),
+            },
+            recent: Arc::new(BlockingMutex::new(BTreeMap::new())),
+            recording: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Set how often the dashboard is redrawn
+    pub fn with_refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Start with the dashboard hidden, until it is shown with its `DashboardToggle`
+    pub fn hidden(self) -> Self {
+        self.toggle.hide();
+        self
+    }
+
+    /// The toggle with which the dashboard can be shown and hidden while it is running
+    pub fn toggle(&self) -> DashboardToggle {
+        self.toggle.clone()
+    }
+}
+
+impl<A, P> Clone for DashboardConfig<A, P> {
+    fn clone(&self) -> Self {
+        Self {
+            postmaster: self.postmaster.clone(),
+            refresh: self.refresh,
+            toggle: self.toggle.clone(),
+            recent: self.recent.clone(),
+            recording: self.recording.clone(),
+        }
+    }
+}
+
+/// An Agent drawing a live dashboard of the Postmaster's Agents in the terminal.
+/// Messages sent to the dashboard are ignored.
+///
+/// # Example
+/// ```rust,ignore
+/// let config = DashboardConfig::new(postmaster::instance()).hidden();
+/// let dashboard = config.toggle();
+/// postmaster::register_agent!(Dashboard, Dashboard<Address, Payloads>, config).unwrap();
+/// // Later, e.g. when a key is pressed
+/// dashboard.toggle();
+/// ```
+pub struct Dashboard<A, P> {
+    config: DashboardConfig<A, P>,
+}
+
+impl<A, P> Agent for Dashboard<A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Debug + Send + 'static,
+{
+    type Address = A;
+    type Message = Message<A, P>;
+    type Config = DashboardConfig<A, P>;
+
+    async fn create(_address: Self::Address, config: Self::Config) -> Self {
+        if !config.recording.swap(true, Ordering::Relaxed) {
+            let (visible, recent) = (config.toggle.visible.clone(), config.recent.clone());
+            config.postmaster.add_interceptor(
+                move |destination: A, message: &mut Message<A, P>| {
+                    if visible.load(Ordering::Relaxed) {
+                        recent.lock().unwrap().insert(
+                            destination.index(),
+                            Envelope {
+                                received_at: Instant::now(),
+                                source: format!("{:?}", message.source),
+                                payload: format!("{:?}", message.payload),
+                            },
+                        );
+                    }
+                    Verdict::Pass
+                },
+            );
+        }
+        Self { config }
+    }
+
+    async fn run(self, mut inbox: Inbox<Self::Message>) -> ! {
+        let mut refresh = time::interval(self.config.refresh);
+        refresh.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+        let mut screen = Screen { terminal: None };
+        let mut rates = Rates::default();
+        loop {
+            tokio::select! {
+                _ = refresh.tick() => {
+                    screen.show(self.config.toggle.is_visible());
+                    if screen.terminal.is_some() {
+                        let view = self.view(&mut rates).await;
+                        if let Some(terminal) = &mut screen.terminal {
+                            let _ = draw(terminal, &view);
+                        }
+                    }
+                }
+                message = inbox.recv() => {
+                    message.expect("The Agent's inbox was closed");
+                }
+            }
+        }
+    }
+}
+
+impl<A, P> Dashboard<A, P>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+{
+    /// The dashboard, as it currently stands
+    async fn view(&self, rates: &mut Rates) -> View {
+        let postmaster = &self.config.postmaster;
+        let diagnostics = postmaster.get_diagnostics();
+        let status = postmaster.status().await;
+        let metrics = postmaster.metrics().await;
+        rates.update(
+            metrics
+                .agents
+                .iter()
+                .map(|agent| (agent.address.index(), agent.messages_received)),
+        );
+        let recent = self.config.recent.lock().unwrap();
+        View {
+            summary: format!(
+                "post-haste{}  |  sent {}  failed {}  panics {}  delayed {}",
+                diagnostics
+                    .name
+                    .map(|name| format!(" [{name}]"))
+                    .unwrap_or_default(),
+                diagnostics.messages_sent,
+                diagnostics.send_failures,
+                diagnostics.agent_panics,
+                diagnostics.pending_delayed,
+            ),
+            rows: status
+                .addresses
+                .iter()
+                .map(|address| {
+                    row(
+                        address,
+                        rates.rate(address.address.index()),
+                        recent.get(&address.address.index()),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The contents of the dashboard: a summary of the Postmaster's diagnostics, and a row of cells for each address
+struct View {
+    summary: String,
+    rows: Vec<[String; 5]>,
+}
+
+/// The cells of the dashboard's row for a single address
+fn row<A: Debug>(status: &AddressStatus<A>, rate: f64, recent: Option<&Envelope>) -> [String; 5] {
+    let state = match (status.registered, status.task) {
+        (_, Some(TaskState::Terminated)) => "terminated",
+        (true, Some(TaskState::Running)) => "running",
+        (true, None) => "queue",
+        (false, _) => "-",
+    };
+    let last = recent.map_or(String::new(), |envelope| {
+        format!(
+            "{:.1}s ago  {} -> {}",
+            envelope.received_at.elapsed().as_secs_f64(),
+            envelope.source,
+            envelope.payload
+        )
+    });
+    [
+        format!("{:?}", status.address),
+        String::from(state),
+        format!("{}/{}", status.queue_depth, status.queue_capacity),
+        format!("{rate:.1}"),
+        last,
+    ]
+}
+
+/// The rate at which messages are delivered to each address, measured between redraws
+#[derive(Default)]
+struct Rates {
+    measured_at: Option<Instant>,
+    received: BTreeMap<AddressIndex, u64>,
+    rates: BTreeMap<AddressIndex, f64>,
+}
+
+impl Rates {
+    /// Measure the rates from the number of messages each address has received so far
+    fn update(&mut self, received: impl Iterator<Item = (AddressIndex, u64)>) {
+        let now = Instant::now();
+        let elapsed = self
+            .measured_at
+            .replace(now)
+            .map(|measured_at| now.duration_since(measured_at).as_secs_f64());
+        let received: BTreeMap<_, _> = received.collect();
+        self.rates = received
+            .iter()
+            .filter_map(|(address, &count)| {
+                let previous = *self.received.get(address)?;
+                let elapsed = elapsed.filter(|elapsed| *elapsed > 0.0)?;
+                Some((*address, count.saturating_sub(previous) as f64 / elapsed))
+            })
+            .collect();
+        self.received = received;
+    }
+
+    fn rate(&self, address: AddressIndex) -> f64 {
+        self.rates.get(&address).copied().unwrap_or(0.0)
+    }
+}
+
+/// Switches the terminal to and from the alternate screen as the dashboard is shown and hidden, switching back if the dashboard stops while it is shown.
+/// The `Terminal` only exists while the dashboard is shown.
+struct Screen {
+    terminal: Option<Terminal<CrosstermBackend<Stdout>>>,
+}
+
+impl Screen {
+    fn show(&mut self, show: bool) {
+        if show == self.terminal.is_some() {
+            return;
+        }
+        if show {
+            let mut stdout = io::stdout();
+            let _ = execute!(stdout, terminal::EnterAlternateScreen);
+            self.terminal = Terminal::new(CrosstermBackend::new(stdout)).ok();
+            if let Some(terminal) = &mut self.terminal {
+                let _ = terminal.hide_cursor();
+                let _ = terminal.clear();
+            }
+        } else if let Some(mut terminal) = self.terminal.take() {
+            let _ = terminal.show_cursor();
+            let _ = execute!(terminal.backend_mut(), terminal::LeaveAlternateScreen);
+        }
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        self.show(false);
+    }
+}
+
+/// Draw the dashboard, with the diagnostics above a table of the addresses which is cut down to fit the terminal
+fn draw(terminal: &mut Terminal<CrosstermBackend<Stdout>>, view: &View) -> io::Result<()> {
+    terminal.draw(|frame| {
+        let [summary, table] =
+            Layout::vertical([Constraint::Length(2), Constraint::Fill(1)]).areas(frame.area());
+        frame.render_widget(Paragraph::new(view.summary.as_str()), summary);
+        let header = Row::new(["ADDRESS", "STATE", "QUEUE", "MSG/S", "LAST MESSAGE"])
+            .style(Style::new().add_modifier(Modifier::BOLD));
+        // The queue depth and rate are right-aligned, so that their digits line up
+        let rows = view.rows.iter().map(|[address, state, queue, rate, last]| {
+            Row::new([
+                Cell::from(address.as_str()),
+                Cell::from(state.as_str()),
+                Cell::from(Line::from(queue.as_str()).right_aligned()),
+                Cell::from(Line::from(rate.as_str()).right_aligned()),
+                Cell::from(last.as_str()),
+            ])
+        });
+        let widths = [
+            Constraint::Length(24),
+            Constraint::Length(11),
+            Constraint::Length(9),
+            Constraint::Length(9),
+            Constraint::Fill(1),
+        ];
+        frame.render_widget(
+            Table::new(rows, widths).header(header).column_spacing(2),
+            table,
+        );
+    })?;
+    Ok(())
+}
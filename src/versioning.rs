@@ -0,0 +1,168 @@
+//! Version tags for the messages which are encoded to be sent across a bridge or stored in a journal, so that messages encoded by an older version of a project can still be decoded once its payloads have changed.
+//! Enabled along with any of the `remote`, `outbox` and `persistence` features.
+//!
+//! A `Versioned` codec wraps the project's own codec, prefixing each encoded message with the version of the format it was encoded in.
+//! When a message encoded at an older version is decoded, it is first brought up to date by the project's `Upgrade` hook, one version at a time, before being decoded by the project's codec as usual.
+//! This means that a process can be upgraded while the other end of its bridge (or its journal) still holds messages in the old format, rather than every process being upgraded in lockstep.
+//!
+//! A message encoded at a newer version than the codec's own can't be decoded, as there is no way to know what has changed, so during a rolling upgrade the processes which receive messages should be upgraded before those which send them.
+//! The tag also changes the encoding of every message, so an existing journal (or a bridge to a process without version tags) can't be switched over to a `Versioned` codec without being emptied first.
+//!
+//! # Example
+//! ```rust,ignore
+//! // Version 2 added a `unit` to each reading, which version 1 readings are given when they are decoded
+//! let codec = Versioned::new(JsonCodec, 2).with_upgrade(|version, bytes: &[u8]| match version {
+//!     1 => {
+//!         let mut message: serde_json::Value = serde_json::from_slice(bytes).ok()?;
+//!         message["payload"]["Reading"]["unit"] = "celsius".into();
+//!         serde_json::to_vec(&message).ok()
+//!     }
+//!     _ => None,
+//! });
+//! let bridge = Bridge::new(postmaster::instance(), codec).remote(Address::Storage);
+//! ```
+
+use std::borrow::Cow;
+
+#[cfg(feature = "outbox")]
+use crate::outbox::{OutboxCodec, OutboxMessage};
+#[cfg(feature = "remote")]
+use crate::remote::{Codec, RemoteMessage};
+#[cfg(feature = "persistence")]
+use crate::schedule::{ScheduleCodec, ScheduledMessage};
+
+/// The number of bytes taken by the version tag at the start of each encoded message
+const TAG_SIZE: usize = size_of::<u32>();
+
+/// Brings messages encoded at an older version up to date, so that they can be decoded by the current version's codec.
+/// This is implemented for closures taking the version and the encoded bytes, so a closure can be given to `Versioned::with_upgrade()` directly.
+pub trait Upgrade: Send + Sync + 'static {
+    /// Convert a message encoded at `version` into the format of `version + 1`, returning `None` if it can't be converted (in which case it is treated as invalid).
+    /// A message several versions behind is converted one version at a time, so each change only needs handling once.
+    fn upgrade(&self, version: u32, bytes: &[u8]) -> Option<Vec<u8>>;
+}
+
+impl<F> Upgrade for F
+where
+    F: Fn(u32, &[u8]) -> Option<Vec<u8>> + Send + Sync + 'static,
+{
+    fn upgrade(&self, version: u32, bytes: &[u8]) -> Option<Vec<u8>> {
+        self(version, bytes)
+    }
+}
+
+/// The `Upgrade` hook for a codec which only decodes messages encoded at its own version, treating any older message as invalid
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoUpgrade;
+
+impl Upgrade for NoUpgrade {
+    fn upgrade(&self, _version: u32, _bytes: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// A codec which tags each message it encodes with a version, and upgrades messages encoded at older versions when decoding them.
+/// It wraps any bridge (`Codec`), outbox (`OutboxCodec`) or schedule (`ScheduleCodec`) codec, and can be used wherever that codec could.
+/// Each message is encoded by the wrapped codec, and prefixed with the version as a 32 bit big-endian number.
+#[derive(Debug, Clone)]
+pub struct Versioned<C, U = NoUpgrade> {
+    codec: C,
+    version: u32,
+    upgrade: U,
+}
+
+impl<C> Versioned<C> {
+    /// Wrap a codec which encodes messages in the format of the given version.
+    /// Without an upgrade hook, messages encoded at older versions are treated as invalid.
+    pub fn new(codec: C, version: u32) -> Self {
+        Self {
+            codec,
+            version,
+            upgrade: NoUpgrade,
+        }
+    }
+}
+
+impl<C, U: Upgrade> Versioned<C, U> {
+    /// Set the hook which brings messages encoded at older versions up to date
+    pub fn with_upgrade<V: Upgrade>(self, upgrade: V) -> Versioned<C, V> {
+        Versioned {
+            codec: self.codec,
+            version: self.version,
+            upgrade,
+        }
+    }
+
+    /// The version which messages are encoded at
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Prefix a message encoded by the wrapped codec with the current version
+    fn tag(&self, bytes: Vec<u8>) -> Vec<u8> {
+        let mut tagged = Vec::with_capacity(TAG_SIZE + bytes.len());
+        tagged.extend_from_slice(&self.version.to_be_bytes());
+        tagged.extend_from_slice(&bytes);
+        tagged
+    }
+
+    /// Remove the version tag from an encoded message, upgrading it to the current version if it is older, or return `None` if it can't be decoded at the current version
+    fn untag<'a>(&self, bytes: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        let (tag, bytes) = bytes.split_first_chunk::<TAG_SIZE>()?;
+        let mut version = u32::from_be_bytes(*tag);
+        if version > self.version {
+            return None;
+        }
+        let mut bytes = Cow::Borrowed(bytes);
+        while version < self.version {
+            bytes = Cow::Owned(self.upgrade.upgrade(version, &bytes)?);
+            version += 1;
+        }
+        Some(bytes)
+    }
+}
+
+#[cfg(feature = "remote")]
+impl<A, P, C, U> Codec<A, P> for Versioned<C, U>
+where
+    C: Codec<A, P>,
+    U: Upgrade,
+{
+    fn encode(&self, message: &RemoteMessage<A, P>) -> Vec<u8> {
+        self.tag(self.codec.encode(message))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<RemoteMessage<A, P>> {
+        self.codec.decode(&self.untag(bytes)?)
+    }
+}
+
+#[cfg(feature = "outbox")]
+impl<A, P, C, U> OutboxCodec<A, P> for Versioned<C, U>
+where
+    C: OutboxCodec<A, P>,
+    U: Upgrade,
+{
+    fn encode(&self, message: &OutboxMessage<A, P>) -> Vec<u8> {
+        self.tag(self.codec.encode(message))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<OutboxMessage<A, P>> {
+        self.codec.decode(&self.untag(bytes)?)
+    }
+}
+
+#[cfg(feature = "persistence")]
+impl<A, P, C, U> ScheduleCodec<A, P> for Versioned<C, U>
+where
+    C: ScheduleCodec<A, P>,
+    U: Upgrade,
+{
+    fn encode(&self, message: &ScheduledMessage<A, P>) -> Vec<u8> {
+        self.tag(self.codec.encode(message))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<ScheduledMessage<A, P>> {
+        self.codec.decode(&self.untag(bytes)?)
+    }
+}
@@ -0,0 +1,323 @@
+//! Agents which connect WebSocket peers (such as browsers) to a Postmaster, e.g. for dashboards and remote control.
+//! Enabled with the `websocket` feature.
+//!
+//! A `WebSocketServer` accepts WebSocket connections: each message received from a client is decoded into a payload and sent to a configured address, while every message sent to the server's own address is encoded and pushed to all of the connected clients.
+//! A `WebSocketClient` is its counterpart, connecting to a WebSocket server: messages received from the server are sent to a configured address, and messages sent to the client's address are pushed to the server.
+//! Messages arriving over WebSocket are sent from the address of the Agent which received them, so replies to them are pushed back out over the WebSocket.
+//!
+//! Post-haste does not depend on any particular serialisation format, so the project provides a `WebSocketCodec` to translate between payloads and WebSocket messages, e.g. JSON in text messages or MessagePack in binary messages.
+//! Only unencrypted (`ws://`) connections are supported; TLS can be provided by a reverse proxy in front of the server.
+
+mod protocol;
+
+use core::fmt::Debug;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{Sender, channel};
+use tokio::task;
+
+use crate::address::AddressSpace;
+use crate::agent::{Agent, Inbox};
+use crate::postmaster::{Message, Postmaster};
+use protocol::{Incoming, Outgoing, Role};
+
+/// The number of messages which can be waiting to be pushed to each client.
+/// Messages for a client which is too slow to keep up are dropped, so that one slow client doesn't hold up the others.
+const CLIENT_QUEUE_SIZE: usize = 64;
+
+/// A message sent or received over a WebSocket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebSocketMessage {
+    /// A text message, e.g. holding JSON
+    Text(String),
+    /// A binary message, e.g. holding MessagePack
+    Binary(Vec<u8>),
+}
+
+/// Translates between payloads and the messages sent over a WebSocket
+pub trait WebSocketCodec<P>: Send + Sync + 'static {
+    /// Encode a payload to be pushed over the WebSocket, or return `None` if the payload should not be pushed
+    fn encode(&self, payload: &P) -> Option<WebSocketMessage>;
+
+    /// Decode a message received over the WebSocket, or return `None` if it is not a valid payload (in which case it is dropped)
+    fn decode(&self, message: WebSocketMessage) -> Option<P>;
+}
+
+/// The configuration of a `WebSocketServer`
+pub struct WebSocketServerConfig<A, P, C> {
+    postmaster: Postmaster<A, P>,
+    bind: SocketAddr,
+    destination: A,
+    codec: Arc<C>,
+}
+
+impl<A, P, C> WebSocketServerConfig<A, P, C> {
+    /// Listen for connections on the given socket address, sending the payloads received from clients to the given destination through the given Postmaster
+    pub fn new(postmaster: &Postmaster<A, P>, bind: SocketAddr, destination: A, codec: C) -> Self {
+        Self {
+            postmaster: postmaster.clone(),
+            bind,
+            destination,
+            codec: Arc::new(codec),
+        }
+    }
+}
+
+impl<A: Copy, P, C> Clone for WebSocketServerConfig<A, P, C> {
+    fn clone(&self) -> Self {
+        Self {
+            postmaster: self.postmaster.clone(),
+            bind: self.bind,
+            destination: self.destination,
+            codec: self.codec.clone(),
+        }
+    }
+}
+
+/// An Agent which exposes a Postmaster over a WebSocket server.
+/// Each message received from a client is sent to the configured destination, and every message sent to the server is pushed to all of the connected clients.
+///
+/// # Panics
+/// Panics if the server can't listen on its socket address, so it should be registered with a `RestartPolicy` if it should try again.
+///
+/// # Example
+/// ```rust,ignore
+/// let config = WebSocketServerConfig::new(postmaster::instance(), "0.0.0.0:9000".parse()?, Address::Controller, JsonCodec);
+/// postmaster::register_agent!(Dashboard, WebSocketServer<Address, Payloads, JsonCodec>, config, 16).unwrap();
+/// ```
+pub struct WebSocketServer<A, P, C> {
+    address: A,
+    config: WebSocketServerConfig<A, P, C>,
+}
+
+impl<A, P, C> Agent for WebSocketServer<A, P, C>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+    C: WebSocketCodec<P>,
+{
+    type Address = A;
+    type Message = Message<A, P>;
+    type Config = WebSocketServerConfig<A, P, C>;
+
+    async fn create(address: Self::Address, config: Self::Config) -> Self {
+        Self { address, config }
+    }
+
+    async fn run(self, mut inbox: Inbox<Self::Message>) -> ! {
+        let listener = TcpListener::bind(self.config.bind)
+            .await
+            .expect("Failed to bind the WebSocket server");
+        let mut clients: Vec<Sender<WebSocketMessage>> = Vec::new();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let Ok((mut stream, _)) = accepted else {
+                        continue;
+                    };
+                    let (client, messages) = channel(CLIENT_QUEUE_SIZE);
+                    clients.push(client);
+                    let postmaster = self.config.postmaster.clone();
+                    let codec = self.config.codec.clone();
+                    let (source, destination) = (self.address, self.config.destination);
+                    task::spawn(async move {
+                        if protocol::accept(&mut stream).await.is_ok() {
+                            let _ = exchange(
+                                stream,
+                                Role::Server,
+                                &mut Inbox::from(messages),
+                                Some,
+                                &postmaster,
+                                source,
+                                destination,
+                                codec,
+                            )
+                            .await;
+                        }
+                    });
+                }
+                message = inbox.recv() => {
+                    let message = message.expect("The Agent's inbox was closed");
+                    let Some(encoded) = self.config.codec.encode(&message.payload) else {
+                        continue;
+                    };
+                    // Forget the clients which have disconnected
+                    clients.retain(|client| {
+                        !matches!(client.try_send(encoded.clone()), Err(TrySendError::Closed(_)))
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// The configuration of a `WebSocketClient`
+pub struct WebSocketClientConfig<A, P, C> {
+    postmaster: Postmaster<A, P>,
+    url: String,
+    destination: A,
+    codec: Arc<C>,
+}
+
+impl<A, P, C> WebSocketClientConfig<A, P, C> {
+    /// Connect to the server at the given URL (e.g. `ws://localhost:9000/`), sending the payloads received from the server to the given destination through the given Postmaster
+    pub fn new(
+        postmaster: &Postmaster<A, P>,
+        url: impl Into<String>,
+        destination: A,
+        codec: C,
+    ) -> Self {
+        Self {
+            postmaster: postmaster.clone(),
+            url: url.into(),
+            destination,
+            codec: Arc::new(codec),
+        }
+    }
+}
+
+impl<A: Copy, P, C> Clone for WebSocketClientConfig<A, P, C> {
+    fn clone(&self) -> Self {
+        Self {
+            postmaster: self.postmaster.clone(),
+            url: self.url.clone(),
+            destination: self.destination,
+            codec: self.codec.clone(),
+        }
+    }
+}
+
+/// An Agent which connects a Postmaster to a WebSocket server, such as another process's `WebSocketServer`.
+/// Each message received from the server is sent to the configured destination, and every message sent to the client is pushed to the server.
+///
+/// # Panics
+/// Panics if the connection can't be made, or once it closes, so it should be registered with a `RestartPolicy` if it should reconnect.
+///
+/// # Example
+/// ```rust,ignore
+/// let config = WebSocketClientConfig::new(postmaster::instance(), "ws://backend:9000/", Address::Display, JsonCodec);
+/// postmaster::register_agent!(Backend, WebSocketClient<Address, Payloads, JsonCodec>, config, 16, RestartPolicy::always()).unwrap();
+/// ```
+pub struct WebSocketClient<A, P, C> {
+    address: A,
+    config: WebSocketClientConfig<A, P, C>,
+}
+
+impl<A, P, C> Agent for WebSocketClient<A, P, C>
+where
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+    C: WebSocketCodec<P>,
+{
+    type Address = A;
+    type Message = Message<A, P>;
+    type Config = WebSocketClientConfig<A, P, C>;
+
+    async fn create(address: Self::Address, config: Self::Config) -> Self {
+        Self { address, config }
+    }
+
+    async fn run(self, mut inbox: Inbox<Self::Message>) -> ! {
+        let url = &self.config.url;
+        let (host, path) = url
+            .strip_prefix("ws://")
+            .map(|rest| rest.split_at(rest.find('/').unwrap_or(rest.len())))
+            .unwrap_or_else(|| panic!("Unsupported WebSocket URL {url}"));
+        let path = if path.is_empty() { "/" } else { path };
+        let mut stream = TcpStream::connect(host)
+            .await
+            .unwrap_or_else(|error| panic!("Failed to connect to {url}: {error}"));
+        protocol::connect(&mut stream, host, path)
+            .await
+            .unwrap_or_else(|error| panic!("Failed to connect to {url}: {error}"));
+        let codec = self.config.codec.clone();
+        let result = exchange(
+            stream,
+            Role::Client,
+            &mut inbox,
+            |message: Message<A, P>| codec.encode(&message.payload),
+            &self.config.postmaster,
+            self.address,
+            self.config.destination,
+            self.config.codec.clone(),
+        )
+        .await;
+        match result {
+            Ok(()) => panic!("The WebSocket connection to {url} was closed"),
+            Err(error) => panic!("The WebSocket connection to {url} failed: {error}"),
+        }
+    }
+}
+
+/// Exchange messages over an open WebSocket until it closes: the messages taken from `outgoing` are encoded and pushed over the WebSocket, while the messages received are sent from `source` to `destination`
+#[allow(clippy::too_many_arguments)]
+async fn exchange<S, M, A, P, C>(
+    stream: S,
+    role: Role,
+    outgoing: &mut Inbox<M>,
+    encode: impl Fn(M) -> Option<WebSocketMessage>,
+    postmaster: &Postmaster<A, P>,
+    source: A,
+    destination: A,
+    codec: Arc<C>,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+    A: AddressSpace + Debug + Send + Sync + 'static,
+    P: Send + 'static,
+    C: WebSocketCodec<P>,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = protocol::Reader::new(reader);
+    let (controls, mut control) = channel(1);
+    let postmaster = postmaster.clone();
+    // Reading runs in its own task, as a frame which has only been partly read can't be cancelled
+    let reading = task::spawn(async move {
+        loop {
+            match reader.read().await {
+                Ok(Incoming::Message(message)) => {
+                    if let Some(payload) = codec.decode(message) {
+                        // A message which can't be delivered is dropped, as it would have been had it been sent locally
+                        let _ = postmaster.send(destination, source, payload).await;
+                    }
+                }
+                Ok(Incoming::Ping(payload)) => {
+                    if controls.send(Outgoing::Pong(payload)).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(Incoming::Close) | Err(_) => {
+                    let _ = controls.send(Outgoing::Close).await;
+                    return;
+                }
+            }
+        }
+    });
+
+    let result = loop {
+        let frame = tokio::select! {
+            message = outgoing.recv() => match message {
+                Some(message) => match encode(message) {
+                    Some(message) => Outgoing::Message(message),
+                    None => continue,
+                },
+                None => Outgoing::Close,
+            },
+            Some(frame) = control.recv() => frame,
+        };
+        let closing = matches!(frame, Outgoing::Close);
+        if let Err(error) = protocol::write(&mut writer, role, frame).await {
+            break Err(error);
+        }
+        if closing {
+            break Ok(());
+        }
+    };
+    reading.abort();
+    result
+}
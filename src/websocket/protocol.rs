@@ -0,0 +1,529 @@
+//! Just enough of the WebSocket protocol (RFC 6455) for the WebSocket Agents: the opening handshake and message framing.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::mem;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::WebSocketMessage;
+
+/// Appended to the client's key to form the server's accept value
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The longest opening handshake which will be read, to bound the memory used by a misbehaving peer
+const MAX_HANDSHAKE_LENGTH: usize = 8 * 1024;
+
+/// The largest message which will be received, to bound the memory used by a misbehaving peer
+const MAX_MESSAGE_LENGTH: usize = 16 * 1024 * 1024;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// A frame to be written to the connection
+pub(super) enum Outgoing {
+    Message(WebSocketMessage),
+    Pong(Vec<u8>),
+    Close,
+}
+
+/// What was read from the connection
+pub(super) enum Incoming {
+    Message(WebSocketMessage),
+    Ping(Vec<u8>),
+    Close,
+}
+
+/// Which end of the connection this is, as only clients mask the frames they send
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum Role {
+    Server,
+    Client,
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Accept the opening handshake from a client
+pub(super) async fn accept<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> io::Result<()> {
+    let request = read_head(stream).await?;
+    let mut lines = request.split("\r\n");
+    if !lines.next().is_some_and(|line| line.starts_with("GET ")) {
+        return Err(invalid("Not a WebSocket handshake"));
+    }
+    let key =
+        header(lines, "sec-websocket-key").ok_or_else(|| invalid("Missing Sec-WebSocket-Key"))?;
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_value(key)
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await
+}
+
+/// Perform the opening handshake with a server
+pub(super) async fn connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    host: &str,
+    path: &str,
+) -> io::Result<()> {
+    let key = base64(&random_bytes::<16>());
+    let request = format!(
+        "GET {path} HTTP/1.1\r\nHost: {host}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+    let response = read_head(stream).await?;
+    let mut lines = response.split("\r\n");
+    if !lines
+        .next()
+        .is_some_and(|status| status.split(' ').nth(1) == Some("101"))
+    {
+        return Err(invalid("The server refused the WebSocket handshake"));
+    }
+    if header(lines, "sec-websocket-accept") != Some(accept_value(&key).as_str()) {
+        return Err(invalid("The server's Sec-WebSocket-Accept did not match"));
+    }
+    Ok(())
+}
+
+/// The largest payload of a control frame, which can't be fragmented
+const MAX_CONTROL_LENGTH: usize = 125;
+
+/// Reads messages and control frames from the connection, reassembling fragmented messages and skipping pongs.
+/// A control frame (e.g. a ping) may arrive between the fragments of a message (RFC 6455 section 5.4), so the part of the message received so far is kept by the reader until the rest of it arrives.
+pub(super) struct Reader<R> {
+    reader: R,
+    message: Vec<u8>,
+    message_opcode: Option<u8>,
+}
+
+impl<R: AsyncRead + Unpin> Reader<R> {
+    pub(super) fn new(reader: R) -> Self {
+        Self {
+            reader,
+            message: Vec::new(),
+            message_opcode: None,
+        }
+    }
+
+    /// Read the next message or control frame from the connection
+    pub(super) async fn read(&mut self) -> io::Result<Incoming> {
+        loop {
+            let mut head = [0; 2];
+            self.reader.read_exact(&mut head).await?;
+            let fin = head[0] & 0x80 != 0;
+            let opcode = head[0] & 0x0F;
+            let control = opcode & 0x08 != 0;
+            let masked = head[1] & 0x80 != 0;
+            let length = match head[1] & 0x7F {
+                126 => u64::from(self.reader.read_u16().await?),
+                127 => self.reader.read_u64().await?,
+                length => u64::from(length),
+            };
+            if control && (!fin || length > MAX_CONTROL_LENGTH as u64) {
+                return Err(invalid("Invalid WebSocket control frame"));
+            }
+            let length = usize::try_from(length)
+                .ok()
+                .filter(|&length| {
+                    control
+                        || self
+                            .message
+                            .len()
+                            .checked_add(length)
+                            .is_some_and(|total| total <= MAX_MESSAGE_LENGTH)
+                })
+                .ok_or_else(|| invalid("WebSocket message too large"))?;
+            let mut mask = [0; 4];
+            if masked {
+                self.reader.read_exact(&mut mask).await?;
+            }
+            let mut payload = vec![0; length];
+            self.reader.read_exact(&mut payload).await?;
+            if masked {
+                apply_mask(&mut payload, mask);
+            }
+
+            match opcode {
+                OPCODE_PING => return Ok(Incoming::Ping(payload)),
+                OPCODE_PONG => continue,
+                OPCODE_CLOSE => return Ok(Incoming::Close),
+                OPCODE_TEXT | OPCODE_BINARY if self.message_opcode.is_none() => {
+                    self.message_opcode = Some(opcode)
+                }
+                OPCODE_CONTINUATION if self.message_opcode.is_some() => (),
+                _ => return Err(invalid("Unexpected WebSocket frame")),
+            }
+            self.message.extend_from_slice(&payload);
+            if fin {
+                let message = mem::take(&mut self.message);
+                return Ok(Incoming::Message(match self.message_opcode.take() {
+                    Some(OPCODE_TEXT) => WebSocketMessage::Text(
+                        String::from_utf8(message)
+                            .map_err(|_| invalid("Text message is not UTF-8"))?,
+                    ),
+                    _ => WebSocketMessage::Binary(message),
+                }));
+            }
+        }
+    }
+}
+
+/// Write a frame to the connection
+pub(super) async fn write<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    role: Role,
+    outgoing: Outgoing,
+) -> io::Result<()> {
+    let (opcode, mut payload) = match outgoing {
+        Outgoing::Message(WebSocketMessage::Text(text)) => (OPCODE_TEXT, text.into_bytes()),
+        Outgoing::Message(WebSocketMessage::Binary(bytes)) => (OPCODE_BINARY, bytes),
+        Outgoing::Pong(payload) => (OPCODE_PONG, payload),
+        Outgoing::Close => (OPCODE_CLOSE, Vec::new()),
+    };
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+    let mask_bit = if role == Role::Client { 0x80 } else { 0 };
+    match payload.len() {
+        length @ 0..=125 => frame.push(mask_bit | length as u8),
+        length @ 126..=0xFFFF => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    if role == Role::Client {
+        let mask = random_bytes::<4>();
+        frame.extend_from_slice(&mask);
+        apply_mask(&mut payload, mask);
+    }
+    frame.extend_from_slice(&payload);
+    writer.write_all(&frame).await?;
+    writer.flush().await
+}
+
+/// Read the head of an HTTP request or response, up to the blank line which ends it
+async fn read_head<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<String> {
+    // Read a byte at a time, so that nothing after the head (i.e. the first frame) is consumed
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HANDSHAKE_LENGTH {
+            return Err(invalid("WebSocket handshake too long"));
+        }
+        head.push(stream.read_u8().await?);
+    }
+    String::from_utf8(head).map_err(|_| invalid("WebSocket handshake is not UTF-8"))
+}
+
+/// Find the value of a header (whose name is given in lower case) among the lines of an HTTP head
+fn header<'a>(lines: impl Iterator<Item = &'a str>, name: &str) -> Option<&'a str> {
+    lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(header, _)| header.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+/// The value a server must return in Sec-WebSocket-Accept for the given Sec-WebSocket-Key
+fn accept_value(key: &str) -> String {
+    base64(&sha1(format!("{key}{HANDSHAKE_GUID}").as_bytes()))
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[index % 4];
+    }
+}
+
+/// Bytes for handshake keys and frame masks, which only need to be unpredictable to intermediaries rather than cryptographically secure
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    for chunk in bytes.chunks_mut(8) {
+        let random = RandomState::new().build_hasher().finish().to_le_bytes();
+        chunk.copy_from_slice(&random[..chunk.len()]);
+    }
+    bytes
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let triple = u32::from(chunk[0]) << 16
+            | u32::from(*chunk.get(1).unwrap_or(&0)) << 8
+            | u32::from(*chunk.get(2).unwrap_or(&0));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(ALPHABET[(triple >> (18 - 6 * index) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// SHA-1, as required by the opening handshake (where it is not used for security)
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in padded.chunks(64) {
+        let mut words = [0u32; 80];
+        for (index, word) in block.chunks(4).enumerate() {
+            words[index] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for index in 16..80 {
+            words[index] =
+                (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16])
+                    .rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame with the given length field and extended length, followed by its payload
+    fn frame(fin: bool, opcode: u8, mask: Option<[u8; 4]>, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![u8::from(fin) << 7 | opcode];
+        let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+        match payload.len() {
+            length @ 0..=125 => frame.push(mask_bit | length as u8),
+            length @ 126..=0xFFFF => {
+                frame.push(mask_bit | 126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(mask_bit | 127);
+                frame.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        let mut payload = payload.to_vec();
+        if let Some(mask) = mask {
+            frame.extend_from_slice(&mask);
+            apply_mask(&mut payload, mask);
+        }
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    async fn read_all(bytes: &[u8]) -> Vec<io::Result<Incoming>> {
+        let mut reader = Reader::new(bytes);
+        let mut incoming = Vec::new();
+        loop {
+            let result = reader.read().await;
+            let end = result.is_err();
+            incoming.push(result);
+            if end {
+                return incoming;
+            }
+        }
+    }
+
+    fn text(incoming: &io::Result<Incoming>) -> Option<&str> {
+        match incoming {
+            Ok(Incoming::Message(WebSocketMessage::Text(text))) => Some(text),
+            _ => None,
+        }
+    }
+
+    fn error_kind(incoming: &io::Result<Incoming>) -> Option<io::ErrorKind> {
+        incoming.as_ref().err().map(io::Error::kind)
+    }
+
+    #[test]
+    fn accept_value_matches_the_rfc() {
+        // The example from RFC 6455 section 1.3
+        assert_eq!(
+            accept_value("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[tokio::test]
+    async fn fragments_are_reassembled_around_control_frames() {
+        let mask = Some([0x37, 0xfa, 0x21, 0x3d]);
+        let bytes = [
+            frame(false, OPCODE_TEXT, mask, b"Hel"),
+            frame(true, OPCODE_PING, mask, b"are you there?"),
+            frame(false, OPCODE_CONTINUATION, mask, b"lo, "),
+            frame(true, OPCODE_PONG, mask, b""),
+            frame(true, OPCODE_CONTINUATION, mask, b"world"),
+            frame(true, OPCODE_BINARY, None, &[0, 1, 2]),
+            frame(true, OPCODE_CLOSE, None, b""),
+        ]
+        .concat();
+        let incoming = read_all(&bytes).await;
+        assert!(
+            matches!(&incoming[0], Ok(Incoming::Ping(payload)) if payload == b"are you there?")
+        );
+        assert_eq!(text(&incoming[1]), Some("Hello, world"));
+        assert!(matches!(
+            &incoming[2],
+            Ok(Incoming::Message(WebSocketMessage::Binary(bytes))) if bytes == &[0, 1, 2]
+        ));
+        assert!(matches!(incoming[3], Ok(Incoming::Close)));
+        assert_eq!(error_kind(&incoming[4]), Some(io::ErrorKind::UnexpectedEof));
+    }
+
+    #[tokio::test]
+    async fn extended_lengths_are_written_and_read() {
+        // Each length with the length field it needs: 7 bits, then 16 bits after 126, then 64 bits after 127
+        for (length, length_field) in [(125, 125), (126, 126), (0xFFFF, 126), (0x10000, 127)] {
+            let mut written = Vec::new();
+            let message = WebSocketMessage::Text("x".repeat(length));
+            write(&mut written, Role::Client, Outgoing::Message(message))
+                .await
+                .unwrap();
+            assert_eq!(written[1], 0x80 | length_field);
+            let incoming = read_all(&written).await;
+            assert_eq!(text(&incoming[0]).map(str::len), Some(length));
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_messages_are_refused_before_being_read() {
+        // Only the header is sent, so the payload would have to be allocated before anything more could be read
+        let mut huge = vec![0x80 | OPCODE_BINARY, 127];
+        huge.extend_from_slice(&(MAX_MESSAGE_LENGTH as u64 + 1).to_be_bytes());
+        assert_eq!(
+            error_kind(&read_all(&huge).await[0]),
+            Some(io::ErrorKind::InvalidData)
+        );
+        let mut largest = vec![0x80 | OPCODE_BINARY, 127];
+        largest.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert_eq!(
+            error_kind(&read_all(&largest).await[0]),
+            Some(io::ErrorKind::InvalidData)
+        );
+
+        // A length which would overflow once added to the fragments already received
+        let mut overflowing = frame(false, OPCODE_BINARY, None, b"first");
+        overflowing.extend_from_slice(&[OPCODE_CONTINUATION, 127]);
+        overflowing.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert_eq!(
+            error_kind(&read_all(&overflowing).await[0]),
+            Some(io::ErrorKind::InvalidData)
+        );
+
+        // A fragmented message is limited as a whole
+        let half = vec![0; MAX_MESSAGE_LENGTH / 2 + 1];
+        let bytes = [
+            frame(false, OPCODE_BINARY, None, &half),
+            frame(true, OPCODE_CONTINUATION, None, &half),
+        ]
+        .concat();
+        assert_eq!(
+            error_kind(&read_all(&bytes).await[0]),
+            Some(io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[tokio::test]
+    async fn malformed_frames_are_refused() {
+        let long_ping = frame(true, OPCODE_PING, None, &[0; MAX_CONTROL_LENGTH + 1]);
+        let fragmented_ping = frame(false, OPCODE_PING, None, b"");
+        let stray_continuation = frame(true, OPCODE_CONTINUATION, None, b"lost");
+        let interrupted = [
+            frame(false, OPCODE_TEXT, None, b"first"),
+            frame(true, OPCODE_TEXT, None, b"second"),
+        ]
+        .concat();
+        let unknown_opcode = frame(true, 0x3, None, b"");
+        let not_utf8 = frame(true, OPCODE_TEXT, None, &[0xff, 0xfe]);
+        for bytes in [
+            long_ping,
+            fragmented_ping,
+            stray_continuation,
+            interrupted,
+            unknown_opcode,
+            not_utf8,
+        ] {
+            assert_eq!(
+                error_kind(&read_all(&bytes).await[0]),
+                Some(io::ErrorKind::InvalidData)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn truncated_frames_fail() {
+        let whole = frame(true, OPCODE_TEXT, Some([1, 2, 3, 4]), &[b'x'; 200]);
+        // Cut off in the header, the extended length, the mask and the payload
+        for cut in [1, 3, 6, 100] {
+            assert_eq!(
+                error_kind(&read_all(&whole[..cut]).await[0]),
+                Some(io::ErrorKind::UnexpectedEof)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn handshakes_are_checked() {
+        let mut request = &b"GET /ws HTTP/1.1\r\nHost: localhost\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"[..];
+        let mut response = Vec::new();
+        accept(&mut tokio::io::join(&mut request, &mut response))
+            .await
+            .unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101 "));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        let mut missing_key = &b"GET /ws HTTP/1.1\r\nHost: localhost\r\n\r\n"[..];
+        let error = accept(&mut tokio::io::join(&mut missing_key, Vec::new()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let long = [&b"GET / HTTP/1.1\r\nX: "[..], &[b'a'; MAX_HANDSHAKE_LENGTH]].concat();
+        let error = accept(&mut tokio::io::join(&long[..], Vec::new()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,77 @@
+use post_haste::postmaster::{Message, Postmaster, Verdict};
+use post_haste::{AddressSpace, PostmasterError};
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AddressSpace)]
+enum Address {
+    Logger,
+    Archive,
+    Clock,
+}
+
+#[tokio::test]
+async fn rejected_message_fails_the_whole_batch() {
+    let postmaster = Postmaster::<Address, u32>::new();
+    let (mailbox, mut inbox) = mpsc::channel(8);
+    postmaster
+        .register(Address::Logger, mailbox.clone())
+        .await
+        .unwrap();
+    postmaster
+        .register(Address::Archive, mailbox)
+        .await
+        .unwrap();
+    postmaster.add_interceptor(|_, message: &mut Message<_, u32>| match message.payload {
+        1 => Verdict::Redirect(Address::Archive),
+        3 => Verdict::Reject,
+        _ => Verdict::Pass,
+    });
+
+    assert_eq!(
+        postmaster
+            .send_all(Address::Logger, Address::Clock, 1..=4)
+            .await,
+        Err(PostmasterError::Rejected)
+    );
+    assert!(inbox.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn redirected_message_keeps_its_place_in_the_batch() {
+    let postmaster = Postmaster::<Address, u32>::new();
+    // Both addresses share one queue, so that the order the messages were delivered in can be seen
+    let (mailbox, mut inbox) = mpsc::channel(8);
+    postmaster
+        .register(Address::Logger, mailbox.clone())
+        .await
+        .unwrap();
+    postmaster
+        .register(Address::Archive, mailbox)
+        .await
+        .unwrap();
+    postmaster.add_interceptor(|_, message: &mut Message<_, u32>| {
+        if message.payload == 2 {
+            Verdict::Redirect(Address::Archive)
+        } else {
+            Verdict::Pass
+        }
+    });
+
+    postmaster
+        .send_all(Address::Logger, Address::Clock, 1..=4)
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    while let Ok(message) = inbox.try_recv() {
+        received.push((message.payload, message.destination()));
+    }
+    assert_eq!(
+        received,
+        [
+            (1, Some(Address::Logger)),
+            (2, Some(Address::Archive)),
+            (3, Some(Address::Logger)),
+            (4, Some(Address::Logger)),
+        ]
+    );
+}
@@ -0,0 +1,103 @@
+#![cfg(all(feature = "lz4", feature = "zstd", feature = "persistence"))]
+
+use post_haste::agent::persistent::{EventJournal, PersistentAgent};
+use post_haste::compression::{Compression, Lz4, Zstd};
+
+/// A reading, as a large and repetitive JSON blob
+fn reading(index: usize) -> Vec<u8> {
+    format!(
+        "{{\"sensor\":\"field-7\",\"index\":{index},\"samples\":[{}]}}",
+        "0.25,".repeat(200)
+    )
+    .into_bytes()
+}
+
+#[test]
+fn lz4_and_zstd_round_trip() {
+    let compressions: [&dyn Compression; 2] = [&Lz4::new(), &Zstd::new()];
+    for compression in compressions {
+        let compressed = compression.compress(&reading(1));
+        assert!(compressed.len() < reading(1).len() / 4);
+        assert_eq!(compression.decompress(&compressed), Some(reading(1)));
+        assert_eq!(
+            compression.decompress(&compressed[..compressed.len() / 2]),
+            None
+        );
+    }
+}
+
+#[test]
+fn decompressing_past_the_maximum_length_fails() {
+    let compressed = Lz4::new().compress(&reading(1));
+    assert_eq!(Lz4::new().with_max_length(64).decompress(&compressed), None);
+    // A length prefix claiming far more than the maximum is refused before anything is allocated
+    let mut claimed = compressed.clone();
+    claimed[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(Lz4::new().decompress(&claimed), None);
+
+    let compressed = Zstd::new().compress(&reading(1));
+    assert_eq!(
+        Zstd::new().with_max_length(64).decompress(&compressed),
+        None
+    );
+}
+
+#[derive(Default)]
+struct Recorder {
+    readings: Vec<Vec<u8>>,
+}
+
+impl PersistentAgent for Recorder {
+    type Message = ();
+    type Event = Vec<u8>;
+
+    async fn on_message(&mut self, _: ()) -> Vec<Vec<u8>> {
+        Vec::new()
+    }
+
+    fn apply(&mut self, event: &Vec<u8>) {
+        self.readings.push(event.clone());
+    }
+
+    fn encode_event(event: &Vec<u8>) -> Vec<u8> {
+        event.clone()
+    }
+
+    fn decode_event(bytes: &[u8]) -> Option<Vec<u8>> {
+        Some(bytes.to_vec())
+    }
+}
+
+#[tokio::test]
+async fn event_journal_is_compressed() {
+    let path = std::env::temp_dir().join(format!(
+        "post-haste-compressed-journal-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    let events = [reading(1), b"small".to_vec(), reading(2)];
+
+    let mut journal = EventJournal::open(&path)
+        .await
+        .unwrap()
+        .with_compression(Zstd::new(), 64);
+    for event in &events {
+        journal.append(event).await.unwrap();
+    }
+    let uncompressed: usize = events.iter().map(Vec::len).sum();
+    assert!((std::fs::metadata(&path).unwrap().len() as usize) < uncompressed / 4);
+
+    let mut recorder = Recorder::default();
+    let mut journal = EventJournal::open(&path)
+        .await
+        .unwrap()
+        .with_compression(Zstd::new(), 64);
+    assert_eq!(journal.replay(&mut recorder).await.unwrap(), 3);
+    assert_eq!(recorder.readings, events);
+
+    // The snapshot is compressed along with the events
+    journal.compact(&reading(3)).await.unwrap();
+    assert!((std::fs::metadata(&path).unwrap().len() as usize) < reading(3).len() / 4);
+    assert_eq!(journal.snapshot().await.unwrap(), Some(reading(3)));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,184 @@
+#![cfg(feature = "cron")]
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use post_haste::postmaster::{CronError, CronSchedule};
+
+/// The given UTC time, counted a day at a time so as not to share the schedule's own date arithmetic
+fn utc(year: u32, month: u32, day: u32, hour: u64, minute: u64, second: u64) -> SystemTime {
+    let leap = |year: u32| {
+        year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+    };
+    let month_days = |year: u32, month: u32| match month {
+        2 if leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    let mut days = u64::from(day - 1);
+    days += (1970..year)
+        .map(|year| if leap(year) { 366 } else { 365 })
+        .sum::<u64>();
+    days += (1..month).map(|month| month_days(year, month)).sum::<u64>();
+    UNIX_EPOCH + Duration::from_secs(days * 86_400 + hour * 3_600 + minute * 60 + second)
+}
+
+/// The next few times at which the schedule fires after the given time
+fn fires(expression: &str, after: SystemTime, count: usize) -> Vec<SystemTime> {
+    let schedule = CronSchedule::parse(expression).unwrap();
+    let mut fires = Vec::new();
+    let mut time = after;
+    for _ in 0..count {
+        time = schedule.next_after(time).unwrap();
+        fires.push(time);
+    }
+    fires
+}
+
+fn midnights(dates: &[(u32, u32, u32)]) -> Vec<SystemTime> {
+    dates
+        .iter()
+        .map(|&(year, month, day)| utc(year, month, day, 0, 0, 0))
+        .collect()
+}
+
+#[test]
+fn steps_select_every_nth_value() {
+    let start = utc(2026, 10, 14, 0, 7, 0);
+    let at = |hour, minute| utc(2026, 10, 14, hour, minute, 0);
+    assert_eq!(
+        fires("*/15 * * * *", start, 4),
+        [at(0, 15), at(0, 30), at(0, 45), at(1, 0)]
+    );
+    // A single value with a step runs to the end of the field
+    assert_eq!(
+        fires("5/20 * * * *", start, 4),
+        [at(0, 25), at(0, 45), at(1, 5), at(1, 25)]
+    );
+    // A stepped range, listed alongside a single value
+    assert_eq!(
+        fires("0-30/10,59 * * * *", start, 5),
+        [at(0, 10), at(0, 20), at(0, 30), at(0, 59), at(1, 0)]
+    );
+    let seconds = |second| utc(2026, 10, 14, 0, 7, second);
+    assert_eq!(
+        fires("*/20 * * * * *", start, 3),
+        [seconds(20), seconds(40), utc(2026, 10, 14, 0, 8, 0)]
+    );
+}
+
+#[test]
+fn next_fire_is_strictly_after_the_given_time() {
+    let nightly = CronSchedule::parse("0 2 * * *").unwrap();
+    let fire = utc(2026, 10, 14, 2, 0, 0);
+    assert_eq!(
+        nightly.next_after(fire - Duration::from_secs(1)),
+        Some(fire)
+    );
+    assert_eq!(nightly.next_after(fire), Some(utc(2026, 10, 15, 2, 0, 0)));
+}
+
+#[test]
+fn restricted_day_of_month_or_week_is_enough() {
+    // 2026-10-14 is a Wednesday
+    let start = utc(2026, 10, 14, 0, 0, 0);
+    assert_eq!(
+        fires("0 0 13 * 5", start, 10),
+        midnights(&[
+            (2026, 10, 16),
+            (2026, 10, 23),
+            (2026, 10, 30),
+            (2026, 11, 6),
+            (2026, 11, 13),
+            (2026, 11, 20),
+            (2026, 11, 27),
+            (2026, 12, 4),
+            (2026, 12, 11),
+            (2026, 12, 13),
+        ])
+    );
+    // With only one of the day fields restricted, it alone decides
+    assert_eq!(
+        fires("0 0 13 * *", start, 2),
+        midnights(&[(2026, 11, 13), (2026, 12, 13)])
+    );
+    assert_eq!(
+        fires("0 0 * * 5", start, 2),
+        midnights(&[(2026, 10, 16), (2026, 10, 23)])
+    );
+    // Restricting the month as well as the day of the week fires on those days of that month
+    assert_eq!(
+        fires("0 0 * 11 5", start, 2),
+        midnights(&[(2026, 11, 6), (2026, 11, 13)])
+    );
+}
+
+#[test]
+fn days_of_the_week_take_ranges_steps_and_either_sunday() {
+    let start = utc(2026, 10, 14, 0, 0, 0);
+    assert_eq!(
+        fires("0 0 * * 1-5/2", start, 4),
+        midnights(&[
+            (2026, 10, 16),
+            (2026, 10, 19),
+            (2026, 10, 21),
+            (2026, 10, 23)
+        ])
+    );
+    let sundays = midnights(&[(2026, 10, 18), (2026, 10, 25), (2026, 11, 1)]);
+    assert_eq!(fires("0 0 * * 0", start, 3), sundays);
+    assert_eq!(fires("0 0 * * 7", start, 3), sundays);
+}
+
+#[test]
+fn dates_roll_over_months_years_and_leap_days() {
+    assert_eq!(
+        fires("0 0 1 1 *", utc(2026, 12, 31, 23, 59, 59), 1),
+        midnights(&[(2027, 1, 1)])
+    );
+    assert_eq!(
+        fires("0 0 31 * *", utc(2026, 10, 31, 0, 0, 0), 2),
+        midnights(&[(2026, 12, 31), (2027, 1, 31)])
+    );
+    assert_eq!(
+        fires("0 0 29 2 *", utc(2026, 10, 14, 0, 0, 0), 2),
+        midnights(&[(2028, 2, 29), (2032, 2, 29)])
+    );
+    // 2100 isn't a leap year
+    assert_eq!(
+        fires("0 0 29 2 *", utc(2097, 1, 1, 0, 0, 0), 1),
+        midnights(&[(2104, 2, 29)])
+    );
+    let never = CronSchedule::parse("0 0 30 2 *").unwrap();
+    assert_eq!(never.next_after(utc(2026, 10, 14, 0, 0, 0)), None);
+}
+
+#[test]
+fn invalid_expressions_are_refused() {
+    assert_eq!(
+        CronSchedule::parse("* * * *"),
+        Err(CronError::FieldCount(4))
+    );
+    assert_eq!(
+        CronSchedule::parse("* * * * * * *"),
+        Err(CronError::FieldCount(7))
+    );
+    for (expression, field) in [
+        ("60 * * * *", "minute"),
+        ("*/0 * * * *", "minute"),
+        ("5-1 * * * *", "minute"),
+        ("1/ * * * *", "minute"),
+        ("* 24 * * *", "hour"),
+        ("* * 0 * *", "day of month"),
+        ("* * * 13 *", "month"),
+        ("* * * * 8", "day of week"),
+        ("* * * * mon", "day of week"),
+        ("60 * * * * *", "second"),
+    ] {
+        assert_eq!(
+            CronSchedule::parse(expression),
+            Err(CronError::InvalidField(field)),
+            "{expression}"
+        );
+    }
+}
@@ -0,0 +1,44 @@
+use post_haste::AddressSpace;
+use post_haste::agent;
+use post_haste::postmaster::{DelayClock, Postmaster, PostmasterConfig};
+use tokio::time::Duration;
+
+#[derive(Debug, Clone, Copy, AddressSpace)]
+enum Address {
+    Worker,
+    Controller,
+}
+
+/// The number of threads running in this process
+#[cfg(target_os = "linux")]
+fn threads() -> usize {
+    std::fs::read_dir("/proc/self/task").unwrap().count()
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn cancelled_real_delays_dont_leave_threads_asleep() {
+    let postmaster = Postmaster::<Address, u32>::with_config(
+        PostmasterConfig::default().with_delay_clock(DelayClock::Real),
+    );
+    let (mailbox, _inbox) = agent::inbox(1);
+    postmaster
+        .register_inbox(Address::Worker, mailbox)
+        .await
+        .unwrap();
+    let before = threads();
+
+    // Each delayed message is due sooner than the one before it, cancelling the wait for that one
+    for payload in 0..50 {
+        postmaster
+            .message(Address::Worker, Address::Controller, payload)
+            .with_delay(Duration::from_secs(3600 - u64::from(payload)))
+            .send()
+            .await
+            .unwrap();
+        tokio::task::yield_now().await;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert!(threads() < before + 5);
+}
@@ -0,0 +1,32 @@
+use post_haste::agent;
+use post_haste::postmaster::{Postmaster, SendError, TrySendError, Verdict};
+use post_haste::{AddressSpace, PostmasterError};
+
+#[derive(Debug, Clone, Copy, AddressSpace)]
+enum Address {
+    Worker,
+    Controller,
+}
+
+#[tokio::test]
+async fn rejected_message_hands_back_its_payload() {
+    let postmaster = Postmaster::<Address, u32>::new();
+    let (mailbox, _inbox) = agent::inbox(1);
+    postmaster
+        .register_inbox(Address::Worker, mailbox)
+        .await
+        .unwrap();
+    postmaster.add_interceptor(|_, _: &mut _| Verdict::Reject);
+
+    match postmaster
+        .send_returning(Address::Worker, Address::Controller, 1)
+        .await
+    {
+        Err(SendError::Failed(PostmasterError::Rejected, Some(1))) => {}
+        other => panic!("unexpected result {other:?}"),
+    }
+    match postmaster.try_send_returning(Address::Worker, Address::Controller, 2) {
+        Err(TrySendError::Failed(PostmasterError::Rejected, Some(2))) => {}
+        other => panic!("unexpected result {other:?}"),
+    }
+}
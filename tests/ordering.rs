@@ -0,0 +1,86 @@
+use post_haste::postmaster::{
+    MessageOrdering, Postmaster, PostmasterConfig, SequenceTracker, Sequenced,
+};
+use post_haste::{AddressSpace, PostmasterError};
+use tokio::sync::mpsc;
+use tokio::time::{Duration, timeout};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AddressSpace)]
+enum Address {
+    Lights,
+    Sequencer,
+}
+
+fn ordered_postmaster(ordering: MessageOrdering) -> Postmaster<Address, u32> {
+    Postmaster::with_config(
+        PostmasterConfig::new()
+            .with_queue_size(8)
+            .with_ordering(ordering),
+    )
+}
+
+#[tokio::test]
+async fn send_behind_a_strict_delayed_message_times_out() {
+    let postmaster = ordered_postmaster(MessageOrdering::PerSenderStrict);
+    let (mailbox, mut inbox) = mpsc::channel(8);
+    postmaster.register(Address::Lights, mailbox).await.unwrap();
+
+    postmaster
+        .message(Address::Lights, Address::Sequencer, 1)
+        .with_delay(Duration::from_secs(60))
+        .send()
+        .await
+        .unwrap();
+
+    // The later message waits for its turn only as long as its send timeout, rather than for the whole delay
+    let send = postmaster.send(Address::Lights, Address::Sequencer, 2);
+    assert_eq!(
+        timeout(Duration::from_secs(1), send).await,
+        Ok(Err(PostmasterError::Timeout))
+    );
+    let send = postmaster
+        .message(Address::Lights, Address::Sequencer, 3)
+        .with_timeout(Duration::from_millis(20))
+        .send();
+    assert_eq!(
+        timeout(Duration::from_secs(1), send).await,
+        Ok(Err(PostmasterError::Timeout))
+    );
+    assert!(inbox.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn sequences_start_again_once_deregistered() {
+    let postmaster = ordered_postmaster(MessageOrdering::PerSender);
+    let (mailbox, mut inbox) = mpsc::channel(8);
+    postmaster.register(Address::Lights, mailbox).await.unwrap();
+    let mut sequences = SequenceTracker::new();
+
+    for payload in 0..3 {
+        postmaster
+            .send(Address::Lights, Address::Sequencer, payload)
+            .await
+            .unwrap();
+        let message = inbox.recv().await.unwrap();
+        assert_eq!(sequences.check(&message), Sequenced::InOrder);
+    }
+    assert_eq!(
+        postmaster
+            .send(Address::Lights, Address::Sequencer, 3)
+            .await,
+        Ok(())
+    );
+    assert_eq!(inbox.recv().await.unwrap().sequence(), Some(4));
+
+    postmaster.deregister(Address::Lights).await.unwrap();
+    let (mailbox, mut inbox) = mpsc::channel(8);
+    postmaster.register(Address::Lights, mailbox).await.unwrap();
+    postmaster
+        .send(Address::Lights, Address::Sequencer, 4)
+        .await
+        .unwrap();
+    let message = inbox.recv().await.unwrap();
+    assert_eq!(message.sequence(), Some(1));
+    // A tracker which saw the earlier numbers takes the new numbering to be in order
+    assert_eq!(sequences.check(&message), Sequenced::InOrder);
+}
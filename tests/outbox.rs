@@ -0,0 +1,211 @@
+#![cfg(feature = "outbox")]
+
+use std::path::PathBuf;
+
+use post_haste::AddressSpace;
+use post_haste::outbox::{Outbox, OutboxCodec, OutboxMessage};
+use post_haste::postmaster::{Postmaster, RetryPolicy};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AddressSpace)]
+enum Address {
+    Billing,
+    Checkout,
+}
+
+/// Encodes the payload alone, as every message goes from the checkout to billing; a payload of "invalid" can't be decoded
+struct Codec;
+
+impl OutboxCodec<Address, String> for Codec {
+    fn encode(&self, message: &OutboxMessage<Address, String>) -> Vec<u8> {
+        message.payload.clone().into_bytes()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<OutboxMessage<Address, String>> {
+        let payload = String::from_utf8(bytes.to_vec())
+            .ok()
+            .filter(|payload| payload != "invalid")?;
+        Some(OutboxMessage {
+            destination: Address::Billing,
+            source: Address::Checkout,
+            payload,
+        })
+    }
+}
+
+const PENDING: u8 = 1;
+const DONE: u8 = 2;
+
+/// A journal record, as the outbox writes it
+fn record(kind: u8, sequence: u64, bytes: &[u8]) -> Vec<u8> {
+    let mut record = vec![kind];
+    record.extend_from_slice(&sequence.to_be_bytes());
+    record.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    record.extend_from_slice(bytes);
+    record
+}
+
+fn journal_path(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("post-haste-outbox-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// A Postmaster with billing registered, along with the payloads billing receives (each of which is acknowledged once it has been received)
+async fn billing() -> (Postmaster<Address, String>, mpsc::UnboundedReceiver<String>) {
+    let postmaster = Postmaster::new();
+    let (mailbox, mut inbox) = mpsc::channel(8);
+    postmaster
+        .register(Address::Billing, mailbox)
+        .await
+        .unwrap();
+    let (received, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(message) = inbox.recv().await {
+            let _ = received.send(message.payload);
+        }
+    });
+    (postmaster, receiver)
+}
+
+#[tokio::test]
+async fn partly_written_journal_keeps_the_complete_pending_messages() {
+    let path = journal_path("partial");
+    let complete = [
+        record(PENDING, 0, b"charge 1"),
+        record(PENDING, 1, b"charge 2"),
+        record(DONE, 0, b""),
+        record(PENDING, 2, b"charge 3"),
+    ]
+    .concat();
+    // The process stopped part way through appending a fourth message
+    let partial = record(PENDING, 3, b"charge 4");
+    std::fs::write(
+        &path,
+        [&complete[..], &partial[..partial.len() - 3]].concat(),
+    )
+    .unwrap();
+
+    let (postmaster, mut received) = billing().await;
+    let outbox = Outbox::open(&postmaster, &path, Codec).await.unwrap();
+    assert_eq!(outbox.pending().await, 2);
+    // The journal is compacted down to the pending messages, dropping the incomplete record
+    assert_eq!(
+        std::fs::read(&path).unwrap(),
+        [
+            record(PENDING, 1, b"charge 2"),
+            record(PENDING, 2, b"charge 3")
+        ]
+        .concat()
+    );
+
+    assert_eq!(outbox.redeliver().await, 2);
+    assert_eq!(received.recv().await.unwrap(), "charge 2");
+    assert_eq!(received.recv().await.unwrap(), "charge 3");
+    assert_eq!(outbox.pending().await, 0);
+
+    // New messages are numbered after the ones found in the journal
+    outbox
+        .send(Address::Billing, Address::Checkout, "charge 5".to_string())
+        .await
+        .unwrap();
+    let journal = std::fs::read(&path).unwrap();
+    assert!(journal.ends_with(&[record(PENDING, 3, b"charge 5"), record(DONE, 3, b"")].concat()));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn journal_cut_anywhere_replays_the_records_before_the_cut() {
+    let path = journal_path("cut");
+    let records = [
+        record(PENDING, 0, b"charge 1"),
+        record(PENDING, 1, b"charge 2"),
+        record(DONE, 0, b""),
+    ];
+    let journal = records.concat();
+    let (postmaster, _received) = billing().await;
+    for cut in 0..=journal.len() {
+        std::fs::write(&path, &journal[..cut]).unwrap();
+        let outbox = Outbox::open(&postmaster, &path, Codec).await.unwrap();
+        let complete = records
+            .iter()
+            .scan(0, |end, record| {
+                *end += record.len();
+                Some(*end)
+            })
+            .take_while(|&end| end <= cut)
+            .count();
+        let expected = match complete {
+            0 => 0,
+            1 => 1,
+            2 => 2,
+            _ => 1,
+        };
+        assert_eq!(outbox.pending().await, expected, "cut at {cut}");
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn records_after_a_corrupt_one_are_ignored() {
+    let path = journal_path("corrupt");
+    let journal = [
+        record(PENDING, 0, b"charge 1"),
+        record(7, 1, b"garbage"),
+        record(PENDING, 2, b"charge 2"),
+    ]
+    .concat();
+    std::fs::write(&path, journal).unwrap();
+    let (postmaster, mut received) = billing().await;
+    let outbox = Outbox::open(&postmaster, &path, Codec).await.unwrap();
+    assert_eq!(outbox.pending().await, 1);
+    assert_eq!(outbox.redeliver().await, 1);
+    assert_eq!(received.recv().await.unwrap(), "charge 1");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn undecodable_messages_are_discarded_on_redelivery() {
+    let path = journal_path("undecodable");
+    let journal = [
+        record(PENDING, 0, b"invalid"),
+        record(PENDING, 1, b"charge 1"),
+    ]
+    .concat();
+    std::fs::write(&path, journal).unwrap();
+    let (postmaster, mut received) = billing().await;
+    let outbox = Outbox::open(&postmaster, &path, Codec).await.unwrap();
+    assert_eq!(outbox.pending().await, 2);
+    assert_eq!(outbox.redeliver().await, 1);
+    assert_eq!(received.recv().await.unwrap(), "charge 1");
+    assert_eq!(outbox.pending().await, 0);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn undelivered_message_survives_reopening() {
+    let path = journal_path("reopen");
+    {
+        // Billing isn't running, so the message can't be delivered
+        let postmaster = Postmaster::<Address, String>::new();
+        let outbox = Outbox::open(&postmaster, &path, Codec)
+            .await
+            .unwrap()
+            .with_retry(RetryPolicy::exponential(2, Duration::from_millis(1)));
+        assert!(
+            outbox
+                .send(Address::Billing, Address::Checkout, "charge 1".to_string())
+                .await
+                .is_err()
+        );
+        assert_eq!(outbox.pending().await, 1);
+    }
+    let (postmaster, mut received) = billing().await;
+    let outbox = Outbox::open(&postmaster, &path, Codec).await.unwrap();
+    assert_eq!(outbox.pending().await, 1);
+    assert_eq!(outbox.redeliver().await, 1);
+    assert_eq!(received.recv().await.unwrap(), "charge 1");
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,150 @@
+use std::sync::{Arc, Mutex};
+
+use post_haste::agent::{self, AgentTask, AgentTerminated, RestartPolicy};
+use post_haste::postmaster::{PayloadVariant, PolicyViolation, Postmaster, SendPolicy, Verdict};
+use post_haste::{AddressSpace, PostmasterError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AddressSpace)]
+enum Address {
+    Sequencer,
+    Lights,
+    Heater,
+    Plugin,
+    Supervisor,
+}
+
+#[derive(Debug, PayloadVariant)]
+enum Payload {
+    On,
+    Off,
+    Terminated(AgentTerminated<Address>),
+}
+
+/// A Postmaster which only lets the Sequencer turn the Lights on and off, recording the violations
+async fn guarded() -> (
+    Postmaster<Address, Payload>,
+    agent::Inbox<post_haste::postmaster::Message<Address, Payload>>,
+    Arc<Mutex<Vec<PolicyViolation<Address>>>>,
+) {
+    let postmaster = Postmaster::<Address, Payload>::new();
+    let (lights, lights_inbox) = agent::inbox(4);
+    postmaster
+        .register_inbox(Address::Lights, lights)
+        .await
+        .unwrap();
+    let violations = Arc::new(Mutex::new(Vec::new()));
+    let reported = violations.clone();
+    postmaster.set_send_policy(
+        SendPolicy::new()
+            .allow(Address::Sequencer, Address::Lights, &["On", "Off"])
+            .on_violation(move |violation| reported.lock().unwrap().push(*violation)),
+    );
+    (postmaster, lights_inbox, violations)
+}
+
+#[tokio::test]
+async fn violation_is_rejected_and_reported() {
+    let (postmaster, _lights, violations) = guarded().await;
+
+    postmaster
+        .send(Address::Lights, Address::Sequencer, Payload::On)
+        .await
+        .unwrap();
+    assert_eq!(
+        postmaster
+            .send(Address::Lights, Address::Plugin, Payload::Off)
+            .await,
+        Err(PostmasterError::Forbidden)
+    );
+
+    let violations = violations.lock().unwrap();
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].source, Address::Plugin);
+    assert_eq!(violations[0].destination, Address::Lights);
+    assert_eq!(violations[0].variant, "Off");
+    assert_eq!(postmaster.dead_letters().len(), 1);
+}
+
+#[tokio::test]
+async fn control_messages_are_checked() {
+    let (postmaster, _lights, violations) = guarded().await;
+
+    let sent = postmaster
+        .message(Address::Lights, Address::Plugin, Payload::On)
+        .as_control()
+        .send()
+        .await;
+    assert_eq!(sent, Err(PostmasterError::Forbidden));
+    assert_eq!(violations.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn redirected_messages_are_checked_against_their_new_destination() {
+    let (postmaster, _lights, violations) = guarded().await;
+    let (heater, _heater_inbox) = agent::inbox(1);
+    postmaster
+        .register_inbox(Address::Heater, heater)
+        .await
+        .unwrap();
+    postmaster.add_interceptor(|_, _: &mut _| Verdict::Redirect(Address::Heater));
+
+    assert_eq!(
+        postmaster
+            .send(Address::Lights, Address::Sequencer, Payload::On)
+            .await,
+        Err(PostmasterError::Forbidden)
+    );
+    assert_eq!(violations.lock().unwrap()[0].destination, Address::Heater);
+}
+
+#[tokio::test]
+async fn agent_cannot_send_as_another_address() {
+    let (postmaster, mut lights, violations) = guarded().await;
+    let (plugin, _plugin_inbox) = agent::inbox(1);
+    postmaster
+        .register_inbox(Address::Plugin, plugin)
+        .await
+        .unwrap();
+    let (result, mut sent) = tokio::sync::mpsc::channel(1);
+    let impersonator = postmaster.clone();
+    postmaster.supervise(
+        Address::Plugin,
+        Box::pin(async move {
+            let sent = impersonator
+                .send(Address::Lights, Address::Sequencer, Payload::On)
+                .await;
+            result.send(sent).await.unwrap();
+            std::future::pending::<()>().await;
+        }),
+        RestartPolicy::never(),
+        None::<fn() -> AgentTask>,
+    );
+
+    assert_eq!(sent.recv().await, Some(Err(PostmasterError::Forbidden)));
+    assert!(lights.try_recv().is_err());
+    let violations = violations.lock().unwrap();
+    assert_eq!(violations[0].source, Address::Plugin);
+    assert_eq!(violations[0].sent_as, Address::Sequencer);
+}
+
+#[tokio::test]
+async fn watch_notifications_are_exempt() {
+    let (postmaster, _lights, violations) = guarded().await;
+    let (supervisor, mut supervisor_inbox) = agent::inbox(1);
+    postmaster
+        .register_inbox(Address::Supervisor, supervisor)
+        .await
+        .unwrap();
+    postmaster
+        .watch(Address::Supervisor, Address::Lights, Payload::Terminated)
+        .await
+        .unwrap();
+
+    postmaster.deregister(Address::Lights).await.unwrap();
+
+    let Payload::Terminated(terminated) = supervisor_inbox.recv().await.unwrap().payload else {
+        panic!("expected a termination notification");
+    };
+    assert_eq!(terminated.address, Address::Lights);
+    assert!(violations.lock().unwrap().is_empty());
+}
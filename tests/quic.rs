@@ -0,0 +1,154 @@
+#![cfg(feature = "quic")]
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use post_haste::AddressSpace;
+use post_haste::address::AddressIndex;
+use post_haste::agent;
+use post_haste::postmaster::Postmaster;
+use post_haste::remote::{Bridge, Codec, QuicTransport, RemoteMessage, Transport};
+use quinn::rustls::RootCertStore;
+use quinn::rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+use quinn::{ClientConfig, Connection, Endpoint, ServerConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq, AddressSpace)]
+enum Address {
+    Frontend,
+    Storage,
+    Archive,
+}
+
+/// Encodes the source, destination and payload as one byte each
+struct ByteCodec;
+
+fn address_byte(address: Address) -> u8 {
+    match address.index() {
+        AddressIndex::Static(index) => index as u8,
+        AddressIndex::Dynamic(_) => unreachable!(),
+    }
+}
+
+impl Codec<Address, u8> for ByteCodec {
+    fn encode(&self, message: &RemoteMessage<Address, u8>) -> Vec<u8> {
+        vec![
+            address_byte(message.source),
+            address_byte(message.destination),
+            message.payload,
+        ]
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<RemoteMessage<Address, u8>> {
+        let &[source, destination, payload] = bytes else {
+            return None;
+        };
+        Some(RemoteMessage {
+            source: Address::static_address(source.into())?,
+            destination: Address::static_address(destination.into())?,
+            payload,
+            correlation_id: None,
+            reply_to: None,
+        })
+    }
+}
+
+/// Connect a client endpoint to a server endpoint on the loopback interface, returning both ends of the connection
+async fn connect() -> (Connection, Connection) {
+    let certified = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let certificate = CertificateDer::from(certified.cert);
+    let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+    let server_config =
+        ServerConfig::with_single_cert(vec![certificate.clone()], key.into()).unwrap();
+    let loopback = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let server = Endpoint::server(server_config, loopback).unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(certificate).unwrap();
+    let mut client = Endpoint::client(loopback).unwrap();
+    client
+        .set_default_client_config(ClientConfig::with_root_certificates(Arc::new(roots)).unwrap());
+
+    let connecting = client
+        .connect(server.local_addr().unwrap(), "localhost")
+        .unwrap();
+    let (client_connection, server_connection) =
+        tokio::join!(connecting, async { server.accept().await.unwrap().await });
+    (client_connection.unwrap(), server_connection.unwrap())
+}
+
+#[tokio::test]
+async fn messages_cross_a_quic_bridge_in_both_directions() {
+    let (frontend_connection, backend_connection) = connect().await;
+
+    let frontend = Postmaster::<Address, u8>::new();
+    let (mailbox, mut frontend_inbox) = agent::inbox(4);
+    frontend
+        .register_inbox(Address::Frontend, mailbox)
+        .await
+        .unwrap();
+    let frontend_bridge = Bridge::new(&frontend, ByteCodec)
+        .remote(Address::Storage)
+        .remote(Address::Archive)
+        .attach_transport(QuicTransport::new(frontend_connection))
+        .await
+        .unwrap();
+
+    let backend = Postmaster::<Address, u8>::new();
+    let (mailbox, mut storage_inbox) = agent::inbox(4);
+    backend
+        .register_inbox(Address::Storage, mailbox)
+        .await
+        .unwrap();
+    let (mailbox, mut archive_inbox) = agent::inbox(4);
+    backend
+        .register_inbox(Address::Archive, mailbox)
+        .await
+        .unwrap();
+    let _backend_bridge = Bridge::new(&backend, ByteCodec)
+        .remote(Address::Frontend)
+        .attach_transport(QuicTransport::new(backend_connection.clone()))
+        .await
+        .unwrap();
+
+    frontend
+        .send(Address::Storage, Address::Frontend, 1)
+        .await
+        .unwrap();
+    frontend
+        .send(Address::Archive, Address::Frontend, 2)
+        .await
+        .unwrap();
+    let stored = storage_inbox.recv().await.unwrap();
+    assert_eq!((stored.source, stored.payload), (Address::Frontend, 1));
+    let archived = archive_inbox.recv().await.unwrap();
+    assert_eq!((archived.source, archived.payload), (Address::Frontend, 2));
+
+    backend
+        .send(Address::Frontend, Address::Storage, 3)
+        .await
+        .unwrap();
+    let reply = frontend_inbox.recv().await.unwrap();
+    assert_eq!((reply.source, reply.payload), (Address::Storage, 3));
+
+    // Closing the connection ends the bridge cleanly, freeing its remote addresses
+    backend_connection.close(0u32.into(), b"done");
+    frontend_bridge.join().await.unwrap();
+    assert!(
+        frontend
+            .send(Address::Storage, Address::Frontend, 4)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn oversized_frame_is_refused() {
+    let (sending_connection, receiving_connection) = connect().await;
+    let mut receiver = QuicTransport::new(receiving_connection).with_max_frame_length(1024);
+
+    let mut stream = sending_connection.open_uni().await.unwrap();
+    stream.write_all(&[0, 0, 0x10, 0, 1, 2]).await.unwrap();
+
+    let error = receiver.receive().await.unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
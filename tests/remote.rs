@@ -0,0 +1,51 @@
+#![cfg(feature = "remote")]
+
+use std::io;
+
+use post_haste::remote::{FrameSender, StreamTransport, Transport};
+use tokio::io::{AsyncWriteExt, duplex};
+
+/// A transport reading whatever bytes are written to the other end, which is closed once they have been written
+async fn receiving(bytes: &[u8]) -> StreamTransport<tokio::io::DuplexStream> {
+    let (mut other_end, connection) = duplex(64);
+    other_end.write_all(bytes).await.unwrap();
+    drop(other_end);
+    StreamTransport::new(connection)
+}
+
+#[tokio::test]
+async fn frame_round_trip_then_clean_close() {
+    let (one_end, other_end) = duplex(64);
+    let mut sender = StreamTransport::new(one_end).open_sender().await.unwrap();
+    let mut receiver = StreamTransport::new(other_end);
+    sender.send(vec![1, 2, 3]).await.unwrap();
+    drop(sender);
+
+    assert_eq!(receiver.receive().await.unwrap(), Some(vec![1, 2, 3]));
+    assert_eq!(receiver.receive().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn oversized_frame_is_refused() {
+    let mut transport = receiving(&[0, 0, 0x10, 0, 1, 2])
+        .await
+        .with_max_frame_length(1024);
+    let error = transport.receive().await.unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+    // Without a configured maximum, a length of 4 GiB is refused too
+    let mut transport = receiving(&[0xff, 0xff, 0xff, 0xff]).await;
+    let error = transport.receive().await.unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn closing_part_way_through_a_message_is_an_error() {
+    let mut transport = receiving(&[0, 0]).await;
+    let error = transport.receive().await.unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+    let mut transport = receiving(&[0, 0, 0, 4, 1, 2]).await;
+    let error = transport.receive().await.unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+}
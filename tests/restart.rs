@@ -0,0 +1,44 @@
+use post_haste::agent::{self, AgentTerminated, TerminationReason};
+use post_haste::postmaster::Postmaster;
+use post_haste::{AddressSpace, PostmasterError};
+
+#[derive(Debug, Clone, Copy, AddressSpace)]
+enum Address {
+    Worker,
+    Supervisor,
+}
+
+#[derive(Debug)]
+enum Payload {
+    Terminated(AgentTerminated<Address>),
+}
+
+#[tokio::test]
+async fn failed_restart_notifies_watchers() {
+    let postmaster = Postmaster::<Address, Payload>::new();
+    let (worker, _worker_inbox) = agent::inbox(1);
+    postmaster
+        .register_inbox(Address::Worker, worker)
+        .await
+        .unwrap();
+    let (supervisor, mut supervisor_inbox) = agent::inbox(1);
+    postmaster
+        .register_inbox(Address::Supervisor, supervisor)
+        .await
+        .unwrap();
+    postmaster
+        .watch(Address::Supervisor, Address::Worker, Payload::Terminated)
+        .await
+        .unwrap();
+
+    postmaster
+        .restart_failed(Address::Worker, PostmasterError::AddressAlreadyTaken)
+        .await;
+
+    let Payload::Terminated(terminated) = supervisor_inbox.recv().await.unwrap().payload;
+    assert!(matches!(terminated.address, Address::Worker));
+    assert!(matches!(
+        terminated.reason,
+        TerminationReason::RestartFailed(PostmasterError::AddressAlreadyTaken)
+    ));
+}
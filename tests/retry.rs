@@ -0,0 +1,39 @@
+use post_haste::AddressSpace;
+use post_haste::agent;
+use post_haste::postmaster::{Postmaster, RetryPolicy};
+use tokio::time::Duration;
+
+#[derive(Debug, Clone, Copy, AddressSpace)]
+enum Address {
+    Worker,
+    Controller,
+}
+
+#[tokio::test(start_paused = true)]
+async fn retried_control_message_skips_the_full_queue() {
+    let postmaster = Postmaster::<Address, u32>::new();
+    let sender = postmaster.clone();
+    // The first attempt fails, as nothing is registered at the address yet
+    let send = tokio::spawn(async move {
+        sender
+            .message(Address::Worker, Address::Controller, 2)
+            .as_control()
+            .with_retry(RetryPolicy::exponential(5, Duration::from_millis(10)).without_jitter())
+            .send()
+            .await
+    });
+    tokio::task::yield_now().await;
+    let (mailbox, mut inbox) = agent::inbox(1);
+    postmaster
+        .register_inbox(Address::Worker, mailbox)
+        .await
+        .unwrap();
+    postmaster
+        .try_send(Address::Worker, Address::Controller, 1)
+        .unwrap();
+
+    // A retry placed on the full data queue would time out on every attempt
+    send.await.unwrap().unwrap();
+    assert_eq!(inbox.recv().await.unwrap().payload, 2);
+    assert_eq!(inbox.recv().await.unwrap().payload, 1);
+}
@@ -0,0 +1,76 @@
+#![cfg(feature = "serde")]
+
+use post_haste::address::NodeAddress;
+use post_haste::postmaster::{Message, MessageId, Postmaster};
+use post_haste::{AddressSpace, PostmasterError};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AddressSpace, Serialize, Deserialize)]
+enum Address {
+    Worker,
+    Controller,
+    Monitor,
+}
+
+#[tokio::test]
+async fn received_message_survives_a_round_trip() {
+    let postmaster = Postmaster::<Address, String>::new();
+    let (mailbox, mut inbox) = tokio::sync::mpsc::channel(1);
+    postmaster.register(Address::Worker, mailbox).await.unwrap();
+    postmaster
+        .message(Address::Worker, Address::Controller, "start".to_string())
+        .with_correlation_id(7)
+        .reply_to(Address::Monitor)
+        .with_id(3)
+        .as_control()
+        .send()
+        .await
+        .unwrap();
+    let message = inbox.recv().await.unwrap();
+
+    let json = serde_json::to_string(&message).unwrap();
+    let copy: Message<Address, String> = serde_json::from_str(&json).unwrap();
+    assert_eq!(copy.source, Address::Controller);
+    assert_eq!(copy.payload, "start");
+    assert_eq!(copy.correlation_id, Some(7.into()));
+    assert_eq!(copy.reply_to, Some(Address::Monitor));
+    assert_eq!(copy.destination(), Some(Address::Worker));
+    assert_eq!(copy.id(), Some(MessageId(3)));
+    assert!(copy.is_control());
+}
+
+#[test]
+fn message_needs_only_its_source_and_payload() {
+    let message: Message<Address, u32> =
+        serde_json::from_str(r#"{"source":"Controller","payload":5}"#).unwrap();
+    assert_eq!(message.source, Address::Controller);
+    assert_eq!(message.payload, 5);
+    assert_eq!(message.correlation_id, None);
+    assert_eq!(message.reply_address(), Address::Controller);
+    assert_eq!(message.destination(), None);
+}
+
+#[test]
+fn errors_survive_a_round_trip() {
+    let json = serde_json::to_string(&PostmasterError::Timeout).unwrap();
+    assert_eq!(
+        serde_json::from_str::<PostmasterError>(&json).unwrap(),
+        PostmasterError::Timeout
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AddressSpace, Serialize, Deserialize)]
+enum Node {
+    Left,
+    Right,
+}
+
+#[test]
+fn node_addresses_survive_a_round_trip() {
+    let address = NodeAddress::new(Node::Right, Address::Worker);
+    let json = serde_json::to_string(&address).unwrap();
+    assert_eq!(
+        serde_json::from_str::<NodeAddress<Node, Address>>(&json).unwrap(),
+        address
+    );
+}
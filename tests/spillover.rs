@@ -0,0 +1,142 @@
+#![cfg(feature = "spillover")]
+
+use std::path::PathBuf;
+
+use post_haste::AddressSpace;
+use post_haste::agent::{self, Inbox};
+use post_haste::postmaster::{Message, OverflowPolicy, Postmaster, PostmasterConfig};
+use post_haste::spillover::{SpillCodec, SpilledMessage, Spillover};
+use tokio::time::{Duration, timeout};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AddressSpace)]
+enum Address {
+    Ingest,
+    Sensor,
+}
+
+struct Codec;
+
+impl SpillCodec<Address, u32> for Codec {
+    fn encode(&self, message: &SpilledMessage<Address, u32>) -> Vec<u8> {
+        message.payload.to_le_bytes().to_vec()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<SpilledMessage<Address, u32>> {
+        Some(SpilledMessage {
+            source: Address::Sensor,
+            reply_to: None,
+            payload: u32::from_le_bytes(bytes.try_into().ok()?),
+        })
+    }
+}
+
+fn spilling_postmaster(name: &str) -> (Postmaster<Address, u32>, Spillover<Address, u32>) {
+    let postmaster = Postmaster::with_config(
+        PostmasterConfig::new().with_overflow_policy(OverflowPolicy::Spill),
+    );
+    let directory: PathBuf = std::env::temp_dir().join(format!(
+        "post-haste-spillover-{name}-{}",
+        std::process::id()
+    ));
+    let spillover = Spillover::open(&postmaster, directory, Codec).unwrap();
+    (postmaster, spillover)
+}
+
+async fn send(postmaster: &Postmaster<Address, u32>, payloads: impl IntoIterator<Item = u32>) {
+    for payload in payloads {
+        postmaster
+            .message(Address::Ingest, Address::Sensor, payload)
+            .send()
+            .await
+            .unwrap();
+    }
+}
+
+async fn receive(inbox: &mut Inbox<Message<Address, u32>>, count: usize) -> Vec<u32> {
+    let mut payloads = Vec::new();
+    for _ in 0..count {
+        let message = timeout(Duration::from_secs(1), inbox.recv())
+            .await
+            .expect("a spooled message was not replayed")
+            .unwrap();
+        payloads.push(message.payload);
+    }
+    payloads
+}
+
+#[tokio::test]
+async fn spooled_messages_are_replayed_in_order() {
+    let (postmaster, spillover) = spilling_postmaster("order");
+    let (mailbox, mut inbox) = agent::inbox(2);
+    postmaster
+        .register_inbox(Address::Ingest, mailbox)
+        .await
+        .unwrap();
+
+    send(&postmaster, 0..6).await;
+    assert_eq!(spillover.spooled(Address::Ingest), 4);
+    assert_eq!(receive(&mut inbox, 3).await, [0, 1, 2]);
+
+    // Messages sent while others are spooled join the end of the spool
+    send(&postmaster, 6..8).await;
+    assert_eq!(receive(&mut inbox, 5).await, [3, 4, 5, 6, 7]);
+    assert_eq!(spillover.spooled(Address::Ingest), 0);
+    assert_eq!(spillover.stats().spooled, 6);
+    assert_eq!(spillover.stats().replayed, 6);
+}
+
+#[tokio::test]
+async fn replay_restarts_once_a_closed_queue_is_replaced() {
+    let (postmaster, spillover) = spilling_postmaster("closed");
+    let (mailbox, inbox) = agent::inbox(2);
+    postmaster
+        .register_inbox(Address::Ingest, mailbox)
+        .await
+        .unwrap();
+    send(&postmaster, 0..5).await;
+
+    // The Agent goes away, closing its queue, and is registered again, e.g. as when it is restarted
+    drop(inbox);
+    tokio::task::yield_now().await;
+    postmaster.deregister(Address::Ingest).await.unwrap();
+    let (mailbox, mut inbox) = agent::inbox(2);
+    postmaster
+        .register_inbox(Address::Ingest, mailbox)
+        .await
+        .unwrap();
+    assert_eq!(spillover.spooled(Address::Ingest), 3);
+
+    send(&postmaster, [5]).await;
+    assert_eq!(receive(&mut inbox, 4).await, [2, 3, 4, 5]);
+}
+
+#[test]
+fn replay_restarts_after_its_runtime_shuts_down() {
+    let (postmaster, spillover) = spilling_postmaster("runtime");
+    let (mailbox, mut inbox) = agent::inbox(2);
+
+    // The messages overflow from a runtime which then shuts down, e.g. that of a threaded Agent, dropping the task replaying them
+    let sender_runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    sender_runtime.block_on(async {
+        postmaster
+            .register_inbox(Address::Ingest, mailbox)
+            .await
+            .unwrap();
+        send(&postmaster, 0..5).await;
+    });
+    drop(sender_runtime);
+    assert_eq!(spillover.spooled(Address::Ingest), 3);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        assert_eq!(receive(&mut inbox, 2).await, [0, 1]);
+        send(&postmaster, [5]).await;
+        assert_eq!(receive(&mut inbox, 4).await, [2, 3, 4, 5]);
+    });
+}
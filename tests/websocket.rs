@@ -0,0 +1,113 @@
+#![cfg(feature = "websocket")]
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use post_haste::AddressSpace;
+use post_haste::agent;
+use post_haste::postmaster::Postmaster;
+use post_haste::websocket::{
+    WebSocketCodec, WebSocketMessage, WebSocketServer, WebSocketServerConfig,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{Duration, sleep, timeout};
+
+#[derive(Debug, Clone, Copy, AddressSpace)]
+enum Address {
+    Server,
+    Controller,
+}
+
+struct TextCodec;
+
+impl WebSocketCodec<String> for TextCodec {
+    fn encode(&self, payload: &String) -> Option<WebSocketMessage> {
+        Some(WebSocketMessage::Text(payload.clone()))
+    }
+
+    fn decode(&self, message: WebSocketMessage) -> Option<String> {
+        match message {
+            WebSocketMessage::Text(text) => Some(text),
+            WebSocketMessage::Binary(_) => None,
+        }
+    }
+}
+
+/// A masked frame, as sent by a client
+fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [1, 2, 3, 4];
+    let mut frame = vec![u8::from(fin) << 7 | opcode, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&mask);
+    frame.extend(
+        payload
+            .iter()
+            .zip(mask.iter().cycle())
+            .map(|(byte, mask)| byte ^ mask),
+    );
+    frame
+}
+
+#[tokio::test]
+async fn ping_between_fragments_keeps_the_partial_message() {
+    // Find a free port for the server to listen on
+    let bind = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let postmaster = Postmaster::<Address, String>::new();
+    let (mailbox, mut inbox) = agent::inbox(4);
+    postmaster
+        .register_inbox(Address::Controller, mailbox)
+        .await
+        .unwrap();
+    let config = WebSocketServerConfig::new(&postmaster, bind, Address::Controller, TextCodec);
+    post_haste::spawn_agent!(
+        &postmaster,
+        Address::Server,
+        WebSocketServer<Address, String, TextCodec>,
+        config,
+        4
+    )
+    .unwrap();
+
+    let mut stream = loop {
+        match TcpStream::connect(bind).await {
+            Ok(stream) => break stream,
+            Err(_) => sleep(Duration::from_millis(10)).await,
+        }
+    };
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        response.push(stream.read_u8().await.unwrap());
+    }
+    assert!(response.starts_with(b"HTTP/1.1 101"));
+
+    stream
+        .write_all(&client_frame(false, 0x1, b"hel"))
+        .await
+        .unwrap();
+    stream
+        .write_all(&client_frame(true, 0x9, b"ping"))
+        .await
+        .unwrap();
+    stream
+        .write_all(&client_frame(true, 0x0, b"lo"))
+        .await
+        .unwrap();
+
+    let mut pong = [0; 6];
+    stream.read_exact(&mut pong).await.unwrap();
+    assert_eq!(pong, *b"\x8A\x04ping");
+    let message = timeout(Duration::from_secs(5), inbox.recv())
+        .await
+        .expect("The message was lost")
+        .unwrap();
+    assert_eq!(message.payload, "hello");
+}